@@ -4,38 +4,67 @@ use clap::{
 };
 use sol_starter_ido::{
     instruction::{
-        add_to_whitelist, initialize_market, initialize_pool, participate, start_pool, withdraw,
-        InitializeMarket, InitializePool, Participate,
+        add_many_to_whitelist, create_market_user_kyc, fund_distribution, initialize_market,
+        initialize_pool, participate, start_pool, withdraw, CreateMarketUserKyc, FundDistribution,
+        InitializeMarket, InitializePool, Participate, Withdraw,
     },
-    state::{Market, MintWhitelist, Pool},
+    sdk,
+    state::{
+        KycRequirement, Market, MintWhitelist, Pool, UnixTimeSmallDuration, ACCOUNT_TYPE_POOL,
+    },
+    utils::program::{derive_pool_account, pool_account_seed},
 };
 use sol_starter_staking::{
-    instruction::initialize_lock, instruction::initialize_pool as initialize_stake_pool,
-    instruction::InitializePoolInput as InitializeStakePoolInput, state::StakePool, TIERS_COUNT,
+    instruction::initialize_lock,
+    instruction::initialize_pool as initialize_stake_pool,
+    instruction::InitializePoolInput as InitializeStakePoolInput,
+    instruction::{
+        self as staking_instruction, LockInput, StakeStartInput, UnlockInput, UnstakeStartInput,
+    },
+    program::PoolUserAuthority,
+    state::{get_tier, PoolLock, PoolTransit, StakePool, StateVersion, TransitDirection},
+    LOCK_SEED, TIERS_COUNT,
 };
 
 use borsh::BorshDeserialize;
+use num_traits::FromPrimitive;
 use regex::Regex;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use solana_clap_utils::{
     input_parsers::pubkey_of,
     input_validators::{is_keypair, is_parsable, is_pubkey, is_url},
     keypair::signer_from_path,
 };
-use solana_client::rpc_client::RpcClient;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_client::RpcClient,
+};
 use solana_program::{
-    clock::UnixTimestamp, instruction::Instruction, program_pack::Pack, pubkey::Pubkey,
+    clock::{Clock, UnixTimestamp},
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
     system_instruction::create_account_with_seed,
 };
 use solana_sdk::{
+    account::{from_account, Account},
     commitment_config::CommitmentConfig,
+    message::Message,
     native_token::lamports_to_sol,
-    signature::{Keypair, Signer},
-    system_instruction,
-    transaction::Transaction,
+    packet::PACKET_DATA_SIZE,
+    signature::{Keypair, Signature, Signer},
+    system_instruction, sysvar,
+    transaction::{Transaction, TransactionError},
 };
 use spl_token::state::{Account as TokenAccount, Mint};
-use std::{process::exit, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    num::ParseIntError,
+    process::exit,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[allow(dead_code)]
 struct Config {
@@ -44,11 +73,167 @@ struct Config {
     owner: Box<dyn Signer>,
     fee_payer: Box<dyn Signer>,
     commitment_config: CommitmentConfig,
+    dry_run: bool,
+    output: OutputFormat,
+}
+
+/// How read commands print their results
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Human readable lines
+    Text,
+    /// Pretty printed JSON object of command view
+    Json,
+}
+
+fn print_json<T: Serialize>(view: &T) -> Result<(), Error> {
+    println!("{}", serde_json::to_string_pretty(view)?);
+    Ok(())
 }
 
 type Error = Box<dyn std::error::Error>;
 type CommandResult = Result<Option<Transaction>, Error>;
 
+/// Exit code for errors not covered by [CliError]
+const EXIT_CODE_OTHER: i32 = 1;
+/// Exit code for [CliError::Network]
+const EXIT_CODE_NETWORK: i32 = 2;
+/// Exit code for [CliError::InsufficientBalance]
+const EXIT_CODE_INSUFFICIENT_BALANCE: i32 = 3;
+/// Exit code for [CliError::Program]
+const EXIT_CODE_PROGRAM: i32 = 4;
+
+/// Classified CLI errors, each class exits with own code
+#[derive(Debug)]
+enum CliError {
+    /// RPC node is not reachable or failed to respond
+    Network(ClientError),
+    /// Fee payer can not pay for transactions
+    InsufficientBalance(String),
+    /// On chain program failed with custom error
+    Program {
+        program: &'static str,
+        code: u32,
+        name: String,
+        message: String,
+    },
+}
+
+impl CliError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Network(_) => EXIT_CODE_NETWORK,
+            CliError::InsufficientBalance(_) => EXIT_CODE_INSUFFICIENT_BALANCE,
+            CliError::Program { .. } => EXIT_CODE_PROGRAM,
+        }
+    }
+
+    /// Decodes custom error `code` returned by `program_id` against ido and staking errors
+    fn from_custom(program_id: &Pubkey, code: u32) -> Option<Self> {
+        let (program, name, message) = if *program_id == sol_starter_ido::id() {
+            let error = sol_starter_ido::error::Error::from_u32(code)?;
+            ("ido", format!("{:?}", error), error.to_string())
+        } else if *program_id == sol_starter_staking::id() {
+            let error = sol_starter_staking::error::Error::from_u32(code)?;
+            ("staking", format!("{:?}", error), error.to_string())
+        } else {
+            return None;
+        };
+        Some(CliError::Program {
+            program,
+            code,
+            name,
+            message,
+        })
+    }
+
+    /// Decodes transaction error, custom errors resolved by program of failed instruction
+    fn from_transaction_error(transaction: &Transaction, error: &TransactionError) -> Option<Self> {
+        match error {
+            TransactionError::InstructionError(index, InstructionError::Custom(code)) => {
+                let message = &transaction.message;
+                let instruction = message.instructions.get(*index as usize)?;
+                let program_id = message
+                    .account_keys
+                    .get(instruction.program_id_index as usize)?;
+                Self::from_custom(program_id, *code)
+            }
+            _ => None,
+        }
+    }
+
+    /// Classifies client error of sending `transaction`
+    fn from_client_error(transaction: &Transaction, error: ClientError) -> Error {
+        match error.get_transaction_error() {
+            Some(transaction_error) => {
+                match Self::from_transaction_error(transaction, &transaction_error) {
+                    Some(cli_error) => cli_error.into(),
+                    None => error.into(),
+                }
+            }
+            None => CliError::Network(error).into(),
+        }
+    }
+}
+
+/// Program id by name accepted in `decode-error`
+fn program_id_by_name(program: &str) -> Option<Pubkey> {
+    match program {
+        "ido" => Some(sol_starter_ido::id()),
+        "staking" => Some(sol_starter_staking::id()),
+        _ => None,
+    }
+}
+
+/// Parses custom error code, decimal or `0x` prefixed hexadecimal as printed in transaction logs
+fn parse_error_code(code: &str) -> Result<u32, ParseIntError> {
+    match code.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => code.parse(),
+    }
+}
+
+fn is_error_code(code: String) -> Result<(), String> {
+    parse_error_code(&code)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Network(error) => write!(f, "Network error: {}", error),
+            CliError::InsufficientBalance(message) => write!(f, "{}", message),
+            CliError::Program {
+                program,
+                code,
+                name,
+                message,
+            } => write!(
+                f,
+                "Program error: {} {} (0x{:x}): {}",
+                program, name, code, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Exit code for any command error
+fn exit_code(error: &Error) -> i32 {
+    if let Some(cli_error) = error.downcast_ref::<CliError>() {
+        cli_error.exit_code()
+    } else if let Some(client_error) = error.downcast_ref::<ClientError>() {
+        match client_error.kind() {
+            ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => EXIT_CODE_NETWORK,
+            _ => EXIT_CODE_OTHER,
+        }
+    } else {
+        EXIT_CODE_OTHER
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct Record {
     wallet: String,
@@ -56,13 +241,13 @@ struct Record {
 }
 
 impl Record {
+    /// Returns whitelist token account of record, pushing its creation if missing
     fn process_record(
         &self,
         instructions: &mut Vec<Instruction>,
         config: &Config,
-        pool: &Pubkey,
         mint_whitelist: &Pubkey,
-    ) -> Result<(), Error> {
+    ) -> Result<Pubkey, Error> {
         if self.wallet.is_empty() {
             return Err("Wallet account is missing in file".into());
         }
@@ -94,33 +279,248 @@ impl Record {
             whitelist_key = key;
         }
 
-        instructions.push(add_to_whitelist(
-            &sol_starter_ido::program_id(),
-            pool,
-            &config.owner.pubkey(),
-            &whitelist_key,
-            mint_whitelist,
-        )?);
+        Ok(whitelist_key)
+    }
+}
+
+/// Whitelist file rows sent in one transaction
+#[derive(Debug, Default)]
+struct WhitelistBatch {
+    /// rows numbered from 1 after header, with their records
+    rows: Vec<(usize, Record)>,
+    /// whitelist token accounts of rows
+    accounts: Vec<Pubkey>,
+    /// token account creations needed by rows
+    instructions: Vec<Instruction>,
+}
+
+/// Whitelist file row which was not whitelisted
+#[derive(Debug)]
+struct WhitelistFailure {
+    row: usize,
+    /// `None` if row could not be read
+    record: Option<Record>,
+    error: String,
+}
+
+/// Outcome of whitelisting rows of file
+#[derive(Debug, Default)]
+struct WhitelistSummary {
+    succeeded: usize,
+    failed: Vec<WhitelistFailure>,
+}
+
+impl WhitelistSummary {
+    fn print(&self) {
+        println!(
+            "Whitelisted {} row(s), failed {} row(s)",
+            self.succeeded,
+            self.failed.len()
+        );
+        for failure in self.failed.iter() {
+            match &failure.record {
+                Some(record) => println!(
+                    "  row {} wallet {:?}: {}",
+                    failure.row, record.wallet, failure.error
+                ),
+                None => println!("  row {}: {}", failure.row, failure.error),
+            }
+        }
+    }
 
+    /// Writes failed rows in whitelist file format to rerun them, unreadable rows are left out
+    fn write_resume<W: std::io::Write>(&self, mut writer: csv::Writer<W>) -> Result<(), Error> {
+        writer.write_record(&["wallet", "whitelist_token_acc"])?;
+        for record in self
+            .failed
+            .iter()
+            .filter_map(|failure| failure.record.as_ref())
+        {
+            writer.write_record(&[&record.wallet, &record.whitelist_token_acc])?;
+        }
+        writer.flush()?;
         Ok(())
     }
 }
 
+/// Sends each batch by `send`, rows of failed batches are recorded in `summary` and next is sent
+fn send_whitelist_batches<F>(
+    batches: Vec<WhitelistBatch>,
+    summary: &mut WhitelistSummary,
+    mut send: F,
+) where
+    F: FnMut(&WhitelistBatch) -> Result<Option<Signature>, Error>,
+{
+    println!("Will be sent {:?} transaction(s)", batches.len());
+
+    for (index, batch) in batches.into_iter().enumerate() {
+        match send(&batch) {
+            Ok(signature) => {
+                if let Some(signature) = signature {
+                    println!("Hash of {:?} transaction: {:?}", index + 1, signature);
+                }
+                summary.succeeded += batch.rows.len();
+            }
+            Err(err) => {
+                println!("Warning: transaction {} failed, {}", index + 1, err);
+                let error = err.to_string();
+                summary
+                    .failed
+                    .extend(
+                        batch
+                            .rows
+                            .into_iter()
+                            .map(|(row, record)| WhitelistFailure {
+                                row,
+                                record: Some(record),
+                                error: error.clone(),
+                            }),
+                    );
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KycRecord {
+    wallet: String,
+    expiration: String,
+}
+
+impl KycRecord {
+    /// Parses row into wallet and expiration, `None` with warning if row should be skipped
+    fn parse(&self, row: usize, now: UnixTimestamp) -> Option<(Pubkey, UnixTimestamp)> {
+        let wallet = match Pubkey::from_str(self.wallet.trim()) {
+            Ok(wallet) => wallet,
+            Err(_) => {
+                println!(
+                    "Warning: row {} skipped, invalid wallet {:?}",
+                    row, self.wallet
+                );
+                return None;
+            }
+        };
+        let expiration = match self.expiration.trim().parse::<UnixTimestamp>() {
+            Ok(expiration) if expiration > now => expiration,
+            Ok(expiration) => {
+                println!(
+                    "Warning: row {} skipped, expiration {} is not in future",
+                    row, expiration
+                );
+                return None;
+            }
+            Err(_) => {
+                println!(
+                    "Warning: row {} skipped, invalid expiration {:?}",
+                    row, self.expiration
+                );
+                return None;
+            }
+        };
+        Some((wallet, expiration))
+    }
+}
+
+/// Sends each set of instructions in separate transaction signed by `signers` and prints signatures
+fn send_instruction_sets(
+    config: &Config,
+    all_instructions: &[Vec<Instruction>],
+    signers: &[&dyn Signer],
+) -> Result<(), Error> {
+    println!("Will be sent {:?} transaction(s)", all_instructions.len());
+
+    for instructions_set in all_instructions.iter().enumerate() {
+        if let Some(signature) = send_instruction_set(config, instructions_set.1, signers)? {
+            println!(
+                "Hash of {:?} transaction: {:?}",
+                instructions_set.0 + 1,
+                signature
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends `instructions` in one transaction signed by `signers`
+fn send_instruction_set(
+    config: &Config,
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+) -> Result<Option<Signature>, Error> {
+    let mut transaction =
+        Transaction::new_with_payer(instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+
+    transaction.sign(signers, recent_blockhash);
+
+    send_transaction(config, &transaction)
+}
+
+/// Sends transaction, or in dry run mode only simulates it and prints simulation results
+fn send_transaction(
+    config: &Config,
+    transaction: &Transaction,
+) -> Result<Option<Signature>, Error> {
+    if config.dry_run {
+        let result = config.rpc_client.simulate_transaction(transaction)?.value;
+        let logs = result.logs.unwrap_or_default();
+        println!("Simulation logs:");
+        for log in logs.iter() {
+            println!("  {}", log);
+        }
+        let re = Regex::new(r"consumed (\d+) of \d+ compute units").unwrap();
+        let units: u64 = logs
+            .iter()
+            .filter_map(|log| re.captures(log))
+            .filter_map(|captures| captures[1].parse::<u64>().ok())
+            .sum();
+        println!("Compute units consumed: {}", units);
+        match result.err {
+            Some(err) => match CliError::from_transaction_error(transaction, &err) {
+                Some(cli_error) => println!("Simulation error: {}", cli_error),
+                None => println!("Simulation error: {:?}", err),
+            },
+            None => println!("Simulation succeeded"),
+        }
+        Ok(None)
+    } else {
+        let signature = config
+            .rpc_client
+            .send_and_confirm_transaction_with_spinner_and_commitment(
+                transaction,
+                config.commitment_config,
+            )
+            .map_err(|err| CliError::from_client_error(transaction, err))?;
+        Ok(Some(signature))
+    }
+}
+
 fn check_fee_payer_balance(config: &Config, required_balance: u64) -> Result<(), Error> {
     let balance = config.rpc_client.get_balance(&config.fee_payer.pubkey())?;
     if balance < required_balance {
-        Err(format!(
+        Err(CliError::InsufficientBalance(format!(
             "Fee payer, {}, has insufficient balance: {} required, {} available",
             config.fee_payer.pubkey(),
             lamports_to_sol(required_balance),
             lamports_to_sol(balance)
-        )
+        ))
         .into())
     } else {
         Ok(())
     }
 }
 
+/// Data of account, `None` if account does not exist, RPC errors are returned
+fn get_account_data_if_exists(config: &Config, key: &Pubkey) -> Result<Option<Vec<u8>>, Error> {
+    Ok(config
+        .rpc_client
+        .get_account_with_commitment(key, config.commitment_config)?
+        .value
+        .map(|account| account.data))
+}
+
 fn create_pool_lock_account(
     config: &Config,
     instructions: &mut Vec<Instruction>,
@@ -131,8 +531,7 @@ fn create_pool_lock_account(
     let key_to_create =
         Pubkey::create_with_seed(&config.owner.pubkey(), pool_lock_seed, &spl_token::id())?;
 
-    let lock_acc_data = config.rpc_client.get_account_data(&key_to_create)?;
-    if lock_acc_data.is_empty() {
+    if get_account_data_if_exists(config, &key_to_create)?.is_none() {
         println!(
             "New lock token account will be created and initialized: {:?}",
             key_to_create
@@ -224,28 +623,40 @@ fn is_mint_right(config: &Config, token_key: &Pubkey, mint: &Pubkey) -> Result<(
     Ok(())
 }
 
-fn command_create_market(
-    config: &Config,
-    stake_token: Pubkey,
-    transit_incoming: UnixTimestamp,
-    transit_outgoing: UnixTimestamp,
-    tier_balance: [u64; TIERS_COUNT],
-) -> CommandResult {
+/// Stake pool the new market is attached to
+enum MarketStakePool {
+    /// created together with market
+    New {
+        stake_token: Pubkey,
+        transit_incoming: UnixTimestamp,
+        transit_outgoing: UnixTimestamp,
+        min_lock_seconds: UnixTimestamp,
+        twab_window_seconds: UnixTimestamp,
+        tier_balance: [u64; TIERS_COUNT],
+    },
+    /// already initialized stake pool shared with other markets
+    Existing(Pubkey),
+}
+
+/// Checks `account` holds initialized [StakePool] of staking program
+fn existing_stake_pool(account: &Account) -> Result<StakePool, Error> {
+    if account.owner != sol_starter_staking::id() {
+        return Err("Stake pool must be owned by staking program".into());
+    }
+    let state = StakePool::migrate(&account.data)?;
+    if state.version == StateVersion::Uninitialized {
+        return Err("Stake pool is not initialized".into());
+    }
+    Ok(state)
+}
+
+fn command_create_market(config: &Config, stake_pool: MarketStakePool) -> CommandResult {
     let mut instructions = vec![];
     let mut required_balance: u64 = 0;
 
-    let stake_pool_account_balance = config
-        .rpc_client
-        .get_minimum_balance_for_rent_exemption(StakePool::LEN)?;
     let market_account_balance = config
         .rpc_client
         .get_minimum_balance_for_rent_exemption(Market::LEN)?;
-    let token_account_balance = config
-        .rpc_client
-        .get_minimum_balance_for_rent_exemption(TokenAccount::LEN)?;
-    let mint_account_balance = config
-        .rpc_client
-        .get_minimum_balance_for_rent_exemption(Mint::LEN)?;
 
     // Creating market account
     let market_account = Keypair::new();
@@ -259,71 +670,112 @@ fn command_create_market(
     ));
     required_balance += market_account_balance;
 
-    // Creating stake pool account
-    let stake_pool_account = Keypair::new();
-    println!("Stake pool account: {:?}", stake_pool_account.pubkey());
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &stake_pool_account.pubkey(),
-        stake_pool_account_balance,
-        StakePool::LEN as u64,
-        &sol_starter_staking::id(),
-    ));
-    required_balance += stake_pool_account_balance;
-
-    // Creating stake pool mint
-    let stake_mint_account = Keypair::new();
-    println!("Stake pool mint: {:?}", stake_mint_account.pubkey());
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &stake_mint_account.pubkey(),
-        mint_account_balance,
-        Mint::LEN as u64,
-        &spl_token::id(),
-    ));
-    required_balance += mint_account_balance;
-
-    // Creating stake pool token account
-    let stake_token_account = Keypair::new();
-    println!(
-        "Stake pool token account: {:?}",
-        stake_token_account.pubkey()
-    );
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &stake_token_account.pubkey(),
-        token_account_balance,
-        TokenAccount::LEN as u64,
-        &spl_token::id(),
-    ));
-    required_balance += token_account_balance;
+    let market_authority =
+        Pubkey::find_program_address(&[&market_account.to_bytes()[..32]], &sol_starter_ido::id()).0;
 
-    // Initializing stake pool
-    instructions.push(initialize_stake_pool(
-        &stake_pool_account.pubkey(),
-        &stake_token_account.pubkey(),
-        &stake_token,
-        &stake_mint_account.pubkey(),
-        InitializeStakePoolInput {
-            tier_balance,
-            ido_authority: Pubkey::find_program_address(
-                &[&market_account.to_bytes()[..32]],
-                &sol_starter_ido::id(),
-            )
-            .0,
+    let mut stake_pool_signers = vec![];
+    let stake_pool = match stake_pool {
+        MarketStakePool::New {
+            stake_token,
             transit_incoming,
             transit_outgoing,
-        },
-    )?);
+            min_lock_seconds,
+            twab_window_seconds,
+            tier_balance,
+        } => {
+            let stake_pool_account_balance = config
+                .rpc_client
+                .get_minimum_balance_for_rent_exemption(StakePool::LEN)?;
+            let token_account_balance = config
+                .rpc_client
+                .get_minimum_balance_for_rent_exemption(TokenAccount::LEN)?;
+            let mint_account_balance = config
+                .rpc_client
+                .get_minimum_balance_for_rent_exemption(Mint::LEN)?;
+
+            // Creating stake pool account
+            let stake_pool_account = Keypair::new();
+            println!("Stake pool account: {:?}", stake_pool_account.pubkey());
+            instructions.push(system_instruction::create_account(
+                &config.fee_payer.pubkey(),
+                &stake_pool_account.pubkey(),
+                stake_pool_account_balance,
+                StakePool::LEN as u64,
+                &sol_starter_staking::id(),
+            ));
+            required_balance += stake_pool_account_balance;
+
+            // Creating stake pool mint
+            let stake_mint_account = Keypair::new();
+            println!("Stake pool mint: {:?}", stake_mint_account.pubkey());
+            instructions.push(system_instruction::create_account(
+                &config.fee_payer.pubkey(),
+                &stake_mint_account.pubkey(),
+                mint_account_balance,
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ));
+            required_balance += mint_account_balance;
+
+            // Creating stake pool token account
+            let stake_token_account = Keypair::new();
+            println!(
+                "Stake pool token account: {:?}",
+                stake_token_account.pubkey()
+            );
+            instructions.push(system_instruction::create_account(
+                &config.fee_payer.pubkey(),
+                &stake_token_account.pubkey(),
+                token_account_balance,
+                TokenAccount::LEN as u64,
+                &spl_token::id(),
+            ));
+            required_balance += token_account_balance;
+
+            // Initializing stake pool
+            instructions.push(initialize_stake_pool(
+                &stake_pool_account.pubkey(),
+                &stake_token_account.pubkey(),
+                &stake_token,
+                &stake_mint_account.pubkey(),
+                None,
+                InitializeStakePoolInput {
+                    tier_balance,
+                    ido_authority: market_authority,
+                    transit_incoming,
+                    transit_outgoing,
+                    reward_rate: 0,
+                    min_lock_seconds,
+                    twab_window_seconds,
+                },
+            )?);
+
+            let stake_pool = stake_pool_account.pubkey();
+            stake_pool_signers.push(stake_pool_account);
+            stake_pool_signers.push(stake_mint_account);
+            stake_pool_signers.push(stake_token_account);
+            stake_pool
+        }
+        MarketStakePool::Existing(stake_pool) => {
+            let account = config.rpc_client.get_account(&stake_pool)?;
+            let state = existing_stake_pool(&account)?;
+            println!("Stake pool account: {:?}", stake_pool);
+            if state.ido_authority != market_authority {
+                println!(
+                    "Stake pool IDO authority {} is not authority of the new market, pools of the market start only after stake pool authority is set to {}",
+                    state.ido_authority, market_authority
+                );
+            }
+            stake_pool
+        }
+    };
 
     // Initialize market account
     instructions.push(initialize_market(
         &sol_starter_ido::program_id(),
         &market_account.pubkey(),
         &config.owner.pubkey(),
-        InitializeMarket {
-            stake_pool: stake_pool_account.pubkey(),
-        },
+        InitializeMarket { stake_pool },
     )?);
 
     let mut transaction =
@@ -334,18 +786,78 @@ fn command_create_market(
         config,
         required_balance + fee_calculator.calculate_fee(&transaction.message()),
     )?;
-    let signers = vec![
+    let mut signers = vec![
         config.fee_payer.as_ref(),
         &market_account,
-        &stake_pool_account,
-        &stake_mint_account,
-        &stake_token_account,
         config.owner.as_ref(),
     ];
+    for signer in stake_pool_signers.iter() {
+        signers.push(signer);
+    }
     transaction.sign(&signers, recent_blockhash);
     Ok(Some(transaction))
 }
 
+/// Account created by [command_create_pool], signed by own keypair or derived from owner with seed
+enum NewAccount {
+    Keypair(Keypair),
+    Seed { address: Pubkey, seed: String },
+}
+
+impl NewAccount {
+    /// new keypair, or address derived from `market` and `nonce` if it is set
+    fn new(
+        config: &Config,
+        market: &Pubkey,
+        nonce: Option<u64>,
+        kind: &str,
+        owner: &Pubkey,
+    ) -> Result<Self, Error> {
+        Ok(match nonce {
+            None => NewAccount::Keypair(Keypair::new()),
+            Some(nonce) => NewAccount::Seed {
+                address: derive_pool_account(&config.owner.pubkey(), market, nonce, kind, owner)?,
+                seed: pool_account_seed(market, nonce, kind),
+            },
+        })
+    }
+
+    fn pubkey(&self) -> Pubkey {
+        match self {
+            NewAccount::Keypair(keypair) => keypair.pubkey(),
+            NewAccount::Seed { address, .. } => *address,
+        }
+    }
+
+    fn create(&self, config: &Config, lamports: u64, space: usize, owner: &Pubkey) -> Instruction {
+        match self {
+            NewAccount::Keypair(keypair) => system_instruction::create_account(
+                &config.fee_payer.pubkey(),
+                &keypair.pubkey(),
+                lamports,
+                space as u64,
+                owner,
+            ),
+            NewAccount::Seed { address, seed } => create_account_with_seed(
+                &config.fee_payer.pubkey(),
+                address,
+                &config.owner.pubkey(),
+                seed,
+                lamports,
+                space as u64,
+                owner,
+            ),
+        }
+    }
+
+    fn keypair(&self) -> Option<&Keypair> {
+        match self {
+            NewAccount::Keypair(keypair) => Some(keypair),
+            NewAccount::Seed { .. } => None,
+        }
+    }
+}
+
 fn command_create_pool(
     config: &Config,
     market: &Pubkey,
@@ -368,76 +880,67 @@ fn command_create_pool(
         .rpc_client
         .get_minimum_balance_for_rent_exemption(Mint::LEN)?;
 
+    let nonce = init_args.nonce;
+    let new_account = |kind, owner: &Pubkey| NewAccount::new(config, market, nonce, kind, owner);
+
     // Create account for the pool
-    let pool_keypair = Keypair::new();
-    println!("IDO pool account: {:?}", pool_keypair.pubkey());
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &pool_keypair.pubkey(),
+    let pool_account = new_account(sol_starter_ido::POOL_SEED, &sol_starter_ido::id())?;
+    println!("IDO pool account: {:?}", pool_account.pubkey());
+    instructions.push(pool_account.create(
+        config,
         pool_account_balance,
-        Pool::LEN as u64,
+        Pool::LEN,
         &sol_starter_ido::id(),
     ));
     required_balance += pool_account_balance;
 
     // Create account for token collection
-    let account_collection_keypair = Keypair::new();
+    let account_collection = new_account(sol_starter_ido::POOL_COLLECTION_SEED, &spl_token::id())?;
     println!(
         "Token collection account: {:?}",
-        account_collection_keypair.pubkey()
+        account_collection.pubkey()
     );
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &account_collection_keypair.pubkey(),
+    instructions.push(account_collection.create(
+        config,
         token_account_balance,
-        TokenAccount::LEN as u64,
+        TokenAccount::LEN,
         &spl_token::id(),
     ));
     required_balance += token_account_balance;
 
     // Create account for token distribution
-    let account_distribution_keypair = Keypair::new();
+    let account_distribution =
+        new_account(sol_starter_ido::POOL_DISTRIBUTION_SEED, &spl_token::id())?;
     println!(
         "Token distribution account: {:?}",
-        account_distribution_keypair.pubkey()
+        account_distribution.pubkey()
     );
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &account_distribution_keypair.pubkey(),
+    instructions.push(account_distribution.create(
+        config,
         token_account_balance,
-        TokenAccount::LEN as u64,
+        TokenAccount::LEN,
         &spl_token::id(),
     ));
     required_balance += token_account_balance;
 
     // Create account for the pool mint
-    let pool_mint_keypair = Keypair::new();
-    println!("Pool mint account: {:?}", pool_mint_keypair.pubkey());
-    instructions.push(system_instruction::create_account(
-        &config.fee_payer.pubkey(),
-        &pool_mint_keypair.pubkey(),
-        mint_account_balance,
-        Mint::LEN as u64,
-        &spl_token::id(),
-    ));
+    let pool_mint = new_account(sol_starter_ido::POOL_MINT_SEED, &spl_token::id())?;
+    println!("Pool mint account: {:?}", pool_mint.pubkey());
+    instructions.push(pool_mint.create(config, mint_account_balance, Mint::LEN, &spl_token::id()));
     required_balance += mint_account_balance;
 
     // (Optional) Create account for the whitelist mint
-    let whitelist_mint_keypair = Keypair::new();
-    let mint_whitelist = if is_whitelist {
-        println!(
-            "Whitelist mint account: {:?}",
-            whitelist_mint_keypair.pubkey()
-        );
-        instructions.push(system_instruction::create_account(
-            &config.fee_payer.pubkey(),
-            &whitelist_mint_keypair.pubkey(),
+    let whitelist_mint = if is_whitelist {
+        let whitelist_mint = new_account(sol_starter_ido::POOL_WHITELIST_SEED, &spl_token::id())?;
+        println!("Whitelist mint account: {:?}", whitelist_mint.pubkey());
+        instructions.push(whitelist_mint.create(
+            config,
             mint_account_balance,
-            Mint::LEN as u64,
+            Mint::LEN,
             &spl_token::id(),
         ));
         required_balance += mint_account_balance;
-        Some(whitelist_mint_keypair.pubkey())
+        Some(whitelist_mint)
     } else {
         None
     };
@@ -450,42 +953,51 @@ fn command_create_pool(
         config,
         required_balance + fee_calculator.calculate_fee(&transaction.message()),
     )?;
-    let mut signers = vec![
-        config.fee_payer.as_ref(),
-        &pool_keypair,
-        &account_collection_keypair,
-        &account_distribution_keypair,
-        &pool_mint_keypair,
+    let mut signers = vec![config.fee_payer.as_ref()];
+    // accounts derived with seed are signed by owner as base
+    if nonce.is_some() {
+        signers.push(config.owner.as_ref());
+    }
+    let new_accounts = [
+        Some(&pool_account),
+        Some(&account_collection),
+        Some(&account_distribution),
+        Some(&pool_mint),
+        whitelist_mint.as_ref(),
     ];
-    if mint_whitelist.is_some() {
-        signers.push(&whitelist_mint_keypair);
+    for account in new_accounts.iter().flatten() {
+        if let Some(keypair) = account.keypair() {
+            signers.push(keypair);
+        }
     }
     transaction.sign(&signers, recent_blockhash);
 
-    let signature = config
-        .rpc_client
-        .send_and_confirm_transaction_with_spinner_and_commitment(
-            &transaction,
-            config.commitment_config,
-        )?;
-    println!(
-        "Tx hash of preparation signature with accounts creation: {:?}",
-        signature
-    );
+    match send_transaction(config, &transaction)? {
+        Some(signature) => println!(
+            "Tx hash of preparation signature with accounts creation: {:?}",
+            signature
+        ),
+        None => {
+            println!(
+                "Pool initialization transaction depends on accounts created by the preparation transaction, so it can not be simulated"
+            );
+            return Ok(None);
+        }
+    }
 
     instructions.clear();
     // Initialize pool
     instructions.push(initialize_pool(
         &sol_starter_ido::program_id(),
-        &pool_keypair.pubkey(),
+        &pool_account.pubkey(),
         market,
         &config.owner.pubkey(),
         mint_collection,
         mint_distribution,
-        &account_collection_keypair.pubkey(),
-        &account_distribution_keypair.pubkey(),
-        &pool_mint_keypair.pubkey(),
-        mint_whitelist,
+        &account_collection.pubkey(),
+        &account_distribution.pubkey(),
+        &pool_mint.pubkey(),
+        whitelist_mint.map(|whitelist_mint| whitelist_mint.pubkey()),
         init_args,
     )?);
 
@@ -499,9 +1011,45 @@ fn command_create_pool(
     Ok(Some(transaction))
 }
 
-fn command_start_pool(config: &Config, market: &Pubkey, pool_to_start: &Pubkey) -> CommandResult {
+fn command_start_pool(
+    config: &Config,
+    market: &Pubkey,
+    pool_to_start: &Pubkey,
+    preview: bool,
+    require_funded: bool,
+) -> CommandResult {
     let market_data = config.rpc_client.get_account_data(market)?;
-    let market_data = Market::try_from_slice(market_data.as_slice())?;
+    let market_data = Market::migrate(market_data.as_slice())?;
+
+    if preview {
+        let pool_data = config.rpc_client.get_account_data(pool_to_start)?;
+        let pool_data = Pool::migrate(pool_data.as_slice())?;
+        let stake_pool = config
+            .rpc_client
+            .get_account_data(&market_data.stake_pool)?;
+        let stake_pool = StakePool::migrate(stake_pool.as_slice())?;
+        let (tier_allocation, tier_remaining) = pool_data
+            .compute_tier_allocations(stake_pool.tier_users, stake_pool.tier_balance)
+            .map_err(|err| format!("Failed to compute tier allocations: {}", err))?;
+        for tier in 0..TIERS_COUNT {
+            println!(
+                "Tier {}: users {}, balance {}, allocation per user {}, total allocation {}",
+                tier + 1,
+                stake_pool.tier_users[tier],
+                stake_pool.tier_balance[tier],
+                tokens_to_ui(tier_allocation[tier], Pool::PRECISION),
+                tokens_to_ui(tier_remaining[tier], Pool::PRECISION),
+            );
+        }
+        return Ok(None);
+    }
+
+    let account_distribution = if require_funded {
+        let pool_data = config.rpc_client.get_account_data(pool_to_start)?;
+        Some(Pool::migrate(pool_data.as_slice())?.account_distribution)
+    } else {
+        None
+    };
 
     let mut transaction = Transaction::new_with_payer(
         &[start_pool(
@@ -510,6 +1058,7 @@ fn command_start_pool(config: &Config, market: &Pubkey, pool_to_start: &Pubkey)
             &market_data.stake_pool,
             market,
             pool_to_start,
+            account_distribution.as_ref(),
         )
         .unwrap()],
         Some(&config.fee_payer.pubkey()),
@@ -526,9 +1075,14 @@ fn command_start_pool(config: &Config, market: &Pubkey, pool_to_start: &Pubkey)
     Ok(Some(transaction))
 }
 
-fn command_add_to_whitelist(config: &Config, pool: &Pubkey, whitelist_accs: &str) -> CommandResult {
+fn command_add_to_whitelist(
+    config: &Config,
+    pool: &Pubkey,
+    whitelist_accs: &str,
+    resume_file: Option<&str>,
+) -> CommandResult {
     let pool_data = config.rpc_client.get_account_data(pool)?;
-    let pool_data = Pool::try_from_slice(pool_data.as_slice())?;
+    let pool_data = Pool::migrate(pool_data.as_slice())?;
 
     let whitelist_mint;
 
@@ -539,50 +1093,140 @@ fn command_add_to_whitelist(config: &Config, pool: &Pubkey, whitelist_accs: &str
     }
 
     let max_process_per_tx = 10;
-    let mut all_instructions: Vec<Vec<Instruction>> = Vec::new();
-    let mut instructions_fraction: Vec<Instruction> = Vec::new();
+    let mut summary = WhitelistSummary::default();
+    let mut batches: Vec<WhitelistBatch> = Vec::new();
+    let mut batch = WhitelistBatch::default();
 
     let mut rdr = csv::Reader::from_path(whitelist_accs)?;
 
-    for result in rdr.deserialize().enumerate() {
-        let record: Record = result.1?;
-        if (result.0 + 1) % max_process_per_tx == 0 {
-            all_instructions.push(instructions_fraction.clone());
-            instructions_fraction.clear();
-        }
-        record.process_record(&mut instructions_fraction, config, pool, &whitelist_mint)?;
+    for (index, result) in rdr.deserialize::<Record>().enumerate() {
+        let row = index + 1;
+        let record = match result {
+            Ok(record) => record,
+            Err(err) => {
+                summary.failed.push(WhitelistFailure {
+                    row,
+                    record: None,
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let mut instructions = Vec::new();
+        match record.process_record(&mut instructions, config, &whitelist_mint) {
+            Ok(account) => {
+                batch.rows.push((row, record));
+                batch.accounts.push(account);
+                batch.instructions.extend(instructions);
+            }
+            Err(err) => summary.failed.push(WhitelistFailure {
+                row,
+                record: Some(record),
+                error: err.to_string(),
+            }),
+        }
+        if batch.rows.len() == max_process_per_tx {
+            batches.push(std::mem::take(&mut batch));
+        }
+    }
+    if !batch.rows.is_empty() {
+        batches.push(batch);
     }
-    all_instructions.push(instructions_fraction);
 
-    println!("Will be sent {:?} transaction(s)", all_instructions.len());
+    let signers = [config.fee_payer.as_ref(), config.owner.as_ref()];
+    send_whitelist_batches(batches, &mut summary, |batch| {
+        let mut instructions = batch.instructions.clone();
+        instructions.push(add_many_to_whitelist(
+            &sol_starter_ido::program_id(),
+            pool,
+            &config.owner.pubkey(),
+            &batch.accounts,
+            &whitelist_mint,
+        )?);
+        send_instruction_set(config, &instructions, &signers)
+    });
 
-    for instructions_set in all_instructions.iter().enumerate() {
-        let mut transaction = Transaction::new_with_payer(
-            instructions_set.1.as_ref(),
-            Some(&config.fee_payer.pubkey()),
-        );
-        let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
-        check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    summary.print();
+    if summary.failed.is_empty() {
+        return Ok(None);
+    }
+    if let Some(resume_file) = resume_file {
+        summary.write_resume(csv::Writer::from_path(resume_file)?)?;
+        println!("Failed rows written to {}", resume_file);
+    }
+    Err(format!("{} whitelist row(s) failed", summary.failed.len()).into())
+}
 
-        transaction.sign(
-            &[config.fee_payer.as_ref(), config.owner.as_ref()],
-            recent_blockhash,
-        );
+fn command_create_kyc(
+    config: &Config,
+    market: &Pubkey,
+    kyc_accs: &str,
+    kyc_provider: Option<&dyn Signer>,
+) -> CommandResult {
+    let market_data = config.rpc_client.get_account_data(market)?;
+    let market_data = Market::migrate(market_data.as_slice())?;
+    if market_data.owner != config.owner.pubkey() {
+        return Err("Owner is not market owner".into());
+    }
 
-        let signature = config
-            .rpc_client
-            .send_and_confirm_transaction_with_spinner_and_commitment(
-                &transaction,
-                config.commitment_config,
-            )?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as UnixTimestamp;
+    let max_process_per_tx = 8;
+    let mut instructions: Vec<Instruction> = Vec::new();
 
-        println!(
-            "Hash of {:?} transaction: {:?}",
-            instructions_set.0 + 1,
-            signature
+    let mut rdr = csv::Reader::from_path(kyc_accs)?;
+
+    for result in rdr.deserialize().enumerate() {
+        let row = result.0 + 1;
+        let record: KycRecord = match result.1 {
+            Ok(record) => record,
+            Err(err) => {
+                println!("Warning: row {} skipped, {}", row, err);
+                continue;
+            }
+        };
+        let (wallet, expiration) = match record.parse(row, now) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        let (market_user_authority, _) = Pubkey::find_program_address(
+            &[&market.to_bytes()[..32], &wallet.to_bytes()[..32]],
+            &sol_starter_ido::id(),
         );
+        let market_user_kyc = Pubkey::create_with_seed(
+            &market_user_authority,
+            sol_starter_ido::KYC_SEED,
+            &sol_starter_ido::id(),
+        )?;
+        if config.rpc_client.get_account(&market_user_kyc).is_ok() {
+            println!(
+                "Warning: row {} skipped, KYC of {} already exists: {}",
+                row, wallet, market_user_kyc
+            );
+            continue;
+        }
+
+        println!("KYC of {} will be created: {}", wallet, market_user_kyc);
+        instructions.push(create_market_user_kyc(
+            market,
+            &config.owner.pubkey(),
+            &wallet,
+            kyc_provider
+                .map(|kyc_provider| kyc_provider.pubkey())
+                .as_ref(),
+            CreateMarketUserKyc { expiration },
+        )?);
     }
 
+    let all_instructions: Vec<Vec<Instruction>> = instructions
+        .chunks(max_process_per_tx)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+
+    let mut signers = vec![config.fee_payer.as_ref(), config.owner.as_ref()];
+    signers.extend(kyc_provider);
+    send_instruction_sets(config, &all_instructions, &signers)?;
+
     Ok(None)
 }
 
@@ -597,19 +1241,22 @@ fn command_participate(
     pool_lock_token: Option<Pubkey>,
     market_user_kyc: Option<Pubkey>,
     account_whitelist: Option<Pubkey>,
+    whitelist_tickets: u64,
+    min_accepted: u64,
+    receipt: bool,
 ) -> CommandResult {
     let mut instructions: Vec<Instruction> = Vec::new();
 
     let pool_data = config.rpc_client.get_account_data(pool)?;
-    let pool_data = Pool::try_from_slice(pool_data.as_slice())?;
+    let pool_data = Pool::migrate(pool_data.as_slice())?;
 
     let market_data = config.rpc_client.get_account_data(&pool_data.market)?;
-    let market_data = Market::try_from_slice(market_data.as_slice())?;
+    let market_data = Market::migrate(market_data.as_slice())?;
 
     let stake_pool_data = config
         .rpc_client
         .get_account_data(&market_data.stake_pool)?;
-    let stake_pool_data = StakePool::try_from_slice(stake_pool_data.as_slice())?;
+    let stake_pool_data = StakePool::migrate(stake_pool_data.as_slice())?;
 
     let pool_lock_token = pool_lock_token.unwrap_or(create_pool_lock_account(
         config,
@@ -618,19 +1265,7 @@ fn command_participate(
         &stake_pool_data.pool_mint_xsos,
     )?);
 
-    let pool_user_authority = Pubkey::find_program_address(
-        &[
-            &market_data.stake_pool.to_bytes()[..32],
-            &config.owner.pubkey().to_bytes()[..32],
-        ],
-        &sol_starter_ido::id(),
-    )
-    .0;
-    let pool_lock = Pubkey::create_with_seed(
-        &pool_user_authority,
-        sol_starter_staking::LOCK_SEED,
-        &sol_starter_staking::id(),
-    )?;
+    let pool_lock = pool_lock_key(&market_data.stake_pool, &config.owner.pubkey())?;
 
     let mint_whitelist;
 
@@ -670,7 +1305,17 @@ fn command_participate(
         } else {
             None
         },
-        Participate { amount },
+        if market_data.fee_enabled() {
+            Some(&market_data.fee_account)
+        } else {
+            None
+        },
+        Participate {
+            amount,
+            whitelist_tickets,
+            min_accepted,
+            receipt,
+        },
         stage,
     )?);
 
@@ -693,9 +1338,10 @@ fn command_withdraw(
     pool: &Pubkey,
     account_from: &Pubkey,
     account_to: Option<Pubkey>,
+    skip_owner_check: bool,
 ) -> CommandResult {
     let pool_data = config.rpc_client.get_account_data(pool)?;
-    let pool_data = Pool::try_from_slice(pool_data.as_slice())?;
+    let pool_data = Pool::migrate(pool_data.as_slice())?;
 
     let acc_from_data = config.rpc_client.get_account_data(account_from)?;
     let acc_from_data = TokenAccount::unpack(acc_from_data.as_slice())?;
@@ -713,6 +1359,8 @@ fn command_withdraw(
         &config.owner.pubkey(),
         account_from,
         &account_to,
+        None,
+        Withdraw { skip_owner_check },
     )?);
 
     let mut transaction =
@@ -729,17 +1377,333 @@ fn command_withdraw(
     Ok(Some(transaction))
 }
 
-fn command_pool_info(config: &Config, pool: &Pubkey) -> CommandResult {
+fn command_fund_distribution(
+    config: &Config,
+    pool: &Pubkey,
+    amount: f64,
+    account_from: Option<Pubkey>,
+) -> CommandResult {
+    let pool_data = config.rpc_client.get_account_data(pool)?;
+    let pool_data = Pool::migrate(pool_data.as_slice())?;
+
+    let account_distribution = config
+        .rpc_client
+        .get_account_data(&pool_data.account_distribution)?;
+    let account_distribution = TokenAccount::unpack(account_distribution.as_slice())?;
+    let mint = config
+        .rpc_client
+        .get_account_data(&account_distribution.mint)?;
+    let decimals = Mint::unpack(mint.as_slice())?.decimals;
+    let amount = ui_to_tokens(amount, u64::pow(10, decimals.into()));
+
+    let account_from = if amount > 0 {
+        Some(account_from.unwrap_or_else(|| {
+            spl_associated_token_account::get_associated_token_address(
+                &config.owner.pubkey(),
+                &account_distribution.mint,
+            )
+        }))
+    } else {
+        None
+    };
+
+    let mut transaction = Transaction::new_with_payer(
+        &[fund_distribution(
+            &sol_starter_ido::program_id(),
+            pool,
+            &config.owner.pubkey(),
+            &pool_data.account_distribution,
+            account_from.as_ref(),
+            FundDistribution { amount },
+        )?],
+        Some(&config.fee_payer.pubkey()),
+    );
+
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+
+    Ok(Some(transaction))
+}
+
+/// Pools of `market` among ido program `accounts`, accounts of other types are skipped
+fn market_pools(accounts: Vec<(Pubkey, Account)>, market: &Pubkey) -> Vec<(Pubkey, Pool)> {
+    accounts
+        .into_iter()
+        .filter(|(_, account)| account.data.first() == Some(&ACCOUNT_TYPE_POOL))
+        .filter_map(|(key, account)| Pool::migrate(&account.data).ok().map(|pool| (key, pool)))
+        .filter(|(_, pool)| pool.market == *market)
+        .collect()
+}
+
+/// Serialized size of transaction of `instructions` paid by `payer`
+fn transaction_size(payer: &Pubkey, instructions: &[Instruction]) -> usize {
+    let message = Message::new(instructions, Some(payer));
+    // one byte of signatures count, enough for any transaction fitting packet
+    1 + message.header.num_required_signatures as usize * 64 + message.serialize().len()
+}
+
+/// Packs instruction sets into as few transactions fitting packet as possible, set is never split
+fn pack_instruction_sets(payer: &Pubkey, sets: Vec<Vec<Instruction>>) -> Vec<Vec<Instruction>> {
+    let mut packed: Vec<Vec<Instruction>> = Vec::new();
+    for set in sets {
+        if let Some(last) = packed.last_mut() {
+            let mut joined = last.clone();
+            joined.extend(set.iter().cloned());
+            if transaction_size(payer, &joined) <= PACKET_DATA_SIZE {
+                *last = joined;
+                continue;
+            }
+        }
+        packed.push(set);
+    }
+    packed
+}
+
+fn command_claim_all(config: &Config, market: &Pubkey, user: &dyn Signer) -> CommandResult {
+    let clock = config.rpc_client.get_account(&sysvar::clock::id())?;
+    let clock = from_account::<Clock, _>(&clock).ok_or("Failed to decode clock sysvar")?;
+    let accounts = config
+        .rpc_client
+        .get_program_accounts(&sol_starter_ido::id())?;
+
+    let mut claims = Vec::new();
+    for (pool_key, pool) in market_pools(accounts, market) {
+        if !pool.is_finished(&clock) {
+            println!("Pool {} is not finished yet, skipped", pool_key);
+            continue;
+        }
+        if pool.success() && !pool.funded {
+            println!("Pool {} is not funded yet, skipped", pool_key);
+            continue;
+        }
+
+        let account_from = spl_associated_token_account::get_associated_token_address(
+            &user.pubkey(),
+            &pool.mint_pool,
+        );
+        let balance = config
+            .rpc_client
+            .get_account_data(&account_from)
+            .ok()
+            .and_then(|data| TokenAccount::unpack(data.as_slice()).ok())
+            .map_or(0, |account| account.amount);
+        if balance == 0 {
+            println!("Pool {} has nothing to claim, skipped", pool_key);
+            continue;
+        }
+
+        let account_pool = if pool.success() {
+            pool.account_distribution
+        } else {
+            pool.account_collection
+        };
+        let account_pool = config.rpc_client.get_account_data(&account_pool)?;
+        let mint_to = TokenAccount::unpack(account_pool.as_slice())?.mint;
+        println!("Pool {} claims {} pool tokens", pool_key, balance);
+        claims.push(sdk::claim(
+            &sol_starter_ido::program_id(),
+            &pool_key,
+            &pool,
+            &config.fee_payer.pubkey(),
+            &user.pubkey(),
+            &mint_to,
+        )?);
+    }
+
+    if claims.is_empty() {
+        println!("Nothing to claim");
+        return Ok(None);
+    }
+
+    let transactions = pack_instruction_sets(&config.fee_payer.pubkey(), claims);
+    send_instruction_sets(config, &transactions, &[config.fee_payer.as_ref(), user])?;
+
+    Ok(None)
+}
+
+/// Friendly names of known accounts, loaded from JSON object mapping address to name
+#[derive(Debug, Default)]
+struct Labels(HashMap<Pubkey, String>);
+
+impl Labels {
+    fn from_json(json: &str) -> Result<Self, Error> {
+        let names: HashMap<String, String> = serde_json::from_str(json)?;
+        let mut labels = HashMap::with_capacity(names.len());
+        for (key, name) in names {
+            let key = Pubkey::from_str(&key)
+                .map_err(|_| format!("Invalid address in labels: {}", key))?;
+            labels.insert(key, name);
+        }
+        Ok(Labels(labels))
+    }
+
+    fn load(path: &str) -> Result<Self, Error> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// address annotated with its name if labeled, raw address otherwise
+    fn show(&self, key: &Pubkey) -> String {
+        match self.0.get(key) {
+            Some(name) => format!("{} ({})", key, name),
+            None => key.to_string(),
+        }
+    }
+}
+
+/// `pool-info` result printed with `--output json`, token amounts are in token units
+#[derive(Debug, Serialize)]
+struct PoolInfoView {
+    address: String,
+    version: u8,
+    market: String,
+    stake_pool: String,
+    account_collection: String,
+    account_distribution: String,
+    mint_pool: String,
+    mint_whitelist: Option<String>,
+    kyc_requirement: String,
+    price: u64,
+    goal_max_collected: u64,
+    goal_min_collected: u64,
+    amount_investment_min: u64,
+    amount_investment_max: u64,
+    time_start: UnixTimestamp,
+    time_finish: UnixTimestamp,
+    amount_collected: u64,
+    amount_to_distribute: u64,
+    /// `None` if it overflows
+    max_distribution: Option<u64>,
+    owner: String,
+    authority: String,
+    tier_allocation: [u64; TIERS_COUNT],
+    tier_remaining: [u64; TIERS_COUNT],
+    time_table: Vec<UnixTimeSmallDuration>,
+    stages_count: u8,
+    whitelist_ticket_amount: u64,
+    participant_count: u64,
+    funded: bool,
+    per_user_max: u64,
+    refund_deadline: UnixTimestamp,
+    started: bool,
+    /// `None` before start and after finish
+    current_stage: Option<usize>,
+    /// `None` if user is not given or has no tier
+    user_tier: Option<usize>,
+}
+
+impl PoolInfoView {
+    fn new(address: &Pubkey, pool: &Pool, clock: &Clock, user_tier: Option<usize>) -> Self {
+        Self {
+            address: address.to_string(),
+            version: pool.version,
+            market: pool.market.to_string(),
+            stake_pool: pool.stake_pool.to_string(),
+            account_collection: pool.account_collection.to_string(),
+            account_distribution: pool.account_distribution.to_string(),
+            mint_pool: pool.mint_pool.to_string(),
+            mint_whitelist: match &pool.mint_whitelist {
+                MintWhitelist::Key(key) => Some(key.to_string()),
+                MintWhitelist::None(_) => None,
+            },
+            kyc_requirement: format!("{:?}", pool.kyc_requirement),
+            price: pool.price,
+            goal_max_collected: pool.goal_max_collected,
+            goal_min_collected: pool.goal_min_collected,
+            amount_investment_min: pool.amount_investment_min,
+            amount_investment_max: pool.amount_investment_max,
+            time_start: pool.time_start,
+            time_finish: pool.time_finish,
+            amount_collected: pool.amount_collected,
+            amount_to_distribute: pool.amount_to_distribute,
+            max_distribution: pool.max_distribution().ok(),
+            owner: pool.owner.to_string(),
+            authority: pool.authority.to_string(),
+            tier_allocation: pool.tier_allocation,
+            tier_remaining: pool.tier_remaining,
+            time_table: pool
+                .time_table
+                .iter()
+                .take(pool.stages_count as usize)
+                .copied()
+                .collect(),
+            stages_count: pool.stages_count,
+            whitelist_ticket_amount: pool.whitelist_ticket_amount,
+            participant_count: pool.participant_count,
+            funded: pool.funded,
+            per_user_max: pool.per_user_max,
+            refund_deadline: pool.refund_deadline,
+            started: pool.started,
+            current_stage: pool.get_current_stage_index(clock).ok(),
+            user_tier,
+        }
+    }
+}
+
+/// Tier of `user` lock in stake pool of `market`, `None` if user has no lock or tier
+fn user_tier(config: &Config, market: &Pubkey, user: &Pubkey) -> Result<Option<usize>, Error> {
+    let market = config.rpc_client.get_account_data(market)?;
+    let market = Market::migrate(market.as_slice())?;
+    let stake_pool = config.rpc_client.get_account_data(&market.stake_pool)?;
+    let stake_pool = StakePool::migrate(stake_pool.as_slice())?;
+    let pool_lock = pool_lock_key(&market.stake_pool, user)?;
+    Ok(match config.rpc_client.get_account_data(&pool_lock) {
+        Ok(lock_data) => {
+            let lock_data = PoolLock::migrate(lock_data.as_slice())?;
+            let lock_account = config
+                .rpc_client
+                .get_account_data(&lock_data.token_account_xsos)?;
+            let locked = TokenAccount::unpack(lock_account.as_slice())?.amount;
+            get_tier(stake_pool.tier_balance, lock_data.tier_amount(locked))
+        }
+        Err(_) => None,
+    })
+}
+
+fn command_pool_info(
+    config: &Config,
+    pool: &Pubkey,
+    user: Option<Pubkey>,
+    labels: Option<&str>,
+) -> CommandResult {
+    let labels = match labels {
+        Some(path) => Labels::load(path)?,
+        None => Labels::default(),
+    };
     let pool_data = config.rpc_client.get_account_data(pool)?;
-    let pool_data = Pool::try_from_slice(pool_data.as_slice())?;
+    let pool_data = Pool::migrate(pool_data.as_slice())?;
+    let clock = config.rpc_client.get_account(&sysvar::clock::id())?;
+    let clock = from_account::<Clock, _>(&clock).ok_or("Failed to decode clock sysvar")?;
+    let tier = match user {
+        Some(user) => user_tier(config, &pool_data.market, &user)?,
+        None => None,
+    };
+
+    if config.output == OutputFormat::Json {
+        print_json(&PoolInfoView::new(pool, &pool_data, &clock, tier))?;
+        return Ok(None);
+    }
+
+    let mint_whitelist = match &pool_data.mint_whitelist {
+        MintWhitelist::Key(key) => format!("Key({})", labels.show(key)),
+        MintWhitelist::None(_) => "None".to_string(),
+    };
+    let max_distribution = match pool_data.max_distribution() {
+        Ok(amount) => format!("{:?}", tokens_to_ui(amount, Pool::PRECISION)),
+        Err(error) => error.to_string(),
+    };
 
     println!(
         "\nData version: {:?}
-        \nMarket: {:?}
-        \nToken account for tokens used as investment: {:?}
-        \nToken account for tokens to be distributed: {:?}
-        \nMint for the pool tokens (minted on purchase): {:?}
-        \nMint whitelist: {:?}
+        \nMarket: {}
+        \nToken account for tokens used as investment: {}
+        \nToken account for tokens to be distributed: {}
+        \nMint for the pool tokens (minted on purchase): {}
+        \nMint whitelist: {}
         \nKYC requirement: {:?}
         \nPrice: {:?}
         \nMaximum amount to be collected: {:?}
@@ -750,17 +1714,25 @@ fn command_pool_info(config: &Config, pool: &Pubkey) -> CommandResult {
         \nTime when the pool stops accepting investments (and starts token distribution): {:?}
         \nAmount collected: {:?}
         \nAmount to distribute in distribution tokens: {:?}
-        \nPool owner: {:?}
-        \nPool authority: {:?}
+        \nAmount to distribute if maximum goal is reached (to fund in advance): {}
+        \nPool owner: {}
+        \nPool authority: {}
         \nStores amounts available for each user tier: {:?}
         \nTotal allocations for each tier: {:?}
-        \nNon overlapped time for stages: {:?}",
+        \nNon overlapped time for stages: {:?}
+        \nActive stages count: {:?}
+        \nWhitelist tokens minted per whitelisted account: {:?}
+        \nParticipants: {:?}
+        \nDistribution funded: {:?}
+        \nMax total investment per user: {:?}
+        \nOwner may withdraw not refunded tokens of failed pool from: {:?}
+        \nStarted (tier allocations set): {:?}",
         pool_data.version,
-        pool_data.market,
-        pool_data.account_collection,
-        pool_data.account_distribution,
-        pool_data.mint_pool,
-        pool_data.mint_whitelist,
+        labels.show(&pool_data.market),
+        labels.show(&pool_data.account_collection),
+        labels.show(&pool_data.account_distribution),
+        labels.show(&pool_data.mint_pool),
+        mint_whitelist,
         pool_data.kyc_requirement,
         tokens_to_ui(pool_data.price, Pool::PRECISION),
         tokens_to_ui(pool_data.goal_max_collected, Pool::PRECISION),
@@ -771,84 +1743,1017 @@ fn command_pool_info(config: &Config, pool: &Pubkey) -> CommandResult {
         pool_data.time_finish,
         tokens_to_ui(pool_data.amount_collected, Pool::PRECISION),
         tokens_to_ui(pool_data.amount_to_distribute, Pool::PRECISION),
-        pool_data.owner,
-        pool_data.authority,
+        max_distribution,
+        labels.show(&pool_data.owner),
+        labels.show(&pool_data.authority),
         pool_data.tier_allocation,
         pool_data.tier_remaining,
         pool_data.time_table,
+        pool_data.stages_count,
+        pool_data.whitelist_ticket_amount,
+        pool_data.participant_count,
+        pool_data.funded,
+        tokens_to_ui(pool_data.per_user_max, Pool::PRECISION),
+        pool_data.refund_deadline,
+        pool_data.started,
     );
 
+    match pool_data.get_current_stage_index(&clock) {
+        Ok(index) => println!(
+            "\nCurrent stage: {} ({:?}), seconds remaining in stage: {}",
+            index,
+            pool_data.stage_at(index),
+            pool_data.stage_finish(index) - clock.unix_timestamp,
+        ),
+        Err(_) if clock.unix_timestamp < pool_data.time_start => println!(
+            "\nPool is not started yet, seconds until start: {}",
+            pool_data.time_start - clock.unix_timestamp,
+        ),
+        Err(_) => println!("\nPool is finished"),
+    }
+
+    if user.is_some() {
+        match tier {
+            Some(tier) => println!("\nUser tier: {}", tier),
+            None => println!("\nUser has no tier"),
+        }
+    }
+
     Ok(None)
 }
 
-fn main() {
-    let matches = App::new(crate_name!())
-        .about(crate_description!())
-        .version(crate_version!())
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .arg({
-            let arg = Arg::with_name("config_file")
-                .short("C")
-                .long("config")
-                .value_name("PATH")
-                .takes_value(true)
-                .global(true)
-                .help("Configuration file to use");
-            if let Some(ref config_file) = *solana_cli_config::CONFIG_FILE {
-                arg.default_value(&config_file)
-            } else {
-                arg
-            }
-        })
-        .arg(
-            Arg::with_name("verbose")
-                .long("verbose")
-                .short("v")
-                .takes_value(false)
-                .global(true)
-                .help("Show additional information"),
-        )
-        .arg(
-            Arg::with_name("json_rpc_url")
-                .long("url")
-                .value_name("URL")
-                .takes_value(true)
-                .validator(is_url)
-                .help("JSON RPC URL for the cluster.  Default from the configuration file."),
-        )
-        .arg(
-            Arg::with_name("owner")
-                .long("owner")
-                .value_name("KEYPAIR")
-                .validator(is_keypair)
-                .takes_value(true)
-                .help(
-                    "Specify the market/pool's owner. \
-                     This may be a keypair file, the ASK keyword. \
-                     Defaults to the client keypair.",
-                ),
-        )
-        .arg(
-            Arg::with_name("fee_payer")
-                .long("fee-payer")
-                .value_name("KEYPAIR")
-                .validator(is_keypair)
-                .takes_value(true)
-                .help(
-                    "Specify the fee-payer account. \
-                     This may be a keypair file, the ASK keyword. \
-                     Defaults to the client keypair.",
-                ),
+/// Stake pool state with SOS mint and token precision resolved from chain
+struct StakePoolInfo {
+    state: StakePool,
+    mint_sos: Pubkey,
+    precision: u64,
+}
+
+fn get_stake_pool(config: &Config, stake_pool: &Pubkey) -> Result<StakePoolInfo, Error> {
+    let state = config.rpc_client.get_account_data(stake_pool)?;
+    let state = StakePool::migrate(state.as_slice())?;
+    let pool_token_sos = config
+        .rpc_client
+        .get_account_data(&state.token_account_sos)?;
+    let mint_sos = TokenAccount::unpack(pool_token_sos.as_slice())?.mint;
+    let mint = config.rpc_client.get_account_data(&mint_sos)?;
+    let decimals = Mint::unpack(mint.as_slice())?.decimals;
+    Ok(StakePoolInfo {
+        state,
+        mint_sos,
+        precision: u64::pow(10, decimals.into()),
+    })
+}
+
+fn get_pool_transit(
+    config: &Config,
+    pool_transit: &Pubkey,
+    direction: TransitDirection,
+) -> Result<PoolTransit, Error> {
+    let transit_data = config.rpc_client.get_account_data(pool_transit)?;
+    let transit_data = PoolTransit::migrate(transit_data.as_slice())?;
+    if transit_data.direction != direction {
+        return Err(format!("Transit is not {:?}", direction).into());
+    }
+    if transit_data.user_wallet != config.owner.pubkey() {
+        return Err("Transit is not owned by owner wallet".into());
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as UnixTimestamp;
+    if now < transit_data.transit_until {
+        println!(
+            "Transit is not over yet, only part of tokens is released. Whole amount is available after {} (unix time)",
+            transit_data.transit_until
+        );
+    }
+    Ok(transit_data)
+}
+
+/// Adds creation of transit accounts initialized by stake and unstake start, returns rent required
+fn create_transit_accounts(
+    config: &Config,
+    instructions: &mut Vec<Instruction>,
+    pool_transit: &Keypair,
+    pool_transit_token: &Keypair,
+) -> Result<u64, Error> {
+    let transit_account_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(PoolTransit::LEN)?;
+    let token_account_balance = config
+        .rpc_client
+        .get_minimum_balance_for_rent_exemption(TokenAccount::LEN)?;
+
+    println!("Transit account: {}", pool_transit.pubkey());
+    instructions.push(system_instruction::create_account(
+        &config.fee_payer.pubkey(),
+        &pool_transit.pubkey(),
+        transit_account_balance,
+        PoolTransit::LEN as u64,
+        &sol_starter_staking::id(),
+    ));
+    instructions.push(system_instruction::create_account(
+        &config.fee_payer.pubkey(),
+        &pool_transit_token.pubkey(),
+        token_account_balance,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    ));
+
+    Ok(transit_account_balance + token_account_balance)
+}
+
+fn pool_lock_key(stake_pool: &Pubkey, user_wallet: &Pubkey) -> Result<Pubkey, Error> {
+    let pool_user_authority =
+        PoolUserAuthority::derive(stake_pool, user_wallet, &sol_starter_staking::program_id());
+    Ok(Pubkey::create_with_seed(
+        &pool_user_authority.key,
+        LOCK_SEED,
+        &sol_starter_staking::id(),
+    )?)
+}
+
+fn command_stake_start(
+    config: &Config,
+    stake_pool: &Pubkey,
+    amount: f64,
+    user_token_sos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let user_token_sos = user_token_sos.unwrap_or_else(|| {
+        spl_associated_token_account::get_associated_token_address(
+            &config.owner.pubkey(),
+            &pool.mint_sos,
         )
-        .subcommand(
-            SubCommand::with_name("create-market").about("Create a new market")
-            .arg(
+    });
+
+    let mut instructions = vec![];
+    let pool_transit = Keypair::new();
+    let pool_transit_token = Keypair::new();
+    let required_balance = create_transit_accounts(
+        config,
+        &mut instructions,
+        &pool_transit,
+        &pool_transit_token,
+    )?;
+
+    instructions.push(staking_instruction::stake_start(
+        stake_pool,
+        &pool_transit.pubkey(),
+        &pool.state.token_account_sos,
+        &pool_transit_token.pubkey(),
+        &pool.mint_sos,
+        &config.owner.pubkey(),
+        &user_token_sos,
+        StakeStartInput {
+            amount: ui_to_tokens(amount, pool.precision),
+        },
+    )?);
+
+    println!(
+        "SOS tokens are released from transit linearly during {} seconds. Call `stake-finish --stake-pool {} --transit {}` any time to receive xSOS released so far",
+        pool.state.transit_incoming,
+        stake_pool,
+        pool_transit.pubkey()
+    );
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        required_balance + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+    transaction.sign(
+        &[
+            config.fee_payer.as_ref(),
+            config.owner.as_ref(),
+            &pool_transit,
+            &pool_transit_token,
+        ],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_stake_finish(
+    config: &Config,
+    stake_pool: &Pubkey,
+    pool_transit: &Pubkey,
+    user_token_xsos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let transit_data = get_pool_transit(config, pool_transit, TransitDirection::Incoming)?;
+
+    let mut instructions = vec![];
+    let user_token_xsos = user_token_xsos.unwrap_or_else(|| {
+        calculate_and_create_associated_key(config, &pool.state.pool_mint_xsos, &mut instructions)
+    });
+
+    instructions.push(staking_instruction::stake_finish(
+        stake_pool,
+        &pool.state.token_account_sos,
+        pool_transit,
+        &transit_data.token_account_sos,
+        &user_token_xsos,
+        &config.owner.pubkey(),
+        &pool.state.pool_mint_xsos,
+    )?);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_unstake_start(
+    config: &Config,
+    stake_pool: &Pubkey,
+    amount: f64,
+    user_token_xsos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let user_token_xsos = user_token_xsos.unwrap_or_else(|| {
+        spl_associated_token_account::get_associated_token_address(
+            &config.owner.pubkey(),
+            &pool.state.pool_mint_xsos,
+        )
+    });
+
+    let mut instructions = vec![];
+    let pool_transit = Keypair::new();
+    let pool_transit_token = Keypair::new();
+    let required_balance = create_transit_accounts(
+        config,
+        &mut instructions,
+        &pool_transit,
+        &pool_transit_token,
+    )?;
+
+    instructions.push(staking_instruction::unstake_start(
+        stake_pool,
+        &pool.state.token_account_sos,
+        &pool_transit.pubkey(),
+        &pool_transit_token.pubkey(),
+        &pool.mint_sos,
+        &config.owner.pubkey(),
+        &user_token_xsos,
+        &pool.state.pool_mint_xsos,
+        UnstakeStartInput {
+            amount: ui_to_tokens(amount, pool.precision),
+        },
+    )?);
+
+    println!(
+        "SOS tokens are released from transit linearly during {} seconds. Call `unstake-finish --stake-pool {} --transit {}` any time to receive SOS released so far",
+        pool.state.transit_outgoing,
+        stake_pool,
+        pool_transit.pubkey()
+    );
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(
+        config,
+        required_balance + fee_calculator.calculate_fee(&transaction.message()),
+    )?;
+    transaction.sign(
+        &[
+            config.fee_payer.as_ref(),
+            config.owner.as_ref(),
+            &pool_transit,
+            &pool_transit_token,
+        ],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_unstake_finish(
+    config: &Config,
+    stake_pool: &Pubkey,
+    pool_transit: &Pubkey,
+    user_token_sos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let transit_data = get_pool_transit(config, pool_transit, TransitDirection::Outgoing)?;
+
+    let mut instructions = vec![];
+    let user_token_sos = user_token_sos.unwrap_or_else(|| {
+        calculate_and_create_associated_key(config, &pool.mint_sos, &mut instructions)
+    });
+
+    instructions.push(staking_instruction::unstake_finish(
+        stake_pool,
+        pool_transit,
+        &transit_data.token_account_sos,
+        &config.owner.pubkey(),
+        &user_token_sos,
+    )?);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_cancel_transit(
+    config: &Config,
+    stake_pool: &Pubkey,
+    pool_transit: &Pubkey,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let transit_data = config.rpc_client.get_account_data(pool_transit)?;
+    let transit_data = PoolTransit::migrate(transit_data.as_slice())?;
+    if transit_data.user_wallet != config.owner.pubkey() {
+        return Err("Transit is not owned by owner wallet".into());
+    }
+
+    let mut instructions = vec![];
+    let (user_token_sos, user_token_xsos) = match transit_data.direction {
+        TransitDirection::Outgoing => (
+            spl_associated_token_account::get_associated_token_address(
+                &config.owner.pubkey(),
+                &pool.mint_sos,
+            ),
+            calculate_and_create_associated_key(
+                config,
+                &pool.state.pool_mint_xsos,
+                &mut instructions,
+            ),
+        ),
+        _ => (
+            calculate_and_create_associated_key(config, &pool.mint_sos, &mut instructions),
+            spl_associated_token_account::get_associated_token_address(
+                &config.owner.pubkey(),
+                &pool.state.pool_mint_xsos,
+            ),
+        ),
+    };
+
+    instructions.push(staking_instruction::cancel_transit(
+        stake_pool,
+        &pool.state.token_account_sos,
+        pool_transit,
+        &transit_data.token_account_sos,
+        &config.owner.pubkey(),
+        &user_token_sos,
+        &user_token_xsos,
+        &pool.state.pool_mint_xsos,
+    )?);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+/// `transit-info` result printed with `--output json`
+#[derive(Debug, Serialize)]
+struct TransitInfoView {
+    address: String,
+    direction: String,
+    stake_pool: String,
+    user_wallet: String,
+    transit_from: UnixTimestamp,
+    transit_until: UnixTimestamp,
+    amount_claimed: u64,
+    remaining: u64,
+    claimable_now: u64,
+}
+
+fn command_transit_info(config: &Config, pool_transit: &Pubkey) -> CommandResult {
+    let transit_data = config.rpc_client.get_account_data(pool_transit)?;
+    let transit_data = PoolTransit::migrate(transit_data.as_slice())?;
+    let token_account = config
+        .rpc_client
+        .get_account_data(&transit_data.token_account_sos)?;
+    let remaining = TokenAccount::unpack(token_account.as_slice())?.amount;
+
+    let clock = config.rpc_client.get_account(&sysvar::clock::id())?;
+    let clock = from_account::<Clock, _>(&clock).ok_or("Failed to decode clock sysvar")?;
+
+    if config.output == OutputFormat::Json {
+        print_json(&TransitInfoView {
+            address: pool_transit.to_string(),
+            direction: format!("{:?}", transit_data.direction),
+            stake_pool: transit_data.pool.to_string(),
+            user_wallet: transit_data.user_wallet.to_string(),
+            transit_from: transit_data.transit_from,
+            transit_until: transit_data.transit_until,
+            amount_claimed: transit_data.amount_claimed,
+            remaining,
+            claimable_now: transit_data.claimable_now(clock.unix_timestamp, remaining),
+        })?;
+        return Ok(None);
+    }
+
+    println!(
+        "\nDirection: {:?}
+        \nStake pool: {:?}
+        \nUser wallet: {:?}
+        \nTransit starts: {:?}
+        \nTransit ends: {:?}
+        \nClaimed so far (in token units): {:?}
+        \nRemaining in transit (in token units): {:?}
+        \nClaimable now (in token units): {:?}",
+        transit_data.direction,
+        transit_data.pool,
+        transit_data.user_wallet,
+        transit_data.transit_from,
+        transit_data.transit_until,
+        transit_data.amount_claimed,
+        remaining,
+        transit_data.claimable_now(clock.unix_timestamp, remaining),
+    );
+
+    Ok(None)
+}
+
+/// `stake-pool-info` result printed with `--output json`, token amounts are in token units
+#[derive(Debug, Serialize)]
+struct StakePoolInfoView {
+    address: String,
+    version: String,
+    token_account_sos: String,
+    pool_mint_xsos: String,
+    ido_authority: String,
+    transit_incoming: UnixTimestamp,
+    transit_outgoing: UnixTimestamp,
+    pool_active_until: UnixTimestamp,
+    stakes_paused: bool,
+    tier_users: [u32; TIERS_COUNT],
+    tier_balance: [u64; TIERS_COUNT],
+    /// `None` if SOS token account is missing or not initialized
+    total_value_locked: Option<u64>,
+}
+
+fn command_stake_pool_info(config: &Config, stake_pool: &Pubkey) -> CommandResult {
+    let state = config.rpc_client.get_account_data(stake_pool)?;
+    let state = StakePool::migrate(state.as_slice())?;
+    state.initialized()?;
+    let token_account_sos = config
+        .rpc_client
+        .get_account_data(&state.token_account_sos)
+        .ok();
+    let total_value_locked = StakePool::total_value_locked(token_account_sos.as_deref());
+
+    if config.output == OutputFormat::Json {
+        print_json(&StakePoolInfoView {
+            address: stake_pool.to_string(),
+            version: format!("{:?}", state.version),
+            token_account_sos: state.token_account_sos.to_string(),
+            pool_mint_xsos: state.pool_mint_xsos.to_string(),
+            ido_authority: state.ido_authority.to_string(),
+            transit_incoming: state.transit_incoming,
+            transit_outgoing: state.transit_outgoing,
+            pool_active_until: state.pool_active_until,
+            stakes_paused: state.stakes_paused,
+            tier_users: state.tier_users,
+            tier_balance: state.tier_balance,
+            total_value_locked,
+        })?;
+        return Ok(None);
+    }
+
+    println!(
+        "\nData version: {:?}
+        \nToken account for staked SOS tokens: {:?}
+        \nMint for the pool tokens (xSOS): {:?}
+        \nIDO authority: {:?}
+        \nSeconds SOS tokens are in incoming transit: {:?}
+        \nSeconds SOS tokens are in outgoing transit: {:?}
+        \nUnlock prevented until: {:?}
+        \nNew stakes paused: {:?}",
+        state.version,
+        state.token_account_sos,
+        state.pool_mint_xsos,
+        state.ido_authority,
+        state.transit_incoming,
+        state.transit_outgoing,
+        state.pool_active_until,
+        state.stakes_paused,
+    );
+    for (tier, (users, balance)) in state
+        .tier_users
+        .iter()
+        .zip(state.tier_balance.iter())
+        .enumerate()
+    {
+        println!(
+            "\nTier {}: users {}, qualifying balance (in token units) {}",
+            tier, users, balance
+        );
+    }
+    match total_value_locked {
+        Some(amount) => println!("\nTotal value locked (in token units): {}", amount),
+        None => {
+            println!("\nTotal value locked: SOS token account is missing or not initialized")
+        }
+    }
+
+    Ok(None)
+}
+
+fn command_decode_error(program: &str, code: u32) -> CommandResult {
+    let program_id = program_id_by_name(program).ok_or("Unknown program")?;
+    match CliError::from_custom(&program_id, code) {
+        Some(CliError::Program { name, message, .. }) => {
+            println!("\nError: {}\n\nDescription: {}", name, message)
+        }
+        _ => return Err(format!("Unknown {} error code: 0x{:x}", program, code).into()),
+    }
+
+    Ok(None)
+}
+
+fn command_lock(
+    config: &Config,
+    stake_pool: &Pubkey,
+    amount: f64,
+    user_token_xsos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let user_token_xsos = user_token_xsos.unwrap_or_else(|| {
+        spl_associated_token_account::get_associated_token_address(
+            &config.owner.pubkey(),
+            &pool.state.pool_mint_xsos,
+        )
+    });
+
+    let mut instructions = vec![];
+    let pool_lock = pool_lock_key(stake_pool, &config.owner.pubkey())?;
+    let pool_lock_token = match get_account_data_if_exists(config, &pool_lock)? {
+        Some(lock_data) => PoolLock::migrate(lock_data.as_slice())?.token_account_xsos,
+        None => create_pool_lock_account(
+            config,
+            &mut instructions,
+            stake_pool,
+            &pool.state.pool_mint_xsos,
+        )?,
+    };
+
+    instructions.push(staking_instruction::lock(
+        stake_pool,
+        &config.owner.pubkey(),
+        &pool_lock_token,
+        &user_token_xsos,
+        LockInput {
+            amount: ui_to_tokens(amount, pool.precision),
+        },
+    )?);
+
+    if pool.state.min_lock_seconds > 0 && amount > 0.0 {
+        println!(
+            "Lock counts for a tier after {} seconds, call `refresh-lock --stake-pool {}` then",
+            pool.state.min_lock_seconds, stake_pool
+        );
+    }
+    if pool.state.twab_window_seconds > 0 && amount > 0.0 {
+        println!(
+            "Lock counts for a tier by average locked over {} seconds, call `refresh-lock --stake-pool {}` later to update it",
+            pool.state.twab_window_seconds, stake_pool
+        );
+    }
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_compound(
+    config: &Config,
+    stake_pool: &Pubkey,
+    user_token_xsos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let user_token_xsos = user_token_xsos.unwrap_or_else(|| {
+        spl_associated_token_account::get_associated_token_address(
+            &config.owner.pubkey(),
+            &pool.state.pool_mint_xsos,
+        )
+    });
+    let pool_lock = pool_lock_key(stake_pool, &config.owner.pubkey())?;
+    let lock_data = config.rpc_client.get_account_data(&pool_lock)?;
+    let lock_data = PoolLock::migrate(lock_data.as_slice())?;
+
+    let instructions = [staking_instruction::compound(
+        stake_pool,
+        &config.owner.pubkey(),
+        &lock_data.token_account_xsos,
+        &user_token_xsos,
+    )?];
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_refresh_lock(config: &Config, stake_pool: &Pubkey) -> CommandResult {
+    let pool_lock = pool_lock_key(stake_pool, &config.owner.pubkey())?;
+    let lock_data = config.rpc_client.get_account_data(&pool_lock)?;
+    let lock_data = PoolLock::migrate(lock_data.as_slice())?;
+
+    let instructions = [staking_instruction::refresh_lock(
+        stake_pool,
+        &config.owner.pubkey(),
+        &lock_data.token_account_xsos,
+    )?];
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn command_unlock(
+    config: &Config,
+    stake_pool: &Pubkey,
+    amount: f64,
+    user_token_xsos: Option<Pubkey>,
+) -> CommandResult {
+    let pool = get_stake_pool(config, stake_pool)?;
+    let pool_lock = pool_lock_key(stake_pool, &config.owner.pubkey())?;
+    let lock_data = config.rpc_client.get_account_data(&pool_lock)?;
+    let lock_data = PoolLock::migrate(lock_data.as_slice())?;
+
+    let mut instructions = vec![];
+    let user_token_xsos = user_token_xsos.unwrap_or_else(|| {
+        calculate_and_create_associated_key(config, &pool.state.pool_mint_xsos, &mut instructions)
+    });
+
+    instructions.push(staking_instruction::unlock(
+        stake_pool,
+        &config.owner.pubkey(),
+        &lock_data.token_account_xsos,
+        &user_token_xsos,
+        UnlockInput {
+            amount: ui_to_tokens(amount, pool.precision),
+        },
+    )?);
+
+    let mut transaction =
+        Transaction::new_with_payer(&instructions, Some(&config.fee_payer.pubkey()));
+    let (recent_blockhash, fee_calculator) = config.rpc_client.get_recent_blockhash()?;
+    check_fee_payer_balance(config, fee_calculator.calculate_fee(&transaction.message()))?;
+    transaction.sign(
+        &[config.fee_payer.as_ref(), config.owner.as_ref()],
+        recent_blockhash,
+    );
+    Ok(Some(transaction))
+}
+
+fn stake_pool_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("stake-pool")
+        .long("stake-pool")
+        .validator(is_pubkey)
+        .value_name("ADDRESS")
+        .takes_value(true)
+        .required(true)
+        .help("Initialized stake pool account.")
+}
+
+fn stake_amount_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("amount")
+        .long("amount")
+        .validator(is_parsable::<f64>)
+        .value_name("AMOUNT")
+        .takes_value(true)
+        .required(true)
+        .help("Amount of tokens.")
+}
+
+fn transit_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("transit")
+        .long("transit")
+        .validator(is_pubkey)
+        .value_name("ADDRESS")
+        .takes_value(true)
+        .required(true)
+        .help("Transit account printed by start command.")
+}
+
+fn token_account_arg<'a, 'b>(name: &'a str, help: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name(name)
+        .long(name)
+        .validator(is_pubkey)
+        .value_name("ADDRESS")
+        .takes_value(true)
+        .help(help)
+}
+
+/// Arguments of `create-pool` shared by its variants
+fn create_pool_subcommand<'a, 'b>(name: &'a str, about: &'a str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("market")
+                .long("market")
+                .validator(is_pubkey)
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .required(true)
+                .help("Initialized IDO market account."),
+        )
+        .arg(
+            Arg::with_name("mint_collection")
+                .long("mint-collection")
+                .validator(is_pubkey)
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .required(true)
+                .help("Mint of the tokens which pool will collect."),
+        )
+        .arg(
+            Arg::with_name("mint_distribution")
+                .long("mint-distribution")
+                .validator(is_pubkey)
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .required(true)
+                .help("Mint of the tokens which pool will distribute."),
+        )
+        .arg(
+            Arg::with_name("pool_owner")
+                .long("pool-owner")
+                .validator(is_pubkey)
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .required(true)
+                .help("Owner of the pool, able to issue whitelist tokens and withdraw funds."),
+        )
+        .arg(
+            Arg::with_name("is_whitelist")
+                .long("is-whitelist")
+                .validator(is_parsable::<bool>)
+                .value_name("BOOLEAN")
+                .takes_value(true)
+                .required(true)
+                .help("Should be created mint_whitelist or not."),
+        )
+        .arg(
+            Arg::with_name("is_kyc")
+                .long("is-kyc")
+                .validator(is_parsable::<bool>)
+                .value_name("BOOLEAN")
+                .takes_value(true)
+                .required(true)
+                .help("Should IDO be KYC-only."),
+        )
+        .arg(
+            Arg::with_name("kyc_provider")
+                .long("kyc-provider")
+                .validator(is_pubkey)
+                .value_name("ADDRESS")
+                .takes_value(true)
+                .requires("is_kyc")
+                .help("Accept only KYC approved by this provider, any KYC if not set."),
+        )
+        .arg(
+            Arg::with_name("price")
+                .long("price")
+                .validator(is_parsable::<f64>)
+                .value_name("VALUE")
+                .takes_value(true)
+                .required(true)
+                .help("Distributed tokens price."),
+        )
+        .arg(
+            Arg::with_name("goal_max")
+                .long("goal-max")
+                .validator(is_parsable::<f64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .required(true)
+                .help("IDO maximum goal in collection tokens."),
+        )
+        .arg(
+            Arg::with_name("goal_min")
+                .long("goal-min")
+                .validator(is_parsable::<f64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .required(true)
+                .help("IDO minimum goal in collection tokens."),
+        )
+        .arg(
+            Arg::with_name("amount_min")
+                .long("amount-min")
+                .validator(is_parsable::<f64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .required(true)
+                .help("Min investment size in collection tokens."),
+        )
+        .arg(
+            Arg::with_name("amount_max")
+                .long("amount-max")
+                .validator(is_parsable::<f64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .required(true)
+                .help("Max investment size in collection tokens."),
+        )
+        .arg(
+            Arg::with_name("time_start")
+                .long("time-start")
+                .validator(is_parsable::<UnixTimestamp>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .required(true)
+                .help("Time when the pool starts accepting investments, unix timestamp."),
+        )
+        .arg(
+            Arg::with_name("time_finish")
+                .long("time-finish")
+                .validator(is_parsable::<UnixTimestamp>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .required(true)
+                .help("Time when the pool stops accepting investments (and starts token distribution), unix timestamp."),
+        )
+        .arg(
+            Arg::with_name("stage_1")
+                .long("stage-1")
+                .validator(is_parsable::<u32>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .required(true)
+                .help("Length of the first IDO stage (individual user allocations), in seconds."),
+        )
+        .arg(
+            Arg::with_name("stage_2")
+                .long("stage-2")
+                .validator(is_parsable::<u32>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .required(true)
+                .help("Length of the second IDO stage (tier allocations), in seconds."),
+        )
+        .arg(
+            Arg::with_name("stage_3")
+                .long("stage-3")
+                .validator(is_parsable::<u32>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .default_value("0")
+                .help("Length of the optional third IDO stage (tier allocations), in seconds."),
+        )
+        .arg(
+            Arg::with_name("stage_4")
+                .long("stage-4")
+                .validator(is_parsable::<u32>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .default_value("0")
+                .help("Length of the optional fourth IDO stage (tier allocations), in seconds."),
+        )
+        .arg(
+            Arg::with_name("whitelist_ticket_amount")
+                .long("whitelist-ticket-amount")
+                .validator(is_parsable::<u64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .default_value("1")
+                .help("Amount of whitelist tokens minted to each whitelisted account."),
+        )
+        .arg(
+            Arg::with_name("per_user_max")
+                .long("per-user-max")
+                .validator(is_parsable::<f64>)
+                .value_name("AMOUNT")
+                .takes_value(true)
+                .default_value("0")
+                .help("Max total investment of one user in collection tokens, 0 is unlimited."),
+        )
+        .arg(
+            Arg::with_name("refund_grace_period")
+                .long("refund-grace-period")
+                .validator(is_parsable::<u32>)
+                .value_name("SECONDS")
+                .takes_value(true)
+                .default_value("0")
+                .help("Seconds after finish users of failed pool may refund before owner withdraws the rest, 0 is 30 days."),
+        )
+}
+
+fn main() {
+    let matches = App::new(crate_name!())
+        .about(crate_description!())
+        .version(crate_version!())
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg({
+            let arg = Arg::with_name("config_file")
+                .short("C")
+                .long("config")
+                .value_name("PATH")
+                .takes_value(true)
+                .global(true)
+                .help("Configuration file to use");
+            if let Some(ref config_file) = *solana_cli_config::CONFIG_FILE {
+                arg.default_value(&config_file)
+            } else {
+                arg
+            }
+        })
+        .arg(
+            Arg::with_name("verbose")
+                .long("verbose")
+                .short("v")
+                .takes_value(false)
+                .global(true)
+                .help("Show additional information"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .takes_value(false)
+                .global(true)
+                .help("Simulate transactions instead of sending them"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .value_name("FORMAT")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Print results of info commands as text or JSON"),
+        )
+        .arg(
+            Arg::with_name("json_rpc_url")
+                .long("url")
+                .value_name("URL")
+                .takes_value(true)
+                .validator(is_url)
+                .help("JSON RPC URL for the cluster.  Default from the configuration file."),
+        )
+        .arg(
+            Arg::with_name("owner")
+                .long("owner")
+                .value_name("KEYPAIR")
+                .validator(is_keypair)
+                .takes_value(true)
+                .help(
+                    "Specify the market/pool's owner. \
+                     This may be a keypair file, the ASK keyword. \
+                     Defaults to the client keypair.",
+                ),
+        )
+        .arg(
+            Arg::with_name("fee_payer")
+                .long("fee-payer")
+                .value_name("KEYPAIR")
+                .validator(is_keypair)
+                .takes_value(true)
+                .help(
+                    "Specify the fee-payer account. \
+                     This may be a keypair file, the ASK keyword. \
+                     Defaults to the client keypair.",
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("create-market").about("Create a new market")
+            .arg(
                 Arg::with_name("stake_token")
                     .long("stake-token")
                     .validator(is_pubkey)
                     .value_name("ADDRESS")
                     .takes_value(true)
-                    .required(true)
+                    .required_unless("existing_stake_pool")
                     .help("Token mint account to be used for staking."),
             )
             .arg(
@@ -869,13 +2774,31 @@ fn main() {
                     .default_value("0")
                     .help("Token lock interval when unstaking."),
             )
+            .arg(
+                Arg::with_name("min_lock")
+                    .long("min-lock")
+                    .validator(is_parsable::<UnixTimestamp>)
+                    .value_name("SECONDS")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Seconds tokens must stay locked before lock counts for a tier."),
+            )
+            .arg(
+                Arg::with_name("twab_window")
+                    .long("twab-window")
+                    .validator(is_parsable::<UnixTimestamp>)
+                    .value_name("SECONDS")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Seconds of time weighted average of locked tokens counted for a tier. Zero counts locked tokens."),
+            )
             .arg(
                 Arg::with_name("tier_1")
                     .long("tier-1")
                     .validator(is_parsable::<f64>)
                     .value_name("AMOUNT")
                     .takes_value(true)
-                    .required(true)
+                    .required_unless("existing_stake_pool")
                     .help("Staking balance qualifying for the tier 1 (lowest)."),
             )
             .arg(
@@ -884,7 +2807,7 @@ fn main() {
                     .validator(is_parsable::<f64>)
                     .value_name("AMOUNT")
                     .takes_value(true)
-                    .required(true)
+                    .required_unless("existing_stake_pool")
                     .help("Staking balance qualifying for the tier 2."),
             )
             .arg(
@@ -893,157 +2816,43 @@ fn main() {
                     .validator(is_parsable::<f64>)
                     .value_name("AMOUNT")
                     .takes_value(true)
-                    .required(true)
-                    .help("Staking balance qualifying for the tier 3."),
-            )
-            .arg(
-                Arg::with_name("tier_4")
-                    .long("tier-4")
-                    .validator(is_parsable::<f64>)
-                    .value_name("AMOUNT")
-                    .takes_value(true)
-                    .required(true)
-                    .help("Staking balance qualifying for the tier 4 (highest)."),
-            )
-        )
-        .subcommand(
-            SubCommand::with_name("create-pool")
-                .about("Create a new pool")
-                .arg(
-                    Arg::with_name("market")
-                        .long("market")
-                        .validator(is_pubkey)
-                        .value_name("ADDRESS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Initialized IDO market account."),
-                )
-                .arg(
-                    Arg::with_name("mint_collection")
-                        .long("mint-collection")
-                        .validator(is_pubkey)
-                        .value_name("ADDRESS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Mint of the tokens which pool will collect."),
-                )
-                .arg(
-                    Arg::with_name("mint_distribution")
-                        .long("mint-distribution")
-                        .validator(is_pubkey)
-                        .value_name("ADDRESS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Mint of the tokens which pool will distribute."),
-                )
-                .arg(
-                    Arg::with_name("pool_owner")
-                        .long("pool-owner")
-                        .validator(is_pubkey)
-                        .value_name("ADDRESS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Owner of the pool, able to issue whitelist tokens and withdraw funds."),
-                )
-                .arg(
-                    Arg::with_name("is_whitelist")
-                        .long("is-whitelist")
-                        .validator(is_parsable::<bool>)
-                        .value_name("BOOLEAN")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Should be created mint_whitelist or not."),
-                )
-                .arg(
-                    Arg::with_name("is_kyc")
-                        .long("is-kyc")
-                        .validator(is_parsable::<bool>)
-                        .value_name("BOOLEAN")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Should IDO be KYC-only."),
-                )
-                .arg(
-                    Arg::with_name("price")
-                        .long("price")
-                        .validator(is_parsable::<f64>)
-                        .value_name("VALUE")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Distributed tokens price."),
-                )
-                .arg(
-                    Arg::with_name("goal_max")
-                        .long("goal-max")
-                        .validator(is_parsable::<f64>)
-                        .value_name("AMOUNT")
-                        .takes_value(true)
-                        .required(true)
-                        .help("IDO maximum goal in collection tokens."),
-                )
-                .arg(
-                    Arg::with_name("goal_min")
-                        .long("goal-min")
-                        .validator(is_parsable::<f64>)
-                        .value_name("AMOUNT")
-                        .takes_value(true)
-                        .required(true)
-                        .help("IDO minimum goal in collection tokens."),
-                )
-                .arg(
-                    Arg::with_name("amount_min")
-                        .long("amount-min")
-                        .validator(is_parsable::<f64>)
-                        .value_name("AMOUNT")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Min investment size in collection tokens."),
-                )
-                .arg(
-                    Arg::with_name("amount_max")
-                        .long("amount-max")
-                        .validator(is_parsable::<f64>)
-                        .value_name("AMOUNT")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Max investment size in collection tokens."),
-                )
-                .arg(
-                    Arg::with_name("time_start")
-                        .long("time-start")
-                        .validator(is_parsable::<UnixTimestamp>)
-                        .value_name("SECONDS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Time when the pool starts accepting investments, unix timestamp."),
-                )
-                .arg(
-                    Arg::with_name("time_finish")
-                        .long("time-finish")
-                        .validator(is_parsable::<UnixTimestamp>)
-                        .value_name("SECONDS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Time when the pool stops accepting investments (and starts token distribution), unix timestamp."),
-                )
-                .arg(
-                    Arg::with_name("stage_1")
-                        .long("stage-1")
-                        .validator(is_parsable::<u32>)
-                        .value_name("SECONDS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Length of the first IDO stage (individual user allocations), in seconds."),
-                )
-                .arg(
-                    Arg::with_name("stage_2")
-                        .long("stage-2")
-                        .validator(is_parsable::<u32>)
-                        .value_name("SECONDS")
-                        .takes_value(true)
-                        .required(true)
-                        .help("Length of the second IDO stage (tier allocations), in seconds."),
-                )
+                    .required_unless("existing_stake_pool")
+                    .help("Staking balance qualifying for the tier 3."),
+            )
+            .arg(
+                Arg::with_name("tier_4")
+                    .long("tier-4")
+                    .validator(is_parsable::<f64>)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .required_unless("existing_stake_pool")
+                    .help("Staking balance qualifying for the tier 4 (highest)."),
+            )
+            .arg(
+                Arg::with_name("existing_stake_pool")
+                    .long("existing-stake-pool")
+                    .validator(is_pubkey)
+                    .value_name("ADDRESS")
+                    .takes_value(true)
+                    .conflicts_with_all(&["stake_token", "tier_1", "tier_2", "tier_3", "tier_4"])
+                    .help("Initialized stake pool to share with the new market instead of creating one, lock and tier options are not used."),
+            )
+        )
+        .subcommand(create_pool_subcommand("create-pool", "Create a new pool"))
+        .subcommand(
+            create_pool_subcommand(
+                "create-pool-pda",
+                "Create a new pool at addresses derived from market and nonce",
+            )
+            .arg(
+                Arg::with_name("nonce")
+                    .long("nonce")
+                    .validator(is_parsable::<u64>)
+                    .value_name("NUMBER")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Number of pool in market, pool addresses are derived from it and market owner."),
+            ),
         )
         .subcommand(
             SubCommand::with_name("start-pool")
@@ -1066,6 +2875,18 @@ fn main() {
                     .required(true)
                     .help("Pool to start."),
                 )
+                .arg(
+                    Arg::with_name("preview")
+                        .long("preview")
+                        .takes_value(false)
+                        .help("Print tier allocations the pool would get, without starting it."),
+                )
+                .arg(
+                    Arg::with_name("require_funded")
+                        .long("require-funded")
+                        .takes_value(false)
+                        .help("Fail unless pool distribution account already holds maximal distribution amount."),
+                )
         )
         .subcommand(
             SubCommand::with_name("add-to-whitelist")
@@ -1088,6 +2909,44 @@ fn main() {
                     .required(true)
                     .help("CSV file with whitelist token accounts mint tokens to."),
                 )
+                .arg(
+                    Arg::with_name("resume-file")
+                        .long("resume-file")
+                        .validator(is_csv_file)
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help("CSV file to write failed rows to, in whitelist file format to rerun them."),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("create-kyc")
+                .about("Create KYC of users in market")
+                .arg(
+                    Arg::with_name("market")
+                        .long("market")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Initialized IDO market account."),
+                )
+                .arg(
+                    Arg::with_name("kyc-accounts")
+                    .long("csv")
+                    .validator(is_csv_file)
+                    .value_name("PATH")
+                    .takes_value(true)
+                    .required(true)
+                    .help("CSV file with user wallets and KYC expiration unix timestamps."),
+                )
+                .arg(
+                    Arg::with_name("provider")
+                        .long("provider")
+                        .validator(is_keypair)
+                        .value_name("KEYPAIR")
+                        .takes_value(true)
+                        .help("Provider which approved KYC and signs it, checked by pools requiring it."),
+                )
         )
         .subcommand(
             SubCommand::with_name("participate")
@@ -1161,6 +3020,30 @@ fn main() {
                     .takes_value(true)
                     .help("User whitelist token account."),
                 )
+                .arg(
+                    Arg::with_name("whitelist-tickets")
+                    .long("whitelist-tickets")
+                    .validator(is_parsable::<u64>)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .default_value("1")
+                    .help("Amount of whitelist tokens to burn on participation."),
+                )
+                .arg(
+                    Arg::with_name("min-accepted")
+                    .long("min-accepted")
+                    .validator(is_parsable::<f64>)
+                    .value_name("AMOUNT")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Fail if tier caps and market fee reduce accepted amount below this value."),
+                )
+                .arg(
+                    Arg::with_name("receipt")
+                    .long("receipt")
+                    .takes_value(false)
+                    .help("Record participation into user participation receipt account"),
+                )
         )
         .subcommand(
             SubCommand::with_name("withdraw")
@@ -1191,6 +3074,63 @@ fn main() {
                     .takes_value(true)
                     .help("Pool owner's token account to receive tokens from the previous account (either collected or distributed token)"),
                 )
+                .arg(
+                    Arg::with_name("skip-owner-check")
+                    .long("skip-owner-check")
+                    .takes_value(false)
+                    .help("Allow account to of other owner than pool owner, e.g. treasury account"),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("claim-all")
+                .about("Claim pool tokens of user from all finished pools of market.")
+                .arg(
+                    Arg::with_name("market")
+                        .long("market")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Market of pools to claim from."),
+                )
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .validator(is_keypair)
+                        .value_name("KEYPAIR")
+                        .takes_value(true)
+                        .help("User wallet owning pool tokens, defaults to owner."),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("fund-distribution")
+                .about("Top up distribution tokens after pool is over and mark pool funded, so users can claim.")
+                .arg(
+                    Arg::with_name("pool")
+                        .long("pool")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Initialized IDO pool account."),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .long("amount")
+                        .validator(is_parsable::<f64>)
+                        .value_name("NUMBER")
+                        .takes_value(true)
+                        .default_value("0")
+                        .help("Amount of distribution tokens to transfer to the pool, zero only checks pool is funded."),
+                )
+                .arg(
+                    Arg::with_name("account-from")
+                        .long("account-from")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .help("Owner token account to transfer distribution tokens from, defaults to associated token account."),
+                )
         )
         .subcommand(
             SubCommand::with_name("pool-info")
@@ -1203,6 +3143,158 @@ fn main() {
                         .required(true)
                         .help("Initialized IDO pool account."),
                 )
+                .arg(
+                    Arg::with_name("user")
+                        .long("user")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .help("User wallet to resolve staking tier for."),
+                )
+                .arg(
+                    Arg::with_name("labels")
+                        .long("labels")
+                        .value_name("PATH")
+                        .takes_value(true)
+                        .help("JSON file mapping addresses to names shown next to known accounts."),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("stake-pool-info")
+                .about("Get stake pool information including total value locked.")
+                .arg(
+                    Arg::with_name("stake-pool")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Initialized stake pool account."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decode-error")
+                .about("Decode custom error code returned by program.")
+                .arg(
+                    Arg::with_name("program")
+                        .long("program")
+                        .value_name("PROGRAM")
+                        .takes_value(true)
+                        .possible_values(&["ido", "staking"])
+                        .required(true)
+                        .help("Program returned the error."),
+                )
+                .arg(
+                    Arg::with_name("code")
+                        .long("code")
+                        .validator(is_error_code)
+                        .value_name("CODE")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Custom error code, decimal or hexadecimal with 0x prefix."),
+                )
+                .after_help("EXAMPLE:\n    sol-starter decode-error --program ido --code 0x1a"),
+        )
+        .subcommand(
+            SubCommand::with_name("stake-start")
+                .about("Move SOS tokens into transit to the stake pool.")
+                .arg(stake_pool_arg())
+                .arg(stake_amount_arg())
+                .arg(token_account_arg(
+                    "user-token-sos",
+                    "SOS token account to stake from. Default is associated token account of owner.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter stake-start --stake-pool <ADDRESS> --amount 100\n\nTokens are released linearly during transit period of the pool, use printed transit account with `stake-finish`."),
+        )
+        .subcommand(
+            SubCommand::with_name("stake-finish")
+                .about("Receive xSOS tokens for SOS released from stake transit.")
+                .arg(stake_pool_arg())
+                .arg(transit_arg())
+                .arg(token_account_arg(
+                    "user-token-xsos",
+                    "xSOS token account to receive tokens. Default is associated token account of owner, created if missing.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter stake-finish --stake-pool <ADDRESS> --transit <ADDRESS>\n\nCan be called multiple times, each call receives tokens released since previous one."),
+        )
+        .subcommand(
+            SubCommand::with_name("unstake-start")
+                .about("Burn xSOS tokens and move SOS tokens from the stake pool into transit.")
+                .arg(stake_pool_arg())
+                .arg(stake_amount_arg())
+                .arg(token_account_arg(
+                    "user-token-xsos",
+                    "xSOS token account to unstake from. Default is associated token account of owner.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter unstake-start --stake-pool <ADDRESS> --amount 100\n\nTokens are released linearly during transit period of the pool, use printed transit account with `unstake-finish`."),
+        )
+        .subcommand(
+            SubCommand::with_name("unstake-finish")
+                .about("Receive SOS tokens released from unstake transit.")
+                .arg(stake_pool_arg())
+                .arg(transit_arg())
+                .arg(token_account_arg(
+                    "user-token-sos",
+                    "SOS token account to receive tokens. Default is associated token account of owner, created if missing.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter unstake-finish --stake-pool <ADDRESS> --transit <ADDRESS>\n\nCan be called multiple times, each call receives tokens released since previous one."),
+        )
+        .subcommand(
+            SubCommand::with_name("cancel-transit")
+                .about("Return tokens not yet released from stake or unstake transit and close it.")
+                .arg(stake_pool_arg())
+                .arg(transit_arg())
+                .after_help("EXAMPLE:\n    sol-starter cancel-transit --stake-pool <ADDRESS> --transit <ADDRESS>\n\nStake transit returns SOS, unstake transit returns xSOS to associated token account of owner."),
+        )
+        .subcommand(
+            SubCommand::with_name("transit-info")
+                .about("Get stake or unstake transit information.")
+                .arg(
+                    Arg::with_name("transit")
+                        .validator(is_pubkey)
+                        .value_name("ADDRESS")
+                        .takes_value(true)
+                        .required(true)
+                        .help("Transit account printed by start command."),
+                )
+                .after_help("EXAMPLE:\n    sol-starter transit-info <ADDRESS>\n\nClaimable amount is received by `stake-finish` or `unstake-finish`."),
+        )
+        .subcommand(
+            SubCommand::with_name("lock")
+                .about("Lock xSOS tokens to qualify for stake pool tier.")
+                .arg(stake_pool_arg())
+                .arg(stake_amount_arg())
+                .arg(token_account_arg(
+                    "user-token-xsos",
+                    "xSOS token account to lock from. Default is associated token account of owner.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter lock --stake-pool <ADDRESS> --amount 100\n\nLock account is created on first lock."),
+        )
+        .subcommand(
+            SubCommand::with_name("compound")
+                .about("Lock whole xSOS balance into existing lock.")
+                .arg(stake_pool_arg())
+                .arg(token_account_arg(
+                    "user-token-xsos",
+                    "xSOS token account to lock from. Default is associated token account of owner.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter compound --stake-pool <ADDRESS>\n\nLock account must be created by `lock` before."),
+        )
+        .subcommand(
+            SubCommand::with_name("refresh-lock")
+                .about("Recount lock in stake pool tiers without moving tokens.")
+                .arg(stake_pool_arg())
+                .after_help("EXAMPLE:\n    sol-starter refresh-lock --stake-pool <ADDRESS>\n\nCounts lock aged past min lock and updates its average locked amount."),
+        )
+        .subcommand(
+            SubCommand::with_name("unlock")
+                .about("Unlock xSOS tokens.")
+                .arg(stake_pool_arg())
+                .arg(stake_amount_arg())
+                .arg(token_account_arg(
+                    "user-token-xsos",
+                    "xSOS token account to receive tokens. Default is associated token account of owner, created if missing.",
+                ))
+                .after_help("EXAMPLE:\n    sol-starter unlock --stake-pool <ADDRESS> --amount 100\n\nWhile IDO pool is active only amount keeping the tier can be unlocked."),
         )
         .get_matches();
 
@@ -1244,6 +3336,11 @@ fn main() {
             owner,
             fee_payer,
             commitment_config: CommitmentConfig::confirmed(),
+            dry_run: matches.is_present("dry_run"),
+            output: match matches.value_of("output") {
+                Some("json") => OutputFormat::Json,
+                _ => OutputFormat::Text,
+            },
         }
     };
 
@@ -1251,50 +3348,71 @@ fn main() {
 
     let _ = match matches.subcommand() {
         ("create-market", Some(arg_matches)) => {
-            let stake_token: Pubkey = pubkey_of(arg_matches, "stake_token").unwrap();
-            let transit_incoming = value_t_or_exit!(arg_matches, "lock_in", UnixTimestamp);
-            let transit_outgoing = value_t_or_exit!(arg_matches, "lock_out", UnixTimestamp);
-
-            let stake_token_account = config.rpc_client.get_account(&stake_token).unwrap();
-            let stake_token_account = Mint::unpack(&stake_token_account.data).unwrap();
-            let token_precision = <u64>::pow(10, stake_token_account.decimals.into());
-
-            let tier_1 = value_t_or_exit!(arg_matches, "tier_1", f64);
-            let tier_1 = ui_to_tokens(tier_1, token_precision);
-            let tier_2 = value_t_or_exit!(arg_matches, "tier_2", f64);
-            let tier_2 = ui_to_tokens(tier_2, token_precision);
-            let tier_3 = value_t_or_exit!(arg_matches, "tier_3", f64);
-            let tier_3 = ui_to_tokens(tier_3, token_precision);
-            let tier_4 = value_t_or_exit!(arg_matches, "tier_4", f64);
-            let tier_4 = ui_to_tokens(tier_4, token_precision);
-            let tier_balance = [tier_1, tier_2, tier_3, tier_4];
-            command_create_market(
-                &config,
-                stake_token,
-                transit_incoming,
-                transit_outgoing,
-                tier_balance,
-            )
+            let stake_pool = match pubkey_of(arg_matches, "existing_stake_pool") {
+                Some(stake_pool) => MarketStakePool::Existing(stake_pool),
+                None => {
+                    let stake_token: Pubkey = pubkey_of(arg_matches, "stake_token").unwrap();
+                    let transit_incoming = value_t_or_exit!(arg_matches, "lock_in", UnixTimestamp);
+                    let transit_outgoing = value_t_or_exit!(arg_matches, "lock_out", UnixTimestamp);
+                    let min_lock_seconds = value_t_or_exit!(arg_matches, "min_lock", UnixTimestamp);
+                    let twab_window_seconds =
+                        value_t_or_exit!(arg_matches, "twab_window", UnixTimestamp);
+
+                    let stake_token_account = config.rpc_client.get_account(&stake_token).unwrap();
+                    let stake_token_account = Mint::unpack(&stake_token_account.data).unwrap();
+                    let token_precision = <u64>::pow(10, stake_token_account.decimals.into());
+
+                    let tier_1 = value_t_or_exit!(arg_matches, "tier_1", f64);
+                    let tier_1 = ui_to_tokens(tier_1, token_precision);
+                    let tier_2 = value_t_or_exit!(arg_matches, "tier_2", f64);
+                    let tier_2 = ui_to_tokens(tier_2, token_precision);
+                    let tier_3 = value_t_or_exit!(arg_matches, "tier_3", f64);
+                    let tier_3 = ui_to_tokens(tier_3, token_precision);
+                    let tier_4 = value_t_or_exit!(arg_matches, "tier_4", f64);
+                    let tier_4 = ui_to_tokens(tier_4, token_precision);
+                    MarketStakePool::New {
+                        stake_token,
+                        transit_incoming,
+                        transit_outgoing,
+                        min_lock_seconds,
+                        twab_window_seconds,
+                        tier_balance: [tier_1, tier_2, tier_3, tier_4],
+                    }
+                }
+            };
+            command_create_market(&config, stake_pool)
         }
-        ("create-pool", Some(arg_matches)) => {
+        ("create-pool", Some(arg_matches)) | ("create-pool-pda", Some(arg_matches)) => {
             let market: Pubkey = pubkey_of(arg_matches, "market").unwrap();
             let mint_collection: Pubkey = pubkey_of(arg_matches, "mint_collection").unwrap();
             let mint_distribution: Pubkey = pubkey_of(arg_matches, "mint_distribution").unwrap();
             let pool_owner: Pubkey = pubkey_of(arg_matches, "pool_owner").unwrap();
 
-            let price = ui_to_tokens(value_t_or_exit!(arg_matches, "price", f64), Pool::PRECISION);
-
             let is_whitelist = value_t_or_exit!(arg_matches, "is_whitelist", bool);
-            let kyc_requirement = if value_t_or_exit!(arg_matches, "is_kyc", bool) {
-                sol_starter_ido::state::KycRequirement::AnyRequired
-            } else {
-                sol_starter_ido::state::KycRequirement::NotRequired
+            let kyc_requirement = match (
+                value_t_or_exit!(arg_matches, "is_kyc", bool),
+                pubkey_of(arg_matches, "kyc_provider"),
+            ) {
+                (true, Some(provider)) => KycRequirement::SpecificProvider(provider),
+                (true, None) => KycRequirement::AnyRequired,
+                (false, _) => KycRequirement::default(),
             };
 
             let mint_collection_account = config.rpc_client.get_account(&mint_collection).unwrap();
             let mint_collection_account = Mint::unpack(&mint_collection_account.data).unwrap();
             let token_precision = <u64>::pow(10, mint_collection_account.decimals.into());
 
+            // price is for token amounts, so UI price is scaled by difference of mint decimals
+            let mint_distribution_account =
+                config.rpc_client.get_account(&mint_distribution).unwrap();
+            let mint_distribution_account = Mint::unpack(&mint_distribution_account.data).unwrap();
+            let price_scale = 10f64.powi(
+                i32::from(mint_collection_account.decimals)
+                    - i32::from(mint_distribution_account.decimals),
+            );
+            let price = value_t_or_exit!(arg_matches, "price", f64) * price_scale;
+            let price = ui_to_tokens(price, Pool::PRECISION);
+
             let goal_max = value_t_or_exit!(arg_matches, "goal_max", f64);
             let goal_max = ui_to_tokens(goal_max, token_precision);
             let goal_min = value_t_or_exit!(arg_matches, "goal_min", f64);
@@ -1303,6 +3421,21 @@ fn main() {
             let amount_max = ui_to_tokens(amount_max, token_precision);
             let amount_min = value_t_or_exit!(arg_matches, "amount_min", f64);
             let amount_min = ui_to_tokens(amount_min, token_precision);
+            let per_user_max = value_t_or_exit!(arg_matches, "per_user_max", f64);
+            let per_user_max = ui_to_tokens(per_user_max, token_precision);
+
+            let time_table = [
+                value_t_or_exit!(arg_matches, "stage_1", u32),
+                value_t_or_exit!(arg_matches, "stage_2", u32),
+                value_t_or_exit!(arg_matches, "stage_3", u32),
+                value_t_or_exit!(arg_matches, "stage_4", u32),
+            ];
+            // first two stages are always active, optional ones only if set
+            let stages_count = time_table
+                .iter()
+                .rposition(|x| *x != 0)
+                .map_or(0, |i| i + 1)
+                .max(sol_starter_ido::STAGES_ACTIVE_COUNT_V2);
 
             let init_args = InitializePool {
                 pool_owner,
@@ -1313,11 +3446,17 @@ fn main() {
                 amount_max,
                 time_start: value_t_or_exit!(arg_matches, "time_start", UnixTimestamp),
                 time_finish: value_t_or_exit!(arg_matches, "time_finish", UnixTimestamp),
-                kyc_requirement,
-                time_table: [
-                    value_t_or_exit!(arg_matches, "stage_1", u32),
-                    value_t_or_exit!(arg_matches, "stage_2", u32),
-                ],
+                kyc_requirement: Some(kyc_requirement),
+                time_table: Some(time_table),
+                stages_count: stages_count as u8,
+                whitelist_ticket_amount: value_t_or_exit!(
+                    arg_matches,
+                    "whitelist_ticket_amount",
+                    u64
+                ),
+                per_user_max,
+                refund_grace_period: value_t_or_exit!(arg_matches, "refund_grace_period", u32),
+                nonce: value_t!(arg_matches, "nonce", u64).ok(),
             };
 
             command_create_pool(
@@ -1333,13 +3472,31 @@ fn main() {
             let market: Pubkey = pubkey_of(arg_matches, "market").unwrap();
             let pool_to_start: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
 
-            command_start_pool(&config, &market, &pool_to_start)
+            let preview = arg_matches.is_present("preview");
+            let require_funded = arg_matches.is_present("require_funded");
+
+            command_start_pool(&config, &market, &pool_to_start, preview, require_funded)
         }
         ("add-to-whitelist", Some(arg_matches)) => {
             let pool: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
             let whitelist_accs_file = value_t_or_exit!(arg_matches, "whitelist-accounts", String);
+            let resume_file = arg_matches.value_of("resume-file");
+
+            command_add_to_whitelist(&config, &pool, &whitelist_accs_file, resume_file)
+        }
+        ("create-kyc", Some(arg_matches)) => {
+            let market: Pubkey = pubkey_of(arg_matches, "market").unwrap();
+            let kyc_accs_file = value_t_or_exit!(arg_matches, "kyc-accounts", String);
+            let provider = arg_matches.value_of("provider").map(|path| {
+                signer_from_path(arg_matches, path, "provider", &mut wallet_manager).unwrap_or_else(
+                    |e| {
+                        eprintln!("error: {}", e);
+                        exit(1);
+                    },
+                )
+            });
 
-            command_add_to_whitelist(&config, &pool, &whitelist_accs_file)
+            command_create_kyc(&config, &market, &kyc_accs_file, provider.as_deref())
         }
         ("participate", Some(arg_matches)) => {
             let pool_key: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
@@ -1360,6 +3517,10 @@ fn main() {
             let pool_lock_token: Option<Pubkey> = pubkey_of(arg_matches, "pool-lock-token");
             let market_user_kyc: Option<Pubkey> = pubkey_of(arg_matches, "market-user-kyc");
             let account_whitelist: Option<Pubkey> = pubkey_of(arg_matches, "account-whitelist");
+            let whitelist_tickets = value_t_or_exit!(arg_matches, "whitelist-tickets", u64);
+            let min_accepted = value_t_or_exit!(arg_matches, "min-accepted", f64);
+            let min_accepted = ui_to_tokens(min_accepted, token_precision);
+            let receipt = arg_matches.is_present("receipt");
 
             command_participate(
                 &config,
@@ -1371,36 +3532,382 @@ fn main() {
                 pool_lock_token,
                 market_user_kyc,
                 account_whitelist,
+                whitelist_tickets,
+                min_accepted,
+                receipt,
             )
         }
         ("withdraw", Some(arg_matches)) => {
             let pool: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
             let account_from: Pubkey = pubkey_of(arg_matches, "account-from").unwrap();
             let account_to: Option<Pubkey> = pubkey_of(arg_matches, "account-to");
+            let skip_owner_check = arg_matches.is_present("skip-owner-check");
+
+            command_withdraw(&config, &pool, &account_from, account_to, skip_owner_check)
+        }
+        ("claim-all", Some(arg_matches)) => {
+            let market: Pubkey = pubkey_of(arg_matches, "market").unwrap();
+            let user = arg_matches.value_of("user").map(|path| {
+                signer_from_path(arg_matches, path, "user", &mut wallet_manager).unwrap_or_else(
+                    |e| {
+                        eprintln!("error: {}", e);
+                        exit(1);
+                    },
+                )
+            });
+            let user = user.as_deref().unwrap_or_else(|| config.owner.as_ref());
+
+            command_claim_all(&config, &market, user)
+        }
+        ("fund-distribution", Some(arg_matches)) => {
+            let pool: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let account_from: Option<Pubkey> = pubkey_of(arg_matches, "account-from");
 
-            command_withdraw(&config, &pool, &account_from, account_to)
+            command_fund_distribution(&config, &pool, amount, account_from)
         }
         ("pool-info", Some(arg_matches)) => {
             let pool: Pubkey = pubkey_of(arg_matches, "pool").unwrap();
+            let user: Option<Pubkey> = pubkey_of(arg_matches, "user");
+            let labels = arg_matches.value_of("labels");
+
+            command_pool_info(&config, &pool, user, labels)
+        }
+        ("stake-pool-info", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+
+            command_stake_pool_info(&config, &stake_pool)
+        }
+        ("decode-error", Some(arg_matches)) => {
+            let program = arg_matches.value_of("program").unwrap();
+            let code = parse_error_code(arg_matches.value_of("code").unwrap()).unwrap();
+
+            command_decode_error(program, code)
+        }
+        ("stake-start", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let user_token_sos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-sos");
+
+            command_stake_start(&config, &stake_pool, amount, user_token_sos)
+        }
+        ("stake-finish", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let transit: Pubkey = pubkey_of(arg_matches, "transit").unwrap();
+            let user_token_xsos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-xsos");
+
+            command_stake_finish(&config, &stake_pool, &transit, user_token_xsos)
+        }
+        ("unstake-start", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let user_token_xsos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-xsos");
+
+            command_unstake_start(&config, &stake_pool, amount, user_token_xsos)
+        }
+        ("unstake-finish", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let transit: Pubkey = pubkey_of(arg_matches, "transit").unwrap();
+            let user_token_sos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-sos");
+
+            command_unstake_finish(&config, &stake_pool, &transit, user_token_sos)
+        }
+        ("cancel-transit", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let transit: Pubkey = pubkey_of(arg_matches, "transit").unwrap();
+
+            command_cancel_transit(&config, &stake_pool, &transit)
+        }
+        ("transit-info", Some(arg_matches)) => {
+            let transit: Pubkey = pubkey_of(arg_matches, "transit").unwrap();
+
+            command_transit_info(&config, &transit)
+        }
+        ("lock", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let user_token_xsos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-xsos");
+
+            command_lock(&config, &stake_pool, amount, user_token_xsos)
+        }
+        ("compound", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let user_token_xsos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-xsos");
+
+            command_compound(&config, &stake_pool, user_token_xsos)
+        }
+        ("refresh-lock", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+
+            command_refresh_lock(&config, &stake_pool)
+        }
+        ("unlock", Some(arg_matches)) => {
+            let stake_pool: Pubkey = pubkey_of(arg_matches, "stake-pool").unwrap();
+            let amount = value_t_or_exit!(arg_matches, "amount", f64);
+            let user_token_xsos: Option<Pubkey> = pubkey_of(arg_matches, "user-token-xsos");
 
-            command_pool_info(&config, &pool)
+            command_unlock(&config, &stake_pool, amount, user_token_xsos)
         }
         _ => unreachable!(),
     }
     .and_then(|transaction| {
         if let Some(transaction) = transaction {
-            let signature = config
-                .rpc_client
-                .send_and_confirm_transaction_with_spinner_and_commitment(
-                    &transaction,
-                    config.commitment_config,
-                )?;
-            println!("Signature: {}", signature);
+            if let Some(signature) = send_transaction(&config, &transaction)? {
+                println!("Signature: {}", signature);
+            }
         }
         Ok(())
     })
     .map_err(|err| {
-        eprintln!("{:?}", err);
-        exit(1);
+        eprintln!("error: {}", err);
+        exit(exit_code(&err));
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use borsh::BorshSerialize;
+
+    #[test]
+    fn custom_error_prints_enum_name() {
+        let code = sol_starter_ido::error::Error::PoolAlreadyFull as u32;
+        let error = CliError::from_custom(&sol_starter_ido::id(), code).unwrap();
+        assert_eq!(error.exit_code(), EXIT_CODE_PROGRAM);
+        assert!(error.to_string().contains("PoolAlreadyFull"));
+
+        let code = sol_starter_staking::error::Error::NothingToClaim as u32;
+        let error = CliError::from_custom(&sol_starter_staking::id(), code).unwrap();
+        assert!(error.to_string().contains("NothingToClaim"));
+
+        assert!(CliError::from_custom(&Pubkey::new_unique(), 0).is_none());
+    }
+
+    #[test]
+    fn decode_error_code() {
+        assert_eq!(parse_error_code("26"), Ok(26));
+        assert_eq!(parse_error_code("0x1a"), Ok(26));
+        assert!(parse_error_code("1a").is_err());
+        assert!(program_id_by_name("token").is_none());
+
+        let program_id = program_id_by_name("ido").unwrap();
+        let code = sol_starter_ido::error::Error::PoolAlreadyFull as u32;
+        match CliError::from_custom(&program_id, code) {
+            Some(CliError::Program { name, message, .. }) => {
+                assert_eq!(name, "PoolAlreadyFull");
+                assert_eq!(
+                    message,
+                    sol_starter_ido::error::Error::PoolAlreadyFull.description()
+                );
+            }
+            _ => panic!("ido error not decoded"),
+        }
+
+        let program_id = program_id_by_name("staking").unwrap();
+        let code = sol_starter_staking::error::Error::NothingToClaim as u32;
+        match CliError::from_custom(&program_id, code) {
+            Some(CliError::Program { name, .. }) => assert_eq!(name, "NothingToClaim"),
+            _ => panic!("staking error not decoded"),
+        }
+    }
+
+    #[test]
+    fn custom_error_resolved_by_failed_instruction_program() {
+        let payer = Pubkey::new_unique();
+        let instruction = Instruction::new_with_bytes(sol_starter_staking::id(), &[], vec![]);
+        let transaction = Transaction::new_with_payer(&[instruction], Some(&payer));
+        let code = sol_starter_staking::error::Error::NothingToClaim as u32;
+        let error = CliError::from_transaction_error(
+            &transaction,
+            &TransactionError::InstructionError(0, InstructionError::Custom(code)),
+        )
+        .unwrap();
+        assert!(error.to_string().contains("staking NothingToClaim"));
+    }
+
+    #[test]
+    fn claims_packed_into_packet_sized_transactions() {
+        let payer = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let claims: Vec<Vec<Instruction>> = (0..5)
+            .map(|_| {
+                let pool = Pool {
+                    market: Pubkey::new_unique(),
+                    mint_pool: Pubkey::new_unique(),
+                    account_distribution: Pubkey::new_unique(),
+                    amount_collected: 1,
+                    ..Pool::default()
+                };
+                sdk::claim(
+                    &sol_starter_ido::program_id(),
+                    &Pubkey::new_unique(),
+                    &pool,
+                    &payer,
+                    &user,
+                    &Pubkey::new_unique(),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let packed = pack_instruction_sets(&payer, claims.clone());
+        assert!(packed.len() < claims.len());
+        for instructions in packed.iter() {
+            assert!(transaction_size(&payer, instructions) <= PACKET_DATA_SIZE);
+        }
+        assert_eq!(packed.concat(), claims.concat());
+    }
+
+    #[test]
+    fn existing_stake_pool_must_be_initialized_staking_account() {
+        let stake_pool = StakePool {
+            account_type: sol_starter_staking::state::ACCOUNT_TYPE_STAKE_POOL,
+            version: StateVersion::V2,
+            ido_authority: Pubkey::new_unique(),
+            ..StakePool::default()
+        };
+        let mut account = Account {
+            owner: sol_starter_staking::id(),
+            data: stake_pool.try_to_vec().unwrap(),
+            ..Account::default()
+        };
+        assert_eq!(
+            existing_stake_pool(&account).unwrap().ido_authority,
+            stake_pool.ido_authority
+        );
+
+        account.owner = Pubkey::new_unique();
+        assert!(existing_stake_pool(&account).is_err());
+
+        account.owner = sol_starter_staking::id();
+        account.data = StakePool::default().try_to_vec().unwrap();
+        assert!(existing_stake_pool(&account).is_err());
+
+        account.data = Market::default().try_to_vec().unwrap();
+        assert!(existing_stake_pool(&account).is_err());
+    }
+
+    #[test]
+    fn market_pools_filtered_by_market() {
+        let market = Pubkey::new_unique();
+        let pool = |market| Pool {
+            account_type: ACCOUNT_TYPE_POOL,
+            version: sol_starter_ido::state::POOL_VERSION,
+            market,
+            ..Pool::default()
+        };
+        let account = |data: Vec<u8>| Account {
+            data,
+            ..Account::default()
+        };
+        let own = Pubkey::new_unique();
+        let accounts = vec![
+            (own, account(pool(market).try_to_vec().unwrap())),
+            (
+                Pubkey::new_unique(),
+                account(pool(Pubkey::new_unique()).try_to_vec().unwrap()),
+            ),
+            (
+                Pubkey::new_unique(),
+                account(Market::default().try_to_vec().unwrap()),
+            ),
+        ];
+
+        let pools = market_pools(accounts, &market);
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].0, own);
+    }
+
+    #[test]
+    fn whitelist_continues_past_failed_transaction() {
+        let record = |wallet: &str| Record {
+            wallet: wallet.to_string(),
+            whitelist_token_acc: String::new(),
+        };
+        let batch = |rows| WhitelistBatch {
+            rows,
+            ..WhitelistBatch::default()
+        };
+        let batches = vec![
+            batch(vec![(1, record("first")), (2, record("second"))]),
+            batch(vec![(3, record("bad"))]),
+            batch(vec![(4, record("last"))]),
+        ];
+
+        let mut summary = WhitelistSummary::default();
+        let mut sent = 0;
+        send_whitelist_batches(batches, &mut summary, |batch| {
+            sent += 1;
+            if batch.rows.iter().any(|(_, record)| record.wallet == "bad") {
+                Err("rejected".into())
+            } else {
+                Ok(None)
+            }
+        });
+        assert_eq!(sent, 3);
+        assert_eq!(summary.succeeded, 3);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].row, 3);
+        assert_eq!(summary.failed[0].error, "rejected");
+
+        let mut resume = Vec::new();
+        summary
+            .write_resume(csv::Writer::from_writer(&mut resume))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(resume).unwrap(),
+            "wallet,whitelist_token_acc\nbad,\n"
+        );
+    }
+
+    #[test]
+    fn pool_info_json() {
+        let address = Pubkey::new_unique();
+        let pool = Pool {
+            account_type: ACCOUNT_TYPE_POOL,
+            version: sol_starter_ido::state::POOL_VERSION,
+            market: Pubkey::new_unique(),
+            mint_whitelist: MintWhitelist::Key(Pubkey::new_unique()),
+            price: 5,
+            goal_max_collected: 100,
+            time_start: 1_000,
+            time_finish: 2_000,
+            time_table: [100, 200, 0, 0],
+            stages_count: 2,
+            started: true,
+            ..Pool::default()
+        };
+        let clock = Clock {
+            unix_timestamp: 1_050,
+            ..Clock::default()
+        };
+
+        let view = PoolInfoView::new(&address, &pool, &clock, Some(2));
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string_pretty(&view).unwrap()).unwrap();
+        assert_eq!(json["address"], address.to_string());
+        assert_eq!(json["market"], pool.market.to_string());
+        assert_eq!(json["mint_whitelist"], view.mint_whitelist.clone().unwrap());
+        assert_eq!(json["price"], 5);
+        assert_eq!(json["time_table"], serde_json::json!([100, 200]));
+        assert_eq!(json["started"], true);
+        assert_eq!(json["current_stage"], 0);
+        assert_eq!(json["user_tier"], 2);
+
+        let view = PoolInfoView::new(&address, &Pool::default(), &clock, None);
+        let json = serde_json::to_value(&view).unwrap();
+        assert!(json["mint_whitelist"].is_null());
+        assert!(json["user_tier"].is_null());
+    }
+
+    #[test]
+    fn labels_annotate_known_keys() {
+        let market = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let labels = Labels::from_json(&format!("{{\"{}\": \"market\"}}", market)).unwrap();
+
+        assert_eq!(labels.show(&market), format!("{} (market)", market));
+        assert_eq!(labels.show(&unknown), unknown.to_string());
+        assert!(Labels::from_json("{\"not a key\": \"market\"}").is_err());
+    }
+}
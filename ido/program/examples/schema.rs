@@ -0,0 +1,105 @@
+//! Prints borsh layout of program instructions and accounts as JSON for clients.
+//!
+//! `cargo run --example schema --features no-entrypoint`
+
+use std::collections::BTreeMap;
+
+use borsh::{
+    schema::{BorshSchemaContainer, Definition, Fields},
+    BorshSchema,
+};
+use sol_starter_ido::{
+    instruction::{
+        CreateMarketUserKyc, FundDistribution, InitializeMarket, InitializePool, Instruction,
+        MigrateAccount, Participate,
+    },
+    state::{Market, MarketUserKyc, Pool, UserPoolStage},
+};
+
+fn quote(value: &str) -> String {
+    format!("{:?}", value)
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(", "))
+}
+
+fn pairs(items: &[(String, String)]) -> String {
+    list(
+        items
+            .iter()
+            .map(|(name, declaration)| format!("[{}, {}]", quote(name), quote(declaration))),
+    )
+}
+
+fn definition_to_json(definition: &Definition) -> String {
+    match definition {
+        Definition::Array { length, elements } => format!(
+            r#"{{"kind": "array", "length": {}, "elements": {}}}"#,
+            length,
+            quote(elements)
+        ),
+        Definition::Sequence { elements } => {
+            format!(r#"{{"kind": "sequence", "elements": {}}}"#, quote(elements))
+        }
+        Definition::Tuple { elements } => format!(
+            r#"{{"kind": "tuple", "elements": {}}}"#,
+            list(elements.iter().map(|x| quote(x)))
+        ),
+        Definition::Enum { variants } => {
+            format!(r#"{{"kind": "enum", "variants": {}}}"#, pairs(variants))
+        }
+        Definition::Struct { fields } => {
+            let fields = match fields {
+                Fields::NamedFields(fields) => pairs(fields),
+                Fields::UnnamedFields(fields) => list(fields.iter().map(|x| quote(x))),
+                Fields::Empty => "[]".to_string(),
+            };
+            format!(r#"{{"kind": "struct", "fields": {}}}"#, fields)
+        }
+    }
+}
+
+fn container_to_json(container: &BorshSchemaContainer) -> String {
+    let definitions: BTreeMap<_, _> = container.definitions.iter().collect();
+    let definitions = definitions
+        .iter()
+        .map(|(name, definition)| {
+            format!("      {}: {}", quote(name), definition_to_json(definition))
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!(
+        "{{\n    \"declaration\": {},\n    \"definitions\": {{\n{}\n    }}\n  }}",
+        quote(&container.declaration),
+        definitions
+    )
+}
+
+fn main() {
+    let containers = vec![
+        Instruction::schema_container(),
+        InitializeMarket::schema_container(),
+        InitializePool::schema_container(),
+        Participate::schema_container(),
+        CreateMarketUserKyc::schema_container(),
+        MigrateAccount::schema_container(),
+        FundDistribution::schema_container(),
+        Market::schema_container(),
+        Pool::schema_container(),
+        MarketUserKyc::schema_container(),
+        UserPoolStage::schema_container(),
+    ];
+    let types = containers
+        .iter()
+        .map(|container| {
+            format!(
+                "  {}: {}",
+                quote(&container.declaration),
+                container_to_json(container)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    println!("{{\n{}\n}}", types);
+}
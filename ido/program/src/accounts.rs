@@ -0,0 +1,269 @@
+//! Typed accounts of instructions, named in order of instruction accounts
+
+use sol_starter_staking::instruction_accounts;
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::InitializeMarket]
+    pub(crate) struct InitializeMarketAccounts {
+        market,
+        market_owner,
+        rent,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::InitializePool]
+    pub(crate) struct InitializePoolAccounts {
+        market,
+        pool,
+        market_owner,
+        mint_collection,
+        mint_distribution,
+        account_collection,
+        account_distribution,
+        mint_pool,
+        pool_authority,
+        rent,
+        clock,
+        token_program,
+        system_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Participate]
+    pub(crate) struct ParticipateAccounts {
+        market,
+        pool,
+        pool_authority,
+        pool_user_authority,
+        user_wallet,
+        user_account_from,
+        account_collection,
+        user_account_to,
+        pool_lock_account,
+        mint_pool,
+        market_user_kyc,
+        user_pool_stage,
+        pool_lock,
+        stake_pool,
+        token_program_id,
+        system_program,
+        rent,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Claim]
+    pub(crate) struct ClaimAccounts {
+        market,
+        pool,
+        pool_authority,
+        account_from,
+        user_authority,
+        mint_pool,
+        account_pool,
+        account_to,
+        token_program_id,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::AddToWhitelist]
+    pub(crate) struct AddToWhitelistAccounts {
+        pool,
+        pool_authority,
+        pool_owner,
+        account_whitelist,
+        mint_whitelist,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::AddManyToWhitelist]
+    pub(crate) struct AddManyToWhitelistAccounts {
+        pool,
+        pool_authority,
+        pool_owner,
+        mint_whitelist,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::CreateMarketUserKyc]
+    pub(crate) struct CreateMarketUserKycAccounts {
+        market,
+        market_user_authority,
+        market_user_kyc,
+        market_owner,
+        user_wallet,
+        rent,
+        clock,
+        system_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::DeleteMarketUserKyc]
+    pub(crate) struct DeleteMarketUserKycAccounts {
+        market,
+        market_user_authority,
+        market_user_kyc,
+        market_owner,
+        user_wallet,
+        system_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::StartPool]
+    pub(crate) struct StartPoolAccounts {
+        market,
+        market_or_pool_owner,
+        stake_pool,
+        market_authority,
+        pool,
+        clock,
+        staking_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Migrate]
+    pub(crate) struct MigrateAccounts {
+        account,
+        owner,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Migrate] copying market to new account
+    pub(crate) struct MigrateMarketAccounts {
+        new_market,
+        market_authority,
+        rent,
+        stake_pool,
+        staking_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Migrate] copying pool to new account
+    pub(crate) struct MigratePoolAccounts {
+        new_pool,
+        pool_authority,
+        rent,
+        account_collection,
+        account_distribution,
+        mint_pool,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::RenewMarketUserKyc]
+    pub(crate) struct RenewMarketUserKycAccounts {
+        market,
+        market_user_authority,
+        market_user_kyc,
+        market_owner,
+        user_wallet,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::FundDistribution]
+    pub(crate) struct FundDistributionAccounts {
+        pool,
+        pool_owner,
+        account_distribution,
+        token_program,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetMarketDefaults]
+    pub(crate) struct SetMarketDefaultsAccounts {
+        market,
+        market_owner,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetMarketFee]
+    pub(crate) struct SetMarketFeeAccounts {
+        market,
+        market_owner,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::RecomputeTierUsers]
+    pub(crate) struct RecomputeTierUsersAccounts {
+        market,
+        market_owner,
+        market_authority,
+        stake_pool,
+        clock,
+        staking_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetStakesPaused]
+    pub(crate) struct SetStakesPausedAccounts {
+        market,
+        market_owner,
+        market_authority,
+        stake_pool,
+        staking_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetStakePoolIdoAuthority]
+    pub(crate) struct SetStakePoolIdoAuthorityAccounts {
+        market,
+        market_owner,
+        market_authority,
+        stake_pool,
+        staking_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Withdraw]
+    pub(crate) struct WithdrawAccounts {
+        market,
+        pool,
+        pool_authority,
+        pool_owner,
+        account_from,
+        account_to,
+        token_program,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::WithdrawAll]
+    pub(crate) struct WithdrawAllAccounts {
+        market,
+        pool,
+        pool_authority,
+        pool_owner,
+        account_collection,
+        account_distribution,
+        account_to_collection,
+        account_to_distribution,
+        token_program,
+        clock,
+    }
+}
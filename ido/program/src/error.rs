@@ -6,6 +6,7 @@ use solana_program::{
     decode_error::DecodeError,
     msg,
     program_error::{PrintProgramError, ProgramError},
+    pubkey::Pubkey,
 };
 
 /// Errors that may be returned by the SolStarter program.
@@ -154,7 +155,194 @@ pub enum Error {
     /// Can participate only in started pool
     #[error("Can participate only in started pool")]
     CanParticipateOnlyInStartedPool,
+
+    /// Account to create must be associated token account of wallet and mint
+    #[error("Account to create must be associated token account of wallet and mint")]
+    WrongAssociatedTokenAccount,
+
+    /// Account state version is not supported by this program
+    #[error("Account state version is not supported by this program")]
+    UnsupportedStateVersion,
+
+    /// Accepted amount is less than requested minimum
+    #[error("Accepted amount is less than requested minimum")]
+    SlippageExceeded,
+
+    /// Pool account holds less tokens than still owed to users
+    #[error("Pool account holds less tokens than still owed to users")]
+    NotEnoughTokensReservedForUsers,
+
+    /// Can't fund distribution of active pool
+    #[error("Can't fund distribution of active pool")]
+    CantFundActivePool,
+
+    /// Pool distribution is not funded yet
+    #[error("Pool distribution is not funded yet")]
+    DistributionNotFunded,
+
+    /// KYC is not approved by provider required by pool
+    #[error("KYC is not approved by provider required by pool")]
+    WrongKycProvider,
+
+    /// Wrong user pool account
+    #[error("Wrong user pool account")]
+    WrongUserPool,
+
+    /// Participation exceeds maximum total investment per user
+    #[error("Participation exceeds maximum total investment per user")]
+    PerUserMaxExceeded,
+
+    /// Refund exceeds tokens collected by pool
+    #[error("Refund exceeds tokens collected by pool")]
+    RefundExceedsCollected,
+
+    /// Whitelist accounts count is zero or exceeds [crate::WHITELIST_ACCOUNTS_MAX]
+    #[error("Whitelist accounts count is zero or exceeds maximum")]
+    WrongWhitelistAccountsCount,
+
+    /// Whitelist accounts supplied to pool without whitelist
+    #[error("Whitelist accounts supplied to pool without whitelist")]
+    WhitelistNotEnabled,
+
+    /// Account data is of other account type
+    #[error("Account data is of other account type")]
+    WrongAccountType,
+
+    /// Mint decimals overflow price or leave it without precision
+    #[error("Mint decimals overflow price or leave it without precision")]
+    MintDecimalsMismatch,
+
+    /// Pool terms can't be changed after users participated
+    #[error("Pool terms can't be changed after users participated")]
+    PoolHasParticipants,
+
+    /// Market fee exceeds [crate::state::MARKET_FEE_MAX_BASIS_POINTS]
+    #[error("Market fee exceeds maximum")]
+    MarketFeeInvalid,
+
+    /// Fee account must be market fee account
+    #[error("Fee account must be market fee account")]
+    WrongFeeAccount,
+
+    /// Withdraw destination must be owned by pool owner
+    #[error("Withdraw destination must be owned by pool owner")]
+    WithdrawDestinationMustBeOwnedByPoolOwner,
+
+    /// Wrong participation receipt account
+    #[error("Wrong participation receipt account")]
+    WrongParticipationReceipt,
+
+    /// Amount must be greater than zero
+    #[error("Amount must be greater than zero")]
+    ZeroAmount,
+
+    /// Pool user authority must be derived from pool and user wallet
+    #[error("Pool user authority must be derived from pool and user wallet")]
+    WrongPoolUserAuthority,
+
+    /// Wrong pool owner
+    #[error("Wrong pool owner")]
+    WrongPoolOwner,
+
+    /// Pools passed must be all [crate::state::Market::pool_count] pools of market, each once
+    #[error("Pools passed must be all pools of market, each once")]
+    WrongMarketPools,
+
+    /// Pool was already started, tier allocations are fixed
+    #[error("Pool was already started")]
+    PoolAlreadyStarted,
+}
+
+impl Error {
+    /// Stable human readable description, same as [std::fmt::Display]
+    pub fn description(&self) -> &'static str {
+        match self {
+            Error::WrongMarketOwner => "Wrong market owner",
+            Error::MarketOrPoolOwnerRequired => "Market or pool owner required",
+            Error::WrongProgramAddress => "Wrong program address",
+            Error::WrongTokenMint => "Wrong token mint account",
+            Error::WrongPoolTokenMint => "Wrong pool token mint account",
+            Error::WrongMarketAddressForCurrentPool => "Wrong market address for current pool",
+            Error::PoolAuthorityMustBeAlignedToPool => "Pool authority must be aligned to pool",
+            Error::InvalidGoalNumbers => "Invalid goal numbers",
+            Error::InvalidInvestmentSizeNumbers => "Invalid investment size numbers",
+            Error::InvalidPoolTimeFrame => "Invalid pool time frame",
+            Error::MarketAuthorityMustBeDerivedFromMarket => {
+                "Market authority must be derived from market"
+            }
+            Error::InvalidTimeTable => "Invalid time table",
+            Error::WrongCollectAccount => "Wrong account to collect tokens",
+            Error::WrongKycAccount => "Wrong kyc account",
+            Error::CantDepositAtCurrentTime => "Unable to deposit at current time",
+            Error::IncorrectDepositAmount => "Incorrect amount to deposit",
+            Error::PoolAlreadyFull => "Pool already full",
+            Error::CantClaimFromActivePool => "Can't claim tokens till pool is active",
+            Error::WrongPoolAccountToSendTokensFrom => "Wrong pool account to send tokens from",
+            Error::WhitelistMintNotSet => "Pool doesn't have mint whitelist account",
+            Error::CantWithdrawFromActivePool => "Can't withdraw from active pool",
+            Error::Overflow => "Overflow",
+            Error::DivisionByZero => "Division By Zero",
+            Error::Underflow => "Underflow",
+            Error::WhitelistMintMissing => "Whitelist mint account missing",
+            Error::WhitelistMintInvalid => "Whitelist mint account invalid",
+            Error::WrongKycCredentials => "Wrong KYC credentials",
+            Error::WrongUserPoolStage => "Wrong user pool stage",
+            Error::WrongKycOwner => "Wrong KYC owner",
+            Error::LockOwnerMustBeUserWallet => "Lock owner must be user wallet",
+            Error::PoolLockTokenMustBeAttachedToPoolLock => {
+                "Pool lock token must be attached to pool lock"
+            }
+            Error::InputTimeMustBeInFuture => "Input time must be in future",
+            Error::StakePoolMustBelongToMarket => "Stake pool must belong to market",
+            Error::AccountOnThisTierCannotParticipateOnCurrentStage => {
+                "Account on this tier cannot participate on current stage"
+            }
+            Error::AccountAlreadyParticipatedOnThisStage => {
+                "Account already participated on this stage"
+            }
+            Error::CanParticipateOnlyInStartedPool => "Can participate only in started pool",
+            Error::WrongAssociatedTokenAccount => {
+                "Account to create must be associated token account of wallet and mint"
+            }
+            Error::UnsupportedStateVersion => {
+                "Account state version is not supported by this program"
+            }
+            Error::SlippageExceeded => "Accepted amount is less than requested minimum",
+            Error::NotEnoughTokensReservedForUsers => {
+                "Pool account holds less tokens than still owed to users"
+            }
+            Error::CantFundActivePool => "Can't fund distribution of active pool",
+            Error::DistributionNotFunded => "Pool distribution is not funded yet",
+            Error::WrongKycProvider => "KYC is not approved by provider required by pool",
+            Error::WrongUserPool => "Wrong user pool account",
+            Error::PerUserMaxExceeded => "Participation exceeds maximum total investment per user",
+            Error::RefundExceedsCollected => "Refund exceeds tokens collected by pool",
+            Error::WrongWhitelistAccountsCount => {
+                "Whitelist accounts count is zero or exceeds maximum"
+            }
+            Error::WhitelistNotEnabled => "Whitelist accounts supplied to pool without whitelist",
+            Error::WrongAccountType => "Account data is of other account type",
+            Error::MintDecimalsMismatch => {
+                "Mint decimals overflow price or leave it without precision"
+            }
+            Error::PoolHasParticipants => "Pool terms can't be changed after users participated",
+            Error::MarketFeeInvalid => "Market fee exceeds maximum",
+            Error::WrongFeeAccount => "Fee account must be market fee account",
+            Error::WithdrawDestinationMustBeOwnedByPoolOwner => {
+                "Withdraw destination must be owned by pool owner"
+            }
+            Error::WrongParticipationReceipt => "Wrong participation receipt account",
+            Error::ZeroAmount => "Amount must be greater than zero",
+            Error::WrongPoolUserAuthority => {
+                "Pool user authority must be derived from pool and user wallet"
+            }
+            Error::WrongPoolOwner => "Wrong pool owner",
+            Error::WrongMarketPools => "Pools passed must be all pools of market, each once",
+            Error::PoolAlreadyStarted => "Pool was already started",
+        }
+    }
 }
+
 impl From<Error> for ProgramError {
     fn from(e: Error) -> Self {
         ProgramError::Custom(e as u32)
@@ -174,3 +362,55 @@ impl PrintProgramError for Error {
         msg!(&self.to_string())
     }
 }
+
+/// Resolves custom error `code` of failed instruction of `program_id` to ido or staking error description.
+/// `None` if program is neither of them or code is unknown.
+pub fn decode_custom_error(program_id: &Pubkey, code: u32) -> Option<&'static str> {
+    if *program_id == crate::id() {
+        Error::from_u32(code).map(|error| error.description())
+    } else if *program_id == sol_starter_staking::id() {
+        sol_starter_staking::error::Error::from_u32(code).map(|error| error.description())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use num_traits::ToPrimitive;
+
+    use super::*;
+
+    #[test]
+    fn round_trip_u32() {
+        let mut code = 0;
+        while let Some(error) = Error::from_u32(code) {
+            assert_eq!(error.to_u32(), Some(code));
+            assert_eq!(
+                ProgramError::from(error.clone()),
+                ProgramError::Custom(code)
+            );
+            assert_eq!(error.to_string(), error.description());
+            code += 1;
+        }
+        assert!(code > Error::WrongKycProvider as u32);
+    }
+
+    #[test]
+    fn decode_custom_error_by_program() {
+        let code = Error::PoolAlreadyFull as u32;
+        assert_eq!(
+            decode_custom_error(&crate::id(), code),
+            Some(Error::PoolAlreadyFull.description())
+        );
+
+        let staking_error = sol_starter_staking::error::Error::NothingToClaim;
+        assert_eq!(
+            decode_custom_error(&sol_starter_staking::id(), staking_error.clone() as u32),
+            Some(staking_error.description())
+        );
+
+        assert_eq!(decode_custom_error(&Pubkey::new_unique(), code), None);
+        assert_eq!(decode_custom_error(&crate::id(), u32::MAX), None);
+    }
+}
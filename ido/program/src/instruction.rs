@@ -1,13 +1,15 @@
 //! Instruction types
 
-use sol_starter_staking::utils::program::{ProgramPubkey, PubkeyPatterns};
+use sol_starter_staking::utils::program::{
+    MarketUserAuthority, PoolUserAuthority, ProgramPubkey, PubkeyPatterns,
+};
 
 use crate::{
     error::Error,
-    state::{KycRequirement, UnixTimeSmallDuration},
+    state::{KycRequirement, PoolDefaults, Timeframe, UnixTimeSmallDuration},
     CollectionToken,
 };
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::{
     clock::{Clock, UnixTimestamp},
     entrypoint::ProgramResult,
@@ -17,7 +19,11 @@ use solana_program::{
     system_program, sysvar,
 };
 /// Init pool instruction parameters
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+///
+/// `price` over [crate::state::Pool::PRECISION] is for token amounts, pool mint gets collection mint decimals.
+/// For mints of different decimals it is the UI price scaled by `10^(collection decimals - distribution decimals)`,
+/// decimals which overflow it or leave it without precision are rejected by [crate::state::Pool::validate_decimals].
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
 pub struct InitializePool {
     /// pool owner
     pub pool_owner: Pubkey,
@@ -27,23 +33,59 @@ pub struct InitializePool {
     pub goal_max: u64,
     /// Minimum amount of [crate::state::Pool::account_collection] to be collected. If the collected amount is less than `goal_min` the pool should refund all the collected tokens.
     pub goal_min: u64,
-    /// The minimum  amount of one single investment transaction.
+    /// The minimum  amount of one single investment transaction, zero inherits [PoolDefaults::amount_investment_min]
     pub amount_min: u64,
-    /// The maximum  amount of one single investment transaction.
+    /// The maximum  amount of one single investment transaction, zero inherits [PoolDefaults::amount_investment_max]
     pub amount_max: u64,
     /// Time when the pool starts accepting investments into [crate::state::Pool::account_collection]
     pub time_start: UnixTimestamp,
     /// Time when the pool stops accepting investments (and starts token distribution by allowing claiming purchased account_distribution tokens).
     pub time_finish: UnixTimestamp,
-    /// KYC requirement
-    pub kyc_requirement: KycRequirement,
-    /// stages non overlapped time
-    pub time_table: [UnixTimeSmallDuration; crate::STAGES_ACTIVE_COUNT],
+    /// KYC requirement, `None` inherits [PoolDefaults::kyc_requirement] or is not required if market has no defaults
+    pub kyc_requirement: Option<KycRequirement>,
+    /// stages non overlapped time, only first `stages_count` are used, `None` inherits [PoolDefaults::time_table] and [PoolDefaults::stages_count]
+    pub time_table: Option<[UnixTimeSmallDuration; crate::STAGES_ACTIVE_COUNT]>,
+    /// number of active stages before final stage, at most [crate::STAGES_ACTIVE_COUNT], ignored if `time_table` is `None`
+    pub stages_count: u8,
+    /// Whitelist tokens minted per [Instruction::AddToWhitelist], zero means [crate::state::WHITELIST_TOKEN_AMOUNT]
+    pub whitelist_ticket_amount: u64,
+    /// Maximum total amount of [crate::state::Pool::account_collection] accepted from one user, zero is unlimited
+    pub per_user_max: u64,
+    /// Seconds after `time_finish` users of failed pool may refund before owner sweeps collected tokens, zero means [crate::REFUND_GRACE_PERIOD]
+    pub refund_grace_period: UnixTimeSmallDuration,
+    /// If set, pool and its accounts must be created by market owner with seeds of [crate::utils::program::pool_account_seed] of this nonce,
+    /// so clients can precompute pool addresses before creation. `None` accepts any addresses.
+    pub nonce: Option<u64>,
 }
 
 impl InitializePool {
+    /// fills unset fields from market defaults, explicit values are kept
+    pub fn inherit(&mut self, defaults: &PoolDefaults) {
+        if !defaults.is_set {
+            return;
+        }
+        if self.amount_min == 0 {
+            self.amount_min = defaults.amount_investment_min;
+        }
+        if self.amount_max == 0 {
+            self.amount_max = defaults.amount_investment_max;
+        }
+        if self.kyc_requirement.is_none() {
+            self.kyc_requirement = Some(defaults.kyc_requirement);
+        }
+        if self.time_table.is_none() {
+            self.time_table = Some(defaults.time_table);
+            self.stages_count = defaults.stages_count;
+        }
+    }
+
     /// validates
     pub fn validate(&self, clock: &Clock) -> ProgramResult {
+        self.validate_at(clock.unix_timestamp)
+    }
+
+    /// validates at `now`, see [Self::validate]
+    pub fn validate_at(&self, now: UnixTimestamp) -> ProgramResult {
         if self.goal_min == 0 || self.goal_max == 0 || self.goal_min > self.goal_max {
             return Err(Error::InvalidGoalNumbers.into());
         }
@@ -52,14 +94,28 @@ impl InitializePool {
             return Err(Error::InvalidGoalNumbers.into());
         }
 
-        if self.time_start < clock.unix_timestamp
-            || self.time_finish < clock.unix_timestamp
-            || self.time_start > self.time_finish
-        {
-            return Err(Error::InvalidPoolTimeFrame.into());
+        if self.per_user_max != 0 && self.per_user_max < self.amount_min {
+            return Err(Error::InvalidInvestmentSizeNumbers.into());
+        }
+
+        let timeframe = Timeframe {
+            start: self.time_start,
+            finish: self.time_finish,
+        };
+        timeframe.validate(now)?;
+
+        let stages_count = self.stages_count as usize;
+        let time_table = self.time_table.ok_or(Error::InvalidTimeTable)?;
+        if stages_count > crate::STAGES_ACTIVE_COUNT {
+            return Err(Error::InvalidTimeTable.into());
         }
 
-        if self.time_table.iter().sum::<u32>() as i64 > self.time_finish - self.time_start {
+        if time_table[..stages_count]
+            .iter()
+            .map(|x| *x as i64)
+            .sum::<i64>()
+            > timeframe.duration()
+        {
             return Err(Error::InvalidTimeTable.into());
         }
 
@@ -68,21 +124,53 @@ impl InitializePool {
 }
 
 /// input
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
 pub struct Participate {
     /// value holding the amount of collected tokens to transfer to the pool
     pub amount: CollectionToken,
+    /// whitelist tokens to burn for whitelist pool, zero means [crate::state::WHITELIST_TOKEN_AMOUNT]
+    pub whitelist_tickets: u64,
+    /// fails if stage caps and market fee reduce amount credited to pool below this value, zero accepts any
+    pub min_accepted: CollectionToken,
+    /// records participation into `participation_receipt`, see [crate::state::ParticipationReceipt]
+    pub receipt: bool,
+}
+
+/// accounts to create associated `account_to` on demand in [claim] and [withdraw]
+#[derive(PartialEq, Debug, Clone)]
+pub struct CreateAccountTo {
+    /// pays for created account, must sign
+    pub payer: Pubkey,
+    /// owner of associated token account
+    pub wallet: Pubkey,
+    /// mint of tokens to be received
+    pub mint: Pubkey,
+}
+
+impl CreateAccountTo {
+    fn push_accounts(&self, accounts: &mut Vec<AccountMeta>) {
+        accounts.push(AccountMeta::new(self.payer, true));
+        accounts.push(AccountMeta::new_readonly(self.wallet, false));
+        accounts.push(AccountMeta::new_readonly(self.mint, false));
+        accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+        accounts.push(AccountMeta::new_readonly(sysvar::rent::id(), false));
+        accounts.push(AccountMeta::new_readonly(
+            spl_associated_token_account::id(),
+            false,
+        ));
+    }
 }
 
 /// input
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
 pub struct InitializeMarket {
     /// reference to stake pool
     pub stake_pool: Pubkey,
 }
 
 /// Instruction definition
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+#[non_exhaustive]
 pub enum Instruction {
     /// Initializes new market account and sets up its owner account.
     ///
@@ -96,7 +184,7 @@ pub enum Instruction {
     /// Creates all the required accounts, you just need to supply derived account public keys as parameters seed by `market` `pool` and .
     ///
     /// Accounts:
-    /// - *read*                    `market`                  Market account this pool will belong to
+    /// - *write*                   `market`                  Market account this pool will belong to, counts initialized pools
     /// - *write*                   `pool`                    New pool account to initialize
     /// - *read, signer, payer*     `market_owner`            Market owner account, has to sign this transaction. Also pays for accounts creation
     /// - *read*                    `mint_collection`         Mint for the tokens to be collected into the pool (users will be paying with these tokens)
@@ -114,6 +202,13 @@ pub enum Instruction {
 
     /// Issued by the user participating in the pool tokensale. Only allowed for the pool after their start time, but before the finish time.
     ///
+    /// User participates at most once per stage. `user_pool_stage` is created with the current stage index as seed,
+    /// so second participation in the same stage fails with [Error::AccountAlreadyParticipatedOnThisStage],
+    /// while each next stage has own marker and accepts one more participation.
+    /// `user_pool` is required, it counts the user in [crate::state::Pool::participant_count] once and tracks user total over all stages,
+    /// participation exceeding non zero [crate::state::Pool::per_user_max] fails.
+    /// If [Participate::receipt] is set, `participation_receipt` accumulates collected, distributed owed and tier of the user.
+    ///
     /// Accounts:
     ///                             
     // - *read*             `market`
@@ -126,16 +221,22 @@ pub enum Instruction {
     // - *write*            `user_account_to`                 Token account to receive back pool tokens (which can be later exchanged for the distributed tokens)
     // - *read*             `pool_lock_account`               Token account with `user_wallet` owner
     // - *write*            `mint_pool`                       Pool mint account, will mint new tokens to the previous account
-    ///- *read, derived*    `market_user_kyc`                 If pool is [KycRequirement::NotRequired] than this MUST be account holding [crate::state::MarketUserKyc], else it should be `user_wallet`
+    ///- *read, derived*    `market_user_kyc`                 If pool [KycRequirement::is_required] than this MUST be account holding [crate::state::MarketUserKyc] (of required provider if any), else it should be `user_wallet`
     ///- *read*             `pool_lock`                       [staking::state::PoolLock] owned `user_wallet`
     ///- *read*             `stake_pool`                      [staking::state::StakePool] aligned to `market`
-    ///- *write, derived*   `user_pool_stage`                 Marker account forcing one time participation of `user_wallet` per stage
+    ///- *write, derived*   `user_pool_stage`                 Marker account forcing one time participation of `user_wallet` per stage, derived from `pool_user_authority` with stage index seed
     // - *read*             `_token_program_id`               Used to call transfer and mint for the collected and pool tokens
     // - *read, system*     `_system_program`                 Used to initialize accounts
     // - *read, system*     `rent`                            Used to check if pool is currently active
     // - *read, system*     `clock`                           Used to check if pool is currently active
-    // - *write, option*    `account_whitelist`               Token account holding whitelist tokens, if the pool is whitelist-only a single token will be burned by this instruction. You need to issue approval for the signing authority to burn this 1 token
+    // - *write, option*    `account_whitelist`               Token account holding whitelist tokens, if the pool is whitelist-only `whitelist_tickets` tokens will be burned by this instruction
     // - *write, option*    `account_mint_whitelist`          Again, only for whitelist pools, the mint which will be burning user's whitelist tokens (the same as the pool's whitelist mint)
+    ///- *write, derived*   `user_pool`                       [crate::state::UserPool] of `user_wallet`, derived from `pool_user_authority` with [crate::USER_POOL_SEED], created on first participation, found by its address, missing fails with [Error::WrongUserPool]
+    ///- *write, option*    `participation_receipt`           [crate::state::ParticipationReceipt] of `user_wallet`, derived from `pool_user_authority` with [crate::PARTICIPATION_RECEIPT_SEED], created on first participation with receipt, found by its address
+    // - *write, option*    `account_fee`                     Market [crate::state::Market::fee_account], last account, required only if market fee is enabled
+    //
+    // Whitelist accounts supplied to pool without whitelist fail with [Error::WhitelistNotEnabled].
+    // Market fee is transferred from `user_account_from` to `account_fee`, pool is credited with accepted amount without fee.
     Participate(Participate),
 
     /// Claims purchased distribution tokens after the pool finish time (if [crate::state::Pool::goal_min] is reached) or refunds collected tokens (if not).
@@ -143,7 +244,7 @@ pub enum Instruction {
     /// Accounts:           
     ///                        
     /// - *read*            `market`                    
-    /// - *read*            `pool`                  Finished pool account to collect funds from
+    /// - *write*           `pool`                  Finished pool account to collect funds from, tracks amount still owed to users
     /// - *read*            `pool_authority`        Pool authority, used to control pool token accounts and mints
     /// - *write*           `account_from`          User token account holding pool tokens (received after pool participation), will be burned by this action
     /// - *read, signer*    `user_authority`        Single-use user authority approved for burning tokens from the previous account
@@ -152,6 +253,12 @@ pub enum Instruction {
     /// - *write*           `account_to`            User account to receive claimed tokens (just as with the previous account can either be collected or distributed token account)    
     /// - *read*            `_token_program_id`     used for burning pool tokens and transfers
     /// - *read, system*    `clock`                 used to check if the pool is finished collecting funds    
+    /// - *write, signer, option* `payer`           Pays for `account_to` if it is associated token account not created yet
+    /// - *read, option*    `wallet`                Owner of associated `account_to`
+    /// - *read, option*    `mint`                  Mint of associated `account_to`
+    /// - *read, option*    `_system_program`       Used to create `account_to`
+    /// - *read, option*    `rent`                  Used to create `account_to`
+    /// - *read, option*    `_associated_token_program` Used to create `account_to`
     Claim,
 
     /// Called by the pool owner before the pool starts to add particular users to the pool whitelist.
@@ -159,13 +266,15 @@ pub enum Instruction {
     /// - *read*             `pool`                 Pool account
     /// - *read, signer*     `pool_authority`       Pool authority account controlling whitelist mint account
     /// - *write*            `pool_owner`           Pool owner account, should sign this instruction
-    /// - *write*            `account_whitelist`    User account to receive a new minted whitelist token
+    /// - *write*            `account_whitelist`    User account to receive [crate::state::Pool::whitelist_ticket_amount] of new minted whitelist tokens
     /// - *write*            `mint_whitelist`       Pool whitelist mint account, which will mint the whitelist token to the account above
     /// - *read*            `_token_program_id`     used for burning pool tokens and transfers    
     AddToWhitelist,
 
     /// Called by the pool owner after the pool is over to collect the user investments (in collected tokens) and leftover distributed tokens.
     /// Or if the pool failed to reach its [crate::state::Pool::goal_min] returns all of the distribution tokens.
+    /// After [crate::state::Pool::refund_deadline] of failed pool collected tokens not refunded to users are withdrawn too.
+    /// `account_to` must be owned by pool owner, see [Instruction::WithdrawTo] to skip the check.
     ///
     /// Accounts:
     ///
@@ -177,6 +286,12 @@ pub enum Instruction {
     /// - *write*          `account_to`       Pool owner's token account to receive tokens from the previous account (either collected or distributed token)
     /// - *read*           `_token_program`   Used to transfer tokens
     /// - *read, system*   `clock`            used to check if pool sale is over
+    /// - *write, signer, option* `payer`     Pays for `account_to` if it is associated token account not created yet
+    /// - *read, option*   `wallet`           Owner of associated `account_to`
+    /// - *read, option*   `mint`             Mint of associated `account_to`
+    /// - *read, option*   `_system_program`  Used to create `account_to`
+    /// - *read, option*   `rent`             Used to create `account_to`
+    /// - *read, option*   `_associated_token_program` Used to create `account_to`
     Withdraw,
 
     ///  Creates new account to store market user KYC data
@@ -190,6 +305,8 @@ pub enum Instruction {
     /// - *read, system*           `rent`                  New account will be rent exempt
     /// - *read, system*           `clock`                 Must provide KYC which actual for some time
     /// - *read, system*           `_system_program`       Implicitly used to create account
+    /// - *read, signer, optional* `kyc_provider`          Provider which approved KYC, see [KycRequirement::SpecificProvider].
+    ///                                                    KYC created without it has [crate::state::DEFAULT_KYC_PROVIDER_KEY] provider
     CreateMarketUserKyc(CreateMarketUserKyc),
 
     /// Transfers all SOLs from `user_kyc` to `market_owner` so account is deleted.
@@ -204,6 +321,9 @@ pub enum Instruction {
     DeleteMarketUserKyc,
 
     /// Starts pool.
+    /// Extends active window of `stake_pool` till pool finish, so users can not unlock during sale, window is never shortened.
+    /// Tier allocations are set from `stake_pool` tier users, counted by time weighted average of locks if stake pool has window set.
+    /// Pool is started once, second start fails with [Error::PoolAlreadyStarted].
     ///
     /// Accounts:
     /// - *read*            `market`                    Market to start pool at
@@ -213,16 +333,251 @@ pub enum Instruction {
     /// - *write*           `pool`                      Pool to start.
     /// - *read, system*    `clock`                     Used to check time start and  finish
     /// - *read*            `_staking_program`          Implicitly used for CPI
+    /// - *read, optional*  `account_distribution`      If provided, must hold [crate::state::Pool::max_distribution] or start fails
     StartPool,
+
+    /// Upgrades market or pool account written by older program version to current layout.
+    /// Account large enough for current layout is upgraded in place.
+    /// Smaller account is copied to new account of current size, which replaces it, and is closed with lamports sent to `owner`.
+    ///
+    /// Accounts:
+    /// - *write*           `account`                   Market or pool to migrate
+    /// - *read, signer*    `owner`                     Owner of market or pool
+    ///
+    /// Accounts to upgrade market of older layout in place, it did not count its pools:
+    /// - *read, many*      `pools`                     All pools of market, each once, [crate::state::Market::pool_count] is set to their number
+    ///
+    /// Accounts to copy market, records derived from old market key, like KYC, must be created again for new market:
+    /// - *write*           `new_market`                Uninitialized rent exempt account of [crate::state::Market::LEN] owned by program
+    /// - *read, derived*   `market_authority`          Derived from `account`, moves stake pool IDO authority to one of `new_market`
+    /// - *read, system*    `rent`
+    /// - *write*           `stake_pool`                Stake pool of market
+    /// - *read*            `_staking_program`          Implicitly used for CPI
+    /// - *write, many*     `pools`                     Pools of market of current layout to point to `new_market`, pools not passed stay with closed market
+    ///
+    /// Accounts to copy pool, only pool nobody participated in can be copied:
+    /// - *write*           `new_pool`                  Uninitialized rent exempt account of [crate::state::Pool::LEN] owned by program
+    /// - *read, derived*   `pool_authority`            Derived from `account`, moves authority of pool token accounts and mints to one of `new_pool`
+    /// - *read, system*    `rent`
+    /// - *write*           `account_collection`
+    /// - *write*           `account_distribution`
+    /// - *write*           `mint_pool`
+    /// - *read*            `token_program`
+    /// - *write, optional* `mint_whitelist`            Required if pool has whitelist
+    Migrate(MigrateAccount),
+
+    /// Updates expiration of existing market user KYC in place.
+    /// KYC created before [crate::state::MarketUserKyc::provider] was added is too small, it must be deleted and created again.
+    ///
+    /// Accounts:
+    /// - *read*                   `market`
+    /// - *read*                   `market_user_authority`  Derived from `market` and 'user_wallet'
+    /// - *write, derived*         `market_user_kyc`        Initialized KYC of `user_wallet` in `market`
+    /// - *read, signer*           `market_owner`           Owner of `market`
+    /// - *read*                   `user_wallet`            Related KYC related `user_wallet`
+    /// - *read, system*           `clock`                  New expiration must be in future
+    RenewMarketUserKyc(RenewMarketUserKyc),
+
+    /// Called by the pool owner after the pool is over to top up distribution tokens and mark pool as funded.
+    /// Fails if `account_distribution` holds less than [crate::state::Pool::amount_to_distribute], users can claim only from funded pool.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`                   Pool after the sale is over
+    /// - *read, signer*           `pool_owner`             Pool owner, authority of `account_from`
+    /// - *write*                  `account_distribution`   Pool distribution token account
+    /// - *read*                   `_token_program`         Used to transfer tokens
+    /// - *read, system*           `clock`                  Used to check if pool sale is over
+    /// - *write, option*          `account_from`           Pool owner token account to transfer distribution tokens from, required if amount is not zero
+    FundDistribution(FundDistribution),
+
+    /// Same as [Instruction::Withdraw] for both pool collection and distribution accounts in one instruction.
+    /// Tokens reserved for users (to claim or refund) stay on pool accounts, nothing is transferred from account with nothing to withdraw.
+    /// Both destination accounts must be owned by pool owner, see [Instruction::WithdrawAllTo] to skip the check.
+    ///
+    /// Accounts:
+    ///
+    /// - *read*           `market`
+    /// - *read*           `pool`                     Pool account after the sale is over
+    /// - *read*           `pool_authority`           Authority
+    /// - *read, signer*   `pool_owner`               Pool owner account, should sign this instruction
+    /// - *write*          `account_collection`       Pool collection token account
+    /// - *write*          `account_distribution`     Pool distribution token account
+    /// - *write*          `account_to_collection`    Pool owner's token account to receive collected tokens
+    /// - *write*          `account_to_distribution`  Pool owner's token account to receive distribution tokens
+    /// - *read*           `_token_program`           Used to transfer tokens
+    /// - *read, system*   `clock`                    Used to check if pool sale is over
+    WithdrawAll,
+
+    /// Sets pool defaults of market, inherited by [Instruction::InitializePool] for unset input fields.
+    /// Defaults with [PoolDefaults::is_set] false are cleared.
+    ///
+    /// Accounts:
+    /// - *write*          `market`         Market to update
+    /// - *read, signer*   `market_owner`   Owner of `market`
+    SetMarketDefaults(PoolDefaults),
+
+    /// Same as [Instruction::AddToWhitelist] for up to [crate::WHITELIST_ACCOUNTS_MAX] users at once.
+    ///
+    /// Accounts:
+    /// - *read*           `pool`                 Pool account
+    /// - *read*           `pool_authority`       Pool authority controlling whitelist mint
+    /// - *read, signer*   `pool_owner`           Pool owner account
+    /// - *write*          `mint_whitelist`       Pool whitelist mint
+    /// - *read*           `_token_program`       Used to mint whitelist tokens
+    /// - *write*          `accounts_whitelist`   Remaining accounts, whitelist token accounts each receiving [crate::state::Pool::whitelist_ticket_amount]
+    AddManyToWhitelist,
+
+    /// Sets fee market takes from each participation in its pools, zero [SetMarketFee::fee_basis_points] disables fee.
+    /// Fails while any pool of market is not finished and [crate::state::Pool::has_participants].
+    ///
+    /// Accounts:
+    /// - *write*          `market`         Market to update
+    /// - *read, signer*   `market_owner`   Owner of `market`
+    /// - *read*           `clock`          Clock sysvar
+    /// - *read*           `pools`          Remaining accounts, all pools of `market`, each once
+    SetMarketFee(SetMarketFee),
+
+    /// Recounts tier users of market stake pool from its locks, signed by market authority.
+    /// Locks are counted in batches sorted by key, see [sol_starter_staking::instruction::Instruction::RecomputeTierUsersBatch].
+    ///
+    /// Accounts:
+    /// - *read*            `market`                        Market of `stake_pool`
+    /// - *read, signer*    `market_owner`                  Owner of `market`
+    /// - *read, derived*   `market_authority`              Used to sign recount CPI, derived from `market`
+    /// - *write*           `stake_pool`                    Stake pool of `market`
+    /// - *read, system*    `clock`                         Used to refresh locks
+    /// - *read*            `_staking_program`              Implicitly used for CPI
+    /// - *write*           `pool_lock`                     Remaining accounts, pairs of lock of `stake_pool` in ascending order of keys
+    /// - *read*            `pool_lock_token_account_xsos`  and its xSOS token account
+    RecomputeTierUsers(RecomputeTierUsers),
+
+    /// Pauses or resumes new stakes of market stake pool, signed by market authority.
+    /// See [sol_starter_staking::instruction::Instruction::SetStakesPaused].
+    ///
+    /// Accounts:
+    /// - *read*            `market`              Market of `stake_pool`
+    /// - *read, signer*    `market_owner`        Owner of `market`
+    /// - *read, derived*   `market_authority`    Used to sign pause CPI, derived from `market`
+    /// - *write*           `stake_pool`          Stake pool of `market`
+    /// - *read*            `_staking_program`    Implicitly used for CPI
+    SetStakesPaused(SetStakesPaused),
+
+    /// Hands market stake pool over to other IDO authority, signed by market authority.
+    /// See [sol_starter_staking::instruction::Instruction::SetIdoAuthority], market can not control stake pool after it.
+    ///
+    /// Accounts:
+    /// - *read*            `market`              Market of `stake_pool`
+    /// - *read, signer*    `market_owner`        Owner of `market`
+    /// - *read, derived*   `market_authority`    Used to sign rotation CPI, derived from `market`
+    /// - *write*           `stake_pool`          Stake pool of `market`
+    /// - *read*            `_staking_program`    Implicitly used for CPI
+    SetStakePoolIdoAuthority(SetStakePoolIdoAuthority),
+
+    /// Same as [Instruction::Withdraw], with [Withdraw::skip_owner_check] set `account_to` may be of any owner.
+    ///
+    /// Accounts: same as [Instruction::Withdraw]
+    WithdrawTo(Withdraw),
+
+    /// Same as [Instruction::WithdrawAll], with [Withdraw::skip_owner_check] set destination accounts may be of any owner.
+    ///
+    /// Accounts: same as [Instruction::WithdrawAll]
+    WithdrawAllTo(Withdraw),
+}
+
+/// kind of account to migrate
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone, Copy)]
+pub enum MigrateAccount {
+    /// [crate::state::Market]
+    Market,
+    /// [crate::state::Pool]
+    Pool,
 }
 
 /// instruction input
-#[derive(BorshSerialize, BorshDeserialize, PartialEq, Debug, Clone)]
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
 pub struct CreateMarketUserKyc {
     /// expiration of wallet
     pub expiration: UnixTimestamp,
 }
 
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct RenewMarketUserKyc {
+    /// new expiration of wallet
+    pub expiration: UnixTimestamp,
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct FundDistribution {
+    /// distribution tokens to transfer from `account_from` before check, zero only checks
+    pub amount: u64,
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct Withdraw {
+    /// allows `account_to` of any owner, e.g. to sweep to treasury
+    pub skip_owner_check: bool,
+}
+
+impl Withdraw {
+    /// input of [Instruction::Withdraw] and [Instruction::WithdrawAll]
+    pub const CHECKED: Withdraw = Withdraw {
+        skip_owner_check: false,
+    };
+
+    fn instruction(self, all: bool) -> Instruction {
+        match (self == Withdraw::CHECKED, all) {
+            (true, false) => Instruction::Withdraw,
+            (true, true) => Instruction::WithdrawAll,
+            (false, false) => Instruction::WithdrawTo(self),
+            (false, true) => Instruction::WithdrawAllTo(self),
+        }
+    }
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct SetMarketFee {
+    /// see [crate::state::Market::fee_basis_points], at most [crate::state::MARKET_FEE_MAX_BASIS_POINTS]
+    pub fee_basis_points: u16,
+    /// see [crate::state::Market::fee_account]
+    pub fee_account: Pubkey,
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct RecomputeTierUsers {
+    /// see [sol_starter_staking::instruction::RecomputeTierUsersInput::first]
+    pub first: bool,
+    /// see [sol_starter_staking::instruction::RecomputeTierUsersInput::last]
+    pub last: bool,
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct SetStakesPaused {
+    /// see [sol_starter_staking::instruction::SetStakesPausedInput::stakes_paused]
+    pub stakes_paused: bool,
+}
+
+/// instruction input
+#[derive(BorshSerialize, BorshDeserialize, BorshSchema, PartialEq, Debug, Clone)]
+pub struct SetStakePoolIdoAuthority {
+    /// see [sol_starter_staking::instruction::SetIdoAuthorityInput::ido_authority]
+    pub ido_authority: Pubkey,
+}
+
+/// Accounts of `InitializeMarket` instruction
+pub fn initialize_market_accounts(market: &Pubkey, market_owner: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*market_owner, true),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ]
+}
+
 /// Create `InitializeMarket` instruction
 pub fn initialize_market(
     program_id: &ProgramPubkey,
@@ -232,11 +587,7 @@ pub fn initialize_market(
 ) -> Result<SolanaInstruction, ProgramError> {
     let data = Instruction::InitializeMarket(input);
 
-    let accounts = vec![
-        AccountMeta::new(*market, false),
-        AccountMeta::new_readonly(*market_owner, true),
-        AccountMeta::new_readonly(sysvar::rent::id(), false),
-    ];
+    let accounts = initialize_market_accounts(market, market_owner);
 
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
@@ -245,9 +596,9 @@ pub fn initialize_market(
     ))
 }
 
-/// Create `InitializePool` instruction
+/// Accounts of `InitializePool` instruction
 #[allow(clippy::too_many_arguments)]
-pub fn initialize_pool(
+pub fn initialize_pool_accounts(
     program_id: &ProgramPubkey,
     pool: &Pubkey,
     market: &Pubkey,
@@ -258,14 +609,11 @@ pub fn initialize_pool(
     account_distribution: &Pubkey,
     mint_pool: &Pubkey,
     mint_whitelist: Option<Pubkey>,
-    input: InitializePool,
-) -> Result<SolanaInstruction, ProgramError> {
-    let data = Instruction::InitializePool(input);
-
+) -> Vec<AccountMeta> {
     let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
 
     let mut accounts = vec![
-        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new(*market, false),
         AccountMeta::new(*pool, false),
         AccountMeta::new_readonly(*market_owner, true),
         AccountMeta::new_readonly(*mint_collection, false),
@@ -284,6 +632,39 @@ pub fn initialize_pool(
         accounts.push(AccountMeta::new(mint_whitelist, false));
     }
 
+    accounts
+}
+
+/// Create `InitializePool` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn initialize_pool(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    mint_collection: &Pubkey,
+    mint_distribution: &Pubkey,
+    account_collection: &Pubkey,
+    account_distribution: &Pubkey,
+    mint_pool: &Pubkey,
+    mint_whitelist: Option<Pubkey>,
+    input: InitializePool,
+) -> Result<SolanaInstruction, ProgramError> {
+    let data = Instruction::InitializePool(input);
+
+    let accounts = initialize_pool_accounts(
+        program_id,
+        pool,
+        market,
+        market_owner,
+        mint_collection,
+        mint_distribution,
+        account_collection,
+        account_distribution,
+        mint_pool,
+        mint_whitelist,
+    );
+
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
         &data,
@@ -291,9 +672,9 @@ pub fn initialize_pool(
     ))
 }
 
-/// Create `Participate` instruction
+/// Accounts of `Participate` instruction, `stage` is index of current stage (see [crate::state::Pool::get_current_stage_index])
 #[allow(clippy::too_many_arguments)]
-pub fn participate(
+pub fn participate_accounts(
     program_id: &ProgramPubkey,
     pool: &Pubkey,
     market: &Pubkey,
@@ -308,20 +689,31 @@ pub fn participate(
     market_user_kyc: Option<&Pubkey>,
     account_whitelist: Option<&Pubkey>,
     mint_whitelist: Option<&Pubkey>,
-    input: Participate,
+    account_fee: Option<&Pubkey>,
     stage: u8,
-) -> Result<SolanaInstruction, ProgramError> {
-    let data = Instruction::Participate(input);
-
+) -> Result<Vec<AccountMeta>, ProgramError> {
     let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
 
-    let (pool_user_authority, _) = Pubkey::find_2key_program_address(pool, user_wallet, program_id);
+    let pool_user_authority = PoolUserAuthority::derive(pool, user_wallet, program_id).key;
 
-    let user_pool_stage = Pubkey::create_with_seed(&pool_user_authority,
+    let user_pool_stage = Pubkey::create_with_seed(
+        &pool_user_authority,
         format!("{}", stage).as_str(),
         &program_id.pubkey(),
     )?;
 
+    let user_pool = Pubkey::create_with_seed(
+        &pool_user_authority,
+        crate::USER_POOL_SEED,
+        &program_id.pubkey(),
+    )?;
+
+    let participation_receipt = Pubkey::create_with_seed(
+        &pool_user_authority,
+        crate::PARTICIPATION_RECEIPT_SEED,
+        &program_id.pubkey(),
+    )?;
+
     let market_user_kyc_or_user_wallet = market_user_kyc.unwrap_or(user_wallet);
 
     let mut accounts = vec![
@@ -353,6 +745,58 @@ pub fn participate(
         accounts.push(AccountMeta::new(*mint_whitelist, false))
     }
 
+    accounts.push(AccountMeta::new(user_pool, false));
+    accounts.push(AccountMeta::new(participation_receipt, false));
+
+    if let Some(account_fee) = account_fee {
+        accounts.push(AccountMeta::new(*account_fee, false))
+    }
+
+    Ok(accounts)
+}
+
+/// Create `Participate` instruction, `stage` is index of current stage (see [crate::state::Pool::get_current_stage_index])
+#[allow(clippy::too_many_arguments)]
+pub fn participate(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    market: &Pubkey,
+    user_wallet: &Pubkey,
+    user_account_from: &Pubkey,
+    account_collection: &Pubkey,
+    user_account_to: &Pubkey,
+    pool_lock_account: &Pubkey,
+    mint_pool: &Pubkey,
+    pool_lock: &Pubkey,
+    stake_pool: &Pubkey,
+    market_user_kyc: Option<&Pubkey>,
+    account_whitelist: Option<&Pubkey>,
+    mint_whitelist: Option<&Pubkey>,
+    account_fee: Option<&Pubkey>,
+    input: Participate,
+    stage: u8,
+) -> Result<SolanaInstruction, ProgramError> {
+    let data = Instruction::Participate(input);
+
+    let accounts = participate_accounts(
+        program_id,
+        pool,
+        market,
+        user_wallet,
+        user_account_from,
+        account_collection,
+        user_account_to,
+        pool_lock_account,
+        mint_pool,
+        pool_lock,
+        stake_pool,
+        market_user_kyc,
+        account_whitelist,
+        mint_whitelist,
+        account_fee,
+        stage,
+    )?;
+
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
         &data,
@@ -360,9 +804,9 @@ pub fn participate(
     ))
 }
 
-/// Create `Claim` instruction
+/// Accounts of `Claim` instruction
 #[allow(clippy::too_many_arguments)]
-pub fn claim(
+pub fn claim_accounts(
     program_id: &ProgramPubkey,
     pool: &Pubkey,
     market: &Pubkey,
@@ -371,12 +815,13 @@ pub fn claim(
     mint_pool: &Pubkey,
     account_pool: &Pubkey,
     account_to: &Pubkey,
-) -> Result<SolanaInstruction, ProgramError> {
+    create_account_to: Option<&CreateAccountTo>,
+) -> Vec<AccountMeta> {
     let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*market, false),
-        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new(*pool, false),
         AccountMeta::new_readonly(pool_authority, false),
         AccountMeta::new(*account_from, false),
         AccountMeta::new_readonly(*user_authority, true),
@@ -386,6 +831,36 @@ pub fn claim(
         AccountMeta::new(spl_token::id(), false),
         AccountMeta::new_readonly(sysvar::clock::id(), false),
     ];
+    if let Some(create_account_to) = create_account_to {
+        create_account_to.push_accounts(&mut accounts);
+    }
+    accounts
+}
+
+/// Create `Claim` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn claim(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    market: &Pubkey,
+    account_from: &Pubkey,
+    user_authority: &Pubkey,
+    mint_pool: &Pubkey,
+    account_pool: &Pubkey,
+    account_to: &Pubkey,
+    create_account_to: Option<&CreateAccountTo>,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = claim_accounts(
+        program_id,
+        pool,
+        market,
+        account_from,
+        user_authority,
+        mint_pool,
+        account_pool,
+        account_to,
+        create_account_to,
+    );
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
         &Instruction::Claim,
@@ -393,6 +868,27 @@ pub fn claim(
     ))
 }
 
+/// Accounts of `AddToWhitelist` instruction
+pub fn add_to_whitelist_accounts(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    account_whitelist: &Pubkey,
+    mint_whitelist: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
+
+    vec![
+        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new_readonly(pool_authority, false),
+        AccountMeta::new_readonly(*pool_owner, true),
+        AccountMeta::new(*account_whitelist, false),
+        AccountMeta::new(*mint_whitelist, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]
+}
+
 /// Create `AddToWhitelist` instruction
 pub fn add_to_whitelist(
     program_id: &ProgramPubkey,
@@ -403,41 +899,85 @@ pub fn add_to_whitelist(
 ) -> Result<SolanaInstruction, ProgramError> {
     let input = Instruction::AddToWhitelist;
 
+    let accounts = add_to_whitelist_accounts(
+        program_id,
+        pool,
+        pool_owner,
+        account_whitelist,
+        mint_whitelist,
+    );
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &input,
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::AddManyToWhitelist] instruction, count of `accounts_whitelist` is checked by [add_many_to_whitelist]
+pub fn add_many_to_whitelist_accounts(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    accounts_whitelist: &[Pubkey],
+    mint_whitelist: &Pubkey,
+) -> Vec<AccountMeta> {
     let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*pool, false),
         AccountMeta::new_readonly(pool_authority, false),
         AccountMeta::new_readonly(*pool_owner, true),
-        AccountMeta::new(*account_whitelist, false),
         AccountMeta::new(*mint_whitelist, false),
         AccountMeta::new_readonly(spl_token::id(), false),
-        AccountMeta::new_readonly(sysvar::clock::id(), false),
     ];
+    accounts.extend(
+        accounts_whitelist
+            .iter()
+            .map(|account_whitelist| AccountMeta::new(*account_whitelist, false)),
+    );
+    accounts
+}
+
+/// Create [Instruction::AddManyToWhitelist] instruction
+pub fn add_many_to_whitelist(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    accounts_whitelist: &[Pubkey],
+    mint_whitelist: &Pubkey,
+) -> Result<SolanaInstruction, ProgramError> {
+    if accounts_whitelist.is_empty() || accounts_whitelist.len() > crate::WHITELIST_ACCOUNTS_MAX {
+        return Err(Error::WrongWhitelistAccountsCount.into());
+    }
+
+    let accounts = add_many_to_whitelist_accounts(
+        program_id,
+        pool,
+        pool_owner,
+        accounts_whitelist,
+        mint_whitelist,
+    );
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
-        &input,
+        &Instruction::AddManyToWhitelist,
         accounts,
     ))
 }
 
-/// Create `Withdraw` instruction
-pub fn withdraw(
+/// Accounts of `Withdraw` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_accounts(
     program_id: &ProgramPubkey,
     pool: &Pubkey,
     market: &Pubkey,
     pool_owner: &Pubkey,
     account_from: &Pubkey,
     account_to: &Pubkey,
-) -> Result<SolanaInstruction, ProgramError> {
-    let init_data = Instruction::Withdraw;
-    let data = init_data
-        .try_to_vec()
-        .or(Err(ProgramError::InvalidArgument))?;
-
+    create_account_to: Option<&CreateAccountTo>,
+) -> Vec<AccountMeta> {
     let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*market, false),
         AccountMeta::new_readonly(*pool, false),
         AccountMeta::new_readonly(pool_authority, false),
@@ -447,26 +987,117 @@ pub fn withdraw(
         AccountMeta::new(spl_token::id(), false),
         AccountMeta::new_readonly(sysvar::clock::id(), false),
     ];
-    Ok(SolanaInstruction {
-        program_id: program_id.pubkey(),
-        accounts,
-        data,
-    })
+    if let Some(create_account_to) = create_account_to {
+        create_account_to.push_accounts(&mut accounts);
+    }
+    accounts
 }
 
-/// Create [CreateMarketUserKyc] instruction
-pub fn create_market_user_kyc(
+/// Create `Withdraw` instruction
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
     market: &Pubkey,
-    market_owner: &Pubkey,
-    user_wallet: &Pubkey,
-    input: CreateMarketUserKyc,
+    pool_owner: &Pubkey,
+    account_from: &Pubkey,
+    account_to: &Pubkey,
+    create_account_to: Option<&CreateAccountTo>,
+    input: Withdraw,
+) -> Result<SolanaInstruction, ProgramError> {
+    // checked withdraw is encoded as before input was added
+    let init_data = input.instruction(false);
+    let data = init_data
+        .try_to_vec()
+        .or(Err(ProgramError::InvalidArgument))?;
+
+    let accounts = withdraw_accounts(
+        program_id,
+        pool,
+        market,
+        pool_owner,
+        account_from,
+        account_to,
+        create_account_to,
+    );
+    Ok(SolanaInstruction {
+        program_id: program_id.pubkey(),
+        accounts,
+        data,
+    })
+}
+
+/// Accounts of [Instruction::WithdrawAll] instruction
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_all_accounts(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    market: &Pubkey,
+    pool_owner: &Pubkey,
+    account_collection: &Pubkey,
+    account_distribution: &Pubkey,
+    account_to_collection: &Pubkey,
+    account_to_distribution: &Pubkey,
+) -> Vec<AccountMeta> {
+    let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
+
+    vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new_readonly(pool_authority, false),
+        AccountMeta::new_readonly(*pool_owner, true),
+        AccountMeta::new(*account_collection, false),
+        AccountMeta::new(*account_distribution, false),
+        AccountMeta::new(*account_to_collection, false),
+        AccountMeta::new(*account_to_distribution, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ]
+}
+
+/// Create [Instruction::WithdrawAll] instruction
+#[allow(clippy::too_many_arguments)]
+pub fn withdraw_all(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    market: &Pubkey,
+    pool_owner: &Pubkey,
+    account_collection: &Pubkey,
+    account_distribution: &Pubkey,
+    account_to_collection: &Pubkey,
+    account_to_distribution: &Pubkey,
+    input: Withdraw,
 ) -> Result<SolanaInstruction, ProgramError> {
-    let (market_user_authority_key, _) =
-        Pubkey::find_2key_program_address(&market, &user_wallet, &crate::program_id());
+    let accounts = withdraw_all_accounts(
+        program_id,
+        pool,
+        market,
+        pool_owner,
+        account_collection,
+        account_distribution,
+        account_to_collection,
+        account_to_distribution,
+    );
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &input.instruction(true),
+        accounts,
+    ))
+}
+
+/// Accounts of [CreateMarketUserKyc] instruction
+pub fn create_market_user_kyc_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    user_wallet: &Pubkey,
+    kyc_provider: Option<&Pubkey>,
+) -> Result<Vec<AccountMeta>, ProgramError> {
+    let market_user_authority_key =
+        MarketUserAuthority::derive(market, user_wallet, &crate::program_id()).key;
     let market_user_kyc =
         Pubkey::create_with_seed(&market_user_authority_key, crate::KYC_SEED, &crate::id())?;
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*market, false),
         AccountMeta::new_readonly(market_user_authority_key, false),
         AccountMeta::new(market_user_kyc, false),
@@ -476,35 +1107,59 @@ pub fn create_market_user_kyc(
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(system_program::id(), false),
     ];
-Ok(
-    SolanaInstruction::new_with_borsh(
+    if let Some(kyc_provider) = kyc_provider {
+        accounts.push(AccountMeta::new_readonly(*kyc_provider, true));
+    }
+    Ok(accounts)
+}
+
+/// Create [CreateMarketUserKyc] instruction
+pub fn create_market_user_kyc(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    user_wallet: &Pubkey,
+    kyc_provider: Option<&Pubkey>,
+    input: CreateMarketUserKyc,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts =
+        create_market_user_kyc_accounts(market, market_owner, user_wallet, kyc_provider)?;
+    Ok(SolanaInstruction::new_with_borsh(
         crate::program_id().pubkey(),
         &Instruction::CreateMarketUserKyc(input),
         accounts,
     ))
 }
 
-/// Create [DeleteMarketUserKyc] instruction
-pub fn delete_market_user_kyc(
-    program_id: &ProgramPubkey,
+/// Accounts of [DeleteMarketUserKyc] instruction
+pub fn delete_market_user_kyc_accounts(
     market: &Pubkey,
     market_owner: &Pubkey,
     user_wallet: &Pubkey,
-) -> Result<SolanaInstruction, ProgramError> {
-    let (market_user_authority_key, _) =
-        Pubkey::find_2key_program_address(&market, &user_wallet, &crate::program_id());
+) -> Result<Vec<AccountMeta>, ProgramError> {
+    let market_user_authority_key =
+        MarketUserAuthority::derive(market, user_wallet, &crate::program_id()).key;
 
     let market_user_kyc =
         Pubkey::create_with_seed(&market_user_authority_key, crate::KYC_SEED, &crate::id())?;
 
-    let accounts = vec![
+    Ok(vec![
         AccountMeta::new_readonly(*market, false),
         AccountMeta::new_readonly(market_user_authority_key, false),
         AccountMeta::new(market_user_kyc, false),
         AccountMeta::new_readonly(*market_owner, true),
         AccountMeta::new_readonly(*user_wallet, false),
         AccountMeta::new_readonly(system_program::id(), false),
-    ];
+    ])
+}
+
+/// Create [DeleteMarketUserKyc] instruction
+pub fn delete_market_user_kyc(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    user_wallet: &Pubkey,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = delete_market_user_kyc_accounts(market, market_owner, user_wallet)?;
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
         &Instruction::DeleteMarketUserKyc,
@@ -512,16 +1167,58 @@ pub fn delete_market_user_kyc(
     ))
 }
 
-/// Create [StartPool] instruction
-pub fn start_pool(
+/// Accounts of [Instruction::RenewMarketUserKyc] instruction
+pub fn renew_market_user_kyc_accounts(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    user_wallet: &Pubkey,
+) -> Result<Vec<AccountMeta>, ProgramError> {
+    let market_user_authority_key =
+        MarketUserAuthority::derive(market, user_wallet, program_id).key;
+
+    let market_user_kyc = Pubkey::create_with_seed(
+        &market_user_authority_key,
+        crate::KYC_SEED,
+        &program_id.pubkey(),
+    )?;
+
+    Ok(vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(market_user_authority_key, false),
+        AccountMeta::new(market_user_kyc, false),
+        AccountMeta::new_readonly(*market_owner, true),
+        AccountMeta::new_readonly(*user_wallet, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ])
+}
+
+/// Create [Instruction::RenewMarketUserKyc] instruction
+pub fn renew_market_user_kyc(
     program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    user_wallet: &Pubkey,
+    input: RenewMarketUserKyc,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = renew_market_user_kyc_accounts(program_id, market, market_owner, user_wallet)?;
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::RenewMarketUserKyc(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [StartPool] instruction
+pub fn start_pool_accounts(
     market_or_pool_owner: &Pubkey,
     stake_pool: &Pubkey,
     market: &Pubkey,
     pool: &Pubkey,
-) -> Result<SolanaInstruction, ProgramError> {
+    require_funded: Option<&Pubkey>,
+) -> Vec<AccountMeta> {
     let market_authority = Pubkey::find_key_program_address(market, &crate::program_id()).0;
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new_readonly(*market, false),
         AccountMeta::new_readonly(*market_or_pool_owner, true),
         AccountMeta::new(*stake_pool, false),
@@ -530,9 +1227,513 @@ pub fn start_pool(
         AccountMeta::new_readonly(sysvar::clock::id(), false),
         AccountMeta::new_readonly(sol_starter_staking::id(), false),
     ];
+    if let Some(account_distribution) = require_funded {
+        accounts.push(AccountMeta::new_readonly(*account_distribution, false));
+    }
+    accounts
+}
+
+/// Create [StartPool] instruction.
+/// `require_funded` is pool distribution account, start fails unless it covers max distribution.
+pub fn start_pool(
+    program_id: &ProgramPubkey,
+    market_or_pool_owner: &Pubkey,
+    stake_pool: &Pubkey,
+    market: &Pubkey,
+    pool: &Pubkey,
+    require_funded: Option<&Pubkey>,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = start_pool_accounts(
+        market_or_pool_owner,
+        stake_pool,
+        market,
+        pool,
+        require_funded,
+    );
     Ok(SolanaInstruction::new_with_borsh(
         program_id.pubkey(),
         &Instruction::StartPool,
         accounts,
     ))
 }
+
+/// Accounts of [Instruction::Migrate] instruction
+pub fn migrate_accounts(account: &Pubkey, owner: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*account, false),
+        AccountMeta::new_readonly(*owner, true),
+    ]
+}
+
+/// Create [Instruction::Migrate] instruction
+pub fn migrate(
+    program_id: &ProgramPubkey,
+    account: &Pubkey,
+    owner: &Pubkey,
+    input: MigrateAccount,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = migrate_accounts(account, owner);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::Migrate(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::Migrate] instruction upgrading `market` of older layout in place
+pub fn migrate_market_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    pools: &[Pubkey],
+) -> Vec<AccountMeta> {
+    let mut accounts = migrate_accounts(market, market_owner);
+    accounts.extend(
+        pools
+            .iter()
+            .map(|pool| AccountMeta::new_readonly(*pool, false)),
+    );
+    accounts
+}
+
+/// Create [Instruction::Migrate] instruction upgrading `market` of older layout in place
+pub fn migrate_market(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    pools: &[Pubkey],
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = migrate_market_accounts(market, market_owner, pools);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::Migrate(MigrateAccount::Market),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::Migrate] instruction copying `market` of older layout to `new_market`
+pub fn migrate_market_to_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    new_market: &Pubkey,
+    stake_pool: &Pubkey,
+    pools: &[Pubkey],
+) -> Vec<AccountMeta> {
+    let market_authority = Pubkey::find_key_program_address(market, &crate::program_id()).0;
+    let mut accounts = migrate_accounts(market, market_owner);
+    accounts.extend_from_slice(&[
+        AccountMeta::new(*new_market, false),
+        AccountMeta::new_readonly(market_authority, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new_readonly(sol_starter_staking::id(), false),
+    ]);
+    accounts.extend(pools.iter().map(|pool| AccountMeta::new(*pool, false)));
+    accounts
+}
+
+/// Create [Instruction::Migrate] instruction copying `market` of older layout to `new_market`
+pub fn migrate_market_to(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    new_market: &Pubkey,
+    stake_pool: &Pubkey,
+    pools: &[Pubkey],
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = migrate_market_to_accounts(market, market_owner, new_market, stake_pool, pools);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::Migrate(MigrateAccount::Market),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::Migrate] instruction copying `pool` of older layout to `new_pool`
+#[allow(clippy::too_many_arguments)]
+pub fn migrate_pool_to_accounts(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    new_pool: &Pubkey,
+    account_collection: &Pubkey,
+    account_distribution: &Pubkey,
+    mint_pool: &Pubkey,
+    mint_whitelist: Option<&Pubkey>,
+) -> Vec<AccountMeta> {
+    let (pool_authority, _) = Pubkey::find_key_program_address(pool, program_id);
+    let mut accounts = migrate_accounts(pool, pool_owner);
+    accounts.extend_from_slice(&[
+        AccountMeta::new(*new_pool, false),
+        AccountMeta::new_readonly(pool_authority, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(*account_collection, false),
+        AccountMeta::new(*account_distribution, false),
+        AccountMeta::new(*mint_pool, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ]);
+    if let Some(mint_whitelist) = mint_whitelist {
+        accounts.push(AccountMeta::new(*mint_whitelist, false));
+    }
+    accounts
+}
+
+/// Create [Instruction::Migrate] instruction copying `pool` of older layout to `new_pool`
+#[allow(clippy::too_many_arguments)]
+pub fn migrate_pool_to(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    new_pool: &Pubkey,
+    account_collection: &Pubkey,
+    account_distribution: &Pubkey,
+    mint_pool: &Pubkey,
+    mint_whitelist: Option<&Pubkey>,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = migrate_pool_to_accounts(
+        program_id,
+        pool,
+        pool_owner,
+        new_pool,
+        account_collection,
+        account_distribution,
+        mint_pool,
+        mint_whitelist,
+    );
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::Migrate(MigrateAccount::Pool),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::FundDistribution] instruction
+pub fn fund_distribution_accounts(
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    account_distribution: &Pubkey,
+    account_from: Option<&Pubkey>,
+) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*pool_owner, true),
+        AccountMeta::new(*account_distribution, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    if let Some(account_from) = account_from {
+        accounts.push(AccountMeta::new(*account_from, false));
+    }
+    accounts
+}
+
+/// Create [Instruction::FundDistribution] instruction
+pub fn fund_distribution(
+    program_id: &ProgramPubkey,
+    pool: &Pubkey,
+    pool_owner: &Pubkey,
+    account_distribution: &Pubkey,
+    account_from: Option<&Pubkey>,
+    input: FundDistribution,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = fund_distribution_accounts(pool, pool_owner, account_distribution, account_from);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::FundDistribution(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::SetMarketDefaults] instruction
+pub fn set_market_defaults_accounts(market: &Pubkey, market_owner: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*market_owner, true),
+    ]
+}
+
+/// Create [Instruction::SetMarketDefaults] instruction
+pub fn set_market_defaults(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    input: PoolDefaults,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = set_market_defaults_accounts(market, market_owner);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::SetMarketDefaults(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::SetMarketFee] instruction
+pub fn set_market_fee_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    pools: &[Pubkey],
+) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new(*market, false),
+        AccountMeta::new_readonly(*market_owner, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    accounts.extend(
+        pools
+            .iter()
+            .map(|pool| AccountMeta::new_readonly(*pool, false)),
+    );
+    accounts
+}
+
+/// Create [Instruction::SetMarketFee] instruction
+pub fn set_market_fee(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    pools: &[Pubkey],
+    input: SetMarketFee,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = set_market_fee_accounts(market, market_owner, pools);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::SetMarketFee(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::RecomputeTierUsers] instruction, `locks` are pairs of lock and its xSOS token account
+pub fn recompute_tier_users_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    stake_pool: &Pubkey,
+    locks: &[(Pubkey, Pubkey)],
+) -> Vec<AccountMeta> {
+    let market_authority = Pubkey::find_key_program_address(market, &crate::program_id()).0;
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(*market_owner, true),
+        AccountMeta::new_readonly(market_authority, false),
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(sol_starter_staking::id(), false),
+    ];
+    for (pool_lock, pool_lock_token_account_xsos) in locks {
+        accounts.push(AccountMeta::new(*pool_lock, false));
+        accounts.push(AccountMeta::new_readonly(
+            *pool_lock_token_account_xsos,
+            false,
+        ));
+    }
+    accounts
+}
+
+/// Create [Instruction::RecomputeTierUsers] instruction, `locks` must be sorted by lock key
+pub fn recompute_tier_users(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    stake_pool: &Pubkey,
+    locks: &[(Pubkey, Pubkey)],
+    input: RecomputeTierUsers,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = recompute_tier_users_accounts(market, market_owner, stake_pool, locks);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::RecomputeTierUsers(input),
+        accounts,
+    ))
+}
+
+/// Accounts of [Instruction::SetStakesPaused] and [Instruction::SetStakePoolIdoAuthority] instructions
+pub fn set_stakes_paused_accounts(
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    stake_pool: &Pubkey,
+) -> Vec<AccountMeta> {
+    let market_authority = Pubkey::find_key_program_address(market, &crate::program_id()).0;
+    vec![
+        AccountMeta::new_readonly(*market, false),
+        AccountMeta::new_readonly(*market_owner, true),
+        AccountMeta::new_readonly(market_authority, false),
+        AccountMeta::new(*stake_pool, false),
+        AccountMeta::new_readonly(sol_starter_staking::id(), false),
+    ]
+}
+
+/// Create [Instruction::SetStakesPaused] instruction
+pub fn set_stakes_paused(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    stake_pool: &Pubkey,
+    input: SetStakesPaused,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = set_stakes_paused_accounts(market, market_owner, stake_pool);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::SetStakesPaused(input),
+        accounts,
+    ))
+}
+
+/// Create [Instruction::SetStakePoolIdoAuthority] instruction
+pub fn set_stake_pool_ido_authority(
+    program_id: &ProgramPubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    stake_pool: &Pubkey,
+    input: SetStakePoolIdoAuthority,
+) -> Result<SolanaInstruction, ProgramError> {
+    let accounts = set_stakes_paused_accounts(market, market_owner, stake_pool);
+    Ok(SolanaInstruction::new_with_borsh(
+        program_id.pubkey(),
+        &Instruction::SetStakePoolIdoAuthority(input),
+        accounts,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn initialize_pool() -> InitializePool {
+        let mut time_table = [0; crate::STAGES_ACTIVE_COUNT];
+        time_table[0] = 10;
+        time_table[1] = 20;
+        InitializePool {
+            pool_owner: Pubkey::new_unique(),
+            price: 1_000_000_000,
+            goal_max: 1_000,
+            goal_min: 100,
+            amount_min: 10,
+            amount_max: 100,
+            time_start: 100,
+            time_finish: 130,
+            kyc_requirement: None,
+            time_table: Some(time_table),
+            stages_count: 2,
+            whitelist_ticket_amount: 0,
+            per_user_max: 0,
+            refund_grace_period: 0,
+            nonce: None,
+        }
+    }
+
+    #[test]
+    fn initialize_pool_validate_at() {
+        let input = initialize_pool();
+        assert_eq!(input.validate_at(input.time_start), Ok(()));
+        assert_eq!(
+            input.validate_at(input.time_start + 1),
+            Err(Error::InvalidPoolTimeFrame.into())
+        );
+        assert_eq!(
+            input.validate(&Clock {
+                unix_timestamp: input.time_start + 1,
+                ..Clock::default()
+            }),
+            input.validate_at(input.time_start + 1)
+        );
+
+        // stages fill whole sale exactly
+        let short = InitializePool {
+            time_finish: 129,
+            ..initialize_pool()
+        };
+        assert_eq!(
+            short.validate_at(short.time_start),
+            Err(Error::InvalidTimeTable.into())
+        );
+        let unused_stages = InitializePool {
+            stages_count: 1,
+            ..short.clone()
+        };
+        assert_eq!(unused_stages.validate_at(unused_stages.time_start), Ok(()));
+        let too_many_stages = InitializePool {
+            stages_count: crate::STAGES_ACTIVE_COUNT as u8 + 1,
+            ..initialize_pool()
+        };
+        assert_eq!(
+            too_many_stages.validate_at(too_many_stages.time_start),
+            Err(Error::InvalidTimeTable.into())
+        );
+        let reversed = InitializePool {
+            time_finish: 99,
+            ..initialize_pool()
+        };
+        assert_eq!(
+            reversed.validate_at(0),
+            Err(Error::InvalidPoolTimeFrame.into())
+        );
+
+        let goals = InitializePool {
+            goal_min: 1_001,
+            ..initialize_pool()
+        };
+        assert_eq!(
+            goals.validate_at(goals.time_start),
+            Err(Error::InvalidGoalNumbers.into())
+        );
+        let per_user_max = InitializePool {
+            per_user_max: 9,
+            ..initialize_pool()
+        };
+        assert_eq!(
+            per_user_max.validate_at(per_user_max.time_start),
+            Err(Error::InvalidInvestmentSizeNumbers.into())
+        );
+    }
+
+    #[test]
+    fn participate_accounts_match_instruction() {
+        let keys: Vec<_> = (0..13).map(|_| Pubkey::new_unique()).collect();
+        let accounts = participate_accounts(
+            &crate::program_id(),
+            &keys[0],
+            &keys[1],
+            &keys[2],
+            &keys[3],
+            &keys[4],
+            &keys[5],
+            &keys[6],
+            &keys[7],
+            &keys[8],
+            &keys[9],
+            Some(&keys[10]),
+            Some(&keys[11]),
+            None,
+            Some(&keys[12]),
+            1,
+        )
+        .unwrap();
+        let instruction = participate(
+            &crate::program_id(),
+            &keys[0],
+            &keys[1],
+            &keys[2],
+            &keys[3],
+            &keys[4],
+            &keys[5],
+            &keys[6],
+            &keys[7],
+            &keys[8],
+            &keys[9],
+            Some(&keys[10]),
+            Some(&keys[11]),
+            None,
+            Some(&keys[12]),
+            Participate {
+                amount: 1,
+                whitelist_tickets: 0,
+                min_accepted: 0,
+                receipt: false,
+            },
+            1,
+        )
+        .unwrap();
+        assert_eq!(instruction.accounts, accounts);
+        assert_eq!(accounts.len(), 22);
+        assert!(accounts[4].is_signer && accounts[4].is_writable);
+        assert_eq!(accounts[21].pubkey, keys[12]);
+    }
+}
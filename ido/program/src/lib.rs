@@ -3,15 +3,19 @@
 
 //! SolStarter program
 
+mod accounts;
 pub mod error;
 pub mod instruction;
 pub mod processor;
+#[cfg(feature = "client")]
+pub mod sdk;
 pub mod state;
 pub mod utils;
 
 use sol_starter_staking::program::ProgramPubkey;
 pub use utils::invoke;
 pub use utils::math;
+pub use utils::prelude;
 
 /// Current program version
 pub const PROGRAM_VERSION: u8 = 1;
@@ -19,8 +23,17 @@ pub const PROGRAM_VERSION: u8 = 1;
 /// tiers count
 pub const TIERS_COUNT: usize = 4;
 
-/// in use
-pub const STAGES_ACTIVE_COUNT: usize = 2;
+/// max number of active stages before [state::Stage::FinalStage]
+pub const STAGES_ACTIVE_COUNT: usize = 4;
+
+/// active stages of pools created before stages count was configurable
+pub const STAGES_ACTIVE_COUNT_V2: usize = 2;
+
+/// max whitelist token accounts minted to by one [instruction::Instruction::AddManyToWhitelist]
+pub const WHITELIST_ACCOUNTS_MAX: usize = 20;
+
+/// default seconds after [state::Pool::time_finish] users of failed pool have to refund before owner may sweep collected tokens
+pub const REFUND_GRACE_PERIOD: i64 = 30 * 24 * 60 * 60;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
@@ -33,6 +46,23 @@ solana_program::declare_id!("FY4Vb99dAuPa4ujpFBYzaHJYx9zaYgNJxnoe4FkoPbcA");
 /// Seed for the accounts holding KYC information
 pub const KYC_SEED: &str = "kyc";
 
+/// Seed for the accounts holding user totals in pool, see [state::UserPool]
+pub const USER_POOL_SEED: &str = "user";
+
+/// Seed for the accounts recording user participations in pool, see [state::ParticipationReceipt]
+pub const PARTICIPATION_RECEIPT_SEED: &str = "receipt";
+
+/// Kind of pool state account derived by [utils::program::pool_account_seed]
+pub const POOL_SEED: &str = "pool";
+/// Kind of [state::Pool::account_collection] derived by [utils::program::pool_account_seed]
+pub const POOL_COLLECTION_SEED: &str = "collection";
+/// Kind of [state::Pool::account_distribution] derived by [utils::program::pool_account_seed]
+pub const POOL_DISTRIBUTION_SEED: &str = "distribution";
+/// Kind of [state::Pool::mint_pool] derived by [utils::program::pool_account_seed]
+pub const POOL_MINT_SEED: &str = "mint";
+/// Kind of [state::Pool::mint_whitelist] derived by [utils::program::pool_account_seed]
+pub const POOL_WHITELIST_SEED: &str = "whitelist";
+
 /// marker type for collection token amount
 type CollectionToken = u64;
 
@@ -1,19 +1,29 @@
 //! Program state processor
 
 use crate::{
+    accounts::*,
     error::Error,
     instruction::{
-        CreateMarketUserKyc, InitializeMarket, InitializePool, Instruction, Participate,
+        CreateMarketUserKyc, FundDistribution, InitializeMarket, InitializePool, Instruction,
+        MigrateAccount, Participate, RecomputeTierUsers, RenewMarketUserKyc, SetMarketFee,
+        SetStakePoolIdoAuthority, SetStakesPaused, Withdraw,
     },
     state::*,
-    utils::{invoke::*, math::*, program::AccountPatterns},
+    utils::{
+        invoke::*,
+        math::*,
+        program::{derive_pool_account, AccountPatterns},
+    },
 };
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshSerialize;
 use num_traits::ToPrimitive;
 use sol_starter_staking::{
-    instruction::StartPoolInput,
+    borsh::{try_from_slice_exact, try_instruction_from_slice},
+    instruction::{
+        ExtendPoolActiveInput, RecomputeTierUsersInput, SetIdoAuthorityInput, SetStakesPausedInput,
+    },
     program::{
-        create_account_with_seed_signed, ProgramPubkey,
+        create_account_with_seed_signed, MarketUserAuthority, PoolUserAuthority, ProgramPubkey,
         PubkeyPatterns,
     },
     state::{PoolLock, StakePool},
@@ -21,8 +31,10 @@ use sol_starter_staking::{
 use solana_program::{
     account_info::AccountInfo,
     entrypoint::ProgramResult,
+    instruction::Instruction as SolanaInstruction,
     msg,
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
     system_instruction::SystemError,
@@ -30,24 +42,10 @@ use solana_program::{
     sysvar::rent::Rent,
     sysvar::Sysvar,
 };
-use spl_token::state::{Account, Mint};
-
-/// checks that program is owner of system account
-macro_rules! is_owner {
-    (
-        $program_id:expr,
-        $($account:expr),+
-    )
-    => {
-        {
-            $(
-                if *$account.owner != $program_id.pubkey() {
-                    return Err(ProgramError::IncorrectProgramId);
-                }
-            )+
-        }
-    }
-}
+use spl_token::{
+    instruction::AuthorityType,
+    state::{Account, Mint},
+};
 
 /// Program state handler.
 pub struct Processor {}
@@ -60,15 +58,16 @@ impl Processor {
         rent: &AccountInfo,
         input: &InitializeMarket,
     ) -> ProgramResult {
-        is_owner!(&program_id, market);
+        market.owned_by(program_id)?;
         let rent = &Rent::from_account_info(rent)?;
-        let mut market_state = Market::try_from_slice(&market.data.borrow()).unwrap();
+        let mut market_state = try_from_slice_exact::<Market>(&market.data.borrow(), Market::LEN)?;
         market_state.uninitialized()?;
         market_owner.is_signer()?;
         if !rent.is_exempt(market.lamports(), market.data_len()) {
             return Err(ProgramError::AccountNotRentExempt);
         }
 
+        market_state.account_type = ACCOUNT_TYPE_MARKET;
         market_state.version = MARKET_VERSION;
         market_state.owner = *market_owner.key;
         market_state.stake_pool = input.stake_pool;
@@ -98,19 +97,22 @@ impl Processor {
         mint_whitelist: Option<&'b AccountInfo<'a>>,
         input: &InitializePool,
     ) -> ProgramResult {
-        is_owner!(&program_id, pool, market);
+        pool.owned_by(program_id)?;
+        market.owned_by(program_id)?;
         let rent_state = &Rent::from_account_info(rent)?;
         let clock = &Clock::from_account_info(clock)?;
-        input.validate(clock)?;
 
-        let mut pool_state = Pool::try_from_slice(&pool.data.borrow())?;
+        let mut pool_state = try_from_slice_exact::<Pool>(&pool.data.borrow(), Pool::LEN)?;
         pool_state.uninitialized()?;
 
         if !rent_state.is_exempt(pool.lamports(), pool.data_len()) {
             return Err(ProgramError::AccountNotRentExempt);
         }
 
-        validate_market_owner(market, market_owner)?;
+        let mut market_state = validate_market_owner(market, market_owner)?;
+        let mut input = input.clone();
+        input.inherit(&market_state.pool_defaults);
+        input.validate(clock)?;
 
         if mint_collection.key == mint_distribution.key {
             return Err(Error::WrongTokenMint.into());
@@ -124,8 +126,37 @@ impl Processor {
         if !mint_distribution_state.is_initialized() {
             return Err(ProgramError::UninitializedAccount);
         }
+        Pool::validate_decimals(
+            mint_collection_state.decimals,
+            mint_distribution_state.decimals,
+        )?;
+
+        if let Some(nonce) = input.nonce {
+            let derived = [
+                (pool, crate::POOL_SEED, program_id.pubkey()),
+                (
+                    account_collection,
+                    crate::POOL_COLLECTION_SEED,
+                    spl_token::id(),
+                ),
+                (
+                    account_distribution,
+                    crate::POOL_DISTRIBUTION_SEED,
+                    spl_token::id(),
+                ),
+                (mint_pool, crate::POOL_MINT_SEED, spl_token::id()),
+            ];
+            let whitelist = mint_whitelist.map(|mint_whitelist| {
+                (mint_whitelist, crate::POOL_WHITELIST_SEED, spl_token::id())
+            });
+            for (account, kind, owner) in derived.iter().chain(whitelist.iter()) {
+                let key = derive_pool_account(market_owner.key, market.key, nonce, kind, owner)?;
+                same_key(key, account, Error::WrongProgramAddress)?;
+            }
+        }
 
-        let (_, authority_bump_seed) = Pubkey::find_key_program_address(pool.key, program_id);
+        let (authority, authority_bump_seed) =
+            Pubkey::find_key_program_address(pool.key, program_id);
 
         initialize_token_account(
             account_collection.clone(),
@@ -149,19 +180,34 @@ impl Processor {
         )?;
 
         pool_state.mint_whitelist = if let Some(mint_whitelist) = mint_whitelist {
-            initialize_mint(
-                mint_whitelist.clone(),
-                pool_authority.clone(),
-                0,
-                rent.clone(),
-            )?;
+            let mint_whitelist_state = Mint::unpack_unchecked(&mint_whitelist.data.borrow())?;
+            if mint_whitelist_state.is_initialized() {
+                // tickets are burned whole and minted only by pool, so external mint must be alike
+                if mint_whitelist_state.decimals != 0
+                    || mint_whitelist_state.supply != 0
+                    || mint_whitelist_state.mint_authority != COption::Some(authority)
+                    || (mint_whitelist_state.freeze_authority.is_some()
+                        && mint_whitelist_state.freeze_authority != COption::Some(authority))
+                {
+                    return Err(Error::WhitelistMintInvalid.into());
+                }
+            } else {
+                initialize_mint(
+                    mint_whitelist.clone(),
+                    pool_authority.clone(),
+                    0,
+                    rent.clone(),
+                )?;
+            }
             MintWhitelist::Key(*mint_whitelist.key)
         } else {
             MintWhitelist::None(DEFAULT_WHITELIST_KEY)
         };
 
+        pool_state.account_type = ACCOUNT_TYPE_POOL;
         pool_state.version = POOL_VERSION;
         pool_state.market = *market.key;
+        pool_state.stake_pool = market_state.stake_pool;
         pool_state.account_collection = *account_collection.key;
         pool_state.account_distribution = *account_distribution.key;
         pool_state.mint_pool = mint_pool.pubkey();
@@ -175,11 +221,25 @@ impl Processor {
         pool_state.owner = input.pool_owner;
         pool_state.authority = *pool_authority.key;
         pool_state.authority_bump_seed = authority_bump_seed;
-        pool_state.kyc_requirement = input.kyc_requirement;
-        pool_state.time_table[..crate::STAGES_ACTIVE_COUNT].copy_from_slice(&input.time_table);
+        pool_state.kyc_requirement = input.kyc_requirement.unwrap_or_default();
+        pool_state.time_table = input.time_table.unwrap_or_default();
+        pool_state.stages_count = input.stages_count;
+        pool_state.whitelist_ticket_amount = if input.whitelist_ticket_amount == 0 {
+            WHITELIST_TOKEN_AMOUNT
+        } else {
+            input.whitelist_ticket_amount
+        };
+        pool_state.per_user_max = input.per_user_max;
+        pool_state.set_refund_deadline(input.refund_grace_period);
 
         pool_state.serialize(&mut *pool.data.borrow_mut())?;
 
+        // market account created for older layout has no room for counter
+        if market.data_len() >= Market::LEN {
+            market_state.pool_count = market_state.pool_count.error_increment()?;
+            market_state.serialize(&mut *market.data.borrow_mut())?;
+        }
+
         Ok(())
     }
 
@@ -205,30 +265,50 @@ impl Processor {
         _system_program: &AccountInfo<'a>,
         rent: &AccountInfo<'a>,
         clock: &AccountInfo<'a>,
-        account_whitelist: Option<&'b AccountInfo<'a>>,
-        account_mint_whitelist: Option<&'b AccountInfo<'a>>,
+        optional_accounts: &'b [AccountInfo<'a>],
         input: Participate,
     ) -> ProgramResult {
-        is_owner!(&program_id, pool, market);
-        
+        if input.amount == 0 {
+            return Err(Error::ZeroAmount.into());
+        }
+        pool.owned_by(program_id)?;
+        market.owned_by(program_id)?;
+
         user_wallet.is_signer()?;
         let clock = &Clock::from_account_info(clock)?;
         let rent = &Rent::from_account_info(rent)?;
 
-        let mut pool_state = Pool::try_from_slice(&pool.data.borrow())?;
-        pool_state.was_started(clock.unix_timestamp)?;
+        {
+            let pool_data = pool.data.borrow();
+            let pool_view = PoolView::new(&pool_data)?;
+            pool_view.was_started()?;
+
+            if pool_view.market() != *market.key {
+                return Err(Error::WrongMarketAddressForCurrentPool.into());
+            }
+
+            if *account_collection.key != pool_view.account_collection() {
+                return Err(Error::WrongCollectAccount.into());
+            }
+        }
+        let mut pool_state = Pool::migrate(&pool.data.borrow())?;
 
-        let stage = pool_state.get_current_stage(&clock)?;
+        let stage_index = pool_state.get_current_stage_index(&clock)?;
+        let stage = pool_state.stage_at(stage_index);
 
-        let (user_pool_key, user_pool_bump_seed) = Pubkey::find_2key_program_address(pool.key, user_wallet.key, program_id);
+        let user_pool_authority = PoolUserAuthority::derive(pool.key, user_wallet.key, program_id);
         same_key(
-            user_pool_key,
+            user_pool_authority.key,
             pool_user_authority,
-            Error::WrongUserPoolStage,
+            Error::WrongPoolUserAuthority,
         )?;
 
-        let seed = format!("{}", stage.to_u8().unwrap_or(0));
-        let user_pool_stage_key = Pubkey::create_with_seed(&user_pool_key, seed.as_str(), &program_id.pubkey())?;
+        let seed = format!("{}", stage_index);
+        let user_pool_stage_key = Pubkey::create_with_seed(
+            &user_pool_authority.key,
+            seed.as_str(),
+            &program_id.pubkey(),
+        )?;
 
         same_key(
             user_pool_stage_key,
@@ -236,11 +316,7 @@ impl Processor {
             Error::WrongUserPoolStage,
         )?;
 
-        let signature = &[
-            &pool.key.to_bytes()[..32],
-            &user_wallet.key.to_bytes()[..32],
-            &[user_pool_bump_seed],
-        ];
+        let signer = user_pool_authority.signer();
         create_account_with_seed_signed(
             user_wallet,
             user_pool_stage,
@@ -249,7 +325,7 @@ impl Processor {
             rent.minimum_balance(UserPoolStage::LEN),
             UserPoolStage::LEN as u64,
             program_id,
-            signature,
+            &signer.seeds(),
         )
         .map_err(|x| {
             if x == ProgramError::Custom(SystemError::AccountAlreadyInUse.to_u32().unwrap()) {
@@ -259,29 +335,60 @@ impl Processor {
             }
         })?;
 
-        let market_state = Market::try_from_slice(&market.data.borrow()).unwrap();
-        if market_state.stake_pool != *stake_pool.key {
+        let market_state = Market::migrate(&market.data.borrow())?;
+        // pools migrated from older layouts get stake pool on start, until then market is checked
+        let pool_stake_pool = if pool_state.stake_pool == Pubkey::default() {
+            market_state.stake_pool
+        } else {
+            pool_state.stake_pool
+        };
+        if pool_stake_pool != *stake_pool.key {
             return Err(Error::StakePoolMustBelongToMarket.into());
         }
 
-        if pool_state.kyc_requirement != KycRequirement::NotRequired {
-            is_owner!(&program_id, market_user_kyc);
-            let market_user_kyc = MarketUserKyc::try_from_slice(&market_user_kyc.data.borrow())?;
+        let optional_accounts: Vec<&AccountInfo<'a>> = optional_accounts.iter().collect();
+        // fee account is found by its address, so whitelist accounts keep their positions
+        let (account_fee, optional_accounts) = if market_state.fee_enabled() {
+            let (account_fee, optional_accounts) =
+                optional_account_by_key(&optional_accounts, &market_state.fee_account);
+            (
+                Some(account_fee.ok_or(Error::WrongFeeAccount)?),
+                optional_accounts,
+            )
+        } else {
+            (None, optional_accounts)
+        };
+        let user_pool_key = Pubkey::create_with_seed(
+            &user_pool_authority.key,
+            crate::USER_POOL_SEED,
+            &program_id.pubkey(),
+        )?;
+        let (user_pool, optional_accounts) =
+            optional_account_by_key(&optional_accounts, &user_pool_key);
+        let participation_receipt_key = Pubkey::create_with_seed(
+            &user_pool_authority.key,
+            crate::PARTICIPATION_RECEIPT_SEED,
+            &program_id.pubkey(),
+        )?;
+        let (participation_receipt, optional_accounts) =
+            optional_account_by_key(&optional_accounts, &participation_receipt_key);
+        let account_whitelist = optional_accounts.first().copied();
+        let account_mint_whitelist = optional_accounts.get(1).copied();
+
+        if pool_state.kyc_requirement.is_required() {
+            market_user_kyc.owned_by(program_id)?;
+            let market_user_kyc = MarketUserKyc::migrate(&market_user_kyc.data.borrow())?;
 
-            if market_user_kyc.market != market.pubkey()
-                || market_user_kyc.expiration < clock.unix_timestamp
-                || market_user_kyc.user_wallet != user_wallet.pubkey()
-            {
+            if !market_user_kyc.is_valid_for(market.key, user_wallet.key, clock.unix_timestamp) {
                 return Err(Error::WrongKycCredentials.into());
             }
-        }
 
-        if pool_state.market != *market.key {
-            return Err(Error::WrongMarketAddressForCurrentPool.into());
-        }
-
-        if *account_collection.key != pool_state.account_collection {
-            return Err(Error::WrongCollectAccount.into());
+            if !pool_state
+                .kyc_requirement
+                .accepts(&market_user_kyc.provider)
+            {
+                return Err(Error::WrongKycProvider.into());
+            }
         }
 
         // NOTE: if these are not setup properly, user deposit many times with zero increase to distributed
@@ -304,22 +411,29 @@ impl Processor {
                 if pool_whitelist_mint != *account_mint_whitelist.key {
                     return Err(Error::WhitelistMintInvalid.into());
                 }
+                let whitelist_tickets = if input.whitelist_tickets == 0 {
+                    WHITELIST_TOKEN_AMOUNT
+                } else {
+                    input.whitelist_tickets
+                };
                 burn_tokens_with_user_authority(
                     account_whitelist.clone(),
                     account_mint_whitelist.clone(),
                     user_wallet.clone(),
-                    WHITELIST_TOKEN_AMOUNT as u64,
+                    whitelist_tickets,
                 )?;
             } else {
                 return Err(Error::WhitelistMintMissing.into());
             }
+        } else if account_whitelist.is_some() || account_mint_whitelist.is_some() {
+            return Err(Error::WhitelistNotEnabled.into());
         }
 
         let (amount_collected, tier) = if stage != Stage::FinalStage {
-            is_owner!(&sol_starter_staking::program_id(), pool_lock);
-            let stake_pool_state = StakePool::try_from_slice(&stake_pool.data.borrow())?;
+            pool_lock.owned_by(&sol_starter_staking::program_id())?;
+            let stake_pool_state = StakePool::migrate(&stake_pool.data.borrow())?;
 
-            let pool_lock = PoolLock::try_from_slice(&pool_lock.data.borrow())?;
+            let pool_lock = PoolLock::migrate(&pool_lock.data.borrow())?;
 
             if pool_lock.user_wallet != user_wallet.pubkey() {
                 return Err(Error::LockOwnerMustBeUserWallet.into());
@@ -338,17 +452,96 @@ impl Processor {
                 input.amount,
                 stage,
                 stake_pool_state.tier_balance,
-                pool_lock_account_state.amount,
+                pool_lock.tier_amount(pool_lock_account_state.amount),
             )?
         } else {
             (input.amount, None)
         };
 
+        let fee = market_state.fee(amount_collected)?;
+        let amount_collected = amount_collected.error_sub(fee)?;
+
+        // pool is credited with amount without fee
+        if amount_collected < input.min_accepted {
+            return Err(Error::SlippageExceeded.into());
+        }
+
+        // user is counted once, when its record in pool is created, so every participation must pass it
+        let user_pool = user_pool.ok_or(Error::WrongUserPool)?;
+        let mut user_pool_state = if *user_pool.owner == program_id.pubkey() {
+            try_from_slice_exact::<UserPool>(&user_pool.data.borrow(), UserPool::LEN)?
+        } else {
+            create_account_with_seed_signed(
+                user_wallet,
+                user_pool,
+                pool_user_authority,
+                crate::USER_POOL_SEED,
+                rent.minimum_balance(UserPool::LEN),
+                UserPool::LEN as u64,
+                program_id,
+                &signer.seeds(),
+            )?;
+            pool_state.participant_count = pool_state.participant_count.error_increment()?;
+            UserPool::default()
+        };
+
+        user_pool_state.amount_collected = user_pool_state
+            .amount_collected
+            .error_add(amount_collected)?;
+        if pool_state.per_user_max != 0
+            && user_pool_state.amount_collected > pool_state.per_user_max
+        {
+            return Err(Error::PerUserMaxExceeded.into());
+        }
+        user_pool_state.version = USER_POOL_VERSION;
+        user_pool_state.serialize(&mut *user_pool.data.borrow_mut())?;
+
         pool_state.amount_collected = pool_state.amount_collected.error_add(amount_collected)?;
 
         pool_state.update_distributed_from_collected(amount_collected, tier, stage)?;
 
-        pool_state.serialize(&mut *pool.data.borrow_mut())?;
+        if input.receipt {
+            let participation_receipt =
+                participation_receipt.ok_or(Error::WrongParticipationReceipt)?;
+            let mut receipt_state = if *participation_receipt.owner == program_id.pubkey() {
+                try_from_slice_exact::<ParticipationReceipt>(
+                    &participation_receipt.data.borrow(),
+                    ParticipationReceipt::LEN,
+                )?
+            } else {
+                create_account_with_seed_signed(
+                    user_wallet,
+                    participation_receipt,
+                    pool_user_authority,
+                    crate::PARTICIPATION_RECEIPT_SEED,
+                    rent.minimum_balance(ParticipationReceipt::LEN),
+                    ParticipationReceipt::LEN as u64,
+                    program_id,
+                    &signer.seeds(),
+                )?;
+                ParticipationReceipt::new(pool.pubkey(), user_wallet.pubkey())
+            };
+
+            receipt_state.record(
+                amount_collected,
+                pool_state.collected_to_distributed_ceil(amount_collected)?,
+                tier,
+            )?;
+            receipt_state.serialize(&mut *participation_receipt.data.borrow_mut())?;
+        }
+
+        write_pool(pool, &pool_state)?;
+
+        if let Some(account_fee) = account_fee {
+            if fee > 0 {
+                token_transfer_with_user_authority(
+                    user_account_from.clone(),
+                    account_fee.clone(),
+                    user_wallet.clone(),
+                    fee,
+                )?;
+            }
+        }
 
         token_transfer_with_user_authority(
             user_account_from.clone(),
@@ -381,28 +574,61 @@ impl Processor {
         mint_pool: &AccountInfo<'a>,
         account_pool: &AccountInfo<'a>,
         account_to: &AccountInfo<'a>,
-        _token_program_id: &AccountInfo<'a>,
+        token_program_id: &AccountInfo<'a>,
         clock: &AccountInfo<'a>,
+        create_account_to: Option<&[AccountInfo<'a>]>,
     ) -> ProgramResult {
-        is_owner!(&program_id, pool, market);
+        pool.owned_by(program_id)?;
+        market.owned_by(program_id)?;
         let clock = &Clock::from_account_info(clock)?;
-        
-        let pool_state = Pool::try_from_slice(&pool.data.borrow())?;
-        pool_state.was_started(clock.unix_timestamp)?;
 
-        if pool_state.market != *market.key {
-            return Err(Error::WrongMarketAddressForCurrentPool.into());
-        }
+        let account_from_state = {
+            let pool_data = pool.data.borrow();
+            let pool_view = PoolView::new(&pool_data)?;
+            pool_view.was_started()?;
 
-        if clock.unix_timestamp < pool_state.time_finish {
-            return Err(Error::CantClaimFromActivePool.into());
-        }
+            if pool_view.market() != *market.key {
+                return Err(Error::WrongMarketAddressForCurrentPool.into());
+            }
 
-        if *mint_pool.key != pool_state.mint_pool {
-            return Err(Error::WrongPoolTokenMint.into());
-        }
+            if !pool_view.timeframe().is_finished(clock.unix_timestamp) {
+                return Err(Error::CantClaimFromActivePool.into());
+            }
 
-        let account_from_state = Account::unpack(&account_from.data.borrow())?;
+            if *mint_pool.key != pool_view.mint_pool() {
+                return Err(Error::WrongPoolTokenMint.into());
+            }
+
+            let account_from_state = Account::unpack(&account_from.data.borrow())?;
+            if account_from_state.mint != pool_view.mint_pool() {
+                return Err(Error::WrongPoolTokenMint.into());
+            }
+
+            if pool_view.success() {
+                if *account_pool.key != pool_view.account_distribution() {
+                    return Err(Error::WrongPoolAccountToSendTokensFrom.into());
+                }
+
+                if !pool_view.funded() {
+                    return Err(Error::DistributionNotFunded.into());
+                }
+            } else {
+                if *account_pool.key != pool_view.account_collection() {
+                    return Err(Error::WrongPoolAccountToSendTokensFrom.into());
+                }
+
+                // guards against pool tokens minted over collected, fails before burning them
+                let account_pool_state = Account::unpack(&account_pool.data.borrow())?;
+                if account_from_state.amount > account_pool_state.amount
+                    || account_from_state.amount > pool_view.amount_collected()
+                {
+                    return Err(Error::RefundExceedsCollected.into());
+                }
+            }
+            account_from_state
+        };
+
+        create_account_to_if_missing(account_to, token_program_id, create_account_to)?;
 
         burn_tokens_with_user_authority(
             account_from.clone(),
@@ -411,12 +637,14 @@ impl Processor {
             account_from_state.amount,
         )?;
 
-        if pool_state.amount_collected >= pool_state.goal_min_collected {
-            if *account_pool.key != pool_state.account_distribution {
-                return Err(Error::WrongPoolAccountToSendTokensFrom.into());
-            }
-
+        let mut pool_state = Pool::migrate(&pool.data.borrow())?;
+        if pool_state.success() {
             let distributed = pool_state.collected_to_distributed(account_from_state.amount)?;
+            // reserved by participations rounding up, pools participated before rounded down
+            pool_state.amount_to_distribute =
+                pool_state.amount_to_distribute.saturating_sub(distributed);
+            write_pool(pool, &pool_state)?;
+
             token_transfer(
                 pool.key,
                 account_pool.clone(),
@@ -426,9 +654,11 @@ impl Processor {
                 distributed,
             )?;
         } else {
-            if *account_pool.key != pool_state.account_collection {
-                return Err(Error::WrongPoolAccountToSendTokensFrom.into());
-            }
+            // pool tokens are minted 1:1 to collected, stays below goal so pool remains failed
+            pool_state.amount_collected = pool_state
+                .amount_collected
+                .error_sub(account_from_state.amount)?;
+            write_pool(pool, &pool_state)?;
 
             token_transfer(
                 pool.key,
@@ -453,22 +683,7 @@ impl Processor {
         mint_whitelist: &AccountInfo<'a>,
         _token_program_id: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(&program_id, pool);
-        let pool_state = Pool::try_from_slice(&pool.data.borrow())?;
-        pool_state.initialized()?;
-        pool_owner.is_signer()?;
-
-        if *pool_owner.key != pool_state.owner {
-            return Err(Error::WrongMarketOwner.into());
-        }
-
-        if let MintWhitelist::Key(pool_whitelist_mint) = pool_state.mint_whitelist {
-            if pool_whitelist_mint != *mint_whitelist.key {
-                return Err(Error::WrongTokenMint.into());
-            }
-        } else {
-            return Err(Error::WhitelistMintNotSet.into());
-        }
+        let pool_state = Self::validate_whitelist(program_id, pool, pool_owner, mint_whitelist)?;
 
         token_mint_to(
             pool.key,
@@ -476,73 +691,286 @@ impl Processor {
             account_whitelist.clone(),
             pool_authority.clone(),
             pool_state.authority_bump_seed,
-            WHITELIST_TOKEN_AMOUNT as u64,
+            pool_state.whitelist_ticket_amount,
         )?;
         Ok(())
     }
 
-    /// Process `Withdraw` instruction
-    #[allow(clippy::too_many_arguments)]
-    pub fn withdraw<'a>(
+    /// Process [Instruction::AddManyToWhitelist]
+    pub fn add_many_to_whitelist<'a>(
         program_id: &ProgramPubkey,
-        market: &AccountInfo<'a>,
         pool: &AccountInfo<'a>,
         pool_authority: &AccountInfo<'a>,
         pool_owner: &AccountInfo<'a>,
-        account_from: &AccountInfo<'a>,
-        account_to: &AccountInfo<'a>,
-        _token_program: &AccountInfo<'a>,
-        clock: &AccountInfo<'a>,
+        mint_whitelist: &AccountInfo<'a>,
+        _token_program_id: &AccountInfo<'a>,
+        accounts_whitelist: &[AccountInfo<'a>],
     ) -> ProgramResult {
-        is_owner!(&program_id, pool);
+        if accounts_whitelist.is_empty() || accounts_whitelist.len() > crate::WHITELIST_ACCOUNTS_MAX
+        {
+            return Err(Error::WrongWhitelistAccountsCount.into());
+        }
+        let pool_state = Self::validate_whitelist(program_id, pool, pool_owner, mint_whitelist)?;
+
+        for account_whitelist in accounts_whitelist {
+            let account_whitelist_state = Account::unpack(&account_whitelist.data.borrow())?;
+            if account_whitelist_state.mint != *mint_whitelist.key {
+                return Err(Error::WrongTokenMint.into());
+            }
+        }
+
+        for account_whitelist in accounts_whitelist {
+            token_mint_to(
+                pool.key,
+                mint_whitelist.clone(),
+                account_whitelist.clone(),
+                pool_authority.clone(),
+                pool_state.authority_bump_seed,
+                pool_state.whitelist_ticket_amount,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Checks that `pool_owner` can mint whitelist tokens of `pool` with `mint_whitelist` before anybody participated
+    fn validate_whitelist(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo,
+        pool_owner: &AccountInfo,
+        mint_whitelist: &AccountInfo,
+    ) -> Result<Pool, ProgramError> {
+        pool.owned_by(program_id)?;
+        let pool_state = Pool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
         pool_owner.is_signer()?;
 
-        let clock = &Clock::from_account_info(clock)?;
-        let pool_state = Pool::try_from_slice(&pool.data.borrow())?;
-        pool_state.was_started(clock.unix_timestamp)?;
+        if *pool_owner.key != pool_state.owner {
+            return Err(Error::WrongPoolOwner.into());
+        }
+
+        if let MintWhitelist::Key(pool_whitelist_mint) = pool_state.mint_whitelist {
+            if pool_whitelist_mint != *mint_whitelist.key {
+                return Err(Error::WrongTokenMint.into());
+            }
+        } else {
+            return Err(Error::WhitelistMintNotSet.into());
+        }
+
+        pool_state.terms_mutable()?;
+
+        Ok(pool_state)
+    }
+
+    /// Checks that `pool_owner` can withdraw from finished `pool` of `market`
+    fn validate_withdraw(
+        pool_view: &PoolView,
+        market: &AccountInfo,
+        pool_owner: &AccountInfo,
+        clock: &Clock,
+    ) -> ProgramResult {
+        pool_view.was_started()?;
         {
-            let market_state = Market::try_from_slice(&market.data.borrow()).unwrap();
+            let market_state = Market::migrate(&market.data.borrow())?;
             market_state.initialized()?;
         }
 
-        if pool_state.market != market.pubkey() {
+        if pool_view.market() != market.pubkey() {
             return Err(Error::WrongMarketAddressForCurrentPool.into());
         }
-        if *pool_owner.key != pool_state.owner {
-            return Err(Error::WrongMarketOwner.into());
+        if *pool_owner.key != pool_view.owner() {
+            return Err(Error::WrongPoolOwner.into());
         }
 
-        if clock.unix_timestamp < pool_state.time_finish {
+        if !pool_view.timeframe().is_finished(clock.unix_timestamp) {
             return Err(Error::CantWithdrawFromActivePool.into());
         }
+        Ok(())
+    }
 
+    /// Amount of pool collection or distribution `account_from` not reserved for users
+    /// After [Pool::refund_deadline] of failed pool collected tokens not refunded are swept too.
+    fn withdraw_amount(
+        pool_view: &PoolView,
+        account_from: &AccountInfo,
+        clock: &Clock,
+    ) -> Result<u64, ProgramError> {
         let account_from_state = Account::unpack(&account_from.data.borrow())?;
 
-        let adjustment = match (*account_from.key, pool_state.success()) {
-            (from, true) if from == pool_state.account_collection => Ok(0),
-            (from, true) if from == pool_state.account_distribution => {
-                Ok(pool_state.amount_to_distribute)
+        let adjustment = match (*account_from.key, pool_view.success()) {
+            (from, true) if from == pool_view.account_collection() => Ok(0),
+            (from, true) if from == pool_view.account_distribution() => {
+                Ok(pool_view.amount_to_distribute())
             }
-            (from, false) if from == pool_state.account_collection => {
-                Ok(pool_state.amount_collected)
+            (from, false) if from == pool_view.account_collection() => {
+                if clock.unix_timestamp >= pool_view.refund_deadline() {
+                    msg!(
+                        "Sweeping {} collected tokens not refunded before {}",
+                        account_from_state.amount,
+                        pool_view.refund_deadline()
+                    );
+                    Ok(0)
+                } else {
+                    Ok(pool_view.amount_collected())
+                }
             }
-            (from, false) if from == pool_state.account_distribution => Ok(0),
+            (from, false) if from == pool_view.account_distribution() => Ok(0),
             _ => Err(Error::WrongPoolAccountToSendTokensFrom),
         }?;
 
-        let amount_to_withdraw = account_from_state.amount.error_sub(adjustment)?;
+        Ok(account_from_state
+            .amount
+            .checked_sub(adjustment)
+            .ok_or(Error::NotEnoughTokensReservedForUsers)?)
+    }
+
+    /// Process `Withdraw` instruction
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        pool: &AccountInfo<'a>,
+        pool_authority: &AccountInfo<'a>,
+        pool_owner: &AccountInfo<'a>,
+        account_from: &AccountInfo<'a>,
+        account_to: &AccountInfo<'a>,
+        token_program: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        create_account_to: Option<&[AccountInfo<'a>]>,
+        input: &Withdraw,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        pool_owner.is_signer()?;
+
+        let clock = &Clock::from_account_info(clock)?;
+        let pool_data = pool.data.borrow();
+        let pool_view = PoolView::new(&pool_data)?;
+        Self::validate_withdraw(&pool_view, market, pool_owner, clock)?;
+
+        let amount_to_withdraw = Self::withdraw_amount(&pool_view, account_from, clock)?;
+
+        create_account_to_if_missing(account_to, token_program, create_account_to)?;
+        validate_withdraw_destination(&pool_view, account_to, input)?;
 
         token_transfer(
             pool.key,
             account_from.clone(),
             account_to.clone(),
             pool_authority.clone(),
-            pool_state.authority_bump_seed,
+            pool_view.authority_bump_seed(),
             amount_to_withdraw,
         )?;
         Ok(())
     }
 
+    /// Process [Instruction::WithdrawAll]
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_all<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        pool: &AccountInfo<'a>,
+        pool_authority: &AccountInfo<'a>,
+        pool_owner: &AccountInfo<'a>,
+        account_collection: &AccountInfo<'a>,
+        account_distribution: &AccountInfo<'a>,
+        account_to_collection: &AccountInfo<'a>,
+        account_to_distribution: &AccountInfo<'a>,
+        _token_program: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        input: &Withdraw,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        pool_owner.is_signer()?;
+
+        let clock = &Clock::from_account_info(clock)?;
+        let pool_data = pool.data.borrow();
+        let pool_view = PoolView::new(&pool_data)?;
+        Self::validate_withdraw(&pool_view, market, pool_owner, clock)?;
+
+        same_key(
+            pool_view.account_collection(),
+            account_collection,
+            Error::WrongCollectAccount,
+        )?;
+        same_key(
+            pool_view.account_distribution(),
+            account_distribution,
+            Error::WrongPoolAccountToSendTokensFrom,
+        )?;
+
+        for (account_from, account_to) in [
+            (account_collection, account_to_collection),
+            (account_distribution, account_to_distribution),
+        ]
+        .iter()
+        {
+            validate_withdraw_destination(&pool_view, account_to, input)?;
+            let amount_to_withdraw = Self::withdraw_amount(&pool_view, account_from, clock)?;
+            if amount_to_withdraw > 0 {
+                token_transfer(
+                    pool.key,
+                    (*account_from).clone(),
+                    (*account_to).clone(),
+                    pool_authority.clone(),
+                    pool_view.authority_bump_seed(),
+                    amount_to_withdraw,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Process [Instruction::FundDistribution]
+    #[allow(clippy::too_many_arguments)]
+    pub fn fund_distribution<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        pool_owner: &AccountInfo<'a>,
+        account_distribution: &AccountInfo<'a>,
+        _token_program: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        account_from: Option<&AccountInfo<'a>>,
+        input: &FundDistribution,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        pool_owner.is_signer()?;
+
+        let clock = &Clock::from_account_info(clock)?;
+        let mut pool_state = Pool::migrate(&pool.data.borrow())?;
+        pool_state.was_started()?;
+
+        same_key(pool_state.owner, pool_owner, Error::WrongPoolOwner)?;
+        same_key(
+            pool_state.account_distribution,
+            account_distribution,
+            Error::WrongPoolAccountToSendTokensFrom,
+        )?;
+
+        // amount to distribute is final only after sale is over
+        if !pool_state.is_finished(clock) {
+            return Err(Error::CantFundActivePool.into());
+        }
+
+        if input.amount > 0 {
+            let account_from = account_from.ok_or(ProgramError::NotEnoughAccountKeys)?;
+            token_transfer_with_user_authority(
+                account_from.clone(),
+                account_distribution.clone(),
+                pool_owner.clone(),
+                input.amount,
+            )?;
+        }
+
+        let account_distribution_state = Account::unpack(&account_distribution.data.borrow())?;
+        if account_distribution_state.amount < pool_state.amount_to_distribute {
+            return Err(Error::NotEnoughTokensReservedForUsers.into());
+        }
+
+        pool_state.funded = true;
+        write_pool(pool, &pool_state)?;
+
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn create_market_user_kyc<'a>(
         program_id: &ProgramPubkey,
@@ -554,22 +982,31 @@ impl Processor {
         rent: &AccountInfo<'a>,
         clock: &AccountInfo<'a>,
         _system_program: &AccountInfo<'a>,
+        kyc_provider: Option<&AccountInfo<'a>>,
         input: &CreateMarketUserKyc,
     ) -> ProgramResult {
-        is_owner!(&program_id, market);
+        market.owned_by(program_id)?;
         let rent = &Rent::from_account_info(rent)?;
         validate_market_owner(market, market_owner)?;
 
+        // provider approves KYC by signing it
+        let provider = match kyc_provider {
+            Some(kyc_provider) => {
+                kyc_provider.is_signer()?;
+                kyc_provider.pubkey()
+            }
+            None => DEFAULT_KYC_PROVIDER_KEY,
+        };
+
         let clock = &Clock::from_account_info(clock)?;
         if clock.unix_timestamp > input.expiration {
             return Err(Error::InputTimeMustBeInFuture.into());
         }
 
-        let (market_user_authority_key, bump) =
-            Pubkey::find_2key_program_address(&market.pubkey(), &user_wallet.pubkey(), program_id);
+        let authority = MarketUserAuthority::derive(market.key, user_wallet.key, program_id);
 
         same_key(
-            market_user_authority_key,
+            authority.key,
             market_user_authority,
             Error::MarketAuthorityMustBeDerivedFromMarket,
         )?;
@@ -582,11 +1019,7 @@ impl Processor {
 
         same_key(market_user_kyc_key, market_user_kyc, Error::WrongKycAccount)?;
 
-        let signature = &[
-            &market.key.to_bytes()[..32],
-            &user_wallet.key.to_bytes()[..32],
-            &[bump],
-        ];
+        let signer = authority.signer();
 
         create_account_with_seed_signed(
             market_owner,
@@ -596,15 +1029,19 @@ impl Processor {
             rent.minimum_balance(MarketUserKyc::LEN),
             MarketUserKyc::LEN as u64,
             program_id,
-            signature,
+            &signer.seeds(),
         )?;
 
-        let mut user_kyc_state =
-            MarketUserKyc::try_from_slice(*market_user_kyc.data.borrow()).unwrap();
+        let mut user_kyc_state = try_from_slice_exact::<MarketUserKyc>(
+            &market_user_kyc.data.borrow(),
+            MarketUserKyc::LEN,
+        )?;
         user_kyc_state.uninitialized()?;
         user_kyc_state.market = market.pubkey();
         user_kyc_state.expiration = input.expiration;
         user_kyc_state.user_wallet = user_wallet.pubkey();
+        user_kyc_state.provider = provider;
+        user_kyc_state.account_type = ACCOUNT_TYPE_MARKET_USER_KYC;
         user_kyc_state.version = USER_KYC_VERSION;
         user_kyc_state.serialize(&mut *market_user_kyc.data.borrow_mut())?;
         Ok(())
@@ -619,14 +1056,14 @@ impl Processor {
         user_wallet: &AccountInfo<'a>,
         _system_program: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(&program_id, market, market_user_kyc);
+        market.owned_by(program_id)?;
+        market_user_kyc.owned_by(program_id)?;
         validate_market_owner(market, market_owner)?;
 
-        let (market_user_authority_key, _) =
-            Pubkey::find_2key_program_address(&market.pubkey(), &user_wallet.pubkey(), program_id);
+        let authority = MarketUserAuthority::derive(market.key, user_wallet.key, program_id);
 
         same_key(
-            market_user_authority_key,
+            authority.key,
             market_user_authority,
             Error::MarketAuthorityMustBeDerivedFromMarket,
         )?;
@@ -639,38 +1076,91 @@ impl Processor {
 
         same_key(market_user_kyc_key, market_user_kyc, Error::WrongKycAccount)?;
 
-        crate::utils::program::burn_account(market_user_kyc, market_owner);
-
-        Ok(())
+        crate::utils::program::burn_account(market_user_kyc, market_owner)
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn start_pool<'a>(
+    fn renew_market_user_kyc<'a>(
         program_id: &ProgramPubkey,
         market: &AccountInfo<'a>,
-        market_or_pool_owner: &AccountInfo<'a>,
-        stake_pool: &AccountInfo<'a>,
+        market_user_authority: &AccountInfo<'a>,
+        market_user_kyc: &AccountInfo<'a>,
+        market_owner: &AccountInfo<'a>,
+        user_wallet: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        input: &RenewMarketUserKyc,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        market_user_kyc.owned_by(program_id)?;
+        validate_market_owner(market, market_owner)?;
+
+        let clock = &Clock::from_account_info(clock)?;
+        if clock.unix_timestamp > input.expiration {
+            return Err(Error::InputTimeMustBeInFuture.into());
+        }
+
+        let authority = MarketUserAuthority::derive(market.key, user_wallet.key, program_id);
+
+        same_key(
+            authority.key,
+            market_user_authority,
+            Error::MarketAuthorityMustBeDerivedFromMarket,
+        )?;
+
+        let market_user_kyc_key = Pubkey::create_with_seed(
+            &market_user_authority.pubkey(),
+            crate::KYC_SEED,
+            &program_id.pubkey(),
+        )?;
+
+        same_key(market_user_kyc_key, market_user_kyc, Error::WrongKycAccount)?;
+
+        if market_user_kyc.data_len() < MarketUserKyc::LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let mut user_kyc_state = MarketUserKyc::migrate(*market_user_kyc.data.borrow())?;
+        if user_kyc_state.market != market.pubkey()
+            || user_kyc_state.user_wallet != user_wallet.pubkey()
+        {
+            return Err(Error::WrongKycAccount.into());
+        }
+
+        user_kyc_state.expiration = input.expiration;
+        user_kyc_state.serialize(&mut *market_user_kyc.data.borrow_mut())?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn start_pool<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        market_or_pool_owner: &AccountInfo<'a>,
+        stake_pool: &AccountInfo<'a>,
         market_authority: &AccountInfo<'a>,
         pool: &AccountInfo<'a>,
         clock: &AccountInfo<'a>,
         _staking_program: &AccountInfo<'a>,
+        account_distribution: Option<&AccountInfo<'a>>,
     ) -> ProgramResult {
-        is_owner!(&program_id, market, pool);
+        market.owned_by(program_id)?;
+        pool.owned_by(program_id)?;
         market_or_pool_owner.is_signer()?;
 
-        let mut pool_state = Pool::try_from_slice(*pool.data.borrow()).unwrap();
+        let mut pool_state = Pool::migrate(&pool.data.borrow())?;
         pool_state.initialized()?;
+        // tier allocations are already shared by participants
+        if pool_state.started {
+            return Err(Error::PoolAlreadyStarted.into());
+        }
 
         {
             let clock = &Clock::from_account_info(clock)?;
-            if clock.unix_timestamp < pool_state.time_start
-                || clock.unix_timestamp > pool_state.time_finish
-            {
+            if !pool_state.is_active(clock) {
                 return Err(Error::InvalidPoolTimeFrame.into());
             }
         }
 
-        let market_state = Market::try_from_slice(&market.data.borrow()).unwrap();
+        let market_state = Market::migrate(&market.data.borrow())?;
         market_state.initialized()?;
 
         if market_state.stake_pool != stake_pool.pubkey() {
@@ -680,6 +1170,7 @@ impl Processor {
         if pool_state.market != market.pubkey() {
             return Err(Error::WrongMarketAddressForCurrentPool.into());
         }
+        pool_state.stake_pool = market_state.stake_pool;
 
         if pool_state.owner != market_or_pool_owner.pubkey()
             && market_state.owner != market_or_pool_owner.pubkey()
@@ -687,10 +1178,24 @@ impl Processor {
             return Err(Error::MarketOrPoolOwnerRequired.into());
         }
 
-        let stake_pool_state = StakePool::try_from_slice(*stake_pool.data.borrow()).unwrap();
+        let stake_pool_state = StakePool::migrate(&stake_pool.data.borrow())?;
 
         pool_state
             .set_tier_allocations(stake_pool_state.tier_users, stake_pool_state.tier_balance)?;
+        pool_state.started = true;
+
+        // owner opted in to have distribution covered before sale starts
+        if let Some(account_distribution) = account_distribution {
+            same_key(
+                pool_state.account_distribution,
+                account_distribution,
+                Error::WrongPoolAccountToSendTokensFrom,
+            )?;
+            let account_distribution_state = Account::unpack(&account_distribution.data.borrow())?;
+            if account_distribution_state.amount < pool_state.max_distribution()? {
+                return Err(Error::NotEnoughTokensReservedForUsers.into());
+            }
+        }
 
         let (_, market_authority_bump) =
             Pubkey::find_key_program_address(&market.pubkey(), &crate::program_id());
@@ -698,216 +1203,717 @@ impl Processor {
         let market_authority_signature =
             &[&market.pubkey().to_bytes()[..32], &[market_authority_bump]];
 
-        solana_program::program::invoke_signed(
-            &sol_starter_staking::instruction::start_pool(
+        // stake pool may be already active for other pool of market, so window is only extended
+        if pool_state.time_finish > stake_pool_state.pool_active_until {
+            invoke_signed_tagged(
+                "sol_starter_staking extend_pool_active",
+                &sol_starter_staking::instruction::extend_pool_active(
+                    &stake_pool.pubkey(),
+                    &market_authority.pubkey(),
+                    ExtendPoolActiveInput {
+                        pool_active_until: pool_state.time_finish,
+                    },
+                ),
+                &[stake_pool.clone(), market_authority.clone(), clock.clone()],
+                &[&market_authority_signature[..]],
+            )?;
+        }
+
+        write_pool(pool, &pool_state)?;
+
+        Ok(())
+    }
+
+    /// Process [Instruction::SetMarketDefaults]
+    pub fn set_market_defaults(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo,
+        market_owner: &AccountInfo,
+        input: PoolDefaults,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        if market.data_len() < Market::LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let mut market_state = validate_market_owner(market, market_owner)?;
+
+        if input.is_set {
+            if input.amount_investment_min != 0
+                && input.amount_investment_max != 0
+                && input.amount_investment_min > input.amount_investment_max
+            {
+                return Err(Error::InvalidInvestmentSizeNumbers.into());
+            }
+            if input.stages_count as usize > crate::STAGES_ACTIVE_COUNT {
+                return Err(Error::InvalidTimeTable.into());
+            }
+            market_state.pool_defaults = input;
+        } else {
+            market_state.pool_defaults = PoolDefaults::default();
+        }
+
+        market_state.serialize(&mut *market.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Process [Instruction::SetMarketFee]
+    pub fn set_market_fee(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo,
+        market_owner: &AccountInfo,
+        clock: &AccountInfo,
+        pools: &[AccountInfo],
+        input: SetMarketFee,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        if market.data_len() < Market::LEN {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let mut market_state = validate_market_owner(market, market_owner)?;
+        let clock = Clock::from_account_info(clock)?;
+
+        if input.fee_basis_points > MARKET_FEE_MAX_BASIS_POINTS {
+            return Err(Error::MarketFeeInvalid.into());
+        }
+
+        // fee must not change terms of sale users already participated in
+        if !unique_keys(pools) || pools.len() as u64 != market_state.pool_count {
+            return Err(Error::WrongMarketPools.into());
+        }
+        for pool in pools {
+            pool.owned_by(program_id)?;
+            let pool_state = Pool::migrate(&pool.data.borrow())?;
+            if pool_state.market != market.pubkey() {
+                return Err(Error::WrongMarketAddressForCurrentPool.into());
+            }
+            if pool_state.has_participants() && clock.unix_timestamp <= pool_state.time_finish {
+                return Err(Error::PoolHasParticipants.into());
+            }
+        }
+
+        market_state.fee_basis_points = input.fee_basis_points;
+        market_state.fee_account = input.fee_account;
+
+        market_state.serialize(&mut *market.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Process [Instruction::RecomputeTierUsers]
+    #[allow(clippy::too_many_arguments)]
+    pub fn recompute_tier_users<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        market_owner: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        stake_pool: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        _staking_program: &AccountInfo<'a>,
+        locks: &[AccountInfo<'a>],
+        input: RecomputeTierUsers,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        validate_market_stake_pool(market, market_owner, stake_pool)?;
+
+        let lock_keys = locks
+            .chunks(2)
+            .map(|pair| match pair {
+                [pool_lock, pool_lock_token_account_xsos] => {
+                    Ok((pool_lock.pubkey(), pool_lock_token_account_xsos.pubkey()))
+                }
+                _ => Err(ProgramError::NotEnoughAccountKeys),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut account_infos = vec![stake_pool.clone(), market_authority.clone(), clock.clone()];
+        account_infos.extend_from_slice(locks);
+
+        invoke_signed_by_market(
+            "sol_starter_staking recompute_tier_users_batch",
+            market,
+            &sol_starter_staking::instruction::recompute_tier_users_batch(
                 &stake_pool.pubkey(),
                 &market_authority.pubkey(),
-                StartPoolInput {
-                    pool_active_until: pool_state.time_finish,
+                &lock_keys,
+                RecomputeTierUsersInput {
+                    first: input.first,
+                    last: input.last,
                 },
             ),
-            &[stake_pool.clone(), market_authority.clone(), clock.clone()],
-            &[&market_authority_signature[..]],
-        )?;
+            &account_infos,
+        )
+    }
 
-        market_state.serialize(&mut *market.data.borrow_mut())?;
+    /// Process [Instruction::SetStakesPaused]
+    pub fn set_stakes_paused<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        market_owner: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        stake_pool: &AccountInfo<'a>,
+        _staking_program: &AccountInfo<'a>,
+        input: SetStakesPaused,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        validate_market_stake_pool(market, market_owner, stake_pool)?;
 
-        pool_state.serialize(&mut *pool.data.borrow_mut())?;
+        invoke_signed_by_market(
+            "sol_starter_staking set_stakes_paused",
+            market,
+            &sol_starter_staking::instruction::set_stakes_paused(
+                &stake_pool.pubkey(),
+                &market_authority.pubkey(),
+                SetStakesPausedInput {
+                    stakes_paused: input.stakes_paused,
+                },
+            ),
+            &[stake_pool.clone(), market_authority.clone()],
+        )
+    }
+
+    /// Process [Instruction::SetStakePoolIdoAuthority]
+    pub fn set_stake_pool_ido_authority<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        market_owner: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        stake_pool: &AccountInfo<'a>,
+        _staking_program: &AccountInfo<'a>,
+        input: SetStakePoolIdoAuthority,
+    ) -> ProgramResult {
+        market.owned_by(program_id)?;
+        validate_market_stake_pool(market, market_owner, stake_pool)?;
+
+        invoke_signed_by_market(
+            "sol_starter_staking set_ido_authority",
+            market,
+            &sol_starter_staking::instruction::set_ido_authority(
+                &stake_pool.pubkey(),
+                &market_authority.pubkey(),
+                SetIdoAuthorityInput {
+                    ido_authority: input.ido_authority,
+                },
+            ),
+            &[stake_pool.clone(), market_authority.clone()],
+        )
+    }
+
+    /// Process [Instruction::Migrate]
+    pub fn migrate<'a>(
+        program_id: &ProgramPubkey,
+        account: &AccountInfo<'a>,
+        owner: &AccountInfo<'a>,
+        copy_accounts: &[AccountInfo<'a>],
+        input: MigrateAccount,
+    ) -> ProgramResult {
+        account.owned_by(program_id)?;
+        owner.is_signer()?;
+
+        match input {
+            MigrateAccount::Market => {
+                let mut market_state = Market::migrate(&account.data.borrow())?;
+                same_key(market_state.owner, owner, Error::WrongMarketOwner)?;
+                if account.data_len() >= Market::LEN {
+                    // older layouts did not count pools, so pools of market are passed to count them
+                    let upgraded = account.data.borrow().get(..2)
+                        != Some(&[ACCOUNT_TYPE_MARKET, MARKET_VERSION][..]);
+                    if upgraded {
+                        market_state.pool_count =
+                            count_market_pools(program_id, account, copy_accounts)?;
+                    }
+                    market_state.serialize(&mut *account.data.borrow_mut())?;
+                } else {
+                    Self::copy_market(program_id, account, owner, market_state, copy_accounts)?;
+                }
+            }
+            MigrateAccount::Pool => {
+                let pool_state = Pool::migrate(&account.data.borrow())?;
+                same_key(pool_state.owner, owner, Error::WrongPoolOwner)?;
+                if account.data_len() >= Pool::LEN {
+                    pool_state.serialize(&mut *account.data.borrow_mut())?;
+                } else {
+                    Self::copy_pool(program_id, account, owner, pool_state, copy_accounts)?;
+                }
+            }
+        }
 
         Ok(())
     }
 
+    /// Copies market too small for current layout to new market account and closes it.
+    /// Stake pool authority and given pools are moved to new market.
+    fn copy_market<'a>(
+        program_id: &ProgramPubkey,
+        market: &AccountInfo<'a>,
+        market_owner: &AccountInfo<'a>,
+        mut market_state: Market,
+        accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let MigrateMarketAccounts {
+            new_market,
+            market_authority,
+            rent,
+            stake_pool,
+            staking_program: _,
+            remaining: pools,
+        } = MigrateMarketAccounts::new(accounts)?;
+        if !unique_keys(pools) {
+            return Err(Error::WrongMarketPools.into());
+        }
+        new_market.owned_by(program_id)?;
+        let rent = &Rent::from_account_info(rent)?;
+        try_from_slice_exact::<Market>(&new_market.data.borrow(), Market::LEN)?.uninitialized()?;
+        if !rent.is_exempt(new_market.lamports(), new_market.data_len()) {
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+        if market_state.stake_pool != stake_pool.pubkey() {
+            return Err(Error::StakePoolMustBelongToMarket.into());
+        }
+
+        for pool in pools {
+            pool.owned_by(program_id)?;
+            let mut pool_state = Pool::migrate(&pool.data.borrow())?;
+            if pool_state.market != market.pubkey() {
+                return Err(Error::WrongMarketAddressForCurrentPool.into());
+            }
+            pool_state.market = new_market.pubkey();
+            write_pool(pool, &pool_state)?;
+        }
+        // pools not passed stay with old market, so only moved ones are counted
+        market_state.pool_count = pools.len() as u64;
+
+        let (new_market_authority, _) =
+            Pubkey::find_key_program_address(new_market.key, program_id);
+        invoke_signed_by_market(
+            "sol_starter_staking set_ido_authority",
+            market,
+            &sol_starter_staking::instruction::set_ido_authority(
+                &stake_pool.pubkey(),
+                &market_authority.pubkey(),
+                SetIdoAuthorityInput {
+                    ido_authority: new_market_authority,
+                },
+            ),
+            &[stake_pool.clone(), market_authority.clone()],
+        )?;
+
+        market_state.serialize(&mut *new_market.data.borrow_mut())?;
+        close_account(market, market_owner)
+    }
+
+    /// Copies pool too small for current layout to new pool account and closes it.
+    /// Pool authority is derived from pool key, so authority over pool token accounts and mints is moved to authority of new pool.
+    fn copy_pool<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        pool_owner: &AccountInfo<'a>,
+        mut pool_state: Pool,
+        accounts: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        let MigratePoolAccounts {
+            new_pool,
+            pool_authority,
+            rent,
+            account_collection,
+            account_distribution,
+            mint_pool,
+            token_program: _,
+            remaining: optional,
+        } = MigratePoolAccounts::new(accounts)?;
+        new_pool.owned_by(program_id)?;
+        let rent = &Rent::from_account_info(rent)?;
+        try_from_slice_exact::<Pool>(&new_pool.data.borrow(), Pool::LEN)?.uninitialized()?;
+        if !rent.is_exempt(new_pool.lamports(), new_pool.data_len()) {
+            return Err(ProgramError::AccountNotRentExempt);
+        }
+
+        // user pools, stages and receipts are derived from pool key and would be lost
+        if pool_state.participant_count != 0 || pool_state.amount_collected != 0 {
+            return Err(Error::PoolHasParticipants.into());
+        }
+
+        same_key(
+            pool_state.authority,
+            pool_authority,
+            Error::PoolAuthorityMustBeAlignedToPool,
+        )?;
+        same_key(
+            pool_state.account_collection,
+            account_collection,
+            Error::WrongCollectAccount,
+        )?;
+        same_key(
+            pool_state.account_distribution,
+            account_distribution,
+            Error::WrongPoolAccountToSendTokensFrom,
+        )?;
+        same_key(pool_state.mint_pool, mint_pool, Error::WrongPoolTokenMint)?;
+        let mint_whitelist = match (pool_state.mint_whitelist, optional.first()) {
+            (MintWhitelist::Key(key), Some(mint_whitelist)) => {
+                same_key(key, mint_whitelist, Error::WhitelistMintInvalid)?;
+                Some(mint_whitelist)
+            }
+            (MintWhitelist::Key(_), None) => return Err(Error::WhitelistMintMissing.into()),
+            (MintWhitelist::None(_), _) => None,
+        };
+
+        let (authority, authority_bump_seed) =
+            Pubkey::find_key_program_address(new_pool.key, program_id);
+
+        let moved = [
+            (account_collection, AuthorityType::AccountOwner),
+            (account_distribution, AuthorityType::AccountOwner),
+            (mint_pool, AuthorityType::MintTokens),
+        ];
+        let mut whitelist = Vec::new();
+        if let Some(mint_whitelist) = mint_whitelist {
+            whitelist.push((mint_whitelist, AuthorityType::MintTokens));
+            let mint_whitelist_state = Mint::unpack(&mint_whitelist.data.borrow())?;
+            if mint_whitelist_state.freeze_authority == COption::Some(pool_state.authority) {
+                whitelist.push((mint_whitelist, AuthorityType::FreezeAccount));
+            }
+        }
+        for (account, authority_type) in moved.iter().chain(whitelist.iter()) {
+            token_set_authority(
+                pool.key,
+                (*account).clone(),
+                pool_authority.clone(),
+                pool_state.authority_bump_seed,
+                authority_type.clone(),
+                &authority,
+            )?;
+        }
+
+        pool_state.authority = authority;
+        pool_state.authority_bump_seed = authority_bump_seed;
+        pool_state.serialize(&mut *new_pool.data.borrow_mut())?;
+        close_account(pool, pool_owner)
+    }
+
     /// Processes an instruction
     pub fn process_instruction(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         input: &[u8],
     ) -> ProgramResult {
-        let instruction =
-            Instruction::try_from_slice(input).or(Err(ProgramError::InvalidInstructionData))?;
+        let instruction = try_instruction_from_slice::<Instruction>(input)?;
         let program_id = ProgramPubkey(*program_id);
         match instruction {
             Instruction::InitializeMarket(input) => {
                 msg!("Instruction::InitializeMarket");
-                match accounts {
-                    [market, market_owner, rent, ..] => {
-                        Self::initialize_market(&program_id, market, market_owner, rent, &input)
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = InitializeMarketAccounts::new(accounts)?;
+                Self::initialize_market(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    accounts.rent,
+                    &input,
+                )
             }
             Instruction::InitializePool(input) => {
                 msg!("Instruction::InitializePool");
-                match accounts {
-                    [market, pool, market_owner, mint_collection, mint_distribution, account_collection, account_distribution, mint_pool, pool_authority, rent, clock, token_program, system_program, ..] => {
-                        Self::initialize_pool(
-                            &program_id,
-                            market,
-                            pool,
-                            market_owner,
-                            mint_collection,
-                            mint_distribution,
-                            account_collection,
-                            account_distribution,
-                            mint_pool,
-                            pool_authority,
-                            rent,
-                            clock,
-                            token_program,
-                            system_program,
-                            accounts.get(13),
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = InitializePoolAccounts::new(accounts)?;
+                Self::initialize_pool(
+                    &program_id,
+                    accounts.market,
+                    accounts.pool,
+                    accounts.market_owner,
+                    accounts.mint_collection,
+                    accounts.mint_distribution,
+                    accounts.account_collection,
+                    accounts.account_distribution,
+                    accounts.mint_pool,
+                    accounts.pool_authority,
+                    accounts.rent,
+                    accounts.clock,
+                    accounts.token_program,
+                    accounts.system_program,
+                    accounts.remaining.first(),
+                    &input,
+                )
             }
             Instruction::Participate(input) => {
                 msg!("Instruction::Participate");
-                match accounts {
-                    [market, pool, pool_authority, pool_user_authority, user_wallet, user_account_from, account_collection, user_account_to, pool_lock_account, mint_pool, market_user_kyc, user_pool_stage, pool_lock, stake_pool, _token_program_id, _system_program, rent, clock, ..] => {
-                        Self::participate(
-                            &program_id,
-                            market,
-                            pool,
-                            pool_authority,
-                            pool_user_authority,
-                            user_wallet,
-                            user_account_from,
-                            account_collection,
-                            user_account_to,
-                            pool_lock_account,
-                            mint_pool,
-                            market_user_kyc,
-                            user_pool_stage,
-                            pool_lock,
-                            stake_pool,
-                            _token_program_id,
-                            _system_program,
-                            rent,
-                            clock,
-                            accounts.get(18),
-                            accounts.get(19),
-                            input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = ParticipateAccounts::new(accounts)?;
+                Self::participate(
+                    &program_id,
+                    accounts.market,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_user_authority,
+                    accounts.user_wallet,
+                    accounts.user_account_from,
+                    accounts.account_collection,
+                    accounts.user_account_to,
+                    accounts.pool_lock_account,
+                    accounts.mint_pool,
+                    accounts.market_user_kyc,
+                    accounts.user_pool_stage,
+                    accounts.pool_lock,
+                    accounts.stake_pool,
+                    accounts.token_program_id,
+                    accounts.system_program,
+                    accounts.rent,
+                    accounts.clock,
+                    accounts.remaining,
+                    input,
+                )
             }
             Instruction::Claim => {
                 msg!("Instruction::Claim");
-                match accounts {
-                    [market, pool, pool_authority, account_from, user_authority, mint_pool, account_pool, account_to, token_program_id, clock, ..] => {
-                        Self::claim(
-                            &program_id,
-                            market,
-                            pool,
-                            pool_authority,
-                            account_from,
-                            user_authority,
-                            mint_pool,
-                            account_pool,
-                            account_to,
-                            token_program_id,
-                            clock,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = ClaimAccounts::new(accounts)?;
+                Self::claim(
+                    &program_id,
+                    accounts.market,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.account_from,
+                    accounts.user_authority,
+                    accounts.mint_pool,
+                    accounts.account_pool,
+                    accounts.account_to,
+                    accounts.token_program_id,
+                    accounts.clock,
+                    accounts.remaining.get(..6),
+                )
             }
             Instruction::AddToWhitelist => {
                 msg!("Instruction::AddToWhitelist");
-                match accounts {
-                    [pool, pool_authority, pool_owner, account_whitelist, mint_whitelist, token_program, ..] => {
-                        Self::add_to_whitelist(
-                            &program_id,
-                            pool,
-                            pool_authority,
-                            pool_owner,
-                            account_whitelist,
-                            mint_whitelist,
-                            token_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = AddToWhitelistAccounts::new(accounts)?;
+                Self::add_to_whitelist(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_owner,
+                    accounts.account_whitelist,
+                    accounts.mint_whitelist,
+                    accounts.token_program,
+                )
+            }
+            Instruction::AddManyToWhitelist => {
+                msg!("Instruction::AddManyToWhitelist");
+                let accounts = AddManyToWhitelistAccounts::new(accounts)?;
+                Self::add_many_to_whitelist(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_owner,
+                    accounts.mint_whitelist,
+                    accounts.token_program,
+                    accounts.remaining,
+                )
             }
             Instruction::Withdraw => {
                 msg!("Instruction::Withdraw");
-                match accounts {
-                    [market, pool, pool_authority, pool_owner, account_from, account_to, token_program, clock, ..] => {
-                        Self::withdraw(
-                            &program_id,
-                            market,
-                            pool,
-                            pool_authority,
-                            pool_owner,
-                            account_from,
-                            account_to,
-                            token_program,
-                            clock,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                Self::dispatch_withdraw(&program_id, accounts, &Withdraw::CHECKED)
             }
             Instruction::CreateMarketUserKyc(input) => {
                 msg!("Instruction::CreateMarketUserKyc");
-                match accounts {
-                    [market, market_user_authority, market_user_kyc, market_owner, user_wallet, rent, clock, _system_program, ..] => {
-                        Self::create_market_user_kyc(
-                            &program_id,
-                            market,
-                            market_user_authority,
-                            market_user_kyc,
-                            market_owner,
-                            user_wallet,
-                            rent,
-                            clock,
-                            _system_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = CreateMarketUserKycAccounts::new(accounts)?;
+                Self::create_market_user_kyc(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_user_authority,
+                    accounts.market_user_kyc,
+                    accounts.market_owner,
+                    accounts.user_wallet,
+                    accounts.rent,
+                    accounts.clock,
+                    accounts.system_program,
+                    accounts.remaining.first(),
+                    &input,
+                )
             }
             Instruction::DeleteMarketUserKyc => {
                 msg!("Instruction::DeleteMarketUserKyc");
-                match accounts {
-                    [market, market_user_authority, market_user_kyc, market_owner, user_wallet, _system_program, ..] => {
-                        Self::delete_market_user_kyc(
-                            &program_id,
-                            market,
-                            market_user_authority,
-                            market_user_kyc,
-                            market_owner,
-                            user_wallet,
-                            _system_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = DeleteMarketUserKycAccounts::new(accounts)?;
+                Self::delete_market_user_kyc(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_user_authority,
+                    accounts.market_user_kyc,
+                    accounts.market_owner,
+                    accounts.user_wallet,
+                    accounts.system_program,
+                )
             }
             Instruction::StartPool => {
                 msg!("Instruction::StartPool");
-                match accounts {
-                    [market, market_or_pool_owner, stake_pool, market_authority, pool, clock, _staking_program, ..] => {
-                        Self::start_pool(
-                            &program_id,
-                            market,
-                            market_or_pool_owner,
-                            stake_pool,
-                            market_authority,
-                            pool,
-                            clock,
-                            _staking_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = StartPoolAccounts::new(accounts)?;
+                Self::start_pool(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_or_pool_owner,
+                    accounts.stake_pool,
+                    accounts.market_authority,
+                    accounts.pool,
+                    accounts.clock,
+                    accounts.staking_program,
+                    accounts.remaining.first(),
+                )
+            }
+            Instruction::Migrate(input) => {
+                msg!("Instruction::Migrate");
+                let accounts = MigrateAccounts::new(accounts)?;
+                Self::migrate(
+                    &program_id,
+                    accounts.account,
+                    accounts.owner,
+                    accounts.remaining,
+                    input,
+                )
+            }
+            Instruction::RenewMarketUserKyc(input) => {
+                msg!("Instruction::RenewMarketUserKyc");
+                let accounts = RenewMarketUserKycAccounts::new(accounts)?;
+                Self::renew_market_user_kyc(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_user_authority,
+                    accounts.market_user_kyc,
+                    accounts.market_owner,
+                    accounts.user_wallet,
+                    accounts.clock,
+                    &input,
+                )
+            }
+            Instruction::FundDistribution(input) => {
+                msg!("Instruction::FundDistribution");
+                let accounts = FundDistributionAccounts::new(accounts)?;
+                Self::fund_distribution(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_owner,
+                    accounts.account_distribution,
+                    accounts.token_program,
+                    accounts.clock,
+                    accounts.remaining.first(),
+                    &input,
+                )
+            }
+            Instruction::WithdrawAll => {
+                msg!("Instruction::WithdrawAll");
+                Self::dispatch_withdraw_all(&program_id, accounts, &Withdraw::CHECKED)
+            }
+            Instruction::SetMarketDefaults(input) => {
+                msg!("Instruction::SetMarketDefaults");
+                let accounts = SetMarketDefaultsAccounts::new(accounts)?;
+                Self::set_market_defaults(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    input,
+                )
+            }
+            Instruction::SetMarketFee(input) => {
+                msg!("Instruction::SetMarketFee");
+                let accounts = SetMarketFeeAccounts::new(accounts)?;
+                Self::set_market_fee(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    accounts.clock,
+                    accounts.remaining,
+                    input,
+                )
+            }
+            Instruction::RecomputeTierUsers(input) => {
+                msg!("Instruction::RecomputeTierUsers");
+                let accounts = RecomputeTierUsersAccounts::new(accounts)?;
+                Self::recompute_tier_users(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    accounts.market_authority,
+                    accounts.stake_pool,
+                    accounts.clock,
+                    accounts.staking_program,
+                    accounts.remaining,
+                    input,
+                )
+            }
+            Instruction::SetStakesPaused(input) => {
+                msg!("Instruction::SetStakesPaused");
+                let accounts = SetStakesPausedAccounts::new(accounts)?;
+                Self::set_stakes_paused(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    accounts.market_authority,
+                    accounts.stake_pool,
+                    accounts.staking_program,
+                    input,
+                )
+            }
+            Instruction::SetStakePoolIdoAuthority(input) => {
+                msg!("Instruction::SetStakePoolIdoAuthority");
+                let accounts = SetStakePoolIdoAuthorityAccounts::new(accounts)?;
+                Self::set_stake_pool_ido_authority(
+                    &program_id,
+                    accounts.market,
+                    accounts.market_owner,
+                    accounts.market_authority,
+                    accounts.stake_pool,
+                    accounts.staking_program,
+                    input,
+                )
+            }
+            Instruction::WithdrawTo(input) => {
+                msg!("Instruction::WithdrawTo");
+                Self::dispatch_withdraw(&program_id, accounts, &input)
+            }
+            Instruction::WithdrawAllTo(input) => {
+                msg!("Instruction::WithdrawAllTo");
+                Self::dispatch_withdraw_all(&program_id, accounts, &input)
             }
         }
     }
+
+    /// Dispatches [Instruction::Withdraw] and [Instruction::WithdrawTo]
+    fn dispatch_withdraw(
+        program_id: &ProgramPubkey,
+        accounts: &[AccountInfo],
+        input: &Withdraw,
+    ) -> ProgramResult {
+        let accounts = WithdrawAccounts::new(accounts)?;
+        Self::withdraw(
+            program_id,
+            accounts.market,
+            accounts.pool,
+            accounts.pool_authority,
+            accounts.pool_owner,
+            accounts.account_from,
+            accounts.account_to,
+            accounts.token_program,
+            accounts.clock,
+            accounts.remaining.get(..6),
+            input,
+        )
+    }
+
+    /// Dispatches [Instruction::WithdrawAll] and [Instruction::WithdrawAllTo]
+    fn dispatch_withdraw_all(
+        program_id: &ProgramPubkey,
+        accounts: &[AccountInfo],
+        input: &Withdraw,
+    ) -> ProgramResult {
+        let accounts = WithdrawAllAccounts::new(accounts)?;
+        Self::withdraw_all(
+            program_id,
+            accounts.market,
+            accounts.pool,
+            accounts.pool_authority,
+            accounts.pool_owner,
+            accounts.account_collection,
+            accounts.account_distribution,
+            accounts.account_to_collection,
+            accounts.account_to_distribution,
+            accounts.token_program,
+            accounts.clock,
+            input,
+        )
+    }
 }
 
 /// errors if relation is not expected
@@ -924,7 +1930,7 @@ fn validate_market_owner(
     market: &AccountInfo,
     market_owner: &AccountInfo,
 ) -> Result<Market, ProgramError> {
-    let market_state = Market::try_from_slice(&market.data.borrow()).unwrap();
+    let market_state = Market::migrate(&market.data.borrow())?;
     market_state.initialized()?;
     if *market_owner.key != market_state.owner {
         return Err(Error::WrongMarketOwner.into());
@@ -932,3 +1938,130 @@ fn validate_market_owner(
     market_owner.is_signer()?;
     Ok(market_state)
 }
+
+/// Writes pool state, pool of older layout is smaller than [Pool::LEN] and must be migrated first
+fn write_pool(pool: &AccountInfo, pool_state: &Pool) -> ProgramResult {
+    if pool.data_len() < Pool::LEN {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    pool_state.serialize(&mut *pool.data.borrow_mut())?;
+    Ok(())
+}
+
+/// Validates `account_to` is owned by pool owner unless check is skipped
+fn validate_withdraw_destination(
+    pool_view: &PoolView,
+    account_to: &AccountInfo,
+    input: &Withdraw,
+) -> ProgramResult {
+    if !input.skip_owner_check {
+        let account_to_state = Account::unpack(&account_to.data.borrow())?;
+        if account_to_state.owner != pool_view.owner() {
+            return Err(Error::WithdrawDestinationMustBeOwnedByPoolOwner.into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks no account is passed twice
+fn unique_keys(accounts: &[AccountInfo]) -> bool {
+    let mut keys: Vec<Pubkey> = accounts.iter().map(|account| account.pubkey()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    keys.len() == accounts.len()
+}
+
+/// Number of `pools`, each must be pool of `market` passed once
+fn count_market_pools(
+    program_id: &ProgramPubkey,
+    market: &AccountInfo,
+    pools: &[AccountInfo],
+) -> Result<u64, ProgramError> {
+    if !unique_keys(pools) {
+        return Err(Error::WrongMarketPools.into());
+    }
+    for pool in pools {
+        pool.owned_by(program_id)?;
+        let pool_state = Pool::migrate(&pool.data.borrow())?;
+        if pool_state.market != market.pubkey() {
+            return Err(Error::WrongMarketAddressForCurrentPool.into());
+        }
+    }
+    Ok(pools.len() as u64)
+}
+
+/// Zeroes data of program `account` so it can't be read again and moves its lamports to `beneficiary`
+fn close_account(account: &AccountInfo, beneficiary: &AccountInfo) -> ProgramResult {
+    account
+        .try_borrow_mut_data()?
+        .iter_mut()
+        .for_each(|byte| *byte = 0);
+    crate::utils::program::burn_account(account, beneficiary)
+}
+
+/// Validates market owner and that `stake_pool` is the one of market
+fn validate_market_stake_pool(
+    market: &AccountInfo,
+    market_owner: &AccountInfo,
+    stake_pool: &AccountInfo,
+) -> Result<Market, ProgramError> {
+    let market_state = validate_market_owner(market, market_owner)?;
+    if market_state.stake_pool != stake_pool.pubkey() {
+        return Err(Error::StakePoolMustBelongToMarket.into());
+    }
+    Ok(market_state)
+}
+
+/// Invokes staking `instruction` signed by market derived authority
+fn invoke_signed_by_market(
+    tag: &str,
+    market: &AccountInfo,
+    instruction: &SolanaInstruction,
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    let (_, market_authority_bump) =
+        Pubkey::find_key_program_address(&market.pubkey(), &crate::program_id());
+    let market_authority_signature = &[&market.pubkey().to_bytes()[..32], &[market_authority_bump]];
+    invoke_signed_tagged(
+        tag,
+        instruction,
+        account_infos,
+        &[&market_authority_signature[..]],
+    )
+}
+
+/// Finds optional account by its derived `key`, so it can be passed in any position after the
+/// required accounts. Returns other accounts in their order.
+fn optional_account_by_key<'a, 'b>(
+    accounts: &[&'b AccountInfo<'a>],
+    key: &Pubkey,
+) -> (Option<&'b AccountInfo<'a>>, Vec<&'b AccountInfo<'a>>) {
+    let (found, other): (Vec<&AccountInfo>, Vec<&AccountInfo>) = accounts
+        .iter()
+        .copied()
+        .partition(|account| account.key == key);
+    (found.first().copied(), other)
+}
+
+/// creates associated `account_to` if optional accounts to create it are provided
+fn create_account_to_if_missing<'a>(
+    account_to: &AccountInfo<'a>,
+    token_program: &AccountInfo<'a>,
+    create_account_to: Option<&[AccountInfo<'a>]>,
+) -> ProgramResult {
+    match create_account_to {
+        Some([payer, wallet, mint, system_program, rent, associated_token_program]) => {
+            create_associated_token_account_idempotent(
+                account_to.clone(),
+                payer.clone(),
+                wallet.clone(),
+                mint.clone(),
+                system_program.clone(),
+                token_program.clone(),
+                rent.clone(),
+                associated_token_program.clone(),
+            )
+        }
+        _ => Ok(()),
+    }
+}
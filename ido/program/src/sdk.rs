@@ -0,0 +1,399 @@
+//! Instruction sets for Rust clients, bundling account creation and address derivation
+
+use sol_starter_staking::utils::program::{MarketUserAuthority, PoolUserAuthority, ProgramPubkey};
+use solana_program::{
+    instruction::Instruction as SolanaInstruction, program_error::ProgramError, program_pack::Pack,
+    pubkey::Pubkey, rent::Rent, system_instruction,
+};
+use spl_associated_token_account::{create_associated_token_account, get_associated_token_address};
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use crate::{
+    instruction::{self, CreateAccountTo, InitializePool, Participate},
+    state::{MintWhitelist, Pool},
+};
+
+/// New accounts of a pool, each must sign pool creation transaction
+#[derive(Debug, Clone)]
+pub struct PoolAccounts {
+    /// pool state
+    pub pool: Pubkey,
+    /// token account for collected tokens
+    pub account_collection: Pubkey,
+    /// token account for distributed tokens
+    pub account_distribution: Pubkey,
+    /// mint of pool tokens
+    pub mint_pool: Pubkey,
+    /// mint of whitelist tokens, `None` for public pool
+    pub mint_whitelist: Option<Pubkey>,
+}
+
+/// Creates rent exempt pool accounts paid by `payer` and initializes pool.
+/// Initialization is the last instruction, so set may be split if it does not fit one transaction.
+#[allow(clippy::too_many_arguments)]
+pub fn create_pool(
+    program_id: &ProgramPubkey,
+    rent: &Rent,
+    payer: &Pubkey,
+    market: &Pubkey,
+    market_owner: &Pubkey,
+    mint_collection: &Pubkey,
+    mint_distribution: &Pubkey,
+    accounts: &PoolAccounts,
+    input: InitializePool,
+) -> Result<Vec<SolanaInstruction>, ProgramError> {
+    let create = |account: &Pubkey, len: usize, owner: &Pubkey| {
+        system_instruction::create_account(
+            payer,
+            account,
+            rent.minimum_balance(len),
+            len as u64,
+            owner,
+        )
+    };
+
+    let mut instructions = vec![
+        create(&accounts.pool, Pool::LEN, &program_id.pubkey()),
+        create(
+            &accounts.account_collection,
+            TokenAccount::LEN,
+            &spl_token::id(),
+        ),
+        create(
+            &accounts.account_distribution,
+            TokenAccount::LEN,
+            &spl_token::id(),
+        ),
+        create(&accounts.mint_pool, Mint::LEN, &spl_token::id()),
+    ];
+    if let Some(mint_whitelist) = accounts.mint_whitelist {
+        instructions.push(create(&mint_whitelist, Mint::LEN, &spl_token::id()));
+    }
+
+    instructions.push(instruction::initialize_pool(
+        program_id,
+        &accounts.pool,
+        market,
+        market_owner,
+        mint_collection,
+        mint_distribution,
+        &accounts.account_collection,
+        &accounts.account_distribution,
+        &accounts.mint_pool,
+        accounts.mint_whitelist,
+        input,
+    )?);
+
+    Ok(instructions)
+}
+
+/// Participates in `pool` from `user_account_from`, pool tokens go to associated account of `user_wallet`.
+/// Associated account is created by `payer` if `create_account_to` is set.
+/// KYC and whitelist accounts are derived according to `pool` requirements.
+/// `account_fee` is [crate::state::Market::fee_account] of pool market if its fee is enabled.
+#[allow(clippy::too_many_arguments)]
+pub fn participate(
+    program_id: &ProgramPubkey,
+    pool_key: &Pubkey,
+    pool: &Pool,
+    stake_pool: &Pubkey,
+    payer: &Pubkey,
+    user_wallet: &Pubkey,
+    user_account_from: &Pubkey,
+    pool_lock_account: &Pubkey,
+    create_account_to: bool,
+    account_fee: Option<&Pubkey>,
+    input: Participate,
+    stage: u8,
+) -> Result<Vec<SolanaInstruction>, ProgramError> {
+    let mut instructions = vec![];
+
+    let user_account_to = get_associated_token_address(user_wallet, &pool.mint_pool);
+    if create_account_to {
+        instructions.push(create_associated_token_account(
+            payer,
+            user_wallet,
+            &pool.mint_pool,
+        ));
+    }
+
+    let pool_user_authority =
+        PoolUserAuthority::derive(stake_pool, user_wallet, &sol_starter_staking::program_id()).key;
+    let pool_lock = Pubkey::create_with_seed(
+        &pool_user_authority,
+        sol_starter_staking::LOCK_SEED,
+        &sol_starter_staking::id(),
+    )?;
+
+    let market_user_kyc = if pool.kyc_requirement.is_required() {
+        let market_user_authority =
+            MarketUserAuthority::derive(&pool.market, user_wallet, program_id);
+        Some(Pubkey::create_with_seed(
+            &market_user_authority.key,
+            crate::KYC_SEED,
+            &program_id.pubkey(),
+        )?)
+    } else {
+        None
+    };
+
+    let (mint_whitelist, account_whitelist) = match pool.mint_whitelist {
+        MintWhitelist::Key(mint_whitelist) => (
+            Some(mint_whitelist),
+            Some(get_associated_token_address(user_wallet, &mint_whitelist)),
+        ),
+        MintWhitelist::None(_) => (None, None),
+    };
+
+    instructions.push(instruction::participate(
+        program_id,
+        pool_key,
+        &pool.market,
+        user_wallet,
+        user_account_from,
+        &pool.account_collection,
+        &user_account_to,
+        pool_lock_account,
+        &pool.mint_pool,
+        &pool_lock,
+        stake_pool,
+        market_user_kyc.as_ref(),
+        account_whitelist.as_ref(),
+        mint_whitelist.as_ref(),
+        account_fee,
+        input,
+        stage,
+    )?);
+
+    Ok(instructions)
+}
+
+/// Claims all pool tokens of `user_wallet` from its associated account.
+/// Tokens of `mint_to` (distributed if pool succeeded, collected otherwise) go to associated account of `user_wallet`,
+/// created by `payer` if missing.
+pub fn claim(
+    program_id: &ProgramPubkey,
+    pool_key: &Pubkey,
+    pool: &Pool,
+    payer: &Pubkey,
+    user_wallet: &Pubkey,
+    mint_to: &Pubkey,
+) -> Result<Vec<SolanaInstruction>, ProgramError> {
+    let account_from = get_associated_token_address(user_wallet, &pool.mint_pool);
+    let account_to = get_associated_token_address(user_wallet, mint_to);
+    let account_pool = if pool.success() {
+        pool.account_distribution
+    } else {
+        pool.account_collection
+    };
+
+    Ok(vec![instruction::claim(
+        program_id,
+        pool_key,
+        &pool.market,
+        &account_from,
+        user_wallet,
+        &pool.mint_pool,
+        &account_pool,
+        &account_to,
+        Some(&CreateAccountTo {
+            payer: *payer,
+            wallet: *user_wallet,
+            mint: *mint_to,
+        }),
+    )?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::KycRequirement;
+
+    fn pool_accounts(whitelist: bool) -> PoolAccounts {
+        PoolAccounts {
+            pool: Pubkey::new_unique(),
+            account_collection: Pubkey::new_unique(),
+            account_distribution: Pubkey::new_unique(),
+            mint_pool: Pubkey::new_unique(),
+            mint_whitelist: if whitelist {
+                Some(Pubkey::new_unique())
+            } else {
+                None
+            },
+        }
+    }
+
+    fn initialize_pool_input() -> InitializePool {
+        InitializePool {
+            pool_owner: Pubkey::new_unique(),
+            price: 1,
+            goal_max: 10,
+            goal_min: 1,
+            amount_min: 1,
+            amount_max: 10,
+            time_start: 0,
+            time_finish: 0,
+            kyc_requirement: Some(KycRequirement::default()),
+            time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+            stages_count: 2,
+            whitelist_ticket_amount: 0,
+            per_user_max: 0,
+            refund_grace_period: 0,
+            nonce: None,
+        }
+    }
+
+    fn pool() -> Pool {
+        Pool {
+            market: Pubkey::new_unique(),
+            account_collection: Pubkey::new_unique(),
+            account_distribution: Pubkey::new_unique(),
+            mint_pool: Pubkey::new_unique(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn create_pool_instructions() {
+        for (whitelist, count, accounts) in [(false, 5, 13), (true, 6, 14)].iter() {
+            let pool_accounts = pool_accounts(*whitelist);
+            let instructions = create_pool(
+                &crate::program_id(),
+                &Rent::default(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &pool_accounts,
+                initialize_pool_input(),
+            )
+            .unwrap();
+            assert_eq!(instructions.len(), *count);
+            let initialize = instructions.last().unwrap();
+            assert_eq!(initialize.program_id, crate::id());
+            assert_eq!(initialize.accounts.len(), *accounts);
+            assert_eq!(instructions[0].accounts[1].pubkey, pool_accounts.pool);
+        }
+    }
+
+    #[test]
+    fn participate_instructions() {
+        let mut pool = pool();
+        let participate = |pool: &Pool, create_account_to, account_fee: Option<&Pubkey>| {
+            participate(
+                &crate::program_id(),
+                &Pubkey::new_unique(),
+                pool,
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                create_account_to,
+                account_fee,
+                Participate {
+                    amount: 1,
+                    whitelist_tickets: 0,
+                    min_accepted: 0,
+                    receipt: false,
+                },
+                0,
+            )
+            .unwrap()
+        };
+
+        let instructions = participate(&pool, false, None);
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].accounts.len(), 20);
+
+        pool.kyc_requirement = KycRequirement::SpecificProvider(Pubkey::new_unique());
+        pool.mint_whitelist = MintWhitelist::Key(Pubkey::new_unique());
+        let instructions = participate(&pool, true, None);
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(
+            instructions[0].program_id,
+            spl_associated_token_account::id()
+        );
+        assert_eq!(instructions[1].accounts.len(), 22);
+
+        let account_fee = Pubkey::new_unique();
+        let instructions = participate(&pool, false, Some(&account_fee));
+        assert_eq!(instructions[0].accounts.len(), 23);
+        assert_eq!(instructions[0].accounts[22].pubkey, account_fee);
+    }
+
+    #[test]
+    fn instructions_from_pool_snapshot() {
+        let pool_key = Pubkey::new_unique();
+        let user_wallet = Pubkey::new_unique();
+        let pool = pool();
+        let snapshot = pool.clone();
+
+        let to_participate = participate(
+            &crate::program_id(),
+            &pool_key,
+            &snapshot,
+            &Pubkey::new_unique(),
+            &user_wallet,
+            &user_wallet,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            false,
+            None,
+            Participate {
+                amount: 1,
+                whitelist_tickets: 0,
+                min_accepted: 0,
+                receipt: false,
+            },
+            0,
+        )
+        .unwrap();
+        let to_claim = claim(
+            &crate::program_id(),
+            &pool_key,
+            &snapshot,
+            &user_wallet,
+            &user_wallet,
+            &snapshot.mint_pool,
+        )
+        .unwrap();
+        assert_eq!(to_participate[0].accounts[1].pubkey, pool_key);
+        assert_eq!(to_claim[0].accounts[1].pubkey, pool_key);
+        assert_eq!(snapshot, pool);
+    }
+
+    #[test]
+    fn claim_instructions() {
+        let mut pool = pool();
+        pool.goal_min_collected = 10;
+        let instructions = claim(
+            &crate::program_id(),
+            &Pubkey::new_unique(),
+            &pool,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+        )
+        .unwrap();
+        assert_eq!(instructions.len(), 1);
+        assert_eq!(instructions[0].accounts.len(), 16);
+        assert_eq!(instructions[0].accounts[6].pubkey, pool.account_collection);
+
+        pool.amount_collected = 10;
+        let instructions = claim(
+            &crate::program_id(),
+            &Pubkey::new_unique(),
+            &pool,
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+        )
+        .unwrap();
+        assert_eq!(
+            instructions[0].accounts[6].pubkey,
+            pool.account_distribution
+        );
+    }
+}
@@ -1,9 +1,11 @@
 //! Program state definitions
 use num_derive::{FromPrimitive, ToPrimitive};
-use num_traits::cast::FromPrimitive;
 use num_traits::ToPrimitive;
 
-use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use borsh::{
+    schema::{Declaration, Definition, Fields},
+    BorshDeserialize, BorshSchema, BorshSerialize,
+};
 use sol_starter_staking::state::get_tier;
 use solana_program::{
     clock::{Clock, UnixTimestamp},
@@ -11,47 +13,136 @@ use solana_program::{
     program_error::ProgramError,
     pubkey::Pubkey,
 };
-use std::convert::TryFrom;
-use strum::EnumCount;
+use std::{borrow::Cow, collections::HashMap, convert::TryFrom};
 
 use crate::{
     error::Error,
-    math::{ErrorAddSub, ErrorMulDiv},
+    math::{error_mul_div, ErrorAddSub, ErrorMulDiv, Rounding},
     CollectionToken, DistributionToken, TIERS_COUNT,
 };
 
 /// Uninitialized version of entity
 pub const UNINITIALIZED_VERSION: u8 = 0;
 
+/// Uninitialized account type
+pub const ACCOUNT_TYPE_UNINITIALIZED: u8 = 0;
+/// Set in account types, so they differ from version byte of layouts without account type
+pub const ACCOUNT_TYPE_FLAG: u8 = 0x80;
+/// Account type of [Market], first byte of its data
+pub const ACCOUNT_TYPE_MARKET: u8 = ACCOUNT_TYPE_FLAG | 1;
+/// Account type of [Pool], first byte of its data
+pub const ACCOUNT_TYPE_POOL: u8 = ACCOUNT_TYPE_FLAG | 2;
+/// Account type of [MarketUserKyc], first byte of its data
+pub const ACCOUNT_TYPE_MARKET_USER_KYC: u8 = ACCOUNT_TYPE_FLAG | 3;
+
+/// Market version before [Market::pool_defaults] was added
+pub const MARKET_VERSION_1: u8 = 1;
+/// Market version before [Market::account_type] was added
+pub const MARKET_VERSION_2: u8 = 2;
+/// Market version before [Market::fee_basis_points] was added
+pub const MARKET_VERSION_3: u8 = 3;
+/// Market version before [Market::pool_count] was added
+pub const MARKET_VERSION_4: u8 = 4;
 /// Current market version
-pub const MARKET_VERSION: u8 = 1;
+pub const MARKET_VERSION: u8 = 5;
+/// Market user KYC version before [MarketUserKyc::provider] was added
+pub const USER_KYC_VERSION_1: u8 = 1;
+/// Market user KYC version before [MarketUserKyc::account_type] was added
+pub const USER_KYC_VERSION_2: u8 = 2;
 /// Current version
-pub const USER_KYC_VERSION: u8 = 1;
+pub const USER_KYC_VERSION: u8 = 3;
+/// Pool version before [Pool::participant_count] was added
+pub const POOL_VERSION_1: u8 = 1;
+/// Pool version before [Pool::stages_count] was added
+pub const POOL_VERSION_2: u8 = 2;
+/// Pool version before [Pool::funded] was added
+pub const POOL_VERSION_3: u8 = 3;
+/// Pool version before [KycRequirement] got provider key
+pub const POOL_VERSION_4: u8 = 4;
+/// Pool version before [Pool::per_user_max] was added
+pub const POOL_VERSION_5: u8 = 5;
+/// Pool version before [Pool::account_type] was added
+pub const POOL_VERSION_6: u8 = 6;
+/// Pool version before [Pool::refund_deadline] was added
+pub const POOL_VERSION_7: u8 = 7;
+/// Pool version before [Pool::stake_pool] was added
+pub const POOL_VERSION_8: u8 = 8;
+/// Pool version before [Pool::started] was added
+pub const POOL_VERSION_9: u8 = 9;
 /// Current pool version
-pub const POOL_VERSION: u8 = 1;
+pub const POOL_VERSION: u8 = 10;
 /// Current user pool version
 pub const USER_POOL_STAGE_VERSION: u8 = 1;
+/// Current [UserPool] version
+pub const USER_POOL_VERSION: u8 = 1;
+/// Current [ParticipationReceipt] version
+pub const PARTICIPATION_RECEIPT_VERSION: u8 = 1;
+
+/// Basis points of whole amount
+pub const BASIS_POINTS: u16 = 10_000;
+
+/// Upper bound of [Market::fee_basis_points], a tenth of collected amount
+pub const MARKET_FEE_MAX_BASIS_POINTS: u16 = 1_000;
 
-/// Whitelist token transfer amount
-pub const WHITELIST_TOKEN_AMOUNT: u8 = 1;
+/// Default whitelist token amount minted by [crate::instruction::Instruction::AddToWhitelist] and burned on participation
+pub const WHITELIST_TOKEN_AMOUNT: u64 = 1;
 /// Default key for mint whitelist
 pub const DEFAULT_WHITELIST_KEY: Pubkey = Pubkey::new_from_array([0; 32]);
+/// Default key of KYC provider, also stored by KYC requirements without specific provider
+pub const DEFAULT_KYC_PROVIDER_KEY: Pubkey = Pubkey::new_from_array([0; 32]);
 
 /// Is a group of pools.
 #[repr(C)]
-#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone)]
 pub struct Market {
+    /// [ACCOUNT_TYPE_MARKET]
+    pub account_type: u8,
     /// Market state variable version
     pub version: u8,
     /// Market owner can initialize pools for market
     pub owner: Pubkey,
     /// [sol_starter_staking::StakingPool] account to calculate user tier allocations.    
     pub stake_pool: Pubkey,
+    /// Inherited by pools initialized without explicit values, see [crate::instruction::Instruction::SetMarketDefaults]
+    pub pool_defaults: PoolDefaults,
+    /// Share of each participation transferred to [Self::fee_account], in basis points of collected amount, zero disables fee
+    pub fee_basis_points: u16,
+    /// Collection token account receiving participation fee, see [crate::instruction::Instruction::SetMarketFee]
+    pub fee_account: Pubkey,
+    /// Number of pools initialized in market, counted only if market account is large enough for [Market::LEN].
+    /// Pools themselves are found by program accounts scan matching [Pool::market].
+    pub pool_count: u64,
+}
+
+/// Pool parameters stored in [Market] for [crate::instruction::InitializePool] inputs left unset
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
+pub struct PoolDefaults {
+    /// false until set, pools must provide all parameters explicitly
+    pub is_set: bool,
+    /// Min investment size
+    pub amount_investment_min: CollectionToken,
+    /// Max investment size
+    pub amount_investment_max: CollectionToken,
+    /// KYC requirement
+    pub kyc_requirement: KycRequirement,
+    /// non overlapped time for stages, only first [Self::stages_count] are used
+    pub time_table: [UnixTimeSmallDuration; crate::STAGES_ACTIVE_COUNT],
+    /// Number of active stages before [Stage::FinalStage], at most [crate::STAGES_ACTIVE_COUNT]
+    pub stages_count: u8,
 }
 
 impl Market {
     /// Market LEN
-    pub const LEN: usize = 65;
+    pub const LEN: usize = 175;
+    /// Market LEN in [MARKET_VERSION_1] layout
+    pub const LEN_V1: usize = 65;
+    /// Market LEN in [MARKET_VERSION_2] layout
+    pub const LEN_V2: usize = 132;
+    /// Market LEN in [MARKET_VERSION_3] layout
+    pub const LEN_V3: usize = 133;
+    /// Market LEN in [MARKET_VERSION_4] layout
+    pub const LEN_V4: usize = 167;
     /// Check if already initialized
     pub fn uninitialized(&self) -> ProgramResult {
         if self.version == UNINITIALIZED_VERSION {
@@ -62,30 +153,208 @@ impl Market {
     }
     /// Error if not initialized
     pub fn initialized(&self) -> ProgramResult {
-        if self.version != UNINITIALIZED_VERSION {
-            Ok(())
-        } else {
+        if self.version == UNINITIALIZED_VERSION {
             Err(ProgramError::UninitializedAccount)
+        } else if self.account_type != ACCOUNT_TYPE_MARKET {
+            Err(Error::WrongAccountType.into())
+        } else {
+            Ok(())
         }
     }
+
+    /// Reads market written with any supported version and upgrades it to [MARKET_VERSION].
+    /// Older layouts must get own arm reading them and filling new fields with defaults.
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut market = match data.first() {
+            None | Some(&UNINITIALIZED_VERSION) => Err(ProgramError::UninitializedAccount),
+            Some(&ACCOUNT_TYPE_MARKET) => match data.get(1) {
+                Some(&MARKET_VERSION) => Ok(Self::deserialize(&mut &data[..])?),
+                Some(&MARKET_VERSION_3) if data.len() >= Self::LEN_V3 => {
+                    // fee appended, so zeros read as disabled
+                    let mut data = data[..Self::LEN_V3].to_vec();
+                    data.resize(Self::LEN, 0);
+                    Ok(Self::deserialize(&mut &data[..])?)
+                }
+                Some(&MARKET_VERSION_4) if data.len() >= Self::LEN_V4 => {
+                    // pool count appended, pools initialized before are counted by migration in place
+                    let mut data = data[..Self::LEN_V4].to_vec();
+                    data.resize(Self::LEN, 0);
+                    Ok(Self::deserialize(&mut &data[..])?)
+                }
+                Some(&MARKET_VERSION_3) | Some(&MARKET_VERSION_4) => {
+                    Err(ProgramError::InvalidAccountData)
+                }
+                _ => Err(Error::UnsupportedStateVersion.into()),
+            },
+            Some(&account_type) if is_account_type(account_type) => {
+                Err(Error::WrongAccountType.into())
+            }
+            Some(&MARKET_VERSION_1) if data.len() >= Self::LEN_V1 => {
+                // account type prepended, pool defaults appended, so zeros read as not set
+                let mut data = [&[ACCOUNT_TYPE_MARKET][..], &data[..Self::LEN_V1]].concat();
+                data.resize(Self::LEN, 0);
+                Ok(Self::deserialize(&mut &data[..])?)
+            }
+            Some(&MARKET_VERSION_2) if data.len() >= Self::LEN_V2 => {
+                let mut data = [&[ACCOUNT_TYPE_MARKET][..], &data[..Self::LEN_V2]].concat();
+                data.resize(Self::LEN, 0);
+                Ok(Self::deserialize(&mut &data[..])?)
+            }
+            Some(&MARKET_VERSION_1) | Some(&MARKET_VERSION_2) => {
+                Err(ProgramError::InvalidAccountData)
+            }
+            Some(_) => Err(Error::UnsupportedStateVersion.into()),
+        }?;
+        market.account_type = ACCOUNT_TYPE_MARKET;
+        market.version = MARKET_VERSION;
+        Ok(market)
+    }
+
+    /// true if participation pays fee to [Self::fee_account]
+    pub fn fee_enabled(&self) -> bool {
+        self.fee_basis_points != 0
+    }
+
+    /// Fee taken from `amount` of collected tokens, rounds down
+    pub fn fee(&self, amount: CollectionToken) -> Result<CollectionToken, ProgramError> {
+        error_mul_div(
+            amount,
+            self.fee_basis_points.into(),
+            BASIS_POINTS.into(),
+            Rounding::Floor,
+        )
+    }
 }
 
-/// KYC requirement
-#[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
+/// KYC requirement of pool.
+/// Stored as kind byte followed by provider key, so pool has same size for any requirement.
+/// Provider key is [DEFAULT_KYC_PROVIDER_KEY] unless requirement is [KycRequirement::SpecificProvider].
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum KycRequirement {
     /// no need for KYC (verification)
     NotRequired,
     /// Any KYC presence is required for participation
     AnyRequired,
+    /// KYC approved by given provider is required for participation
+    SpecificProvider(Pubkey),
+}
+
+impl Default for KycRequirement {
+    fn default() -> Self {
+        KycRequirement::NotRequired
+    }
+}
+
+impl KycRequirement {
+    /// Stored size
+    pub const LEN: usize = 33;
+
+    const KIND_NOT_REQUIRED: u8 = 0;
+    const KIND_ANY_REQUIRED: u8 = 1;
+    const KIND_SPECIFIC_PROVIDER: u8 = 2;
+
+    /// true if participation requires [MarketUserKyc]
+    pub fn is_required(&self) -> bool {
+        *self != KycRequirement::NotRequired
+    }
+
+    /// true if KYC approved by `provider` satisfies requirement
+    pub fn accepts(&self, provider: &Pubkey) -> bool {
+        match self {
+            KycRequirement::NotRequired | KycRequirement::AnyRequired => true,
+            KycRequirement::SpecificProvider(required) => required == provider,
+        }
+    }
+}
+
+impl BorshSerialize for KycRequirement {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let (kind, provider) = match self {
+            KycRequirement::NotRequired => (Self::KIND_NOT_REQUIRED, DEFAULT_KYC_PROVIDER_KEY),
+            KycRequirement::AnyRequired => (Self::KIND_ANY_REQUIRED, DEFAULT_KYC_PROVIDER_KEY),
+            KycRequirement::SpecificProvider(provider) => (Self::KIND_SPECIFIC_PROVIDER, *provider),
+        };
+        kind.serialize(writer)?;
+        provider.serialize(writer)
+    }
+}
+
+impl BorshDeserialize for KycRequirement {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let kind = u8::deserialize(buf)?;
+        let provider = Pubkey::deserialize(buf)?;
+        match kind {
+            Self::KIND_NOT_REQUIRED => Ok(KycRequirement::NotRequired),
+            Self::KIND_ANY_REQUIRED => Ok(KycRequirement::AnyRequired),
+            Self::KIND_SPECIFIC_PROVIDER => Ok(KycRequirement::SpecificProvider(provider)),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Unexpected KYC requirement kind",
+            )),
+        }
+    }
+}
+
+impl BorshSchema for KycRequirement {
+    fn add_definitions_recursively(definitions: &mut HashMap<Declaration, Definition>) {
+        let fields = Fields::NamedFields(vec![
+            ("kind".to_string(), u8::declaration()),
+            ("provider".to_string(), Pubkey::declaration()),
+        ]);
+        Self::add_definition(
+            Self::declaration(),
+            Definition::Struct { fields },
+            definitions,
+        );
+        u8::add_definitions_recursively(definitions);
+        Pubkey::add_definitions_recursively(definitions);
+    }
+
+    fn declaration() -> Declaration {
+        "KycRequirement".to_string()
+    }
 }
 
 /// small seconds positive duration
 pub type UnixTimeSmallDuration = u32;
 
+/// Sale time frame of pool, from [Pool::time_start] to [Pool::time_finish]
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Timeframe {
+    /// first second of sale
+    pub start: UnixTimestamp,
+    /// last second of sale, claims are possible from it
+    pub finish: UnixTimestamp,
+}
+
+impl Timeframe {
+    /// errors if frame is reversed or any bound is in past of `now`
+    pub fn validate(&self, now: UnixTimestamp) -> ProgramResult {
+        if self.start < now || self.finish < now || self.start > self.finish {
+            return Err(Error::InvalidPoolTimeFrame.into());
+        }
+        Ok(())
+    }
+
+    /// seconds from start to finish
+    pub fn duration(&self) -> UnixTimestamp {
+        self.finish - self.start
+    }
+
+    /// true at `now` within frame, both bounds inclusive
+    pub fn is_active(&self, now: UnixTimestamp) -> bool {
+        self.start <= now && now <= self.finish
+    }
+
+    /// true from finish inclusive
+    pub fn is_finished(&self, now: UnixTimestamp) -> bool {
+        now >= self.finish
+    }
+}
+
 /// user pool stage marker account
 #[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
 pub struct UserPoolStage {}
 
 impl UserPoolStage {
@@ -93,11 +362,78 @@ impl UserPoolStage {
     pub const LEN: usize = 0;
 }
 
+/// user totals in pool, derived from pool user authority with [crate::USER_POOL_SEED]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
+pub struct UserPool {
+    /// Data version
+    pub version: u8,
+    /// Collected tokens accepted from user over all stages
+    pub amount_collected: CollectionToken,
+}
+
+impl UserPool {
+    /// LEN
+    pub const LEN: usize = 9;
+}
+
+/// Durable record of user participations in pool independent of pool token balance,
+/// derived from pool user authority with [crate::PARTICIPATION_RECEIPT_SEED]
+#[repr(C)]
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
+pub struct ParticipationReceipt {
+    /// Data version
+    pub version: u8,
+    /// Pool participated in
+    pub pool: Pubkey,
+    /// Participating user
+    pub user_wallet: Pubkey,
+    /// Collected tokens accepted from user, without market fee
+    pub amount_collected: CollectionToken,
+    /// Distribution tokens reserved for user, as summed into [Pool::amount_to_distribute]
+    pub amount_to_distribute: DistributionToken,
+    /// Tier of the latest participation on tier limited stage, [TIERS_COUNT] if only [Stage::FinalStage]
+    pub tier: u8,
+}
+
+impl ParticipationReceipt {
+    /// LEN
+    pub const LEN: usize = 82;
+
+    /// empty receipt of `user_wallet` in `pool`
+    pub fn new(pool: Pubkey, user_wallet: Pubkey) -> Self {
+        Self {
+            version: PARTICIPATION_RECEIPT_VERSION,
+            pool,
+            user_wallet,
+            tier: TIERS_COUNT as u8,
+            ..Default::default()
+        }
+    }
+
+    /// adds participation accepting `amount_collected` and reserving `amount_to_distribute`, `tier` is `None` on [Stage::FinalStage]
+    pub fn record(
+        &mut self,
+        amount_collected: CollectionToken,
+        amount_to_distribute: DistributionToken,
+        tier: Option<usize>,
+    ) -> ProgramResult {
+        self.amount_collected = self.amount_collected.error_add(amount_collected)?;
+        self.amount_to_distribute = self.amount_to_distribute.error_add(amount_to_distribute)?;
+        if let Some(tier) = tier {
+            self.tier = tier as u8;
+        }
+        Ok(())
+    }
+}
+
 /// Is a campaign to sell tokens, with rate, goal, min/max investment etc.
 /// Are created by [Market::market_owner]  with [collected tokens](Self::account_collection) and (given tokens)[Self::account_distribution]
 #[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone)]
 pub struct Pool {
+    /// [ACCOUNT_TYPE_POOL]
+    pub account_type: u8,
     /// Data version
     pub version: u8,
     /// Market reference
@@ -126,9 +462,9 @@ pub struct Pool {
     pub time_start: UnixTimestamp,
     /// Time when the pool stops accepting investments (and starts token distribution)
     pub time_finish: UnixTimestamp,
-    /// Amount collected
+    /// Amount collected, decreased by refunds if the pool failed
     pub amount_collected: CollectionToken,
-    /// Amount to distribute in distribution tokens
+    /// Amount to distribute in distribution tokens, decreased by claims
     pub amount_to_distribute: DistributionToken,
     /// Pool owner (the one who can sign transaction to claim money from [Self::account_collection] and [Self::account_distribution] accounts)
     pub owner: Pubkey,
@@ -142,22 +478,60 @@ pub struct Pool {
     /// there total allocations for each tier (before dividing by the number of users)
     pub tier_remaining: [DistributionToken; TIERS_COUNT],
 
-    /// non overlapped time for stages
+    /// non overlapped time for stages, only first [Self::stages_count] are used
     pub time_table: [UnixTimeSmallDuration; crate::STAGES_ACTIVE_COUNT],
+
+    /// Amount of whitelist tokens minted to user by [crate::instruction::Instruction::AddToWhitelist]
+    pub whitelist_ticket_amount: u64,
+
+    /// Number of participants, incremented when [UserPool] of user is created
+    pub participant_count: u64,
+
+    /// Number of active stages before [Stage::FinalStage], at most [crate::STAGES_ACTIVE_COUNT]
+    pub stages_count: u8,
+
+    /// Set by [crate::instruction::Instruction::FundDistribution] when [Self::account_distribution] covers [Self::amount_to_distribute], required to claim
+    pub funded: bool,
+
+    /// Maximum collected tokens accepted from one user over all participations, zero is unlimited
+    pub per_user_max: CollectionToken,
+
+    /// Owner of failed pool may withdraw collected tokens not yet refunded to users from this time
+    pub refund_deadline: UnixTimestamp,
+
+    /// [Market::stake_pool] at pool creation, default for pools migrated from older layouts
+    pub stake_pool: Pubkey,
+
+    /// Set by [crate::instruction::Instruction::StartPool], so tier allocations are computed
+    pub started: bool,
 }
 
-/// Mint whitelist enum
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+/// Mint whitelist enum.
+/// Borsh writes variant index then payload, so variants must not be reordered.
+/// Both variants are 33 bytes, which keeps [Pool::LEN] and offsets of later [Pool] fields fixed.
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
 pub enum MintWhitelist {
-    /// Key of mint whitelist
+    /// Key of mint whitelist, index 0
     Key(Pubkey),
-    /// No key provided
+    /// No key provided, index 1.
+    /// Payload is unused and always [DEFAULT_WHITELIST_KEY], unit variant would make pool size depend on whitelist.
     None(Pubkey),
 }
 
+impl Default for MintWhitelist {
+    fn default() -> Self {
+        MintWhitelist::None(DEFAULT_WHITELIST_KEY)
+    }
+}
+
 impl Pool {
     /// Pool LEN
-    pub const LEN: usize = 372;
+    pub const LEN: usize = 480;
+
+    /// end of [Self::time_table] in [POOL_VERSION_2] and older layouts
+    const TIME_TABLE_END_V2: usize = 372;
+    /// end of [Self::kyc_requirement] in [POOL_VERSION_4] and older layouts
+    const KYC_REQUIREMENT_END_V4: usize = 163;
     /// Check if already initialized
     pub fn uninitialized(&self) -> ProgramResult {
         if self.version == UNINITIALIZED_VERSION {
@@ -168,52 +542,230 @@ impl Pool {
     }
     /// Error if not initialized
     pub fn initialized(&self) -> ProgramResult {
-        if self.version != UNINITIALIZED_VERSION {
-            Ok(())
-        } else {
+        if self.version == UNINITIALIZED_VERSION {
             Err(ProgramError::UninitializedAccount)
+        } else if self.account_type != ACCOUNT_TYPE_POOL {
+            Err(Error::WrongAccountType.into())
+        } else if self.version != POOL_VERSION {
+            Err(Error::UnsupportedStateVersion.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Reads pool written with any supported version and upgrades it to [POOL_VERSION].
+    /// Older layouts are converted step by step to current one, new fields are filled with defaults.
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        let version = match data.first() {
+            None | Some(&UNINITIALIZED_VERSION) => Err(ProgramError::UninitializedAccount),
+            Some(&ACCOUNT_TYPE_POOL) => {
+                return match data.get(1) {
+                    Some(&POOL_VERSION) => Ok(Self::deserialize(&mut &data[..])?),
+                    Some(&version) if (POOL_VERSION_7..POOL_VERSION).contains(&version) => {
+                        let mut data = data.to_vec();
+                        data.resize(data.len().max(Self::LEN), 0);
+                        let mut pool = Self::deserialize(&mut &data[..])?;
+                        if version == POOL_VERSION_7 {
+                            pool.set_refund_deadline(0);
+                        }
+                        pool.started = pool.started_before_migration();
+                        pool.version = POOL_VERSION;
+                        Ok(pool)
+                    }
+                    _ => Err(Error::UnsupportedStateVersion.into()),
+                };
+            }
+            Some(&account_type) if is_account_type(account_type) => {
+                Err(Error::WrongAccountType.into())
+            }
+            Some(&version) if version <= POOL_VERSION_6 => Ok(version),
+            Some(_) => Err(Error::UnsupportedStateVersion.into()),
+        }?;
+        if data.len() < Self::TIME_TABLE_END_V2 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut data = data.to_vec();
+        if version <= POOL_VERSION_2 {
+            // time table grew in place
+            let grown = (crate::STAGES_ACTIVE_COUNT - crate::STAGES_ACTIVE_COUNT_V2)
+                * std::mem::size_of::<UnixTimeSmallDuration>();
+            data.splice(
+                Self::TIME_TABLE_END_V2..Self::TIME_TABLE_END_V2,
+                std::iter::repeat(0).take(grown),
+            );
         }
+        if version <= POOL_VERSION_4 {
+            // kyc requirement grew in place by provider key, zeros read as default key
+            data.splice(
+                Self::KYC_REQUIREMENT_END_V4..Self::KYC_REQUIREMENT_END_V4,
+                std::iter::repeat(0).take(32),
+            );
+        }
+        // account type is prepended, other new fields are appended, so zeros read as defaults
+        data.insert(0, ACCOUNT_TYPE_POOL);
+        data.resize(data.len().max(Self::LEN), 0);
+
+        let mut pool = Self::deserialize(&mut &data[..])?;
+        if version == POOL_VERSION_1 {
+            pool.participant_count = 0;
+        }
+        if version <= POOL_VERSION_2 {
+            pool.stages_count = crate::STAGES_ACTIVE_COUNT_V2 as u8;
+        }
+        pool.set_refund_deadline(0);
+        pool.started = pool.started_before_migration();
+        pool.account_type = ACCOUNT_TYPE_POOL;
+        pool.version = POOL_VERSION;
+        Ok(pool)
+    }
+
+    /// Older layouts did not record start, tier allocations and participants appear only after it
+    fn started_before_migration(&self) -> bool {
+        self.participant_count > 0
+            || self.tier_allocation.iter().any(|amount| *amount > 0)
+            || self.tier_remaining.iter().any(|amount| *amount > 0)
+    }
+
+    /// Sets [Self::refund_deadline] to `grace_period` seconds after [Self::time_finish], zero means [crate::REFUND_GRACE_PERIOD]
+    pub fn set_refund_deadline(&mut self, grace_period: UnixTimeSmallDuration) {
+        let grace_period = if grace_period == 0 {
+            crate::REFUND_GRACE_PERIOD
+        } else {
+            grace_period as UnixTimestamp
+        };
+        self.refund_deadline = self.time_finish.saturating_add(grace_period);
     }
 
     /// Price precision
     pub const PRECISION: u64 = 1_000_000_000;
 
+    /// Decimal digits of [Self::PRECISION]
+    pub const PRECISION_DECIMALS: u8 = 9;
+
+    /// Price applies to token amounts, so it is UI price scaled by `10^(collection_decimals - distribution_decimals)`.
+    /// Errors if distribution mint has [Self::PRECISION_DECIMALS] or more decimals over collection mint, so price has no fraction left,
+    /// or if price of one collection token per distribution token overflows.
+    pub fn validate_decimals(collection_decimals: u8, distribution_decimals: u8) -> ProgramResult {
+        let valid = if distribution_decimals > collection_decimals {
+            distribution_decimals - collection_decimals < Self::PRECISION_DECIMALS
+        } else {
+            let exponent = u32::from(Self::PRECISION_DECIMALS)
+                + u32::from(collection_decimals - distribution_decimals);
+            10u64.checked_pow(exponent).is_some()
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::MintDecimalsMismatch.into())
+        }
+    }
+
     /// success
     pub fn success(&self) -> bool {
         self.amount_collected >= self.goal_min_collected
     }
 
-    /// Transform collected tokens to distributed
+    /// true once any user participated, owner can't change pool terms from then
+    pub fn has_participants(&self) -> bool {
+        self.amount_collected > 0 || self.participant_count > 0
+    }
+
+    /// errors with [Error::PoolHasParticipants] if [Self::has_participants]
+    pub fn terms_mutable(&self) -> ProgramResult {
+        if self.has_participants() {
+            Err(Error::PoolHasParticipants.into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// same as [Pool::success]
+    pub fn min_goal_reached(&self) -> bool {
+        self.success()
+    }
+
+    /// Collected part of [Pool::goal_max_collected] in basis points, at most 10000.
+    /// Pool with zero goal is full.
+    pub fn progress_basis_points(&self) -> u16 {
+        const FULL: u16 = 10_000;
+        if self.goal_max_collected == 0 {
+            return FULL;
+        }
+        let progress =
+            self.amount_collected as u128 * FULL as u128 / self.goal_max_collected as u128;
+        progress.min(FULL as u128) as u16
+    }
+
+    /// Transform collected tokens to distributed, rounds down.
+    /// Used for amounts paid to users, so rounding is in program favor.
     pub fn collected_to_distributed(
         &self,
         amount_collected: CollectionToken,
     ) -> Result<DistributionToken, ProgramError> {
-        let amount_collected = amount_collected as u128;
-        let price = self.price as u128;
-
         // consistent with SOL/lamports logic
-        let distributed = amount_collected
-            .error_mul(Self::PRECISION as u128)?
-            .error_div(price)?;
-        DistributionToken::try_from(distributed).map_err(|_| Error::Overflow.into())
+        error_mul_div(
+            amount_collected,
+            Self::PRECISION,
+            self.price,
+            Rounding::Floor,
+        )
+    }
+
+    /// Transform collected tokens to distributed, rounds up.
+    /// Used for amounts reserved for users, so reserve is never less than later paid by [Self::collected_to_distributed].
+    pub fn collected_to_distributed_ceil(
+        &self,
+        amount_collected: CollectionToken,
+    ) -> Result<DistributionToken, ProgramError> {
+        error_mul_div(
+            amount_collected,
+            Self::PRECISION,
+            self.price,
+            Rounding::Ceil,
+        )
+    }
+
+    /// Transform distributed tokens to collected, rounds down.
+    /// Used to cap investments, so collected amount never distributes more than `amount_distributed`.
+    pub fn distributed_to_collected(
+        &self,
+        amount_distributed: DistributionToken,
+    ) -> Result<CollectionToken, ProgramError> {
+        error_mul_div(
+            amount_distributed,
+            self.price,
+            Self::PRECISION,
+            Rounding::Floor,
+        )
+    }
+
+    /// Distribution obligation of pool filled to [Self::goal_max_collected], lets owner fund [Self::account_distribution] before sale ends.
+    /// Rounds up as reserve does, still reserve of each participation rounds up, so [Self::amount_to_distribute] may exceed it by one token per participation with uneven price.
+    pub fn max_distribution(&self) -> Result<DistributionToken, ProgramError> {
+        self.collected_to_distributed_ceil(self.goal_max_collected)
     }
 
     /// The point of having two fields there is to keep exact cumulative amounts we need for the pool.
     /// Each purchase has a potential rounding error when multiplying by price, so we need to sum up all those individual amounts and not recalculate the whole amount by multiplying it by price.                
+    /// Reserve rounds up, so claim rounding down whole user amount never takes more than reserved.
+    /// [Stage::FinalStage] is not limited by tiers and ignores `tier`, other stages require `tier`.
     pub fn update_distributed_from_collected(
         &mut self,
         amount: CollectionToken,
         tier: Option<usize>,
         stage: Stage,
     ) -> ProgramResult {
-        let amount_to_distribute = self.collected_to_distributed(amount)?;
-        if stage != Stage::FinalStage {
-            if let Some(tier) = tier {
+        match (stage, tier) {
+            (Stage::FinalStage, _) => {}
+            (_, Some(tier)) => {
                 self.tier_remaining[tier] =
-                    self.tier_remaining[tier].error_sub(amount_to_distribute)?;
+                    self.tier_remaining[tier].error_sub(self.collected_to_distributed(amount)?)?;
             }
+            (_, None) => return Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into()),
         }
 
+        let amount_to_distribute = self.collected_to_distributed_ceil(amount)?;
         self.amount_to_distribute = self.amount_to_distribute.error_add(amount_to_distribute)?;
 
         Ok(())
@@ -249,12 +801,35 @@ impl Pool {
     /// let share = total_raise_distributed / total_shares;
     /// assert_eq!(share, 3030.3030303030305);
     /// ```
-    ///    
+    ///
+    /// Allocations round down, remainder of [Self::max_distribution] not allocated to tier users is always
+    /// carried to `tier_remaining` of the highest tier, so the same inputs give the same arrays and
+    /// `tier_remaining` sums to [Self::max_distribution].
     pub fn set_tier_allocations(
         &mut self,
         tier_users: [u32; crate::TIERS_COUNT],
         tier_balance: [u64; crate::TIERS_COUNT],
     ) -> ProgramResult {
+        let (tier_allocation, tier_remaining) =
+            self.compute_tier_allocations(tier_users, tier_balance)?;
+        self.tier_allocation = tier_allocation;
+        self.tier_remaining = tier_remaining;
+        Ok(())
+    }
+
+    /// Computes `(tier_allocation, tier_remaining)` as [Self::set_tier_allocations] would without
+    /// changing pool, so clients can preview allocations before pool start
+    pub fn compute_tier_allocations(
+        &self,
+        tier_users: [u32; crate::TIERS_COUNT],
+        tier_balance: [u64; crate::TIERS_COUNT],
+    ) -> Result<
+        (
+            [DistributionToken; TIERS_COUNT],
+            [DistributionToken; TIERS_COUNT],
+        ),
+        ProgramError,
+    > {
         let tier_balance: Vec<u128> = tier_balance.iter().map(|x| u128::from(*x)).collect();
         let tier_users: Vec<u128> = tier_users.iter().map(|x| u128::from(*x)).collect();
         let price = self.price as u128;
@@ -266,42 +841,130 @@ impl Pool {
             total_shares = total_shares.error_add(share)?;
         }
 
+        let max_distribution = self.max_distribution()?;
+        let mut tier_allocation = [0; TIERS_COUNT];
+        let mut tier_remaining = [0; TIERS_COUNT];
+        if total_shares == 0 {
+            // nobody staked yet, whole goal rolls into top tier to be shared by later stakers
+            tier_remaining[TIERS_COUNT - 1] = max_distribution;
+            return Ok((tier_allocation, tier_remaining));
+        }
         for (i, tier_balance) in tier_balance.iter().enumerate().take(TIERS_COUNT) {
             let per_tier_distributed = tier_balance
                 .error_mul(goal_max_collected)?
                 .error_mul(Self::PRECISION as u128)?
                 .error_div(price)?
                 .error_div(total_shares)?;
-            self.tier_remaining[i] = u64::try_from(per_tier_distributed.error_mul(tier_users[i])?)
+            tier_remaining[i] = u64::try_from(per_tier_distributed.error_mul(tier_users[i])?)
                 .map_err(|_| Error::Overflow)?;
-            let per_tier_distributed =
+            tier_allocation[i] =
                 u64::try_from(per_tier_distributed).map_err(|_| Error::Overflow)?;
-            self.tier_allocation[i] = per_tier_distributed;
         }
 
-        Ok(())
+        // rounding remainder goes to top tier, as when nobody staked
+        let allocated = tier_remaining
+            .iter()
+            .try_fold(0u64, |sum, remaining| sum.error_add(*remaining))?;
+        tier_remaining[TIERS_COUNT - 1] =
+            tier_remaining[TIERS_COUNT - 1].error_add(max_distribution.error_sub(allocated)?)?;
+
+        Ok((tier_allocation, tier_remaining))
     }
 
     /// get current stage
     pub fn get_current_stage(&self, clock: &Clock) -> Result<Stage, ProgramError> {
-        if self.time_start > clock.unix_timestamp || self.time_finish < clock.unix_timestamp {
+        self.get_current_stage_at(clock.unix_timestamp)
+    }
+
+    /// stage at `now`, see [Self::get_current_stage]
+    pub fn get_current_stage_at(&self, now: UnixTimestamp) -> Result<Stage, ProgramError> {
+        Ok(self.stage_at(self.get_current_stage_index_at(now)?))
+    }
+
+    /// index of current stage, [Self::stages_count] for [Stage::FinalStage]
+    pub fn get_current_stage_index(&self, clock: &Clock) -> Result<usize, ProgramError> {
+        self.get_current_stage_index_at(clock.unix_timestamp)
+    }
+
+    /// index of stage at `now`, see [Self::get_current_stage_index]
+    pub fn get_current_stage_index_at(&self, now: UnixTimestamp) -> Result<usize, ProgramError> {
+        if !self.is_active_at(now) {
             return Err(Error::CantDepositAtCurrentTime.into());
         }
 
-        let mut accumulate = clock.unix_timestamp - self.time_start;
+        let mut accumulate = now - self.time_start;
 
-        for (i, value) in self.time_table[..Stage::COUNT - 1].iter().enumerate() {
+        for (i, value) in self.active_time_table().iter().enumerate() {
             let value = *value as i64;
             if accumulate < value {
-                return Ok(Stage::from_usize(i).unwrap());
+                return Ok(i);
             }
             accumulate -= value;
         }
 
-        Ok(Stage::FinalStage)
+        Ok(self.active_time_table().len())
+    }
+
+    /// sale time frame
+    pub fn timeframe(&self) -> Timeframe {
+        Timeframe {
+            start: self.time_start,
+            finish: self.time_finish,
+        }
+    }
+
+    /// true if pool accepts investments at `clock`, both [Pool::time_start] and [Pool::time_finish] inclusive
+    pub fn is_active(&self, clock: &Clock) -> bool {
+        self.is_active_at(clock.unix_timestamp)
+    }
+
+    /// same as [Self::is_active] at `now`
+    pub fn is_active_at(&self, now: UnixTimestamp) -> bool {
+        self.timeframe().is_active(now)
+    }
+
+    /// true if tokens can be claimed at `clock`, from [Pool::time_finish] inclusive
+    pub fn is_finished(&self, clock: &Clock) -> bool {
+        self.is_finished_at(clock.unix_timestamp)
+    }
+
+    /// same as [Self::is_finished] at `now`
+    pub fn is_finished_at(&self, now: UnixTimestamp) -> bool {
+        self.timeframe().is_finished(now)
+    }
+
+    /// kind of stage at `index`, first active stage is [Stage::InitialStage] and others are [Stage::TierAllocationStage]
+    pub fn stage_at(&self, index: usize) -> Stage {
+        if index >= self.active_time_table().len() {
+            Stage::FinalStage
+        } else if index == 0 {
+            Stage::InitialStage
+        } else {
+            Stage::TierAllocationStage
+        }
+    }
+
+    /// time when stage at `index` finishes, final stage lasts until [Pool::time_finish]
+    pub fn stage_finish(&self, index: usize) -> UnixTimestamp {
+        let active = self.active_time_table();
+        if index >= active.len() {
+            return self.time_finish;
+        }
+
+        let duration: i64 = active[..=index].iter().map(|value| *value as i64).sum();
+        self.time_start
+            .saturating_add(duration)
+            .min(self.time_finish)
+    }
+
+    fn active_time_table(&self) -> &[UnixTimeSmallDuration] {
+        let stages_count = (self.stages_count as usize).min(crate::STAGES_ACTIVE_COUNT);
+        &self.time_table[..stages_count]
     }
 
     /// Check investment amount according to the stage rules.
+    /// [Stage::TierAllocationStage] partially fills amount over tier remaining,
+    /// tier which allocation was exhausted is rejected.
     pub fn stage_investment(
         &self,
         amount: CollectionToken,
@@ -312,17 +975,27 @@ impl Pool {
         let tier = get_tier(tier_balance, pool_lock_amount);
         let possible_amount = match (stage, tier) {
             (Stage::InitialStage, Some(tier)) => tier_balance[tier],
-            (Stage::TierAllocationStage, Some(tier)) => self.tier_remaining[tier],
+            (Stage::TierAllocationStage, Some(tier)) => {
+                match self.distributed_to_collected(self.tier_remaining[tier])? {
+                    0 => return Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into()),
+                    remaining => remaining,
+                }
+            }
             (Stage::FinalStage, _) => amount,
             _ => return Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into()),
         };
         Ok((amount.min(possible_amount), tier))
     }
 
-    /// errors if not started
-    pub fn was_started(&self, now: UnixTimestamp) -> ProgramResult {
+    /// allocation of `tier`, zero for tier out of range
+    pub fn tier_allocation_for(&self, tier: usize) -> DistributionToken {
+        self.tier_allocation.get(tier).copied().unwrap_or(0)
+    }
+
+    /// errors if [crate::instruction::Instruction::StartPool] was not run
+    pub fn was_started(&self) -> ProgramResult {
         self.initialized()?;
-        if self.time_start < now {
+        if self.started {
             Ok(())
         } else {
             Err(Error::CanParticipateOnlyInStartedPool.into())
@@ -330,53 +1003,220 @@ impl Pool {
     }
 }
 
-/// Pool stages
-#[repr(C)]
-#[derive(
-    Debug,
-    PartialEq,
-    BorshDeserialize,
-    BorshSerialize,
-    BorshSchema,
-    FromPrimitive,
-    ToPrimitive,
-    strum_macros::EnumCount,
-    Copy,
-    Clone,
-)]
-pub enum Stage {
-    /// On this stage can invest amount equal [tier_balance] (per investor) based on [pool_lock] amount
-    InitialStage,
-    /// On this stage can invest any amount equal [tier_remaining] for invester tier (shared by all investors on this thier)
-    TierAllocationStage,
-    /// On this stage can invest any amount, but not more than remaining total pool collection amount
-    FinalStage,
-}
+/// Read only view of serialized [Pool], reads fields at fixed offsets without deserializing whole account.
+/// Used for checks which do not mutate pool, offsets are tested against [Pool] serialization.
+/// Pool of older version is migrated once and read from its current serialization.
+#[derive(Debug, Clone)]
+pub struct PoolView<'a>(Cow<'a, [u8]>);
 
-impl Stage {
-    /// to bytes
-    pub fn to_be_bytes(self) -> [u8; 1] {
-        self.to_u8().unwrap().to_be_bytes()
+impl<'a> PoolView<'a> {
+    const ACCOUNT_TYPE: usize = 0;
+    const VERSION: usize = 1;
+    const MARKET: usize = 2;
+    const ACCOUNT_COLLECTION: usize = 34;
+    const ACCOUNT_DISTRIBUTION: usize = 66;
+    const MINT_POOL: usize = 98;
+    const GOAL_MIN_COLLECTED: usize = 212;
+    const TIME_START: usize = 236;
+    const TIME_FINISH: usize = 244;
+    const AMOUNT_COLLECTED: usize = 252;
+    const AMOUNT_TO_DISTRIBUTE: usize = 260;
+    const OWNER: usize = 268;
+    const AUTHORITY_BUMP_SEED: usize = 332;
+    const FUNDED: usize = 430;
+    const REFUND_DEADLINE: usize = 439;
+    const STARTED: usize = 479;
+
+    /// Wraps pool account data, pool not of [POOL_VERSION] is read by [Pool::migrate], so errors as it does
+    pub fn new(data: &'a [u8]) -> Result<Self, ProgramError> {
+        if data.len() >= Pool::LEN {
+            match (data[Self::ACCOUNT_TYPE], data[Self::VERSION]) {
+                (ACCOUNT_TYPE_POOL, POOL_VERSION) | (ACCOUNT_TYPE_UNINITIALIZED, _) => {
+                    return Ok(Self(Cow::Borrowed(&data[..Pool::LEN])));
+                }
+                _ => {}
+            }
+        }
+        Ok(Self(Cow::Owned(Pool::migrate(data)?.try_to_vec()?)))
     }
-}
 
-/// verified credentials reference (from Know Your Customer process)
-#[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
-pub struct MarketUserKyc {
-    /// Data version
-    pub version: u8,
-    /// market
-    pub market: Pubkey,
-    /// user
-    pub user_wallet: Pubkey,
-    /// expiration time of self
-    pub expiration: UnixTimestamp,
-}
+    fn pubkey(&self, offset: usize) -> Pubkey {
+        Pubkey::new(&self.0[offset..offset + 32])
+    }
 
-impl MarketUserKyc {
-    /// LEN
-    pub const LEN: usize = 73;
+    fn u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(<[u8; 8]>::try_from(&self.0[offset..offset + 8]).unwrap())
+    }
+
+    /// [Pool::account_type]
+    pub fn account_type(&self) -> u8 {
+        self.0[Self::ACCOUNT_TYPE]
+    }
+
+    /// [Pool::version]
+    pub fn version(&self) -> u8 {
+        self.0[Self::VERSION]
+    }
+
+    /// [Pool::market]
+    pub fn market(&self) -> Pubkey {
+        self.pubkey(Self::MARKET)
+    }
+
+    /// [Pool::account_collection]
+    pub fn account_collection(&self) -> Pubkey {
+        self.pubkey(Self::ACCOUNT_COLLECTION)
+    }
+
+    /// [Pool::account_distribution]
+    pub fn account_distribution(&self) -> Pubkey {
+        self.pubkey(Self::ACCOUNT_DISTRIBUTION)
+    }
+
+    /// [Pool::mint_pool]
+    pub fn mint_pool(&self) -> Pubkey {
+        self.pubkey(Self::MINT_POOL)
+    }
+
+    /// [Pool::goal_min_collected]
+    pub fn goal_min_collected(&self) -> CollectionToken {
+        self.u64(Self::GOAL_MIN_COLLECTED)
+    }
+
+    /// [Pool::time_start]
+    pub fn time_start(&self) -> UnixTimestamp {
+        self.u64(Self::TIME_START) as UnixTimestamp
+    }
+
+    /// [Pool::time_finish]
+    pub fn time_finish(&self) -> UnixTimestamp {
+        self.u64(Self::TIME_FINISH) as UnixTimestamp
+    }
+
+    /// same as [Pool::timeframe]
+    pub fn timeframe(&self) -> Timeframe {
+        Timeframe {
+            start: self.time_start(),
+            finish: self.time_finish(),
+        }
+    }
+
+    /// [Pool::amount_collected]
+    pub fn amount_collected(&self) -> CollectionToken {
+        self.u64(Self::AMOUNT_COLLECTED)
+    }
+
+    /// [Pool::amount_to_distribute]
+    pub fn amount_to_distribute(&self) -> DistributionToken {
+        self.u64(Self::AMOUNT_TO_DISTRIBUTE)
+    }
+
+    /// [Pool::owner]
+    pub fn owner(&self) -> Pubkey {
+        self.pubkey(Self::OWNER)
+    }
+
+    /// [Pool::authority_bump_seed]
+    pub fn authority_bump_seed(&self) -> u8 {
+        self.0[Self::AUTHORITY_BUMP_SEED]
+    }
+
+    /// [Pool::funded]
+    pub fn funded(&self) -> bool {
+        self.0[Self::FUNDED] != 0
+    }
+
+    /// [Pool::refund_deadline]
+    pub fn refund_deadline(&self) -> UnixTimestamp {
+        self.u64(Self::REFUND_DEADLINE) as UnixTimestamp
+    }
+
+    /// [Pool::started]
+    pub fn started(&self) -> bool {
+        self.0[Self::STARTED] != 0
+    }
+
+    /// same as [Pool::success]
+    pub fn success(&self) -> bool {
+        self.amount_collected() >= self.goal_min_collected()
+    }
+
+    /// errors if not initialized or of other version
+    pub fn initialized(&self) -> ProgramResult {
+        if self.version() == UNINITIALIZED_VERSION {
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if self.version() != POOL_VERSION {
+            return Err(Error::UnsupportedStateVersion.into());
+        }
+        Ok(())
+    }
+
+    /// same as [Pool::was_started]
+    pub fn was_started(&self) -> ProgramResult {
+        self.initialized()?;
+        if self.started() {
+            Ok(())
+        } else {
+            Err(Error::CanParticipateOnlyInStartedPool.into())
+        }
+    }
+}
+
+/// Pool stages
+#[repr(C)]
+#[derive(
+    Debug,
+    PartialEq,
+    BorshDeserialize,
+    BorshSerialize,
+    BorshSchema,
+    FromPrimitive,
+    ToPrimitive,
+    strum_macros::EnumCount,
+    Copy,
+    Clone,
+)]
+pub enum Stage {
+    /// On this stage can invest amount equal [tier_balance] (per investor) based on [pool_lock] amount
+    InitialStage,
+    /// On this stage can invest any amount equal [tier_remaining] for invester tier (shared by all investors on this thier).
+    /// All active stages after first are of this kind.
+    TierAllocationStage,
+    /// On this stage can invest any amount, but not more than remaining total pool collection amount
+    FinalStage,
+}
+
+impl Stage {
+    /// to bytes
+    pub fn to_be_bytes(self) -> [u8; 1] {
+        self.to_u8().unwrap().to_be_bytes()
+    }
+}
+
+/// verified credentials reference (from Know Your Customer process)
+#[repr(C)]
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone)]
+pub struct MarketUserKyc {
+    /// [ACCOUNT_TYPE_MARKET_USER_KYC]
+    pub account_type: u8,
+    /// Data version
+    pub version: u8,
+    /// market
+    pub market: Pubkey,
+    /// user
+    pub user_wallet: Pubkey,
+    /// expiration time of self
+    pub expiration: UnixTimestamp,
+    /// provider which approved KYC, checked against [KycRequirement::SpecificProvider]
+    pub provider: Pubkey,
+}
+
+impl MarketUserKyc {
+    /// LEN
+    pub const LEN: usize = 106;
+    /// LEN of [USER_KYC_VERSION_1] layout
+    pub const LEN_V1: usize = 73;
 
     /// Error if not initialized
     pub fn uninitialized(&self) -> ProgramResult {
@@ -388,20 +1228,370 @@ impl MarketUserKyc {
     }
     /// Error if not uninitialized
     pub fn initialized(&self) -> ProgramResult {
-        if self.version != UNINITIALIZED_VERSION {
-            Ok(())
-        } else {
+        if self.version == UNINITIALIZED_VERSION {
             Err(ProgramError::UninitializedAccount)
+        } else if self.account_type != ACCOUNT_TYPE_MARKET_USER_KYC {
+            Err(Error::WrongAccountType.into())
+        } else {
+            Ok(())
         }
     }
+
+    /// true if KYC is issued for `user` on `market` and not expired at `now`, expiration inclusive
+    pub fn is_valid_for(&self, market: &Pubkey, user: &Pubkey, now: UnixTimestamp) -> bool {
+        self.market == *market && self.user_wallet == *user && self.expiration >= now
+    }
+
+    /// Reads KYC written with any supported version and upgrades it to [USER_KYC_VERSION].
+    /// KYC of [USER_KYC_VERSION_1] has default provider, so satisfies only [KycRequirement::AnyRequired].
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut kyc = match data.first() {
+            None | Some(&UNINITIALIZED_VERSION) => Err(ProgramError::UninitializedAccount),
+            Some(&ACCOUNT_TYPE_MARKET_USER_KYC) => match data.get(1) {
+                Some(&USER_KYC_VERSION) => Ok(Self::deserialize(&mut &data[..])?),
+                _ => Err(Error::UnsupportedStateVersion.into()),
+            },
+            Some(&account_type) if is_account_type(account_type) => {
+                Err(Error::WrongAccountType.into())
+            }
+            Some(&USER_KYC_VERSION_1) | Some(&USER_KYC_VERSION_2) => {
+                // account type prepended, provider appended, zeros read as default key
+                let mut data = [&[ACCOUNT_TYPE_MARKET_USER_KYC][..], data].concat();
+                data.resize(data.len().max(Self::LEN), 0);
+                Ok(Self::deserialize(&mut &data[..])?)
+            }
+            Some(_) => Err(Error::UnsupportedStateVersion.into()),
+        }?;
+        kyc.account_type = ACCOUNT_TYPE_MARKET_USER_KYC;
+        kyc.version = USER_KYC_VERSION;
+        Ok(kyc)
+    }
+}
+
+/// true if `first` byte of account data is account type rather than version of older layout
+pub fn is_account_type(first: u8) -> bool {
+    first & ACCOUNT_TYPE_FLAG != 0
 }
 
 #[cfg(test)]
 mod tests {
+    use borsh::schema::BorshSchemaContainer;
     use sol_starter_staking::TIERS_COUNT;
 
     use super::*;
 
+    #[test]
+    fn migrate_market() {
+        let owner = Pubkey::new_unique();
+        let stake_pool = Pubkey::new_unique();
+        let mut data = vec![1u8];
+        data.extend_from_slice(owner.as_ref());
+        data.extend_from_slice(stake_pool.as_ref());
+
+        let market = Market::migrate(&data).unwrap();
+        assert_eq!(market.version, MARKET_VERSION);
+        assert_eq!(market.owner, owner);
+        assert_eq!(market.stake_pool, stake_pool);
+        assert!(!market.pool_defaults.is_set);
+
+        let mut current = market.try_to_vec().unwrap();
+        assert_eq!(current[0], ACCOUNT_TYPE_MARKET);
+        assert_eq!(Market::migrate(&current).unwrap(), market);
+        let mut legacy = current[1..].to_vec();
+        legacy[0] = MARKET_VERSION_2;
+        assert_eq!(Market::migrate(&legacy).unwrap(), market);
+        assert_eq!(
+            Market::migrate(&legacy[..Market::LEN_V2 - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        legacy[0] = MARKET_VERSION_1;
+        assert_eq!(Market::migrate(&legacy).unwrap(), market);
+        assert_eq!(
+            Market::migrate(&data[..Market::LEN_V1 - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        let mut legacy = current[..Market::LEN_V3].to_vec();
+        legacy[1] = MARKET_VERSION_3;
+        assert_eq!(Market::migrate(&legacy).unwrap(), market);
+        assert_eq!(
+            Market::migrate(&legacy[..Market::LEN_V3 - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        let mut legacy = current[..Market::LEN_V4].to_vec();
+        legacy[1] = MARKET_VERSION_4;
+        assert_eq!(Market::migrate(&legacy).unwrap(), market);
+        assert_eq!(
+            Market::migrate(&legacy[..Market::LEN_V4 - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        current[1] = MARKET_VERSION + 1;
+        assert_eq!(
+            Market::migrate(&current),
+            Err(Error::UnsupportedStateVersion.into())
+        );
+
+        assert_eq!(
+            Market::migrate(&[0; Market::LEN]),
+            Err(ProgramError::UninitializedAccount)
+        );
+        data[0] = MARKET_VERSION + 1;
+        assert_eq!(
+            Market::migrate(&data),
+            Err(Error::UnsupportedStateVersion.into())
+        );
+    }
+
+    #[test]
+    fn migrate_pool() {
+        let mut pool = pool_new(10, 10);
+        pool.version = POOL_VERSION;
+        pool.participant_count = 7;
+        pool.time_table = [10, 20, 0, 0];
+        pool.funded = true;
+        pool.kyc_requirement = KycRequirement::AnyRequired;
+        pool.per_user_max = 11;
+        pool.refund_deadline = 12_345;
+        pool.stake_pool = Pubkey::new_unique();
+        pool.started = true;
+        let data = pool.try_to_vec().unwrap();
+        assert_eq!(Pool::migrate(&data).unwrap(), pool);
+
+        // start is inferred from participants for older layouts
+        let mut data_v9 = data.clone();
+        data_v9[1] = POOL_VERSION_9;
+        data_v9.pop();
+        assert_eq!(Pool::migrate(&data_v9).unwrap(), pool);
+        let mut unstarted = pool.clone();
+        unstarted.participant_count = 0;
+        unstarted.tier_remaining = [0; TIERS_COUNT];
+        unstarted.started = false;
+        let mut unstarted_v9 = unstarted.try_to_vec().unwrap();
+        unstarted_v9[1] = POOL_VERSION_9;
+        unstarted_v9.pop();
+        assert_eq!(Pool::migrate(&unstarted_v9).unwrap(), unstarted);
+
+        // stake pool is not known to older layouts
+        pool.stake_pool = Pubkey::default();
+        let mut data_v8 = data_v9.clone();
+        data_v8[1] = POOL_VERSION_8;
+        data_v8.truncate(data_v8.len() - 32);
+        assert_eq!(Pool::migrate(&data_v8).unwrap(), pool);
+
+        // refund deadline defaults to grace period after finish
+        pool.set_refund_deadline(0);
+        let mut data_v7 = data_v8.clone();
+        data_v7[1] = POOL_VERSION_7;
+        data_v7.truncate(data_v7.len() - 8);
+        assert_eq!(Pool::migrate(&data_v7).unwrap(), pool);
+
+        // older layouts start with version
+        let mut data_v6 = data_v7[1..].to_vec();
+        data_v6[0] = POOL_VERSION_6;
+        assert_eq!(Pool::migrate(&data_v6).unwrap(), pool);
+
+        // per user total is unlimited for older layouts
+        pool.per_user_max = 0;
+        let mut data_v5 = data_v6.clone();
+        data_v5[0] = POOL_VERSION_5;
+        data_v5.truncate(data_v5.len() - 8);
+        assert_eq!(Pool::migrate(&data_v5).unwrap(), pool);
+
+        // older layouts stored only kyc requirement kind
+        let mut data_v4 = data_v5.clone();
+        data_v4[0] = POOL_VERSION_4;
+        data_v4.drain(Pool::KYC_REQUIREMENT_END_V4..Pool::KYC_REQUIREMENT_END_V4 + 32);
+        assert_eq!(data_v4.len(), 398);
+        assert_eq!(Pool::migrate(&data_v4).unwrap(), pool);
+
+        // funded flag is not set for older layouts
+        pool.funded = false;
+        let mut data_v3 = data_v4.clone();
+        data_v3[0] = POOL_VERSION_3;
+        data_v3.pop();
+        assert_eq!(Pool::migrate(&data_v3).unwrap(), pool);
+
+        // older layout had two stages and no stages count
+        let mut data_v2 = data_v3.clone();
+        data_v2[0] = POOL_VERSION_2;
+        data_v2.drain(Pool::TIME_TABLE_END_V2..Pool::TIME_TABLE_END_V2 + 8);
+        data_v2.pop();
+        let migrated = Pool::migrate(&data_v2).unwrap();
+        assert_eq!(migrated, pool);
+
+        let mut data_v1 = data_v2.clone();
+        data_v1[0] = POOL_VERSION_1;
+        data_v1.truncate(data_v1.len() - 8);
+        let migrated = Pool::migrate(&data_v1).unwrap();
+        assert_eq!(migrated.version, POOL_VERSION);
+        assert_eq!(migrated.participant_count, 0);
+        assert_eq!(migrated.time_table, pool.time_table);
+        assert_eq!(migrated.stages_count, 2);
+        assert_eq!(
+            migrated.whitelist_ticket_amount,
+            pool.whitelist_ticket_amount
+        );
+    }
+
+    #[test]
+    fn migrate_market_user_kyc() {
+        let kyc = MarketUserKyc {
+            account_type: ACCOUNT_TYPE_MARKET_USER_KYC,
+            version: USER_KYC_VERSION,
+            market: Pubkey::new_unique(),
+            user_wallet: Pubkey::new_unique(),
+            expiration: 42,
+            provider: Pubkey::new_unique(),
+        };
+        let data = kyc.try_to_vec().unwrap();
+        assert_eq!(MarketUserKyc::migrate(&data).unwrap(), kyc);
+
+        let mut data_v2 = data[1..].to_vec();
+        data_v2[0] = USER_KYC_VERSION_2;
+        assert_eq!(MarketUserKyc::migrate(&data_v2).unwrap(), kyc);
+
+        let mut data_v1 = data_v2[..MarketUserKyc::LEN_V1].to_vec();
+        data_v1[0] = USER_KYC_VERSION_1;
+        let migrated = MarketUserKyc::migrate(&data_v1).unwrap();
+        assert_eq!(migrated.version, USER_KYC_VERSION);
+        assert_eq!(migrated.expiration, kyc.expiration);
+        assert_eq!(migrated.provider, DEFAULT_KYC_PROVIDER_KEY);
+
+        assert_eq!(
+            MarketUserKyc::migrate(&[0; MarketUserKyc::LEN]),
+            Err(ProgramError::UninitializedAccount)
+        );
+    }
+
+    #[test]
+    fn market_user_kyc_is_valid_for() {
+        let market = Pubkey::new_unique();
+        let user = Pubkey::new_unique();
+        let kyc = MarketUserKyc {
+            account_type: ACCOUNT_TYPE_MARKET_USER_KYC,
+            version: USER_KYC_VERSION,
+            market,
+            user_wallet: user,
+            expiration: 42,
+            provider: Pubkey::new_unique(),
+        };
+
+        assert!(kyc.is_valid_for(&market, &user, 41));
+        assert!(kyc.is_valid_for(&market, &user, 42));
+        assert!(!kyc.is_valid_for(&Pubkey::new_unique(), &user, 41));
+        assert!(!kyc.is_valid_for(&market, &Pubkey::new_unique(), 41));
+        assert!(!kyc.is_valid_for(&market, &user, 43));
+    }
+
+    #[test]
+    fn market_fee() {
+        let mut market = Market::default();
+        assert!(!market.fee_enabled());
+        assert_eq!(market.fee(1_000).unwrap(), 0);
+
+        market.fee_basis_points = 250;
+        assert!(market.fee_enabled());
+        assert_eq!(market.fee(1_000).unwrap(), 25);
+        assert_eq!(market.fee(39).unwrap(), 0);
+        assert_eq!(market.fee(41).unwrap(), 1);
+
+        market.fee_basis_points = BASIS_POINTS;
+        assert_eq!(market.fee(u64::MAX).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn account_types() {
+        let market = Market {
+            account_type: ACCOUNT_TYPE_MARKET,
+            version: MARKET_VERSION,
+            ..Default::default()
+        };
+        let mut pool = pool_new(10, 10);
+        pool.version = POOL_VERSION;
+        let kyc = MarketUserKyc {
+            account_type: ACCOUNT_TYPE_MARKET_USER_KYC,
+            version: USER_KYC_VERSION,
+            market: Pubkey::new_unique(),
+            user_wallet: Pubkey::new_unique(),
+            expiration: 42,
+            provider: Pubkey::new_unique(),
+        };
+        let market_data = market.try_to_vec().unwrap();
+        let pool_data = pool.try_to_vec().unwrap();
+        let kyc_data = kyc.try_to_vec().unwrap();
+        assert_eq!(market_data[0], ACCOUNT_TYPE_MARKET);
+        assert_eq!(pool_data[0], ACCOUNT_TYPE_POOL);
+        assert_eq!(kyc_data[0], ACCOUNT_TYPE_MARKET_USER_KYC);
+        assert!(market.initialized().is_ok());
+        assert!(pool.initialized().is_ok());
+        assert!(kyc.initialized().is_ok());
+
+        let wrong_type: ProgramError = Error::WrongAccountType.into();
+        assert_eq!(Market::migrate(&pool_data).unwrap_err(), wrong_type);
+        assert_eq!(Market::migrate(&kyc_data).unwrap_err(), wrong_type);
+        assert_eq!(Pool::migrate(&market_data).unwrap_err(), wrong_type);
+        assert_eq!(Pool::migrate(&kyc_data).unwrap_err(), wrong_type);
+        assert_eq!(
+            MarketUserKyc::migrate(&market_data).unwrap_err(),
+            wrong_type
+        );
+        assert_eq!(MarketUserKyc::migrate(&pool_data).unwrap_err(), wrong_type);
+
+        let mut market_sized_as_pool = market_data;
+        market_sized_as_pool.resize(Pool::LEN, 0);
+        assert_eq!(
+            PoolView::new(&market_sized_as_pool).unwrap_err(),
+            wrong_type
+        );
+        let pool_as_market = Pool::try_from_slice(&market_sized_as_pool).unwrap();
+        assert_eq!(pool_as_market.initialized().unwrap_err(), wrong_type);
+    }
+
+    #[test]
+    fn kyc_requirement_accepts() {
+        let provider_a = Pubkey::new_unique();
+        let provider_b = Pubkey::new_unique();
+
+        let not_required = KycRequirement::default();
+        assert!(!not_required.is_required());
+
+        let any = KycRequirement::AnyRequired;
+        assert!(any.is_required());
+        assert!(any.accepts(&provider_a));
+        assert!(any.accepts(&DEFAULT_KYC_PROVIDER_KEY));
+
+        let specific = KycRequirement::SpecificProvider(provider_a);
+        assert!(specific.is_required());
+        assert!(specific.accepts(&provider_a));
+        assert!(!specific.accepts(&provider_b));
+        assert!(!specific.accepts(&DEFAULT_KYC_PROVIDER_KEY));
+    }
+
+    #[test]
+    fn kyc_requirement_layout() {
+        let provider = Pubkey::new_unique();
+        for (requirement, kind, key) in [
+            (KycRequirement::NotRequired, 0, DEFAULT_KYC_PROVIDER_KEY),
+            (KycRequirement::AnyRequired, 1, DEFAULT_KYC_PROVIDER_KEY),
+            (KycRequirement::SpecificProvider(provider), 2, provider),
+        ]
+        .iter()
+        {
+            let data = requirement.try_to_vec().unwrap();
+            assert_eq!(data.len(), KycRequirement::LEN);
+            assert_eq!(data[0], *kind);
+            assert_eq!(data[1..], key.to_bytes());
+            assert_eq!(KycRequirement::try_from_slice(&data).unwrap(), *requirement);
+        }
+        assert_eq!(
+            KycRequirement::LEN,
+            solana_program::borsh::get_packed_len::<KycRequirement>()
+        );
+
+        let mut unknown = KycRequirement::AnyRequired.try_to_vec().unwrap();
+        unknown[0] = 3;
+        assert!(KycRequirement::try_from_slice(&unknown).is_err());
+    }
+
     #[test]
     fn test_pack_pool() {
         let goal_max = 10;
@@ -421,8 +1611,263 @@ mod tests {
         assert_eq!(pool, unpacked);
     }
 
+    #[test]
+    fn pool_view() {
+        let mut pool = pool_new(10, 10);
+        pool.version = POOL_VERSION;
+        pool.goal_min_collected = 7;
+        pool.time_start = 1_000;
+        pool.time_finish = 2_000;
+        pool.amount_collected = 11;
+        pool.amount_to_distribute = 13;
+        pool.authority_bump_seed = 254;
+        pool.funded = true;
+        pool.refund_deadline = 3_000;
+        pool.started = true;
+        let data = pool.try_to_vec().unwrap();
+
+        let view = PoolView::new(&data).unwrap();
+        assert_eq!(view.account_type(), ACCOUNT_TYPE_POOL);
+        assert_eq!(view.version(), pool.version);
+        assert_eq!(view.market(), pool.market);
+        assert_eq!(view.account_collection(), pool.account_collection);
+        assert_eq!(view.account_distribution(), pool.account_distribution);
+        assert_eq!(view.mint_pool(), pool.mint_pool);
+        assert_eq!(view.goal_min_collected(), pool.goal_min_collected);
+        assert_eq!(view.time_start(), pool.time_start);
+        assert_eq!(view.time_finish(), pool.time_finish);
+        assert_eq!(view.amount_collected(), pool.amount_collected);
+        assert_eq!(view.amount_to_distribute(), pool.amount_to_distribute);
+        assert_eq!(view.owner(), pool.owner);
+        assert_eq!(view.authority_bump_seed(), pool.authority_bump_seed);
+        assert_eq!(view.funded(), pool.funded);
+        assert_eq!(view.refund_deadline(), pool.refund_deadline);
+        assert_eq!(view.success(), pool.success());
+        assert_eq!(view.started(), pool.started);
+        assert_eq!(view.was_started(), Ok(()));
+        assert_eq!(view.was_started(), pool.was_started());
+
+        pool.started = false;
+        let data = pool.try_to_vec().unwrap();
+        assert_eq!(
+            PoolView::new(&data).unwrap().was_started(),
+            Err(Error::CanParticipateOnlyInStartedPool.into())
+        );
+        assert_eq!(
+            pool.was_started(),
+            Err(Error::CanParticipateOnlyInStartedPool.into())
+        );
+
+        pool.mint_whitelist = MintWhitelist::Key(Pubkey::new_unique());
+        let data = pool.try_to_vec().unwrap();
+        assert_eq!(PoolView::new(&data).unwrap().owner(), pool.owner);
+
+        let uninitialized = Pool::default().try_to_vec().unwrap();
+        assert_eq!(
+            PoolView::new(&uninitialized).unwrap().was_started(),
+            Err(ProgramError::UninitializedAccount)
+        );
+
+        // account larger than current layout and pool of older version are read as migrated
+        pool.started = true;
+        let mut data = pool.try_to_vec().unwrap();
+        data.push(0);
+        assert_eq!(PoolView::new(&data).unwrap().owner(), pool.owner);
+        pool.version = POOL_VERSION_9;
+        pool.participant_count = 1;
+        let mut data = pool.try_to_vec().unwrap();
+        data.truncate(Pool::LEN - 1);
+        let migrated = Pool::migrate(&data).unwrap();
+        for data in [data.clone(), [&data[..], &[0][..]].concat()].iter() {
+            let view = PoolView::new(data).unwrap();
+            assert_eq!(view.version(), POOL_VERSION);
+            assert_eq!(view.owner(), migrated.owner);
+            assert_eq!(view.refund_deadline(), migrated.refund_deadline);
+            assert_eq!(view.started(), migrated.started);
+            assert_eq!(view.was_started(), Ok(()));
+        }
+
+        pool.version = POOL_VERSION + 1;
+        let data = pool.try_to_vec().unwrap();
+        assert_eq!(
+            PoolView::new(&data).unwrap_err(),
+            Error::UnsupportedStateVersion.into()
+        );
+        assert_eq!(
+            pool.initialized(),
+            Err(Error::UnsupportedStateVersion.into())
+        );
+        assert_eq!(
+            PoolView::new(&data[1..]).unwrap_err(),
+            Error::UnsupportedStateVersion.into()
+        );
+    }
+
+    #[test]
+    fn mint_whitelist_layout() {
+        let key = Pubkey::new_unique();
+        let data = MintWhitelist::Key(key).try_to_vec().unwrap();
+        assert_eq!(data.len(), 1 + 32);
+        assert_eq!(data[0], 0);
+        assert_eq!(&data[1..], key.as_ref());
+        assert_eq!(
+            MintWhitelist::try_from_slice(&data).unwrap(),
+            MintWhitelist::Key(key)
+        );
+
+        let data = MintWhitelist::default().try_to_vec().unwrap();
+        assert_eq!(data.len(), 1 + 32);
+        assert_eq!(data[0], 1);
+        assert_eq!(&data[1..], DEFAULT_WHITELIST_KEY.as_ref());
+        assert_eq!(
+            MintWhitelist::try_from_slice(&data).unwrap(),
+            MintWhitelist::default()
+        );
+
+        assert!(MintWhitelist::try_from_slice(&[2; 33]).is_err());
+    }
+
+    #[test]
+    fn len() {
+        let market = Market {
+            account_type: ACCOUNT_TYPE_MARKET,
+            version: MARKET_VERSION,
+            owner: Pubkey::new_unique(),
+            stake_pool: Pubkey::new_unique(),
+            pool_defaults: PoolDefaults {
+                is_set: true,
+                amount_investment_min: u64::MAX,
+                amount_investment_max: u64::MAX,
+                kyc_requirement: KycRequirement::SpecificProvider(Pubkey::new_unique()),
+                time_table: [u32::MAX; crate::STAGES_ACTIVE_COUNT],
+                stages_count: crate::STAGES_ACTIVE_COUNT as u8,
+            },
+            fee_basis_points: u16::MAX,
+            fee_account: Pubkey::new_unique(),
+            pool_count: u64::MAX,
+        };
+        assert_eq!(Market::LEN, market.try_to_vec().unwrap().len());
+
+        let mut pool = pool_new(u64::MAX, u64::MAX);
+        assert_eq!(Pool::LEN, pool.try_to_vec().unwrap().len());
+        pool.mint_whitelist = MintWhitelist::Key(Pubkey::new_unique());
+        pool.kyc_requirement = KycRequirement::AnyRequired;
+        assert_eq!(Pool::LEN, pool.try_to_vec().unwrap().len());
+
+        pool.kyc_requirement = KycRequirement::SpecificProvider(Pubkey::new_unique());
+        assert_eq!(Pool::LEN, pool.try_to_vec().unwrap().len());
+
+        let market_user_kyc = MarketUserKyc {
+            account_type: ACCOUNT_TYPE_MARKET_USER_KYC,
+            version: USER_KYC_VERSION,
+            market: Pubkey::new_unique(),
+            user_wallet: Pubkey::new_unique(),
+            expiration: UnixTimestamp::MAX,
+            provider: Pubkey::new_unique(),
+        };
+        assert_eq!(
+            MarketUserKyc::LEN,
+            market_user_kyc.try_to_vec().unwrap().len()
+        );
+
+        assert_eq!(
+            UserPoolStage::LEN,
+            UserPoolStage {}.try_to_vec().unwrap().len()
+        );
+
+        let user_pool = UserPool {
+            version: USER_POOL_VERSION,
+            amount_collected: CollectionToken::MAX,
+        };
+        assert_eq!(UserPool::LEN, user_pool.try_to_vec().unwrap().len());
+
+        let receipt = ParticipationReceipt::new(Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(
+            ParticipationReceipt::LEN,
+            receipt.try_to_vec().unwrap().len()
+        );
+    }
+
+    #[test]
+    fn participation_receipt_record() {
+        let mut receipt = ParticipationReceipt::new(Pubkey::new_unique(), Pubkey::new_unique());
+        assert_eq!(receipt.tier, TIERS_COUNT as u8);
+
+        receipt.record(10, 20, Some(2)).unwrap();
+        receipt.record(5, 10, None).unwrap();
+        assert_eq!(receipt.amount_collected, 15);
+        assert_eq!(receipt.amount_to_distribute, 30);
+        assert_eq!(receipt.tier, 2);
+
+        assert_eq!(
+            receipt.record(CollectionToken::MAX, 0, None),
+            Err(Error::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn pool_default_len() {
+        assert_eq!(Pool::LEN, Pool::default().try_to_vec().unwrap().len());
+        assert_eq!(Market::LEN, Market::default().try_to_vec().unwrap().len());
+    }
+
+    #[test]
+    fn pool_schema() {
+        let schema = Pool::schema_container();
+        let packed = schema.try_to_vec().unwrap();
+        let unpacked = BorshSchemaContainer::try_from_slice(&packed).unwrap();
+        assert_eq!(schema, unpacked);
+        assert_eq!(unpacked.declaration, Pool::declaration());
+    }
+
+    #[test]
+    fn progress_basis_points() {
+        let mut pool = pool_new(1, 100);
+        pool.amount_collected = 0;
+        assert_eq!(pool.progress_basis_points(), 0);
+        assert!(!pool.min_goal_reached());
+
+        pool.amount_collected = 50;
+        assert_eq!(pool.progress_basis_points(), 5_000);
+        assert!(pool.min_goal_reached());
+
+        pool.amount_collected = 100;
+        assert_eq!(pool.progress_basis_points(), 10_000);
+        pool.amount_collected = u64::MAX;
+        assert_eq!(pool.progress_basis_points(), 10_000);
+
+        pool.goal_max_collected = u64::MAX;
+        pool.amount_collected = u64::MAX / 2;
+        assert_eq!(pool.progress_basis_points(), 4_999);
+
+        pool.goal_max_collected = 0;
+        pool.amount_collected = 0;
+        assert_eq!(pool.progress_basis_points(), 10_000);
+    }
+
+    #[test]
+    fn has_participants() {
+        let mut pool = pool_new(1, 100);
+        pool.amount_collected = 0;
+        assert!(!pool.has_participants());
+        assert!(pool.terms_mutable().is_ok());
+
+        pool.amount_collected = 1;
+        assert!(pool.has_participants());
+        assert_eq!(
+            pool.terms_mutable().unwrap_err(),
+            Error::PoolHasParticipants.into()
+        );
+
+        // all collected refunded from failed pool, still participated
+        pool.amount_collected = 0;
+        pool.participant_count = 1;
+        assert!(pool.has_participants());
+    }
+
     fn pool_new(price: u64, goal_max: u64) -> Pool {
         let pool = Pool {
+            account_type: ACCOUNT_TYPE_POOL,
             version: 1,
             market: Pubkey::new_unique(),
             account_collection: Pubkey::new_unique(),
@@ -441,10 +1886,18 @@ mod tests {
             owner: Pubkey::new_unique(),
             authority: Pubkey::new_unique(),
             authority_bump_seed: 10,
-            kyc_requirement: KycRequirement::NotRequired,
+            kyc_requirement: KycRequirement::default(),
             tier_allocation: [0; TIERS_COUNT],
             time_table: [0; crate::STAGES_ACTIVE_COUNT],
             tier_remaining: [5; TIERS_COUNT],
+            whitelist_ticket_amount: WHITELIST_TOKEN_AMOUNT,
+            participant_count: 0,
+            stages_count: crate::STAGES_ACTIVE_COUNT_V2 as u8,
+            funded: false,
+            per_user_max: 0,
+            refund_deadline: 0,
+            stake_pool: Pubkey::new_unique(),
+            started: false,
         };
         pool
     }
@@ -463,6 +1916,95 @@ mod tests {
         assert_eq!(pool.tier_allocation[3], 18181);
     }
 
+    #[test]
+    fn pool_collected_to_distributed_rounding() {
+        let pool = pool_new(3, 1_000_000);
+        assert_eq!(pool.collected_to_distributed(1).unwrap(), 333333333);
+        assert_eq!(pool.collected_to_distributed_ceil(1).unwrap(), 333333334);
+        assert_eq!(pool.collected_to_distributed(3).unwrap(), 1_000_000_000);
+        assert_eq!(
+            pool.collected_to_distributed_ceil(3).unwrap(),
+            1_000_000_000
+        );
+        assert_eq!(pool.collected_to_distributed_ceil(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn pool_validate_decimals() {
+        let mismatch: ProgramResult = Err(Error::MintDecimalsMismatch.into());
+        assert_eq!(Pool::validate_decimals(6, 9), Ok(()));
+        assert_eq!(Pool::validate_decimals(9, 6), Ok(()));
+        assert_eq!(Pool::validate_decimals(0, 8), Ok(()));
+        assert_eq!(Pool::validate_decimals(0, 9), mismatch);
+        assert_eq!(Pool::validate_decimals(0, u8::MAX), mismatch);
+        assert_eq!(Pool::validate_decimals(10, 0), Ok(()));
+        assert_eq!(Pool::validate_decimals(11, 0), mismatch);
+        assert_eq!(Pool::validate_decimals(u8::MAX, 0), mismatch);
+
+        // 0.1 collection token of 6 decimals for distribution token of 9 decimals
+        let pool = pool_new(Pool::PRECISION / 10 / 1_000, 1_000_000);
+        assert_eq!(pool.collected_to_distributed(1_000_000), Ok(10_000_000_000));
+    }
+
+    #[test]
+    fn pool_max_distribution() {
+        let goal_max = 1_000;
+        let mut pool = pool_new(2 * Pool::PRECISION, goal_max);
+        assert_eq!(pool.max_distribution(), Ok(500));
+        pool.amount_to_distribute = 0;
+        for amount in [400, goal_max - 400].iter() {
+            pool.update_distributed_from_collected(*amount, None, Stage::FinalStage)
+                .unwrap();
+        }
+        assert_eq!(pool.max_distribution(), Ok(pool.amount_to_distribute));
+
+        let mut pool = pool_new(3, 9);
+        pool.amount_to_distribute = 0;
+        pool.update_distributed_from_collected(9, None, Stage::FinalStage)
+            .unwrap();
+        assert_eq!(pool.max_distribution(), Ok(3_000_000_000));
+        assert_eq!(pool.max_distribution(), Ok(pool.amount_to_distribute));
+
+        // goal not divisible by price rounds up
+        let mut pool = pool_new(3, 1);
+        pool.amount_to_distribute = 0;
+        pool.update_distributed_from_collected(1, None, Stage::FinalStage)
+            .unwrap();
+        assert_eq!(pool.max_distribution(), Ok(333_333_334));
+        assert_eq!(pool.max_distribution(), Ok(pool.amount_to_distribute));
+
+        // reserve of each participation rounds up
+        let mut pool = pool_new(3, 2);
+        pool.amount_to_distribute = 0;
+        for _ in 0..2 {
+            pool.update_distributed_from_collected(1, None, Stage::FinalStage)
+                .unwrap();
+        }
+        assert_eq!(pool.max_distribution(), Ok(666_666_667));
+        assert_eq!(pool.amount_to_distribute, 666_666_668);
+
+        pool.price = 0;
+        assert!(pool.max_distribution().is_err());
+    }
+
+    #[test]
+    fn pool_tier_allocation_for() {
+        let tier_balance = [5000, 9000, 16000, 30000];
+        let mut pool = pool_new(1_000_000_000, 1_000_000);
+        pool.set_tier_allocations([100, 50, 25, 10], tier_balance)
+            .unwrap();
+        let allocation_for = |locked| match get_tier(tier_balance, locked) {
+            Some(tier) => pool.tier_allocation_for(tier),
+            None => 0,
+        };
+        assert_eq!(allocation_for(4999), 0);
+        assert_eq!(allocation_for(5000), 3030);
+        assert_eq!(allocation_for(8999), 3030);
+        assert_eq!(allocation_for(9001), 5454);
+        assert_eq!(pool.tier_allocation_for(TIERS_COUNT), 0);
+        assert_eq!(pool.tier_allocation_for(usize::MAX), 0);
+    }
+
     #[test]
     fn pool_math_equal() {
         let goal_max = 1_000_000;
@@ -499,6 +2041,62 @@ mod tests {
         assert_eq!(pool.tier_remaining[3], 0);
     }
 
+    #[test]
+    fn pool_compute_tier_allocations() {
+        let tier_balance = [5000, 9000, 16000, 30000];
+        let tier_users = [100, 0, 25, 10];
+        let mut pool = pool_new(1_000_000_000, 1_000_000);
+        let (tier_allocation, tier_remaining) = pool
+            .compute_tier_allocations(tier_users, tier_balance)
+            .unwrap();
+        assert_eq!(pool.tier_allocation, [0; TIERS_COUNT]);
+        assert_eq!(pool.tier_remaining, [5; TIERS_COUNT]);
+
+        // empty tier gets no remaining, its share is spread over other tiers
+        assert_eq!(tier_allocation, [4166, 7500, 13333, 25000]);
+        assert_eq!(tier_remaining, [416600, 0, 333325, 250075]);
+        assert_eq!(tier_remaining.iter().sum::<u64>(), 1_000_000);
+
+        pool.set_tier_allocations(tier_users, tier_balance).unwrap();
+        assert_eq!(pool.tier_allocation, tier_allocation);
+        assert_eq!(pool.tier_remaining, tier_remaining);
+    }
+
+    #[test]
+    fn pool_tier_allocations_remainder() {
+        // 100 distribution tokens over 140 shares, 98 allocated to users
+        let tier_users = [3, 2, 1, 1];
+        let tier_balance = [10, 20, 30, 40];
+        let mut pool = pool_new(Pool::PRECISION, 100);
+        pool.set_tier_allocations(tier_users, tier_balance).unwrap();
+        assert_eq!(pool.tier_allocation, [7, 14, 21, 28]);
+        assert_eq!(pool.tier_remaining, [21, 28, 21, 28 + 2]);
+        assert_eq!(
+            pool.tier_remaining.iter().sum::<u64>(),
+            pool.max_distribution().unwrap()
+        );
+
+        let (tier_allocation, tier_remaining) = pool
+            .compute_tier_allocations(tier_users, tier_balance)
+            .unwrap();
+        assert_eq!(tier_allocation, pool.tier_allocation);
+        assert_eq!(tier_remaining, pool.tier_remaining);
+    }
+
+    #[test]
+    fn pool_no_tier_users() {
+        let goal_max = 1_000_000;
+        let price = 1_000_000_000;
+        let mut pool = pool_new(price, goal_max);
+        pool.set_tier_allocations([0; TIERS_COUNT], [5000, 9000, 16000, 30000])
+            .unwrap();
+        assert_eq!(pool.tier_allocation, [0; TIERS_COUNT]);
+        assert_eq!(
+            pool.tier_remaining,
+            [0, 0, 0, goal_max * Pool::PRECISION / price]
+        );
+    }
+
     #[test]
     fn pool_skew() {
         let goal_max = 1_000_000;
@@ -514,11 +2112,11 @@ mod tests {
         assert_eq!(pool.tier_remaining[0], 16666 * 10);
         assert_eq!(pool.tier_remaining[1], 16666 * 10);
         assert_eq!(pool.tier_remaining[2], 16666 * 10);
-        assert_eq!(pool.tier_remaining[3], 500000);
+        assert_eq!(pool.tier_remaining[3], 500000 + 20);
 
         assert_eq!(
             pool.tier_remaining.iter().sum::<u64>(),
-            goal_max * Pool::PRECISION / price - 20
+            goal_max * Pool::PRECISION / price
         );
     }
 
@@ -527,7 +2125,7 @@ mod tests {
         let goal_max = 1_000_000;
         let price = 1_000_000_000;
         let pool = Pool {
-            time_table: [10, 20],
+            time_table: [10, 20, 0, 0],
             ..pool_new(price, goal_max)
         };
         let mut clock = Clock {
@@ -547,6 +2145,141 @@ mod tests {
         assert_eq!(pool.get_current_stage(&clock).unwrap(), Stage::FinalStage);
     }
 
+    #[test]
+    fn timeframe_boundaries() {
+        let timeframe = Timeframe {
+            start: 10,
+            finish: 20,
+        };
+        assert_eq!(timeframe.duration(), 10);
+
+        assert!(!timeframe.is_active(9));
+        assert!(timeframe.is_active(10));
+        assert!(timeframe.is_active(20));
+        assert!(!timeframe.is_active(21));
+
+        assert!(!timeframe.is_finished(19));
+        assert!(timeframe.is_finished(20));
+
+        assert!(timeframe.validate(10).is_ok());
+        assert_eq!(
+            timeframe.validate(11).unwrap_err(),
+            Error::InvalidPoolTimeFrame.into()
+        );
+        let instant = Timeframe {
+            start: 20,
+            finish: 20,
+        };
+        assert!(instant.validate(20).is_ok());
+        assert_eq!(
+            instant.validate(21).unwrap_err(),
+            Error::InvalidPoolTimeFrame.into()
+        );
+        let reversed = Timeframe {
+            start: 20,
+            finish: 10,
+        };
+        assert_eq!(
+            reversed.validate(0).unwrap_err(),
+            Error::InvalidPoolTimeFrame.into()
+        );
+    }
+
+    #[test]
+    fn pool_three_stages() {
+        let pool = Pool {
+            time_table: [10, 20, 30, 0],
+            stages_count: 3,
+            ..pool_new(1_000_000_000, 1_000_000)
+        };
+        let stage_at = |unix_timestamp| {
+            let clock = Clock {
+                unix_timestamp,
+                ..Clock::default()
+            };
+            (
+                pool.get_current_stage_index(&clock).unwrap(),
+                pool.get_current_stage(&clock).unwrap(),
+            )
+        };
+
+        assert_eq!(stage_at(pool.time_start), (0, Stage::InitialStage));
+        assert_eq!(stage_at(19), (0, Stage::InitialStage));
+        assert_eq!(stage_at(20), (1, Stage::TierAllocationStage));
+        assert_eq!(stage_at(39), (1, Stage::TierAllocationStage));
+        assert_eq!(stage_at(40), (2, Stage::TierAllocationStage));
+        assert_eq!(stage_at(69), (2, Stage::TierAllocationStage));
+        assert_eq!(stage_at(70), (3, Stage::FinalStage));
+        assert_eq!(stage_at(pool.time_finish), (3, Stage::FinalStage));
+
+        let clock = |unix_timestamp| Clock {
+            unix_timestamp,
+            ..Clock::default()
+        };
+        assert!(!pool.is_active(&clock(pool.time_start - 1)));
+        assert!(pool.is_active(&clock(pool.time_start)));
+        assert!(pool.is_active(&clock(pool.time_finish)));
+        assert!(!pool.is_active(&clock(pool.time_finish + 1)));
+        assert!(!pool.is_finished(&clock(pool.time_finish - 1)));
+        assert!(pool.is_finished(&clock(pool.time_finish)));
+
+        assert_eq!(pool.stage_finish(0), 20);
+        assert_eq!(pool.stage_finish(1), 40);
+        assert_eq!(pool.stage_finish(2), 70);
+        assert_eq!(pool.stage_finish(3), pool.time_finish);
+
+        let pool = Pool {
+            stages_count: 0,
+            ..pool
+        };
+        assert_eq!(
+            pool.get_current_stage(&Clock {
+                unix_timestamp: pool.time_start,
+                ..Clock::default()
+            })
+            .unwrap(),
+            Stage::FinalStage
+        );
+    }
+
+    #[test]
+    fn pool_stage_boundaries_at() {
+        let pool = Pool {
+            time_table: [10, 20, 0, 0],
+            stages_count: 2,
+            ..pool_new(1_000_000_000, 1_000_000)
+        };
+        let before = pool.time_start - 1;
+        let after = pool.time_finish + 1;
+        assert!(!pool.is_active_at(before));
+        assert!(!pool.is_active_at(after));
+        assert!(!pool.is_finished_at(pool.time_finish - 1));
+        assert!(pool.is_finished_at(pool.time_finish));
+        assert!(pool.is_finished_at(after));
+        for now in [before, after].iter() {
+            assert_eq!(
+                pool.get_current_stage_index_at(*now).unwrap_err(),
+                Error::CantDepositAtCurrentTime.into()
+            );
+            assert!(pool.get_current_stage_at(*now).is_err());
+        }
+
+        for (now, index, stage) in [
+            (pool.time_start, 0, Stage::InitialStage),
+            (pool.time_start + 9, 0, Stage::InitialStage),
+            (pool.time_start + 10, 1, Stage::TierAllocationStage),
+            (pool.time_start + 29, 1, Stage::TierAllocationStage),
+            (pool.time_start + 30, 2, Stage::FinalStage),
+            (pool.time_finish, 2, Stage::FinalStage),
+        ]
+        .iter()
+        {
+            assert!(pool.is_active_at(*now));
+            assert_eq!(pool.get_current_stage_index_at(*now), Ok(*index));
+            assert_eq!(pool.get_current_stage_at(*now), Ok(*stage));
+        }
+    }
+
     #[test]
     fn pool_invest_math() {
         let goal_max = 1_000_000;
@@ -573,6 +2306,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pool_invest_exhausted_tier() {
+        let mut pool = pool_new(1_000_000_000, 1_000_000);
+        pool.tier_remaining[1] = 0;
+
+        assert_eq!(
+            pool.stage_investment(10, Stage::TierAllocationStage, [3, 6, 9, 12], 7),
+            Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into())
+        );
+        assert_eq!(
+            pool.stage_investment(10, Stage::TierAllocationStage, [3, 6, 9, 12], 10)
+                .unwrap(),
+            (5, Some(2))
+        );
+        assert_eq!(
+            pool.stage_investment(10, Stage::FinalStage, [3, 6, 9, 12], 7)
+                .unwrap(),
+            (10, Some(1))
+        );
+    }
+
+    #[test]
+    fn pool_invest_partial_fill() {
+        let mut pool = pool_new(2 * Pool::PRECISION, 1_000_000);
+        pool.amount_to_distribute = 0;
+        pool.tier_remaining[1] = 10;
+
+        let (accepted, tier) = pool
+            .stage_investment(100, Stage::TierAllocationStage, [3, 6, 9, 12], 7)
+            .unwrap();
+        assert_eq!((accepted, tier), (20, Some(1)));
+        assert_eq!(pool.collected_to_distributed(accepted).unwrap(), 10);
+
+        pool.update_distributed_from_collected(accepted, tier, Stage::TierAllocationStage)
+            .unwrap();
+        assert_eq!(pool.tier_remaining[1], 0);
+        assert_eq!(pool.amount_to_distribute, 10);
+
+        // remaining less than one collected token is not enough to invest
+        pool.tier_remaining[1] = 1;
+        assert_eq!(
+            pool.stage_investment(100, Stage::TierAllocationStage, [3, 6, 9, 12], 7),
+            Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into())
+        );
+
+        // remaining is never overdrawn by decrement
+        pool.tier_remaining[1] = 10;
+        assert_eq!(
+            pool.update_distributed_from_collected(22, Some(1), Stage::TierAllocationStage),
+            Err(Error::Underflow.into())
+        );
+    }
+
+    #[test]
+    fn pool_update_distributed_from_collected() {
+        let mut pool = pool_new(3, 1_000_000);
+        pool.amount_to_distribute = 0;
+        pool.tier_remaining = [10_000_000_000; TIERS_COUNT];
+
+        pool.update_distributed_from_collected(1, Some(1), Stage::InitialStage)
+            .unwrap();
+        assert_eq!(pool.amount_to_distribute, 333333334);
+        assert_eq!(
+            pool.tier_remaining,
+            [
+                10_000_000_000,
+                9_666_666_667,
+                10_000_000_000,
+                10_000_000_000
+            ]
+        );
+
+        pool.update_distributed_from_collected(3, Some(2), Stage::TierAllocationStage)
+            .unwrap();
+        assert_eq!(pool.amount_to_distribute, 1_333_333_334);
+        assert_eq!(
+            pool.tier_remaining,
+            [10_000_000_000, 9_666_666_667, 9_000_000_000, 10_000_000_000]
+        );
+
+        let tier_remaining = pool.tier_remaining;
+        pool.update_distributed_from_collected(1, None, Stage::FinalStage)
+            .unwrap();
+        assert_eq!(pool.amount_to_distribute, 1_666_666_668);
+        pool.update_distributed_from_collected(3, Some(0), Stage::FinalStage)
+            .unwrap();
+        assert_eq!(pool.amount_to_distribute, 2_666_666_668);
+        assert_eq!(pool.tier_remaining, tier_remaining);
+
+        for stage in [Stage::InitialStage, Stage::TierAllocationStage].iter() {
+            assert_eq!(
+                pool.update_distributed_from_collected(1, None, *stage),
+                Err(Error::AccountOnThisTierCannotParticipateOnCurrentStage.into())
+            );
+        }
+        assert_eq!(pool.amount_to_distribute, 2_666_666_668);
+        assert_eq!(pool.tier_remaining, tier_remaining);
+
+        pool.tier_remaining[3] = 0;
+        assert_eq!(
+            pool.update_distributed_from_collected(1, Some(3), Stage::TierAllocationStage),
+            Err(Error::Underflow.into())
+        );
+    }
+
     #[test]
     fn market() {
         assert_eq!(
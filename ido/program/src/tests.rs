@@ -3,22 +3,29 @@ use std::u64;
 use crate::{
     error::Error,
     instruction::{
-        self, create_market_user_kyc, delete_market_user_kyc, CreateMarketUserKyc, InitializeMarket,
+        self, create_market_user_kyc, delete_market_user_kyc, renew_market_user_kyc,
+        CreateMarketUserKyc, InitializeMarket, RenewMarketUserKyc,
     },
     spl_token_id,
     state::{self, KycRequirement, MarketUserKyc},
     utils::sdk::lock_transaction,
     TIERS_COUNT,
 };
-use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 use num_traits::ToPrimitive;
 use sol_starter_staking::{
     instruction::{InitializePoolInput, StakeStartInput},
-    program::{ProgramPubkey, PubkeyPatterns},
+    program::{PoolUserAuthority, ProgramPubkey, PubkeyPatterns},
     state::{PoolTransit, StakePool},
 };
 use solana_program::{
-    clock::Clock, instruction::InstructionError, program_pack::Pack, pubkey::Pubkey,
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction as SolanaInstruction, InstructionError},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
     system_instruction,
 };
 use solana_program_test::*;
@@ -142,6 +149,28 @@ pub async fn create_mint(
     mint_account: &Keypair,
     mint_rent: u64,
     authority: &Pubkey,
+    decimals: u8,
+    initialize: bool,
+) -> Result<(), TransportError> {
+    create_mint_with_freeze_authority(
+        program_context,
+        mint_account,
+        mint_rent,
+        authority,
+        None,
+        decimals,
+        initialize,
+    )
+    .await
+}
+
+pub async fn create_mint_with_freeze_authority(
+    program_context: &mut ProgramTestContext,
+    mint_account: &Keypair,
+    mint_rent: u64,
+    authority: &Pubkey,
+    freeze_authority: Option<&Pubkey>,
+    decimals: u8,
     initialize: bool,
 ) -> Result<(), TransportError> {
     let mut instructions = vec![system_instruction::create_account(
@@ -158,8 +187,8 @@ pub async fn create_mint(
                 &spl_token_id().pubkey(),
                 &mint_account.pubkey(),
                 authority,
-                None,
-                0,
+                freeze_authority,
+                decimals,
             )
             .unwrap(),
         );
@@ -306,15 +335,23 @@ pub struct Pool {
     pub market: Pubkey,
     pub mint_collection: Keypair,
     pub mint_collection_authority: Keypair,
+    pub mint_collection_decimals: u8,
     pub mint_distribution: Keypair,
     pub mint_distribution_authority: Keypair,
+    pub mint_distribution_decimals: u8,
     pub account_collection: Keypair,
     pub account_distribution: Keypair,
     pub mint_pool: Keypair,
     pub account_pool_authority: Pubkey,
     pub mint_whitelist_account: Option<Pubkey>,
+    /// decimals of whitelist mint initialized before pool, program initializes it if not set
+    pub mint_whitelist_decimals: Option<u8>,
+    /// freeze authority of whitelist mint initialized before pool
+    pub mint_whitelist_freeze_authority: Option<Pubkey>,
     pub stake_pool: Pubkey,
     pub pool_lock: Pubkey,
+    /// market fee account passed to participation if market fee is enabled
+    pub account_fee: Option<Pubkey>,
 }
 
 impl Pool {
@@ -332,15 +369,20 @@ impl Pool {
             market: *market,
             mint_collection: Keypair::new(),
             mint_collection_authority: Keypair::new(),
+            mint_collection_decimals: 0,
             mint_distribution: Keypair::new(),
             mint_distribution_authority: Keypair::new(),
+            mint_distribution_decimals: 0,
             account_collection,
             account_distribution,
             mint_pool,
             account_pool_authority,
             mint_whitelist_account: None,
+            mint_whitelist_decimals: None,
+            mint_whitelist_freeze_authority: None,
             stake_pool,
             pool_lock,
+            account_fee: None,
         }
     }
 
@@ -401,6 +443,7 @@ impl Pool {
             &self.mint_collection,
             mint_account_min_rent,
             &self.mint_collection_authority.pubkey(),
+            self.mint_collection_decimals,
             true,
         )
         .await
@@ -411,6 +454,7 @@ impl Pool {
             &self.mint_distribution,
             mint_account_min_rent,
             &self.mint_distribution_authority.pubkey(),
+            self.mint_distribution_decimals,
             true,
         )
         .await
@@ -418,12 +462,16 @@ impl Pool {
 
         let mint_whitelist_keypair = Keypair::new();
         self.mint_whitelist_account = if mint_whitelist {
-            create_mint(
+            let (pool_authority, _) =
+                Pubkey::find_program_address(&[&self.pool.pubkey().to_bytes()[..32]], &crate::id());
+            create_mint_with_freeze_authority(
                 program_context,
                 &mint_whitelist_keypair,
                 mint_account_min_rent,
-                &self.mint_distribution_authority.pubkey(),
-                false,
+                &pool_authority,
+                self.mint_whitelist_freeze_authority.as_ref(),
+                self.mint_whitelist_decimals.unwrap_or(0),
+                self.mint_whitelist_decimals.is_some(),
             )
             .await
             .unwrap();
@@ -454,8 +502,7 @@ impl Pool {
         program_context
             .banks_client
             .process_transaction(transaction)
-            .await
-            .unwrap();
+            .await?;
         Ok(())
     }
 
@@ -472,6 +519,40 @@ impl Pool {
         mint_whitelist: Option<&Pubkey>,
         amount: u64,
         stage: u8,
+    ) -> Result<(), TransportError> {
+        self.participate_with_input(
+            program_context,
+            user_wallet,
+            user_account_from,
+            user_account_to,
+            pool_lock_account,
+            market_user_kyc,
+            account_whitelist,
+            mint_whitelist,
+            instruction::Participate {
+                amount,
+                whitelist_tickets: 0,
+                min_accepted: 0,
+                receipt: false,
+            },
+            stage,
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn participate_with_input(
+        &self,
+        program_context: &mut ProgramTestContext,
+        user_wallet: &Keypair,
+        user_account_from: &Pubkey,
+        user_account_to: &Pubkey,
+        pool_lock_account: &Pubkey,
+        market_user_kyc: Option<&Pubkey>,
+        account_whitelist: Option<&Pubkey>,
+        mint_whitelist: Option<&Pubkey>,
+        input: instruction::Participate,
+        stage: u8,
     ) -> Result<(), TransportError> {
         let mut transaction = Transaction::new_with_payer(
             &[instruction::participate(
@@ -489,7 +570,8 @@ impl Pool {
                 market_user_kyc,
                 account_whitelist,
                 mint_whitelist,
-                instruction::Participate { amount },
+                self.account_fee.as_ref(),
+                input,
                 stage,
             )
             .unwrap()],
@@ -514,6 +596,7 @@ impl Pool {
         user_authority: &Keypair,
         account_to: &Pubkey,
         claim_collectibles: bool,
+        create_account_to: Option<&instruction::CreateAccountTo>,
     ) -> Result<(), TransportError> {
         let account = if claim_collectibles {
             self.account_collection.pubkey()
@@ -530,6 +613,7 @@ impl Pool {
                 &self.mint_pool.pubkey(),
                 &account,
                 account_to,
+                create_account_to,
             )
             .unwrap()],
             Some(&program_context.payer.pubkey()),
@@ -546,6 +630,92 @@ impl Pool {
         Ok(())
     }
 
+    pub async fn withdraw(
+        &self,
+        program_context: &mut ProgramTestContext,
+        account_from: &Pubkey,
+        account_to: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::withdraw(
+                &crate::program_id(),
+                &self.pool.pubkey(),
+                &self.market,
+                &program_context.payer.pubkey(),
+                account_from,
+                account_to,
+                None,
+                instruction::Withdraw::CHECKED,
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn withdraw_all(
+        &self,
+        program_context: &mut ProgramTestContext,
+        account_to_collection: &Pubkey,
+        account_to_distribution: &Pubkey,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::withdraw_all(
+                &crate::program_id(),
+                &self.pool.pubkey(),
+                &self.market,
+                &program_context.payer.pubkey(),
+                &self.account_collection.pubkey(),
+                &self.account_distribution.pubkey(),
+                account_to_collection,
+                account_to_distribution,
+                instruction::Withdraw::CHECKED,
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn fund_distribution(
+        &self,
+        program_context: &mut ProgramTestContext,
+        account_from: Option<&Pubkey>,
+        amount: u64,
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::fund_distribution(
+                &crate::program_id(),
+                &self.pool.pubkey(),
+                &program_context.payer.pubkey(),
+                &self.account_distribution.pubkey(),
+                account_from,
+                instruction::FundDistribution { amount },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await?;
+        Ok(())
+    }
+
     pub async fn add_to_whitelist(
         &self,
         program_context: &mut ProgramTestContext,
@@ -570,6 +740,31 @@ impl Pool {
             .await?;
         Ok(())
     }
+
+    pub async fn add_many_to_whitelist(
+        &self,
+        program_context: &mut ProgramTestContext,
+        accounts_whitelist: &[Pubkey],
+    ) -> Result<(), TransportError> {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::add_many_to_whitelist(
+                &crate::program_id(),
+                &self.pool.pubkey(),
+                &program_context.payer.pubkey(),
+                accounts_whitelist,
+                &self.mint_whitelist_account.unwrap(),
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await?;
+        Ok(())
+    }
 }
 
 #[tokio::test]
@@ -613,8 +808,13 @@ async fn test_kyc() {
         amount_max: 100,
         time_start: now + 60 * 60,
         time_finish: now + 3 * 60 * 60,
-        kyc_requirement: KycRequirement::AnyRequired,
-        time_table: [0; crate::STAGES_ACTIVE_COUNT],
+        kyc_requirement: Some(KycRequirement::AnyRequired),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
     };
 
     let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
@@ -660,6 +860,12 @@ async fn test_kyc() {
 
     // Rewind slots to do investment
     warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
 
     pool.participate(
         &mut program_context,
@@ -677,7 +883,7 @@ async fn test_kyc() {
     .unwrap_err();
 
     let (transaction, market_user_kyc) =
-        create_market_user_kyc_transaction(market.pubkey(), &program_context, &user_wallet);
+        create_market_user_kyc_transaction(market.pubkey(), &program_context, &user_wallet, None);
 
     program_context
         .banks_client
@@ -753,90 +959,8 @@ async fn test_kyc() {
     assert!(account.is_none());
 }
 
-fn delete_user_market_kyc_transaction(
-    market: &Pubkey,
-    program_context: &ProgramTestContext,
-    user_wallet: &Keypair,
-) -> Transaction {
-    let instruction = delete_market_user_kyc(
-        &crate::program_id(),
-        &market,
-        &program_context.payer.pubkey(),
-        &user_wallet.pubkey(),
-    )
-    .unwrap();
-    let mut transaction =
-        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
-    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
-    transaction
-}
-
-fn calc_market_user_kyc(market: &Pubkey,
-    user_wallet: &Pubkey,) -> Pubkey {
-    let (market_user_authority_key, _) =
-        Pubkey::find_2key_program_address(&market, &user_wallet, &crate::program_id());
-    let market_user_kyc =
-        Pubkey::create_with_seed(&market_user_authority_key, crate::KYC_SEED, &crate::id()).unwrap();
-    market_user_kyc
-}
-
-fn create_market_user_kyc_transaction(
-    market: Pubkey,
-    program_context: &ProgramTestContext,
-    user_wallet: &Keypair,
-) -> (Transaction, Pubkey) {
-    let instruction = create_market_user_kyc(
-        &market,
-        &program_context.payer.pubkey(),
-        &user_wallet.pubkey(),
-        CreateMarketUserKyc {
-            expiration: 1_000_000_000_000_000,
-        },
-    ).unwrap();
-
-    let mut transaction =
-        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
-    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
-    (transaction, calc_market_user_kyc(&market, &user_wallet.pubkey()))
-}
-
-#[tokio::test]
-async fn test_initialize_pool() {
-    let mut program_context = program_test().start_with_context().await;
-    let stake_pool = Pubkey::new_unique();
-    let market = Keypair::new();
-    let market = create_market(&mut program_context, stake_pool, market).await;
-
-    let now = get_clock(&mut program_context).await.unix_timestamp;
-
-    let input = instruction::InitializePool {
-        pool_owner: program_context.payer.pubkey(),
-        price: 5,
-        goal_max: 100,
-        goal_min: 90,
-        amount_min: 3,
-        amount_max: 10,
-        time_start: now + 60 * 60,
-        time_finish: now + 10 * 60 * 60,
-        kyc_requirement: KycRequirement::NotRequired,
-        time_table: [0; crate::STAGES_ACTIVE_COUNT],
-    };
-
-    let pool_lock = Pubkey::new_unique();
-    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
-    pool.create_pool(&mut program_context, false, input)
-        .await
-        .unwrap();
-
-    // Check that pool is initialized
-    let pool_info = get_account(&mut program_context, &pool.pool.pubkey()).await;
-    let pool_info = state::Pool::try_from_slice(&pool_info.data.as_slice()).unwrap();
-
-    pool_info.initialized().unwrap();
-}
-
 #[tokio::test]
-async fn test_participate() {
+async fn test_renew_kyc() {
     let mut program_context = program_test();
 
     program_context.add_program(
@@ -855,43 +979,47 @@ async fn test_participate() {
 
     let market = Keypair::new();
     let tiers_balance = [50, 100, 150, 200];
-    let pool_lock_amount = 2500;
 
     let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
         program_context,
         market.pubkey(),
         &user_wallet,
         tiers_balance,
-        pool_lock_amount,
+        2500,
     )
     .await;
 
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
     let now = get_clock(&mut program_context).await.unix_timestamp;
     let init_args = instruction::InitializePool {
         pool_owner: user_wallet.pubkey(),
         price: 5,
-        goal_max: 1_000_000,
+        goal_max: 150,
         goal_min: 10,
         amount_min: 3,
-        amount_max: 1_000_000,
+        amount_max: 100,
         time_start: now + 60 * 60,
-        time_finish: now + 10 * 60 * 60,
-        kyc_requirement: KycRequirement::NotRequired,
-        time_table: [60 * 60, 60 * 60],
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::AnyRequired),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
     };
-    let user_investment_amount = 50;
-
-    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
 
     let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
     pool.create_pool(&mut program_context, false, init_args.clone())
         .await
         .unwrap();
 
+    let user_investment_amount = 50;
+    let user_collection_account = Keypair::new();
+
     let rent = program_context.banks_client.get_rent().await.unwrap();
     let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
 
-    let user_collection_account = Keypair::new();
     create_token_account(
         &mut program_context,
         &user_collection_account,
@@ -901,7 +1029,6 @@ async fn test_participate() {
     )
     .await
     .unwrap();
-
     mint_tokens_to(
         &mut program_context,
         &pool.mint_collection.pubkey(),
@@ -912,10 +1039,10 @@ async fn test_participate() {
     .await
     .unwrap();
 
-    let user_account_to = Keypair::new();
+    let user_pool_token_account = Keypair::new();
     create_token_account(
         &mut program_context,
-        &user_account_to,
+        &user_pool_token_account,
         token_account_min_rent,
         &pool.mint_pool.pubkey(),
         &user_wallet.pubkey(),
@@ -923,151 +1050,224 @@ async fn test_participate() {
     .await
     .unwrap();
 
-    warp_seconds(&mut program_context, 1 * 60 * 60).await;
-
-    let error = pool
-        .participate(
-            &mut program_context,
-            &user_wallet,
-            &user_collection_account.pubkey(),
-            &user_account_to.pubkey(),
-            &pool_lock_token,
-            None,
-            None,
+    let original_expiration = now + 2 * 60 * 60;
+    let mut transaction = Transaction::new_with_payer(
+        &[create_market_user_kyc(
+            &market.pubkey(),
+            &program_context.payer.pubkey(),
+            &user_wallet.pubkey(),
             None,
-            5,
-            0,
+            CreateMarketUserKyc {
+                expiration: original_expiration,
+            },
         )
-        .await
-        .unwrap_err();
-
-    let _expected_error = TransportError::TransactionError(TransactionError::InstructionError(
-        0,
-        InstructionError::Custom(Error::CanParticipateOnlyInStartedPool.to_u32().unwrap()),
-    ));
-    assert!(matches!(error, _expected_error));
-
-    let transaction = start_pool_transaction(&program_context, &pool);
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
     program_context
         .banks_client
         .process_transaction(transaction)
         .await
         .unwrap();
+    let market_user_kyc = calc_market_user_kyc(&market.pubkey(), &user_wallet.pubkey());
 
-    let pool_account = program_context
+    let renew_transaction = |program_context: &ProgramTestContext, user_wallet: &Pubkey| {
+        let mut transaction = Transaction::new_with_payer(
+            &[renew_market_user_kyc(
+                &crate::program_id(),
+                &market.pubkey(),
+                &program_context.payer.pubkey(),
+                user_wallet,
+                RenewMarketUserKyc {
+                    expiration: now + 10 * 60 * 60,
+                },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
+
+    program_context
         .banks_client
-        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .process_transaction(renew_transaction(&program_context, &Pubkey::new_unique()))
+        .await
+        .unwrap_err();
+
+    program_context
+        .banks_client
+        .process_transaction(renew_transaction(&program_context, &user_wallet.pubkey()))
         .await
         .unwrap();
-    assert_eq!(
-        pool_account.tier_allocation,
-        [
-            50000000000000,
-            100000000000000,
-            150000000000000,
-            200000000000000
-        ]
-    );
-    assert_eq!(pool_account.tier_remaining, [0, 0, 0, 200000000000000]);
 
-    warp_seconds(&mut program_context, 1 * 60 * 60).await;
+    let account_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<MarketUserKyc>(market_user_kyc)
+        .await
+        .unwrap();
+    assert_eq!(account_state.user_wallet, user_wallet.pubkey());
+    assert_eq!(account_state.market, market.pubkey());
+    assert_eq!(account_state.expiration, now + 10 * 60 * 60);
+
+    // original credentials would be expired by now
+    warp_seconds(&mut program_context, 150 * 60).await;
+    assert!(get_clock(&mut program_context).await.unix_timestamp > original_expiration);
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
 
     pool.participate(
         &mut program_context,
         &user_wallet,
         &user_collection_account.pubkey(),
-        &user_account_to.pubkey(),
+        &user_pool_token_account.pubkey(),
         &pool_lock_token,
-        None,
+        Some(&market_user_kyc),
         None,
         None,
         user_investment_amount,
-        1,
+        2,
     )
     .await
     .unwrap();
-
-    let user_pool_token_account_info =
-        get_account(&mut program_context, &user_account_to.pubkey()).await;
-    let user_pool_token_account_info =
-        spl_token::state::Account::unpack_from_slice(user_pool_token_account_info.data.as_slice())
-            .unwrap();
-    assert_eq!(user_pool_token_account_info.amount, user_investment_amount);
 }
 
-fn start_pool_transaction(program_context: &ProgramTestContext, pool: &Pool) -> Transaction {
-    let mut transaction = Transaction::new_with_payer(
-        &[instruction::start_pool(
-            &crate::program_id(),
-            &program_context.payer.pubkey(),
-            &pool.stake_pool,
-            &pool.market.pubkey(),
-            &pool.pool.pubkey(),
-        )
-        .unwrap()],
-        Some(&program_context.payer.pubkey()),
-    );
+fn delete_user_market_kyc_transaction(
+    market: &Pubkey,
+    program_context: &ProgramTestContext,
+    user_wallet: &Keypair,
+) -> Transaction {
+    let instruction = delete_market_user_kyc(
+        &crate::program_id(),
+        &market,
+        &program_context.payer.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .unwrap();
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
     transaction.sign(&[&program_context.payer], program_context.last_blockhash);
     transaction
 }
 
-#[tokio::test]
-async fn test_claim() {
-    let mut program_context = program_test();
+fn calc_market_user_kyc(market: &Pubkey,
+    user_wallet: &Pubkey,) -> Pubkey {
+    let (market_user_authority_key, _) =
+        Pubkey::find_2key_program_address(&market, &user_wallet, &crate::program_id());
+    let market_user_kyc =
+        Pubkey::create_with_seed(&market_user_authority_key, crate::KYC_SEED, &crate::id()).unwrap();
+    market_user_kyc
+}
 
-    program_context.add_program(
-        "sol_starter_staking",
-        sol_starter_staking::id(),
-        processor!(crate::processor::Processor::process_instruction),
-    );
-    let user_wallet = Keypair::new();
-    program_context.add_account(
-        user_wallet.pubkey(),
-        Account {
-            lamports: 1_000_000_000_000_000,
-            ..Default::default()
+fn create_market_user_kyc_transaction(
+    market: Pubkey,
+    program_context: &ProgramTestContext,
+    user_wallet: &Keypair,
+    kyc_provider: Option<&Keypair>,
+) -> (Transaction, Pubkey) {
+    let instruction = create_market_user_kyc(
+        &market,
+        &program_context.payer.pubkey(),
+        &user_wallet.pubkey(),
+        kyc_provider
+            .map(|kyc_provider| kyc_provider.pubkey())
+            .as_ref(),
+        CreateMarketUserKyc {
+            expiration: 1_000_000_000_000_000,
         },
-    );
+    )
+    .unwrap();
 
-    let market = Keypair::new();
-    let tiers_balance = [50, 100, 150, 200];
-    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
-        program_context,
-        market.pubkey(),
-        &user_wallet,
-        tiers_balance,
-        2500,
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
+    let mut signers = vec![&program_context.payer];
+    signers.extend(kyc_provider);
+    transaction.sign(&signers, program_context.last_blockhash);
+    (
+        transaction,
+        calc_market_user_kyc(&market, &user_wallet.pubkey()),
     )
-    .await;
+}
 
-    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
-    let now = get_clock(&mut program_context).await.unix_timestamp;
+/// Creates pool requiring `kyc_requirement`, returns it with funded user collection and pool token accounts
+async fn setup_kyc_pool(
+    program_context: &mut ProgramTestContext,
+    market: &Pubkey,
+    stake_pool: Pubkey,
+    pool_lock: Pubkey,
+    user_wallet: &Keypair,
+    kyc_requirement: KycRequirement,
+    amount: u64,
+) -> (Pool, Pubkey, Pubkey) {
+    let now = get_clock(program_context).await.unix_timestamp;
     let init_args = instruction::InitializePool {
-        pool_owner: user_wallet.pubkey(),
-        price: 5,
         goal_max: 150,
-        goal_min: 10,
-        amount_min: 3,
         amount_max: 100,
-        time_start: now + 60 * 60,
         time_finish: now + 3 * 60 * 60,
-        kyc_requirement: KycRequirement::NotRequired,
-        time_table: [0; crate::STAGES_ACTIVE_COUNT],
+        kyc_requirement: Some(kyc_requirement),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        ..pool_init_args(user_wallet.pubkey(), now)
     };
+    setup_user_pool(
+        program_context,
+        market,
+        stake_pool,
+        pool_lock,
+        user_wallet,
+        init_args,
+        amount,
+    )
+    .await
+}
 
-    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
-    pool.create_pool(&mut program_context, false, init_args.clone())
+/// Pool of two stages an hour long each, starting in an hour from `now`, of price 5 and without KYC
+fn pool_init_args(pool_owner: Pubkey, now: i64) -> instruction::InitializePool {
+    instruction::InitializePool {
+        pool_owner,
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    }
+}
+
+/// Creates pool of `init_args`, collection account of `user_wallet` holding `amount`
+/// and its pool token account. Returns pool, collection account and pool token account.
+async fn setup_user_pool(
+    program_context: &mut ProgramTestContext,
+    market: &Pubkey,
+    stake_pool: Pubkey,
+    pool_lock: Pubkey,
+    user_wallet: &Keypair,
+    init_args: instruction::InitializePool,
+    amount: u64,
+) -> (Pool, Pubkey, Pubkey) {
+    let mut pool = Pool::new(market, stake_pool, pool_lock);
+    pool.create_pool(program_context, false, init_args)
         .await
         .unwrap();
 
     let rent = program_context.banks_client.get_rent().await.unwrap();
     let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
 
-    let user_investment_amount = 50;
     let user_collection_account = Keypair::new();
-
     create_token_account(
-        &mut program_context,
+        program_context,
         &user_collection_account,
         token_account_min_rent,
         &pool.mint_collection.pubkey(),
@@ -1076,18 +1276,18 @@ async fn test_claim() {
     .await
     .unwrap();
     mint_tokens_to(
-        &mut program_context,
+        program_context,
         &pool.mint_collection.pubkey(),
         &user_collection_account.pubkey(),
         &pool.mint_collection_authority,
-        user_investment_amount,
+        amount,
     )
     .await
     .unwrap();
 
     let user_pool_token_account = Keypair::new();
     create_token_account(
-        &mut program_context,
+        program_context,
         &user_pool_token_account,
         token_account_min_rent,
         &pool.mint_pool.pubkey(),
@@ -1096,74 +1296,15 @@ async fn test_claim() {
     .await
     .unwrap();
 
-    // Rewind slots to do investment
-    warp_seconds(&mut program_context, 2 * 60 * 60).await;
-    pool.participate(
-        &mut program_context,
-        &user_wallet,
-        &user_collection_account.pubkey(),
-        &user_pool_token_account.pubkey(),
-        &pool_lock_token,
-        None,
-        None,
-        None,
-        user_investment_amount,
-        2,
-    )
-    .await
-    .unwrap();
-
-    mint_tokens_to(
-        &mut program_context,
-        &pool.mint_distribution.pubkey(),
-        &pool.account_distribution.pubkey(),
-        &pool.mint_distribution_authority,
-        100000000 * crate::state::Pool::PRECISION,
-    )
-    .await
-    .unwrap();
-
-    let user_distribution_token_account = Keypair::new();
-    create_token_account(
-        &mut program_context,
-        &user_distribution_token_account,
-        token_account_min_rent,
-        &pool.mint_distribution.pubkey(),
-        &user_wallet.pubkey(),
-    )
-    .await
-    .unwrap();
-
-    warp_seconds(&mut program_context, 2 * 60 * 60).await;
-
-    pool.claim(
-        &mut program_context,
-        &user_pool_token_account.pubkey(),
-        &user_wallet,
-        &user_distribution_token_account.pubkey(),
-        false,
-    )
-    .await
-    .unwrap();
-
-    let user_distribution_token_account_info = get_account(
-        &mut program_context,
-        &user_distribution_token_account.pubkey(),
+    (
+        pool,
+        user_collection_account.pubkey(),
+        user_pool_token_account.pubkey(),
     )
-    .await;
-    let user_distribution_token_account_info = spl_token::state::Account::unpack_from_slice(
-        user_distribution_token_account_info.data.as_slice(),
-    )
-    .unwrap();
-
-    assert_eq!(
-        user_investment_amount * crate::state::Pool::PRECISION / init_args.price,
-        user_distribution_token_account_info.amount
-    );
 }
 
 #[tokio::test]
-async fn test_add_to_whitelist() {
+async fn test_kyc_specific_provider() {
     let mut program_context = program_test();
 
     program_context.add_program(
@@ -1182,7 +1323,8 @@ async fn test_add_to_whitelist() {
 
     let market = Keypair::new();
     let tiers_balance = [50, 100, 150, 200];
-    let (mut program_context, stake_pool, pool_lock, _) = setup_staking(
+
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
         program_context,
         market.pubkey(),
         &user_wallet,
@@ -1192,206 +1334,5512 @@ async fn test_add_to_whitelist() {
     .await;
 
     let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
-    let now = get_clock(&mut program_context).await.unix_timestamp;
-    let init_args = instruction::InitializePool {
-        pool_owner: program_context.payer.pubkey(),
-        price: 5,
-        goal_max: 150,
-        goal_min: 10,
-        amount_min: 3,
-        amount_max: 100,
-        time_start: now + 60 * 60,
-        time_finish: now + 3 * 60 * 60,
-        kyc_requirement: KycRequirement::NotRequired,
-        time_table: [0; crate::STAGES_ACTIVE_COUNT],
+    let provider_a = Keypair::new();
+    let provider_b = Keypair::new();
+    let user_investment_amount = 50;
+
+    let (pool_a, pool_a_collection_account, pool_a_token_account) = setup_kyc_pool(
+        &mut program_context,
+        &market.pubkey(),
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        KycRequirement::SpecificProvider(provider_a.pubkey()),
+        user_investment_amount,
+    )
+    .await;
+    let (pool_b, pool_b_collection_account, pool_b_token_account) = setup_kyc_pool(
+        &mut program_context,
+        &market.pubkey(),
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        KycRequirement::SpecificProvider(provider_b.pubkey()),
+        user_investment_amount,
+    )
+    .await;
+
+    // provider approves KYC by signing, so its key can not be recorded without it
+    let mut instruction = create_market_user_kyc(
+        &market.pubkey(),
+        &program_context.payer.pubkey(),
+        &user_wallet.pubkey(),
+        Some(&provider_a.pubkey()),
+        CreateMarketUserKyc {
+            expiration: 1_000_000_000_000_000,
+        },
+    )
+    .unwrap();
+    instruction.accounts.last_mut().unwrap().is_signer = false;
+    let mut transaction =
+        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature
+        ))
+    ));
+
+    let (transaction, market_user_kyc) = create_market_user_kyc_transaction(
+        market.pubkey(),
+        &program_context,
+        &user_wallet,
+        Some(&provider_a),
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let account_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<MarketUserKyc>(market_user_kyc)
+        .await
+        .unwrap();
+    assert_eq!(account_state.provider, provider_a.pubkey());
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool_a);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    let transaction = start_pool_transaction(&program_context, &pool_b);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let error = pool_b
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &pool_b_collection_account,
+            &pool_b_token_account,
+            &pool_lock_token,
+            Some(&market_user_kyc),
+            None,
+            None,
+            user_investment_amount,
+            2,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WrongKycProvider.to_u32().unwrap()
+    ));
+
+    pool_a
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &pool_a_collection_account,
+            &pool_a_token_account,
+            &pool_lock_token,
+            Some(&market_user_kyc),
+            None,
+            None,
+            user_investment_amount,
+            2,
+        )
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_pool() {
+    let mut program_context = program_test().start_with_context().await;
+    let stake_pool = Pubkey::new_unique();
+    let market = Keypair::new();
+    let market = create_market(&mut program_context, stake_pool, market).await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+
+    let input = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 100,
+        goal_min: 90,
+        amount_min: 3,
+        amount_max: 10,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let pool_lock = Pubkey::new_unique();
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.create_pool(&mut program_context, false, input.clone())
+        .await
+        .unwrap();
+
+    // Check that pool is initialized
+    let pool_info = get_account(&mut program_context, &pool.pool.pubkey()).await;
+    let pool_info = state::Pool::try_from_slice(&pool_info.data.as_slice()).unwrap();
+
+    pool_info.initialized().unwrap();
+
+    // price scales between mints of different decimals, as USDC for token of 9 decimals
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.mint_collection_decimals = 6;
+    pool.mint_distribution_decimals = 9;
+    pool.create_pool(&mut program_context, false, input.clone())
+        .await
+        .unwrap();
+    let mint_pool = get_account(&mut program_context, &pool.mint_pool.pubkey()).await;
+    assert_eq!(Mint::unpack(&mint_pool.data).unwrap().decimals, 6);
+
+    // price of distribution token with as many decimals over collection token has no fraction
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.mint_distribution_decimals = state::Pool::PRECISION_DECIMALS;
+    let error = pool
+        .create_pool(&mut program_context, false, input.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::MintDecimalsMismatch.to_u32().unwrap()
+    ));
+
+    // whitelist mint initialized before pool is accepted if it is alike to one program initializes
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.mint_whitelist_decimals = Some(0);
+    pool.create_pool(&mut program_context, true, input.clone())
+        .await
+        .unwrap();
+    let pool_info = get_account(&mut program_context, &pool.pool.pubkey()).await;
+    let pool_info = state::Pool::try_from_slice(&pool_info.data.as_slice()).unwrap();
+    assert_eq!(
+        pool_info.mint_whitelist,
+        state::MintWhitelist::Key(pool.mint_whitelist_account.unwrap())
+    );
+
+    // whitelist tickets are burned whole
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.mint_whitelist_decimals = Some(2);
+    let error = pool
+        .create_pool(&mut program_context, true, input.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WhitelistMintInvalid.to_u32().unwrap()
+    ));
+
+    // tickets can not be frozen by anyone but pool
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, pool_lock);
+    pool.mint_whitelist_decimals = Some(0);
+    pool.mint_whitelist_freeze_authority = Some(Pubkey::new_unique());
+    let error = pool
+        .create_pool(&mut program_context, true, input)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WhitelistMintInvalid.to_u32().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn test_initialize_pool_with_nonce() {
+    let mut program_context = program_test().start_with_context().await;
+    let stake_pool = Pubkey::new_unique();
+    let market = Keypair::new();
+    let market = create_market(&mut program_context, stake_pool, market).await;
+    let market_owner = program_context.payer.pubkey();
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+
+    let mint_collection = Keypair::new();
+    let mint_distribution = Keypair::new();
+    for mint in [&mint_collection, &mint_distribution].iter() {
+        create_mint(
+            &mut program_context,
+            mint,
+            rent.minimum_balance(Mint::LEN),
+            &Pubkey::new_unique(),
+            0,
+            true,
+        )
+        .await
+        .unwrap();
+    }
+
+    let nonce = 42;
+    let derive = |kind, owner: &Pubkey| {
+        crate::utils::program::derive_pool_account(
+            &market_owner,
+            &market.pubkey(),
+            nonce,
+            kind,
+            owner,
+        )
+        .unwrap()
+    };
+    let pool = derive(crate::POOL_SEED, &crate::id());
+    let account_collection = derive(crate::POOL_COLLECTION_SEED, &spl_token::id());
+    let account_distribution = derive(crate::POOL_DISTRIBUTION_SEED, &spl_token::id());
+    let mint_pool = derive(crate::POOL_MINT_SEED, &spl_token::id());
+    let mint_whitelist = derive(crate::POOL_WHITELIST_SEED, &spl_token::id());
+
+    let create = |kind, len: usize, owner: &Pubkey| {
+        system_instruction::create_account_with_seed(
+            &market_owner,
+            &derive(kind, owner),
+            &market_owner,
+            &crate::utils::program::pool_account_seed(&market.pubkey(), nonce, kind),
+            rent.minimum_balance(len),
+            len as u64,
+            owner,
+        )
+    };
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            create(crate::POOL_SEED, state::Pool::LEN, &crate::id()),
+            create(
+                crate::POOL_COLLECTION_SEED,
+                TokenAccount::LEN,
+                &spl_token::id(),
+            ),
+            create(
+                crate::POOL_DISTRIBUTION_SEED,
+                TokenAccount::LEN,
+                &spl_token::id(),
+            ),
+            create(crate::POOL_MINT_SEED, Mint::LEN, &spl_token::id()),
+            create(crate::POOL_WHITELIST_SEED, Mint::LEN, &spl_token::id()),
+        ],
+        Some(&market_owner),
+    );
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let input = instruction::InitializePool {
+        pool_owner: market_owner,
+        price: 5,
+        goal_max: 100,
+        goal_min: 90,
+        amount_min: 3,
+        amount_max: 10,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: Some(nonce),
+    };
+    let initialize = |input| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::initialize_pool(
+                &crate::program_id(),
+                &pool,
+                &market.pubkey(),
+                &market_owner,
+                &mint_collection.pubkey(),
+                &mint_distribution.pubkey(),
+                &account_collection,
+                &account_distribution,
+                &mint_pool,
+                Some(mint_whitelist),
+                input,
+            )
+            .unwrap()],
+            Some(&market_owner),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
+
+    // accounts are derived from other nonce
+    let wrong_nonce = initialize(instruction::InitializePool {
+        nonce: Some(nonce + 1),
+        ..input.clone()
+    });
+    let right_nonce = initialize(input);
+    let error = program_context
+        .banks_client
+        .process_transaction(wrong_nonce)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WrongProgramAddress.to_u32().unwrap()
+    ));
+
+    program_context
+        .banks_client
+        .process_transaction(right_nonce)
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.market, market.pubkey());
+    assert_eq!(pool_state.account_collection, account_collection);
+    assert_eq!(pool_state.account_distribution, account_distribution);
+    assert_eq!(pool_state.mint_pool, mint_pool);
+    assert_eq!(
+        pool_state.mint_whitelist,
+        state::MintWhitelist::Key(mint_whitelist)
+    );
+}
+
+#[tokio::test]
+async fn test_initialize_pool_inherits_market_defaults() {
+    let mut program_context = program_test().start_with_context().await;
+    let stake_pool = Pubkey::new_unique();
+    let market = Keypair::new();
+    let market = create_market(&mut program_context, stake_pool, market).await;
+
+    let defaults = state::PoolDefaults {
+        is_set: true,
+        amount_investment_min: 5,
+        amount_investment_max: 50,
+        kyc_requirement: KycRequirement::AnyRequired,
+        time_table: [60 * 60, 60 * 60, 60 * 60, 0],
+        stages_count: 3,
+    };
+
+    let invalid_defaults = state::PoolDefaults {
+        amount_investment_min: 51,
+        ..defaults
+    };
+    let set_defaults = instruction::set_market_defaults(
+        &crate::program_id(),
+        &market.pubkey(),
+        &program_context.payer.pubkey(),
+        invalid_defaults,
+    )
+    .unwrap();
+    let mut transaction =
+        Transaction::new_with_payer(&[set_defaults], Some(&program_context.payer.pubkey()));
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::InvalidInvestmentSizeNumbers.to_u32().unwrap()
+    ));
+
+    let set_defaults = instruction::set_market_defaults(
+        &crate::program_id(),
+        &market.pubkey(),
+        &program_context.payer.pubkey(),
+        defaults,
+    )
+    .unwrap();
+    let mut transaction =
+        Transaction::new_with_payer(&[set_defaults], Some(&program_context.payer.pubkey()));
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(market.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(market_state.pool_defaults, defaults);
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let input = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 100,
+        goal_min: 90,
+        amount_min: 0,
+        amount_max: 0,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: None,
+        time_table: None,
+        stages_count: 0,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    // all unset fields are inherited
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, Pubkey::new_unique());
+    pool.create_pool(&mut program_context, false, input.clone())
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        pool_state.amount_investment_min,
+        defaults.amount_investment_min
+    );
+    assert_eq!(
+        pool_state.amount_investment_max,
+        defaults.amount_investment_max
+    );
+    assert_eq!(pool_state.kyc_requirement, defaults.kyc_requirement);
+    assert_eq!(pool_state.time_table, defaults.time_table);
+    assert_eq!(pool_state.stages_count, defaults.stages_count);
+
+    // explicit values override defaults
+    let input = instruction::InitializePool {
+        amount_min: 3,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        ..input
+    };
+    let mut pool = Pool::new(&market.pubkey(), stake_pool, Pubkey::new_unique());
+    pool.create_pool(&mut program_context, false, input)
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.amount_investment_min, 3);
+    assert_eq!(
+        pool_state.amount_investment_max,
+        defaults.amount_investment_max
+    );
+    assert_eq!(pool_state.kyc_requirement, KycRequirement::default());
+    assert_eq!(pool_state.time_table, [0; crate::STAGES_ACTIVE_COUNT]);
+    assert_eq!(pool_state.stages_count, 2);
+}
+
+#[tokio::test]
+async fn test_participate() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let pool_lock_amount = 2500;
+
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        pool_lock_amount,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+    let user_investment_amount = 50;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args.clone())
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 1 * 60 * 60).await;
+
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            5,
+            0,
+        )
+        .await
+        .unwrap_err();
+
+    let _expected_error = TransportError::TransactionError(TransactionError::InstructionError(
+        0,
+        InstructionError::Custom(Error::CanParticipateOnlyInStartedPool.to_u32().unwrap()),
+    ));
+    assert!(matches!(error, _expected_error));
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let pool_account = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        pool_account.tier_allocation,
+        [
+            50000000000000,
+            100000000000000,
+            150000000000000,
+            200000000000000
+        ]
+    );
+    assert_eq!(pool_account.tier_remaining, [0, 0, 0, 200000000000000]);
+
+    warp_seconds(&mut program_context, 1 * 60 * 60).await;
+
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            Some(&Pubkey::new_unique()),
+            Some(&Pubkey::new_unique()),
+            user_investment_amount,
+            1,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WhitelistNotEnabled.to_u32().unwrap()
+    ));
+
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account.pubkey(),
+        &user_account_to.pubkey(),
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        1,
+    )
+    .await
+    .unwrap();
+
+    let user_pool_token_account_info =
+        get_account(&mut program_context, &user_account_to.pubkey()).await;
+    let user_pool_token_account_info =
+        spl_token::state::Account::unpack_from_slice(user_pool_token_account_info.data.as_slice())
+            .unwrap();
+    assert_eq!(user_pool_token_account_info.amount, user_investment_amount);
+}
+
+#[tokio::test]
+async fn test_participate_once_per_stage() {
+    let user_wallet = Keypair::new();
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 2500)]).await;
+    let (pool_lock, pool_lock_token) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = pool_init_args(user_wallet.pubkey(), now);
+    let user_investment_amount = 10;
+    let (pool, user_collection_account, user_account_to) = setup_user_pool(
+        &mut program_context,
+        &market,
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        init_args,
+        3 * user_investment_amount,
+    )
+    .await;
+
+    // inside of first active stage
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    // repeated participation differs in amount, so it is not deduplicated as same transaction
+    let participations = [
+        (user_investment_amount, 0, true),
+        (user_investment_amount + 1, 0, false),
+    ];
+    for (amount, stage, success) in participations.iter() {
+        let result = pool
+            .participate(
+                &mut program_context,
+                &user_wallet,
+                &user_collection_account,
+                &user_account_to,
+                &pool_lock_token,
+                None,
+                None,
+                None,
+                *amount,
+                *stage,
+            )
+            .await;
+        if *success {
+            result.unwrap();
+        } else {
+            assert!(matches!(
+                result.unwrap_err(),
+                TransportError::TransactionError(TransactionError::InstructionError(
+                    _,
+                    InstructionError::Custom(code),
+                )) if code == Error::AccountAlreadyParticipatedOnThisStage.to_u32().unwrap()
+            ));
+        }
+    }
+
+    // next stage has own marker, so the same user participates again
+    warp_seconds(&mut program_context, 60 * 60).await;
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account,
+        &user_account_to,
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount + 1,
+        1,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_account_to)
+            .await
+            .amount,
+        2 * user_investment_amount + 1
+    );
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    // user is counted once over all stages
+    assert_eq!(pool_state.participant_count, 1);
+}
+
+#[tokio::test]
+async fn test_participate_per_user_max() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let user_investment_amount = 10;
+    let per_user_max = 15;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        3 * user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // inside of first active stage
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    // user pool can't be left out, participant would not be counted
+    let pool_user_authority = PoolUserAuthority::derive(
+        &pool.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &crate::program_id(),
+    );
+    let user_pool = Pubkey::create_with_seed(
+        &pool_user_authority.key,
+        crate::USER_POOL_SEED,
+        &crate::id(),
+    )
+    .unwrap();
+    let mut participate = instruction::participate(
+        &crate::program_id(),
+        &pool.pool.pubkey(),
+        &pool.market,
+        &user_wallet.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.account_collection.pubkey(),
+        &user_account_to.pubkey(),
+        &pool_lock_token,
+        &pool.mint_pool.pubkey(),
+        &pool.pool_lock,
+        &pool.stake_pool,
+        None,
+        None,
+        None,
+        None,
+        instruction::Participate {
+            amount: user_investment_amount,
+            whitelist_tickets: 0,
+            min_accepted: 0,
+            receipt: false,
+        },
+        0,
+    )
+    .unwrap();
+    participate
+        .accounts
+        .retain(|account| account.pubkey != user_pool);
+    let mut transaction =
+        Transaction::new_with_payer(&[participate], Some(&program_context.payer.pubkey()));
+    transaction.sign(
+        &[&program_context.payer, &user_wallet],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WrongUserPool.to_u32().unwrap()
+    ));
+
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account.pubkey(),
+        &user_account_to.pubkey(),
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        0,
+    )
+    .await
+    .unwrap();
+
+    // next stage allows participation again, so only total per user limits it
+    warp_seconds(&mut program_context, 60 * 60).await;
+    let participations = [
+        (user_investment_amount, false),
+        (per_user_max - user_investment_amount, true),
+    ];
+    for (amount, success) in participations.iter() {
+        let result = pool
+            .participate(
+                &mut program_context,
+                &user_wallet,
+                &user_collection_account.pubkey(),
+                &user_account_to.pubkey(),
+                &pool_lock_token,
+                None,
+                None,
+                None,
+                *amount,
+                1,
+            )
+            .await;
+        if *success {
+            result.unwrap();
+        } else {
+            assert!(matches!(
+                result.unwrap_err(),
+                TransportError::TransactionError(TransactionError::InstructionError(
+                    _,
+                    InstructionError::Custom(code),
+                )) if code == Error::PerUserMaxExceeded.to_u32().unwrap()
+            ));
+        }
+    }
+
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_account_to.pubkey())
+            .await
+            .amount,
+        per_user_max
+    );
+    let user_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::UserPool>(user_pool)
+        .await
+        .unwrap();
+    assert_eq!(user_pool_state.amount_collected, per_user_max);
+}
+
+#[tokio::test]
+async fn test_participate_receipt() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        100,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // inside of first active stage
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let pool_user_authority = PoolUserAuthority::derive(
+        &pool.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &crate::program_id(),
+    );
+    let participation_receipt = Pubkey::create_with_seed(
+        &pool_user_authority.key,
+        crate::PARTICIPATION_RECEIPT_SEED,
+        &crate::id(),
+    )
+    .unwrap();
+
+    // receipt account is required only if receipt is requested
+    let mut participate = instruction::participate(
+        &crate::program_id(),
+        &pool.pool.pubkey(),
+        &pool.market,
+        &user_wallet.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.account_collection.pubkey(),
+        &user_account_to.pubkey(),
+        &pool_lock_token,
+        &pool.mint_pool.pubkey(),
+        &pool.pool_lock,
+        &pool.stake_pool,
+        None,
+        None,
+        None,
+        None,
+        instruction::Participate {
+            amount: 10,
+            whitelist_tickets: 0,
+            min_accepted: 0,
+            receipt: true,
+        },
+        0,
+    )
+    .unwrap();
+    participate
+        .accounts
+        .retain(|account| account.pubkey != participation_receipt);
+    let mut transaction =
+        Transaction::new_with_payer(&[participate], Some(&program_context.payer.pubkey()));
+    transaction.sign(
+        &[&program_context.payer, &user_wallet],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WrongParticipationReceipt.to_u32().unwrap()
+    ));
+
+    // second stage allows participation again, so receipt accumulates both
+    for (amount, stage) in [(10, 0), (5, 1)].iter() {
+        pool.participate_with_input(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            instruction::Participate {
+                amount: *amount,
+                whitelist_tickets: 0,
+                min_accepted: 0,
+                receipt: true,
+            },
+            *stage,
+        )
+        .await
+        .unwrap();
+        warp_seconds(&mut program_context, 60 * 60).await;
+    }
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    let receipt_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::ParticipationReceipt>(participation_receipt)
+        .await
+        .unwrap();
+    assert_eq!(receipt_state.version, state::PARTICIPATION_RECEIPT_VERSION);
+    assert_eq!(receipt_state.pool, pool.pool.pubkey());
+    assert_eq!(receipt_state.user_wallet, user_wallet.pubkey());
+    assert_eq!(receipt_state.amount_collected, 15);
+    assert_eq!(receipt_state.amount_collected, pool_state.amount_collected);
+    assert_eq!(
+        receipt_state.amount_to_distribute,
+        pool_state.amount_to_distribute
+    );
+    assert_eq!(receipt_state.tier, (TIERS_COUNT - 1) as u8);
+}
+
+#[tokio::test]
+async fn test_participate_wrong_stake_pool() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.stake_pool, stake_pool.pubkey());
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        100,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    // stake pool stored on pool is compared, so other one is rejected
+    pool.stake_pool = Pubkey::new_unique();
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            10,
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::StakePoolMustBelongToMarket.to_u32().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn test_participate_wrong_user_accounts() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        100,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    // pool user authority and user pool stage are 4th and 12th participate accounts
+    for (index, expected) in [
+        (3, Error::WrongPoolUserAuthority),
+        (11, Error::WrongUserPoolStage),
+    ]
+    .iter()
+    .cloned()
+    {
+        let mut participate = instruction::participate(
+            &crate::program_id(),
+            &pool.pool.pubkey(),
+            &pool.market,
+            &user_wallet.pubkey(),
+            &user_collection_account.pubkey(),
+            &pool.account_collection.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            &pool.mint_pool.pubkey(),
+            &pool.pool_lock,
+            &pool.stake_pool,
+            None,
+            None,
+            None,
+            None,
+            instruction::Participate {
+                amount: 10,
+                whitelist_tickets: 0,
+                min_accepted: 0,
+                receipt: false,
+            },
+            0,
+        )
+        .unwrap();
+        participate.accounts[index].pubkey = Pubkey::new_unique();
+        let mut transaction =
+            Transaction::new_with_payer(&[participate], Some(&program_context.payer.pubkey()));
+        transaction.sign(
+            &[&program_context.payer, &user_wallet],
+            program_context.last_blockhash,
+        );
+        let error = program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TransportError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code)
+            )) if code == expected.to_u32().unwrap()
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_participate_unstarted_pool() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        100,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // pool is within its time frame, but start pool did not compute tier allocations
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            10,
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::CanParticipateOnlyInStartedPool.to_u32().unwrap()
+    ));
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert!(!pool_state.started);
+
+    // owner can not withdraw from pool which was never started
+    warp_seconds(&mut program_context, 10 * 60 * 60).await;
+    let error = pool
+        .withdraw(
+            &mut program_context,
+            &pool.account_distribution.pubkey(),
+            &user_account_to.pubkey(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::CanParticipateOnlyInStartedPool.to_u32().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn test_participate_zero_amount() {
+    let user_wallet = Keypair::new();
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 2500)]).await;
+    let (pool_lock, pool_lock_token) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = pool_init_args(user_wallet.pubkey(), now);
+    let (pool, user_collection_account, user_account_to) = setup_user_pool(
+        &mut program_context,
+        &market,
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        init_args,
+        100,
+    )
+    .await;
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account,
+            &user_account_to,
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            0,
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::ZeroAmount.to_u32().unwrap()
+    ));
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.participant_count, 0);
+}
+
+#[tokio::test]
+async fn test_participate_min_accepted() {
+    let user_wallet = Keypair::new();
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 2500)]).await;
+    let (pool_lock, pool_lock_token) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        time_table: Some([2 * 60 * 60, 60 * 60, 0, 0]),
+        ..pool_init_args(user_wallet.pubkey(), now)
+    };
+    let user_investment_amount = 300;
+    let (pool, user_collection_account, user_account_to) = setup_user_pool(
+        &mut program_context,
+        &market,
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        init_args,
+        user_investment_amount,
+    )
+    .await;
+
+    warp_seconds(&mut program_context, 60 * 60).await;
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    warp_seconds(&mut program_context, 10 * 60).await;
+
+    // initial stage caps investment of top tier user by its tier balance of 200
+    let participate = |min_accepted| instruction::Participate {
+        amount: user_investment_amount,
+        whitelist_tickets: 0,
+        min_accepted,
+        receipt: false,
+    };
+    let error = pool
+        .participate_with_input(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account,
+            &user_account_to,
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            participate(201),
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::SlippageExceeded.to_u32().unwrap()
+    ));
+
+    pool.participate_with_input(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account,
+        &user_account_to,
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        participate(200),
+        0,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to_state =
+        get_token_account_state(&mut program_context, &user_account_to).await;
+    assert_eq!(user_account_to_state.amount, 200);
+}
+
+#[tokio::test]
+async fn test_participate_exhausted_tier() {
+    let user_wallet = Keypair::new();
+    let other_wallet = Keypair::new();
+    // user locks exactly second tier threshold, other user takes top tier share of allocations
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 100), (&other_wallet, 200)]).await;
+    let (pool_lock, pool_lock_token) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        price: state::Pool::PRECISION,
+        goal_max: 3000,
+        amount_min: 1,
+        amount_max: 3000,
+        time_table: Some([60 * 60, 60 * 60, 60 * 60, 0]),
+        stages_count: 3,
+        ..pool_init_args(user_wallet.pubkey(), now)
+    };
+    let (pool, user_collection_account, user_account_to) = setup_user_pool(
+        &mut program_context,
+        &market,
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        init_args,
+        3000,
+    )
+    .await;
+
+    warp_seconds(&mut program_context, 60 * 60).await;
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let pool_info = get_account(&mut program_context, &pool.pool.pubkey()).await;
+    let pool_info = state::Pool::try_from_slice(&pool_info.data.as_slice()).unwrap();
+    assert_eq!(pool_info.tier_remaining, [0, 1000, 0, 2000]);
+
+    // first tier allocation stage takes whole remaining allocation of the tier
+    warp_seconds(&mut program_context, 70 * 60).await;
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account,
+        &user_account_to,
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        pool_info.tier_remaining[1],
+        1,
+    )
+    .await
+    .unwrap();
+
+    let pool_info = get_account(&mut program_context, &pool.pool.pubkey()).await;
+    let pool_info = state::Pool::try_from_slice(&pool_info.data.as_slice()).unwrap();
+    assert_eq!(pool_info.tier_remaining, [0, 0, 0, 2000]);
+    assert_eq!(pool_info.amount_collected, 1000);
+
+    // next tier allocation stage has nothing left for the tier
+    warp_seconds(&mut program_context, 60 * 60).await;
+    let error = pool
+        .participate_with_input(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account,
+            &user_account_to,
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            instruction::Participate {
+                amount: 100,
+                whitelist_tickets: 0,
+                min_accepted: 100,
+                receipt: false,
+            },
+            2,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::AccountOnThisTierCannotParticipateOnCurrentStage.to_u32().unwrap()
+    ));
+
+    // final stage is not limited by tiers
+    warp_seconds(&mut program_context, 60 * 60).await;
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account,
+        &user_account_to,
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        500,
+        3,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to_state =
+        get_token_account_state(&mut program_context, &user_account_to).await;
+    assert_eq!(user_account_to_state.amount, 1500);
+}
+
+#[tokio::test]
+async fn test_participate_market_fee() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([2 * 60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+    let user_investment_amount = 200;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let account_fee = Keypair::new();
+    let fee_owner = program_context.payer.pubkey();
+    create_token_account(
+        &mut program_context,
+        &account_fee,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &fee_owner,
+    )
+    .await
+    .unwrap();
+
+    let set_market_fee = |program_context: &ProgramTestContext, fee_basis_points| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::set_market_fee(
+                &crate::program_id(),
+                &market.pubkey(),
+                &program_context.payer.pubkey(),
+                &[pool.pool.pubkey()],
+                instruction::SetMarketFee {
+                    fee_basis_points,
+                    fee_account: account_fee.pubkey(),
+                },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
+    let invalid_fee = set_market_fee(&program_context, state::MARKET_FEE_MAX_BASIS_POINTS + 1);
+    let fee = set_market_fee(&program_context, 250);
+
+    let error = program_context
+        .banks_client
+        .process_transaction(invalid_fee)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::MarketFeeInvalid.to_u32().unwrap()
+    ));
+    program_context
+        .banks_client
+        .process_transaction(fee)
+        .await
+        .unwrap();
+
+    let market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(market.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(market_state.fee_basis_points, 250);
+    assert_eq!(market_state.fee_account, account_fee.pubkey());
+
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 60 * 60).await;
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    warp_seconds(&mut program_context, 10 * 60).await;
+
+    // fee account is required once market fee is enabled
+    let error = pool
+        .participate(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            user_investment_amount,
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongFeeAccount.to_u32().unwrap()
+    ));
+
+    pool.account_fee = Some(account_fee.pubkey());
+
+    // minimum accepted is checked against amount without fee
+    let error = pool
+        .participate_with_input(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            None,
+            None,
+            instruction::Participate {
+                amount: user_investment_amount,
+                whitelist_tickets: 0,
+                min_accepted: user_investment_amount,
+                receipt: false,
+            },
+            0,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::SlippageExceeded.to_u32().unwrap()
+    ));
+
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account.pubkey(),
+        &user_account_to.pubkey(),
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        0,
+    )
+    .await
+    .unwrap();
+
+    // 2.5% of 200 goes to fee account, pool is credited with the rest
+    let fee_state = get_token_account_state(&mut program_context, &account_fee.pubkey()).await;
+    assert_eq!(fee_state.amount, 5);
+    let collection_state =
+        get_token_account_state(&mut program_context, &pool.account_collection.pubkey()).await;
+    assert_eq!(collection_state.amount, 195);
+    let user_account_from_state =
+        get_token_account_state(&mut program_context, &user_collection_account.pubkey()).await;
+    assert_eq!(user_account_from_state.amount, 0);
+    let user_account_to_state =
+        get_token_account_state(&mut program_context, &user_account_to.pubkey()).await;
+    assert_eq!(user_account_to_state.amount, 195);
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.amount_collected, 195);
+    assert_eq!(
+        pool_state.amount_to_distribute,
+        pool_state.collected_to_distributed_ceil(195).unwrap()
+    );
+
+    // fee is fixed while pool with participants sells
+    let error = program_context
+        .banks_client
+        .process_transaction(set_market_fee(&program_context, 300))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::PoolHasParticipants.to_u32().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn test_set_market_fee_pool_collected_without_participant_count() {
+    let mut program_context = program_test();
+
+    let market_owner = Keypair::new();
+    program_context.add_account(
+        market_owner.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    // pool collected before every participant was counted
+    let market = Pubkey::new_unique();
+    let pool = Pubkey::new_unique();
+    let market_state = state::Market {
+        account_type: state::ACCOUNT_TYPE_MARKET,
+        version: state::MARKET_VERSION,
+        owner: market_owner.pubkey(),
+        stake_pool: Pubkey::new_unique(),
+        pool_count: 1,
+        ..Default::default()
+    };
+    let pool_state = state::Pool {
+        account_type: state::ACCOUNT_TYPE_POOL,
+        version: state::POOL_VERSION,
+        market,
+        amount_collected: 100,
+        participant_count: 0,
+        time_finish: i64::MAX,
+        started: true,
+        ..Default::default()
+    };
+    for (key, data) in [
+        (market, market_state.try_to_vec().unwrap()),
+        (pool, pool_state.try_to_vec().unwrap()),
+    ]
+    .iter()
+    {
+        program_context.add_account(
+            *key,
+            Account {
+                lamports: 1_000_000_000,
+                data: data.clone(),
+                owner: crate::id(),
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut program_context = program_context.start_with_context().await;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_market_fee(
+            &crate::program_id(),
+            &market,
+            &market_owner.pubkey(),
+            &[pool],
+            instruction::SetMarketFee {
+                fee_basis_points: 250,
+                fee_account: Pubkey::new_unique(),
+            },
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&program_context.payer, &market_owner],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::PoolHasParticipants.to_u32().unwrap()
+    ));
+}
+
+fn start_pool_transaction(program_context: &ProgramTestContext, pool: &Pool) -> Transaction {
+    start_pool_transaction_with(program_context, pool, false)
+}
+
+fn start_pool_transaction_with(
+    program_context: &ProgramTestContext,
+    pool: &Pool,
+    require_funded: bool,
+) -> Transaction {
+    let account_distribution = pool.account_distribution.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::start_pool(
+            &crate::program_id(),
+            &program_context.payer.pubkey(),
+            &pool.stake_pool,
+            &pool.market.pubkey(),
+            &pool.pool.pubkey(),
+            if require_funded {
+                Some(&account_distribution)
+            } else {
+                None
+            },
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    transaction
+}
+
+#[tokio::test]
+async fn test_start_pool_without_tier_users() {
+    let user_wallet = Keypair::new();
+    // lock is below lowest tier, so stake pool has no tier users
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 10)]).await;
+    let (pool_lock, _) = locks[0];
+
+    let stake_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(stake_pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(stake_pool_state.tier_users, [0; TIERS_COUNT]);
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = pool_init_args(program_context.payer.pubkey(), now);
+    let mut pool = Pool::new(&market, stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let pool_account = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_account.tier_allocation, [0; TIERS_COUNT]);
+    assert_eq!(pool_account.tier_remaining, [0, 0, 0, 200000000000000]);
+}
+
+#[tokio::test]
+async fn test_recompute_tier_users() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    let other_wallet = Keypair::new();
+    for wallet in [&user_wallet, &other_wallet].iter() {
+        program_context.add_account(
+            wallet.pubkey(),
+            Account {
+                lamports: 1_000_000_000_000_000,
+                ..Default::default()
+            },
+        );
+    }
+
+    let market = Keypair::new();
+    let (mut program_context, stake_pool, mut locks) = setup_staking_users(
+        program_context,
+        Keypair::new(),
+        market.pubkey(),
+        &[(&user_wallet, 100), (&other_wallet, 200)],
+        [50, 100, 150, 200],
+    )
+    .await;
+    locks.sort_by_key(|(pool_lock, _)| *pool_lock);
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+
+    let recompute_tier_users = |market_owner: &Keypair, stake_pool: &Pubkey| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::recompute_tier_users(
+                &crate::program_id(),
+                &market.pubkey(),
+                &market_owner.pubkey(),
+                stake_pool,
+                &locks,
+                instruction::RecomputeTierUsers {
+                    first: true,
+                    last: true,
+                },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(
+            &[&program_context.payer, market_owner],
+            program_context.last_blockhash,
+        );
+        transaction
+    };
+    let not_owner = recompute_tier_users(&user_wallet, &stake_pool.pubkey());
+    let wrong_stake_pool = recompute_tier_users(&program_context.payer, &Pubkey::new_unique());
+    let recount = recompute_tier_users(&program_context.payer, &stake_pool.pubkey());
+
+    let error = program_context
+        .banks_client
+        .process_transaction(not_owner)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongMarketOwner.to_u32().unwrap()
+    ));
+
+    let error = program_context
+        .banks_client
+        .process_transaction(wrong_stake_pool)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::StakePoolMustBelongToMarket.to_u32().unwrap()
+    ));
+
+    program_context
+        .banks_client
+        .process_transaction(recount)
+        .await
+        .unwrap();
+
+    let stake_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(stake_pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(stake_pool_state.tier_users, [0, 1, 0, 1]);
+}
+
+#[tokio::test]
+async fn test_set_stakes_paused() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let (mut program_context, stake_pool, _, _) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        [50, 100, 150, 200],
+        10,
+    )
+    .await;
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+
+    for &stakes_paused in [true, false].iter() {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::set_stakes_paused(
+                &crate::program_id(),
+                &market.pubkey(),
+                &program_context.payer.pubkey(),
+                &stake_pool.pubkey(),
+                instruction::SetStakesPaused { stakes_paused },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        let stake_pool_state = program_context
+            .banks_client
+            .get_account_data_with_borsh::<StakePool>(stake_pool.pubkey())
+            .await
+            .unwrap();
+        assert_eq!(stake_pool_state.stakes_paused, stakes_paused);
+    }
+}
+
+#[tokio::test]
+async fn test_set_stake_pool_ido_authority() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let (mut program_context, stake_pool, _, _) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        [50, 100, 150, 200],
+        10,
+    )
+    .await;
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+
+    let other_market = Keypair::new();
+    let other_market_authority =
+        Pubkey::find_key_program_address(&other_market.pubkey(), &crate::program_id()).0;
+    let set_ido_authority = |ido_authority| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::set_stake_pool_ido_authority(
+                &crate::program_id(),
+                &market.pubkey(),
+                &program_context.payer.pubkey(),
+                &stake_pool.pubkey(),
+                instruction::SetStakePoolIdoAuthority { ido_authority },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
+    let rotate = set_ido_authority(other_market_authority);
+    let rotate_again = set_ido_authority(Pubkey::new_unique());
+
+    program_context
+        .banks_client
+        .process_transaction(rotate)
+        .await
+        .unwrap();
+
+    let stake_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(stake_pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(stake_pool_state.ido_authority, other_market_authority);
+
+    // market does not control stake pool anymore
+    let error = program_context
+        .banks_client
+        .process_transaction(rotate_again)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == sol_starter_staking::error::Error::PoolMustBeRelatedToMarket as u32
+    ));
+}
+
+#[tokio::test]
+async fn test_migrate_market_in_place() {
+    let mut program_context = program_test();
+
+    let market_owner = Keypair::new();
+    program_context.add_account(
+        market_owner.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    // market written before pool count was added into account large enough for current layout
+    let market = Pubkey::new_unique();
+    let mut market_data = state::Market {
+        account_type: state::ACCOUNT_TYPE_MARKET,
+        version: state::MARKET_VERSION_4,
+        owner: market_owner.pubkey(),
+        stake_pool: Pubkey::new_unique(),
+        ..Default::default()
+    }
+    .try_to_vec()
+    .unwrap();
+    market_data.truncate(state::Market::LEN_V4);
+    market_data.resize(state::Market::LEN, 0);
+    program_context.add_account(
+        market,
+        Account {
+            lamports: 1_000_000_000,
+            data: market_data,
+            owner: crate::id(),
+            ..Default::default()
+        },
+    );
+    let pools = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let other_pool = Pubkey::new_unique();
+    for (pool, pool_market) in [
+        (pools[0], market),
+        (pools[1], market),
+        (other_pool, Pubkey::new_unique()),
+    ]
+    .iter()
+    {
+        program_context.add_account(
+            *pool,
+            Account {
+                lamports: 1_000_000_000,
+                data: state::Pool {
+                    account_type: state::ACCOUNT_TYPE_POOL,
+                    version: state::POOL_VERSION,
+                    market: *pool_market,
+                    ..Default::default()
+                }
+                .try_to_vec()
+                .unwrap(),
+                owner: crate::id(),
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut program_context = program_context.start_with_context().await;
+    let migrate = |program_context: &ProgramTestContext, pools: &[Pubkey]| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::migrate_market(
+                &crate::program_id(),
+                &market,
+                &market_owner.pubkey(),
+                pools,
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(
+            &[&program_context.payer, &market_owner],
+            program_context.last_blockhash,
+        );
+        transaction
+    };
+
+    let cases = [
+        (
+            vec![pools[0], other_pool],
+            Error::WrongMarketAddressForCurrentPool,
+        ),
+        (vec![pools[0], pools[0]], Error::WrongMarketPools),
+    ];
+    for (passed, expected) in cases.iter() {
+        let error = program_context
+            .banks_client
+            .process_transaction(migrate(&program_context, passed))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TransportError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code),
+            )) if code == expected.to_u32().unwrap()
+        ));
+    }
+
+    program_context
+        .banks_client
+        .process_transaction(migrate(&program_context, &pools))
+        .await
+        .unwrap();
+    let market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(market)
+        .await
+        .unwrap();
+    assert_eq!(market_state.version, state::MARKET_VERSION);
+    assert_eq!(market_state.pool_count, 2);
+
+    // fee change requires counted pools now
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::set_market_fee(
+            &crate::program_id(),
+            &market,
+            &market_owner.pubkey(),
+            &[],
+            instruction::SetMarketFee {
+                fee_basis_points: 250,
+                fee_account: Pubkey::new_unique(),
+            },
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&program_context.payer, &market_owner],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongMarketPools.to_u32().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn test_migrate_market_by_copy() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    let market_owner = Keypair::new();
+    for wallet in [&user_wallet, &market_owner].iter() {
+        program_context.add_account(
+            wallet.pubkey(),
+            Account {
+                lamports: 1_000_000_000_000_000,
+                ..Default::default()
+            },
+        );
+    }
+
+    // market written before pool count was added is too small to be upgraded in place
+    let market = Keypair::new();
+    let stake_pool = Keypair::new();
+    let mut market_data = state::Market {
+        account_type: state::ACCOUNT_TYPE_MARKET,
+        version: state::MARKET_VERSION_4,
+        owner: market_owner.pubkey(),
+        stake_pool: stake_pool.pubkey(),
+        ..Default::default()
+    }
+    .try_to_vec()
+    .unwrap();
+    market_data.truncate(state::Market::LEN_V4);
+    program_context.add_account(
+        market.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            data: market_data,
+            owner: crate::id(),
+            ..Default::default()
+        },
+    );
+    let pool = Pubkey::new_unique();
+    program_context.add_account(
+        pool,
+        Account {
+            lamports: 1_000_000_000,
+            data: state::Pool {
+                account_type: state::ACCOUNT_TYPE_POOL,
+                version: state::POOL_VERSION,
+                market: market.pubkey(),
+                ..Default::default()
+            }
+            .try_to_vec()
+            .unwrap(),
+            owner: crate::id(),
+            ..Default::default()
+        },
+    );
+
+    let (mut program_context, stake_pool, _) = setup_staking_users(
+        program_context,
+        stake_pool,
+        market.pubkey(),
+        &[(&user_wallet, 10)],
+        [50, 100, 150, 200],
+    )
+    .await;
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let new_market = Keypair::new();
+    create_account(
+        &mut program_context,
+        &new_market,
+        rent.minimum_balance(state::Market::LEN),
+        state::Market::LEN as u64,
+        &crate::program_id(),
+    )
+    .await
+    .unwrap();
+
+    let migrate = |market_owner: &Keypair| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::migrate_market_to(
+                &crate::program_id(),
+                &market.pubkey(),
+                &market_owner.pubkey(),
+                &new_market.pubkey(),
+                &stake_pool.pubkey(),
+                &[pool],
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(
+            &[&program_context.payer, market_owner],
+            program_context.last_blockhash,
+        );
+        transaction
+    };
+    let not_owner = migrate(&user_wallet);
+    let copy = migrate(&market_owner);
+
+    let error = program_context
+        .banks_client
+        .process_transaction(not_owner)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongMarketOwner.to_u32().unwrap()
+    ));
+
+    program_context
+        .banks_client
+        .process_transaction(copy)
+        .await
+        .unwrap();
+
+    let new_market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(new_market.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(new_market_state.version, state::MARKET_VERSION);
+    assert_eq!(new_market_state.owner, market_owner.pubkey());
+    assert_eq!(new_market_state.stake_pool, stake_pool.pubkey());
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.market, new_market.pubkey());
+    let stake_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(stake_pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        stake_pool_state.ido_authority,
+        Pubkey::find_key_program_address(&new_market.pubkey(), &crate::program_id()).0
+    );
+    assert!(program_context
+        .banks_client
+        .get_account(market.pubkey())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_migrate_pool_by_copy() {
+    let mut program_context = program_test();
+
+    let pool_owner = Keypair::new();
+    program_context.add_account(
+        pool_owner.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    // pool written before started flag was added is too small to be upgraded in place
+    let pool = Pubkey::new_unique();
+    let participated_pool = Pubkey::new_unique();
+    let (pool_authority, pool_authority_bump_seed) =
+        Pubkey::find_key_program_address(&pool, &crate::program_id());
+    let account_collection = Keypair::new();
+    let account_distribution = Keypair::new();
+    let mint_pool = Keypair::new();
+    let pool_state = state::Pool {
+        account_type: state::ACCOUNT_TYPE_POOL,
+        version: state::POOL_VERSION_9,
+        market: Pubkey::new_unique(),
+        account_collection: account_collection.pubkey(),
+        account_distribution: account_distribution.pubkey(),
+        mint_pool: mint_pool.pubkey(),
+        owner: pool_owner.pubkey(),
+        authority: pool_authority,
+        authority_bump_seed: pool_authority_bump_seed,
+        ..Default::default()
+    };
+    let participated_pool_state = state::Pool {
+        participant_count: 1,
+        amount_collected: 100,
+        ..pool_state.clone()
+    };
+    for (key, state) in [
+        (pool, &pool_state),
+        (participated_pool, &participated_pool_state),
+    ]
+    .iter()
+    {
+        let mut data = state.try_to_vec().unwrap();
+        data.truncate(state::Pool::LEN - 1);
+        program_context.add_account(
+            *key,
+            Account {
+                lamports: 1_000_000_000,
+                data,
+                owner: crate::id(),
+                ..Default::default()
+            },
+        );
+    }
+
+    let mut program_context = program_context.start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+
+    let mint_collection = Keypair::new();
+    create_mint(
+        &mut program_context,
+        &mint_collection,
+        rent.minimum_balance(Mint::LEN),
+        &Pubkey::new_unique(),
+        6,
+        true,
+    )
+    .await
+    .unwrap();
+    create_mint(
+        &mut program_context,
+        &mint_pool,
+        rent.minimum_balance(Mint::LEN),
+        &pool_authority,
+        6,
+        true,
+    )
+    .await
+    .unwrap();
+    for account in [&account_collection, &account_distribution].iter() {
+        create_token_account(
+            &mut program_context,
+            account,
+            rent.minimum_balance(TokenAccount::LEN),
+            &mint_collection.pubkey(),
+            &pool_authority,
+        )
+        .await
+        .unwrap();
+    }
+    let new_pool = Keypair::new();
+    create_account(
+        &mut program_context,
+        &new_pool,
+        rent.minimum_balance(state::Pool::LEN),
+        state::Pool::LEN as u64,
+        &crate::program_id(),
+    )
+    .await
+    .unwrap();
+
+    let migrate = |pool: &Pubkey| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::migrate_pool_to(
+                &crate::program_id(),
+                pool,
+                &pool_owner.pubkey(),
+                &new_pool.pubkey(),
+                &account_collection.pubkey(),
+                &account_distribution.pubkey(),
+                &mint_pool.pubkey(),
+                None,
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(
+            &[&program_context.payer, &pool_owner],
+            program_context.last_blockhash,
+        );
+        transaction
+    };
+    let participated = migrate(&participated_pool);
+    let copy = migrate(&pool);
+
+    // user records of pool are derived from its key
+    let error = program_context
+        .banks_client
+        .process_transaction(participated)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::PoolHasParticipants.to_u32().unwrap()
+    ));
+
+    program_context
+        .banks_client
+        .process_transaction(copy)
+        .await
+        .unwrap();
+
+    let (new_pool_authority, new_pool_authority_bump_seed) =
+        Pubkey::find_key_program_address(&new_pool.pubkey(), &crate::program_id());
+    let new_pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(new_pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(
+        new_pool_state,
+        state::Pool {
+            version: state::POOL_VERSION,
+            authority: new_pool_authority,
+            authority_bump_seed: new_pool_authority_bump_seed,
+            ..pool_state
+        }
+    );
+    for account in [&account_collection, &account_distribution].iter() {
+        let account_state = TokenAccount::unpack(
+            &get_account(&mut program_context, &account.pubkey())
+                .await
+                .data,
+        )
+        .unwrap();
+        assert_eq!(account_state.owner, new_pool_authority);
+    }
+    let mint_pool_state = Mint::unpack(
+        &get_account(&mut program_context, &mint_pool.pubkey())
+            .await
+            .data,
+    )
+    .unwrap();
+    assert_eq!(
+        mint_pool_state.mint_authority,
+        solana_program::program_option::COption::Some(new_pool_authority)
+    );
+    assert!(program_context
+        .banks_client
+        .get_account(pool)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_market_pool_count() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let (mut program_context, stake_pool, pool_lock, _) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        [50, 100, 150, 200],
+        10,
+    )
+    .await;
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(market.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(market_state.pool_count, 0);
+
+    for _ in 0..2 {
+        let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+        pool.create_pool(&mut program_context, false, init_args.clone())
+            .await
+            .unwrap();
+    }
+
+    let market_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Market>(market.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(market_state.pool_count, 2);
+}
+
+#[tokio::test]
+async fn test_start_pool_require_funded() {
+    let user_wallet = Keypair::new();
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 10)]).await;
+    let (pool_lock, _) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        price: 3,
+        ..pool_init_args(program_context.payer.pubkey(), now)
+    };
+    let mut pool = Pool::new(&market, stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args)
+        .await
+        .unwrap();
+
+    let max_distribution = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap()
+        .max_distribution()
+        .unwrap();
+    // goal is not divisible by price, so funded amount must be rounded up
+    assert_eq!(max_distribution, 333_333_333_333_334);
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        max_distribution - 1,
+    )
+    .await
+    .unwrap();
+
+    let transaction = start_pool_transaction_with(&program_context, &pool, true);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::NotEnoughTokensReservedForUsers.to_u32().unwrap()
+    ));
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        1,
+    )
+    .await
+    .unwrap();
+
+    // same start transaction as above needs new blockhash
+    warp_seconds(&mut program_context, 60).await;
+    program_context.last_blockhash = program_context
+        .banks_client
+        .get_recent_blockhash()
+        .await
+        .unwrap();
+    let transaction = start_pool_transaction_with(&program_context, &pool, true);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert!(pool_state.started);
+}
+
+#[tokio::test]
+async fn test_start_pool_twice() {
+    let user_wallet = Keypair::new();
+    let (mut program_context, stake_pool, market, locks) =
+        setup_staked_market(&[(&user_wallet, 2500)]).await;
+    let (pool_lock, pool_lock_token) = locks[0];
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = pool_init_args(user_wallet.pubkey(), now);
+    let user_investment_amount = 10;
+    let (pool, user_collection_account, user_account_to) = setup_user_pool(
+        &mut program_context,
+        &market,
+        stake_pool.pubkey(),
+        pool_lock,
+        &user_wallet,
+        init_args,
+        user_investment_amount,
+    )
+    .await;
+
+    warp_seconds(&mut program_context, 70 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account,
+        &user_account_to,
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        0,
+    )
+    .await
+    .unwrap();
+    let started_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+
+    // tier allocations are shared by participation already, so they are not recomputed
+    warp_seconds(&mut program_context, 60).await;
+    program_context.last_blockhash = program_context
+        .banks_client
+        .get_recent_blockhash()
+        .await
+        .unwrap();
+    let transaction = start_pool_transaction(&program_context, &pool);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::PoolAlreadyStarted.to_u32().unwrap()
+    ));
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_allocation, started_state.tier_allocation);
+    assert_eq!(pool_state.tier_remaining, started_state.tier_remaining);
+}
+
+#[tokio::test]
+async fn test_claim() {
+    claim_flow(false, None).await;
+}
+
+#[tokio::test]
+async fn test_claim_into_associated_account() {
+    claim_flow(true, None).await;
+}
+
+/// Compute units allowed to participate and claim, which read pool through [state::PoolView].
+/// Metered only when run by `cargo test-bpf`, native processor is not metered.
+#[cfg(feature = "test-bpf")]
+const PARTICIPATE_CLAIM_COMPUTE_MAX_UNITS: u64 = 100_000;
+
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn test_claim_compute_budget() {
+    claim_flow(false, Some(PARTICIPATE_CLAIM_COMPUTE_MAX_UNITS)).await;
+}
+
+#[tokio::test]
+async fn test_withdraw_pool_of_older_version() {
+    for pool_version in [state::POOL_VERSION_9, state::POOL_VERSION].iter() {
+        withdraw_from_injected_pool(*pool_version, None)
+            .await
+            .unwrap();
+    }
+}
+
+/// Smallest compute budget [withdraw_from_injected_pool] fits in,
+/// banks client does not report consumed units, so it is found by bisection
+#[cfg(feature = "test-bpf")]
+async fn withdraw_compute_units(pool_version: u8) -> u64 {
+    let (mut low, mut high) = (0, PARTICIPATE_CLAIM_COMPUTE_MAX_UNITS);
+    withdraw_from_injected_pool(pool_version, Some(high))
+        .await
+        .unwrap();
+    while high - low > 1 {
+        let middle = (low + high) / 2;
+        if withdraw_from_injected_pool(pool_version, Some(middle))
+            .await
+            .is_ok()
+        {
+            high = middle;
+        } else {
+            low = middle;
+        }
+    }
+    high
+}
+
+/// Pool of older version is read by migrating whole pool, as every instruction did before [state::PoolView]
+#[cfg(feature = "test-bpf")]
+#[tokio::test]
+async fn test_withdraw_compute_units_pool_view() {
+    let view = withdraw_compute_units(state::POOL_VERSION).await;
+    let migrated = withdraw_compute_units(state::POOL_VERSION_9).await;
+    println!(
+        "Withdraw consumes {} compute units reading pool through view, {} migrating whole pool",
+        view, migrated
+    );
+    assert!(view < migrated);
+}
+
+/// withdraws collected tokens of finished pool of `pool_version` written into account of [state::Pool::LEN],
+/// transaction fails if it consumes over `bpf_compute_max_units`
+async fn withdraw_from_injected_pool(
+    pool_version: u8,
+    bpf_compute_max_units: Option<u64>,
+) -> Result<(), TransportError> {
+    let mut program_test = program_test();
+    if let Some(bpf_compute_max_units) = bpf_compute_max_units {
+        program_test.set_bpf_compute_max_units(bpf_compute_max_units);
+    }
+
+    let market = Pubkey::new_unique();
+    let pool = Pubkey::new_unique();
+    let pool_owner = Keypair::new();
+    let account_collection = Pubkey::new_unique();
+    let account_to = Pubkey::new_unique();
+    let mint_collection = Pubkey::new_unique();
+    let (pool_authority, authority_bump_seed) =
+        Pubkey::find_key_program_address(&pool, &crate::program_id());
+    program_test.add_account(
+        pool_owner.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Default::default()
+        },
+    );
+    program_test.add_account(
+        market,
+        Account {
+            lamports: 1_000_000_000,
+            data: state::Market {
+                account_type: state::ACCOUNT_TYPE_MARKET,
+                version: state::MARKET_VERSION,
+                owner: pool_owner.pubkey(),
+                ..Default::default()
+            }
+            .try_to_vec()
+            .unwrap(),
+            owner: crate::id(),
+            ..Default::default()
+        },
+    );
+    // older layouts lack started flag, pool with participants counts as started
+    let pool_data = state::Pool {
+        account_type: state::ACCOUNT_TYPE_POOL,
+        version: pool_version,
+        market,
+        account_collection,
+        account_distribution: Pubkey::new_unique(),
+        owner: pool_owner.pubkey(),
+        authority: pool_authority,
+        authority_bump_seed,
+        goal_min_collected: 10,
+        amount_collected: 100,
+        time_start: 0,
+        time_finish: 1,
+        participant_count: 1,
+        started: pool_version == state::POOL_VERSION,
+        ..Default::default()
+    }
+    .try_to_vec()
+    .unwrap();
+    program_test.add_account(
+        pool,
+        Account {
+            lamports: 1_000_000_000,
+            data: pool_data,
+            owner: crate::id(),
+            ..Default::default()
+        },
+    );
+    for (account, owner, amount) in [
+        (account_collection, pool_authority, 100),
+        (account_to, pool_owner.pubkey(), 0),
+    ]
+    .iter()
+    {
+        program_test.add_packable_account(
+            *account,
+            solana_program::rent::Rent::default().minimum_balance(TokenAccount::LEN),
+            &TokenAccount {
+                mint: mint_collection,
+                owner: *owner,
+                amount: *amount,
+                state: spl_token::state::AccountState::Initialized,
+                ..Default::default()
+            },
+            &spl_token::id(),
+        );
+    }
+
+    let mut program_context = program_test.start_with_context().await;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw(
+            &crate::program_id(),
+            &pool,
+            &market,
+            &pool_owner.pubkey(),
+            &account_collection,
+            &account_to,
+            None,
+            instruction::Withdraw::CHECKED,
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&program_context.payer, &pool_owner],
+        program_context.last_blockhash,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await?;
+
+    let account_to_state = get_token_account_state(&mut program_context, &account_to).await;
+    assert_eq!(account_to_state.amount, 100);
+    Ok(())
+}
+
+/// participates and claims distribution, optionally into associated account created by claim,
+/// each transaction fails if it consumes over `bpf_compute_max_units`
+async fn claim_flow(create_associated_account: bool, bpf_compute_max_units: Option<u64>) {
+    let mut program_context = program_test();
+    if let Some(bpf_compute_max_units) = bpf_compute_max_units {
+        program_context.set_bpf_compute_max_units(bpf_compute_max_units);
+    }
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 150,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args.clone())
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_investment_amount = 50;
+    let user_collection_account = Keypair::new();
+
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_pool_token_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_pool_token_account,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // Rewind slots to do investment
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account.pubkey(),
+        &user_pool_token_account.pubkey(),
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        2,
+    )
+    .await
+    .unwrap();
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        100000000 * crate::state::Pool::PRECISION,
+    )
+    .await
+    .unwrap();
+
+    let (user_distribution_token_account, create_account_to) = if create_associated_account {
+        let account = spl_associated_token_account::get_associated_token_address(
+            &user_wallet.pubkey(),
+            &pool.mint_distribution.pubkey(),
+        );
+        let create_account_to = instruction::CreateAccountTo {
+            payer: program_context.payer.pubkey(),
+            wallet: user_wallet.pubkey(),
+            mint: pool.mint_distribution.pubkey(),
+        };
+        (account, Some(create_account_to))
+    } else {
+        let account = Keypair::new();
+        create_token_account(
+            &mut program_context,
+            &account,
+            token_account_min_rent,
+            &pool.mint_distribution.pubkey(),
+            &user_wallet.pubkey(),
+        )
+        .await
+        .unwrap();
+        (account.pubkey(), None)
+    };
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    pool.fund_distribution(&mut program_context, None, 0)
+        .await
+        .unwrap();
+
+    pool.claim(
+        &mut program_context,
+        &user_pool_token_account.pubkey(),
+        &user_wallet,
+        &user_distribution_token_account,
+        false,
+        create_account_to.as_ref(),
+    )
+    .await
+    .unwrap();
+
+    let user_distribution_token_account_info =
+        get_account(&mut program_context, &user_distribution_token_account).await;
+    let user_distribution_token_account_info = spl_token::state::Account::unpack_from_slice(
+        user_distribution_token_account_info.data.as_slice(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        user_investment_amount * crate::state::Pool::PRECISION / init_args.price,
+        user_distribution_token_account_info.amount
+    );
+}
+
+#[tokio::test]
+async fn test_add_to_whitelist() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, _) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 150,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, true, init_args.clone())
+        .await
+        .unwrap();
+
+    let user_wallet = Keypair::new();
+    let user_whitelist_account = Keypair::new();
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    create_token_account(
+        &mut program_context,
+        &user_whitelist_account,
+        token_account_min_rent,
+        &pool.mint_whitelist_account.unwrap(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    pool.add_to_whitelist(&mut program_context, &user_whitelist_account.pubkey())
+        .await
+        .unwrap();
+
+    let user_whitelist_account_info =
+        get_account(&mut program_context, &user_whitelist_account.pubkey()).await;
+    let user_whitelist_account =
+        spl_token::state::Account::unpack_from_slice(user_whitelist_account_info.data.as_slice())
+            .unwrap();
+
+    assert_eq!(user_whitelist_account.amount, state::WHITELIST_TOKEN_AMOUNT);
+}
+
+#[tokio::test]
+async fn test_add_many_to_whitelist() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, _) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 150,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 3,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, true, init_args)
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    let mut accounts_whitelist = vec![];
+    for _ in 0..5 {
+        let account_whitelist = Keypair::new();
+        create_token_account(
+            &mut program_context,
+            &account_whitelist,
+            token_account_min_rent,
+            &pool.mint_whitelist_account.unwrap(),
+            &Keypair::new().pubkey(),
+        )
+        .await
+        .unwrap();
+        accounts_whitelist.push(account_whitelist.pubkey());
+    }
+
+    pool.add_many_to_whitelist(&mut program_context, &accounts_whitelist)
+        .await
+        .unwrap();
+
+    for account_whitelist in accounts_whitelist.iter() {
+        let account_whitelist_info = get_account(&mut program_context, account_whitelist).await;
+        let account_whitelist_state =
+            spl_token::state::Account::unpack_from_slice(account_whitelist_info.data.as_slice())
+                .unwrap();
+        assert_eq!(account_whitelist_state.amount, 3);
+    }
+
+    let too_many = vec![accounts_whitelist[0]; crate::WHITELIST_ACCOUNTS_MAX + 1];
+    assert_eq!(
+        instruction::add_many_to_whitelist(
+            &crate::program_id(),
+            &pool.pool.pubkey(),
+            &program_context.payer.pubkey(),
+            &too_many,
+            &pool.mint_whitelist_account.unwrap(),
+        )
+        .unwrap_err(),
+        ProgramError::from(Error::WrongWhitelistAccountsCount)
+    );
+}
+
+#[tokio::test]
+async fn test_whitelist_tickets() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 1_000_000,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 1_000_000,
+        time_start: now + 60 * 60,
+        time_finish: now + 10 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([60 * 60, 60 * 60, 0, 0]),
+        stages_count: 2,
+        whitelist_ticket_amount: 5,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, true, init_args.clone())
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let user_whitelist_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_whitelist_account,
+        token_account_min_rent,
+        &pool.mint_whitelist_account.unwrap(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    pool.add_to_whitelist(&mut program_context, &user_whitelist_account.pubkey())
+        .await
+        .unwrap();
+
+    let user_whitelist_state =
+        get_token_account_state(&mut program_context, &user_whitelist_account.pubkey()).await;
+    assert_eq!(user_whitelist_state.amount, 5);
+
+    let user_investment_amount = 50;
+    let user_collection_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        2 * user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_account_to = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_account_to,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 60 * 60).await;
+
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    warp_seconds(&mut program_context, 60 * 60).await;
+
+    for stage in [1, 2].iter() {
+        pool.participate_with_input(
+            &mut program_context,
+            &user_wallet,
+            &user_collection_account.pubkey(),
+            &user_account_to.pubkey(),
+            &pool_lock_token,
+            None,
+            Some(&user_whitelist_account.pubkey()),
+            pool.mint_whitelist_account.as_ref(),
+            instruction::Participate {
+                amount: user_investment_amount,
+                whitelist_tickets: 2,
+                min_accepted: 0,
+                receipt: false,
+            },
+            *stage,
+        )
+        .await
+        .unwrap();
+
+        warp_seconds(&mut program_context, 60 * 60).await;
+    }
+
+    let user_whitelist_state =
+        get_token_account_state(&mut program_context, &user_whitelist_account.pubkey()).await;
+    assert_eq!(user_whitelist_state.amount, 1);
+
+    let error = pool
+        .add_to_whitelist(&mut program_context, &user_whitelist_account.pubkey())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::PoolHasParticipants.to_u32().unwrap()
+    ));
+
+    let user_account_to_state =
+        get_token_account_state(&mut program_context, &user_account_to.pubkey()).await;
+    assert_eq!(user_account_to_state.amount, 2 * user_investment_amount);
+}
+
+#[tokio::test]
+async fn test_participant_count() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    let other_user_wallet = Keypair::new();
+    for wallet in [&user_wallet, &other_user_wallet].iter() {
+        program_context.add_account(
+            wallet.pubkey(),
+            Account {
+                lamports: 1_000_000_000_000_000,
+                ..Default::default()
+            },
+        );
+    }
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: user_wallet.pubkey(),
+        price: 5,
+        goal_max: 150,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args.clone())
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    let user_investment_amount = 10;
+
+    let mut user_accounts = vec![];
+    for wallet in [&user_wallet, &other_user_wallet].iter() {
+        let collection_account = Keypair::new();
+        create_token_account(
+            &mut program_context,
+            &collection_account,
+            token_account_min_rent,
+            &pool.mint_collection.pubkey(),
+            &wallet.pubkey(),
+        )
+        .await
+        .unwrap();
+        mint_tokens_to(
+            &mut program_context,
+            &pool.mint_collection.pubkey(),
+            &collection_account.pubkey(),
+            &pool.mint_collection_authority,
+            2 * user_investment_amount,
+        )
+        .await
+        .unwrap();
+
+        let pool_token_account = Keypair::new();
+        create_token_account(
+            &mut program_context,
+            &pool_token_account,
+            token_account_min_rent,
+            &pool.mint_pool.pubkey(),
+            &wallet.pubkey(),
+        )
+        .await
+        .unwrap();
+        user_accounts.push((collection_account.pubkey(), pool_token_account.pubkey()));
+    }
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    // pool has no per user max, still participation without user pool would not be counted
+    let user_pool = Pubkey::create_with_seed(
+        &PoolUserAuthority::derive(
+            &pool.pool.pubkey(),
+            &user_wallet.pubkey(),
+            &crate::program_id(),
+        )
+        .key,
+        crate::USER_POOL_SEED,
+        &crate::id(),
+    )
+    .unwrap();
+    let mut participate = instruction::participate(
+        &crate::program_id(),
+        &pool.pool.pubkey(),
+        &pool.market,
+        &user_wallet.pubkey(),
+        &user_accounts[0].0,
+        &pool.account_collection.pubkey(),
+        &user_accounts[0].1,
+        &pool_lock_token,
+        &pool.mint_pool.pubkey(),
+        &pool.pool_lock,
+        &pool.stake_pool,
+        None,
+        None,
+        None,
+        None,
+        instruction::Participate {
+            amount: user_investment_amount,
+            whitelist_tickets: 0,
+            min_accepted: 0,
+            receipt: false,
+        },
+        2,
+    )
+    .unwrap();
+    participate
+        .accounts
+        .retain(|account| account.pubkey != user_pool);
+    let mut transaction =
+        Transaction::new_with_payer(&[participate], Some(&program_context.payer.pubkey()));
+    transaction.sign(
+        &[&program_context.payer, &user_wallet],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == Error::WrongUserPool.to_u32().unwrap()
+    ));
+
+    // repeated participation differs in amount, so it is not deduplicated as same transaction
+    let participations = [
+        (
+            &user_wallet,
+            user_accounts[0],
+            user_investment_amount,
+            1,
+            true,
+        ),
+        (
+            &user_wallet,
+            user_accounts[0],
+            user_investment_amount + 1,
+            1,
+            false,
+        ),
+        (
+            &other_user_wallet,
+            user_accounts[1],
+            user_investment_amount,
+            2,
+            true,
+        ),
+    ];
+    for (wallet, (account_from, account_to), amount, expected_count, success) in
+        participations.iter()
+    {
+        let result = pool
+            .participate(
+                &mut program_context,
+                wallet,
+                account_from,
+                account_to,
+                &pool_lock_token,
+                None,
+                None,
+                None,
+                *amount,
+                2,
+            )
+            .await;
+        if *success {
+            result.unwrap();
+        } else {
+            assert!(matches!(
+                result.unwrap_err(),
+                TransportError::TransactionError(TransactionError::InstructionError(
+                    _,
+                    InstructionError::Custom(code),
+                )) if code == Error::AccountAlreadyParticipatedOnThisStage.to_u32().unwrap()
+            ));
+        }
+
+        let pool_state = program_context
+            .banks_client
+            .get_account_data_with_borsh::<state::Pool>(pool.pool.pubkey())
+            .await
+            .unwrap();
+        assert_eq!(pool_state.participant_count, *expected_count);
+    }
+}
+
+async fn get_token_account_state(
+    program_context: &mut ProgramTestContext,
+    pubkey: &Pubkey,
+) -> spl_token::state::Account {
+    let account = get_account(program_context, pubkey).await;
+    spl_token::state::Account::unpack_from_slice(account.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn test_withdraw() {
+    let mut program_context = program_test();
+
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price: 5,
+        goal_max: 150,
+        goal_min: 10,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 0,
+        nonce: None,
+    };
+
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.create_pool(&mut program_context, false, init_args.clone())
+        .await
+        .unwrap();
+
+    let user_investment_amount = 50;
+    let user_collection_account = Keypair::new();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+    create_token_account(
+        &mut program_context,
+        &user_collection_account,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &user_collection_account.pubkey(),
+        &pool.mint_collection_authority,
+        user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    let user_pool_token_account = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &user_pool_token_account,
+        token_account_min_rent,
+        &pool.mint_pool.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    // Rewind slots to do investment
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &user_collection_account.pubkey(),
+        &user_pool_token_account.pubkey(),
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        2,
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+
+    let collectible_account_for_withdraw = Keypair::new();
+    create_token_account(
+        &mut program_context,
+        &collectible_account_for_withdraw,
+        token_account_min_rent,
+        &pool.mint_collection.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await
+    .unwrap();
+
+    let account_collection_info =
+        get_account(&mut program_context, &pool.account_collection.pubkey()).await;
+    let account_collection_info =
+        spl_token::state::Account::unpack_from_slice(account_collection_info.data.as_slice())
+            .unwrap();
+    let collection_balance_before = account_collection_info.amount;
+
+    // account of other owner than pool owner is rejected unless explicitly allowed
+    let withdraw_transaction = |program_context: &ProgramTestContext, skip_owner_check| {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::withdraw(
+                &crate::program_id(),
+                &pool.pool.pubkey(),
+                &pool.market,
+                &program_context.payer.pubkey(),
+                &pool.account_collection.pubkey(),
+                &collectible_account_for_withdraw.pubkey(),
+                None,
+                instruction::Withdraw { skip_owner_check },
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
+    let transaction = withdraw_transaction(&program_context, false);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WithdrawDestinationMustBeOwnedByPoolOwner.to_u32().unwrap()
+    ));
+
+    let transaction = withdraw_transaction(&program_context, true);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let withdraw_acc_info = get_account(
+        &mut program_context,
+        &collectible_account_for_withdraw.pubkey(),
+    )
+    .await;
+    let withdraw_acc_info =
+        spl_token::state::Account::unpack_from_slice(withdraw_acc_info.data.as_slice()).unwrap();
+
+    assert_eq!(withdraw_acc_info.amount, collection_balance_before);
+}
+
+/// finished pool with single participation of `user_investment_amount`, pool tokens are split
+/// into two user accounts of `first_claim` and the rest
+struct FinishedPool {
+    program_context: ProgramTestContext,
+    pool: Pool,
+    user_wallet: Keypair,
+    user_collection_account: Pubkey,
+    user_distribution_account: Pubkey,
+    first_pool_token_account: Pubkey,
+    second_pool_token_account: Pubkey,
+    owner_collection_account: Pubkey,
+    owner_distribution_account: Pubkey,
+    price: u64,
+}
+
+async fn setup_finished_pool(
+    price: u64,
+    goal_min: u64,
+    user_investment_amount: u64,
+    first_claim: u64,
+) -> FinishedPool {
+    setup_finished_pool_with(
+        program_test(),
+        Keypair::new(),
+        price,
+        goal_min,
+        user_investment_amount,
+        first_claim,
+    )
+    .await
+}
+
+/// same as [setup_finished_pool], accounts may be preloaded into `program_context` for `mint_pool`
+async fn setup_finished_pool_with(
+    mut program_context: ProgramTest,
+    mint_pool: Keypair,
+    price: u64,
+    goal_min: u64,
+    user_investment_amount: u64,
+    first_claim: u64,
+) -> FinishedPool {
+    program_context.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    let user_wallet = Keypair::new();
+    program_context.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000_000_000,
+            ..Default::default()
+        },
+    );
+
+    let market = Keypair::new();
+    let tiers_balance = [50, 100, 150, 200];
+    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
+        program_context,
+        market.pubkey(),
+        &user_wallet,
+        tiers_balance,
+        2500,
+    )
+    .await;
+
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let init_args = instruction::InitializePool {
+        pool_owner: program_context.payer.pubkey(),
+        price,
+        goal_max: 150,
+        goal_min,
+        amount_min: 3,
+        amount_max: 100,
+        time_start: now + 60 * 60,
+        time_finish: now + 3 * 60 * 60,
+        kyc_requirement: Some(KycRequirement::default()),
+        time_table: Some([0; crate::STAGES_ACTIVE_COUNT]),
+        stages_count: 2,
+        whitelist_ticket_amount: 0,
+        per_user_max: 0,
+        refund_grace_period: 2 * 60 * 60,
+        nonce: None,
     };
 
-    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
-    pool.create_pool(&mut program_context, true, init_args.clone())
+    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
+    pool.mint_pool = mint_pool;
+    pool.create_pool(&mut program_context, false, init_args.clone())
+        .await
+        .unwrap();
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
+
+    let owner = program_context.payer.pubkey();
+    let accounts = [
+        (pool.mint_collection.pubkey(), user_wallet.pubkey()),
+        (pool.mint_distribution.pubkey(), user_wallet.pubkey()),
+        (pool.mint_pool.pubkey(), user_wallet.pubkey()),
+        (pool.mint_pool.pubkey(), user_wallet.pubkey()),
+        (pool.mint_collection.pubkey(), owner),
+        (pool.mint_distribution.pubkey(), owner),
+    ];
+    let mut keys = vec![];
+    for (mint, owner) in accounts.iter() {
+        let account = Keypair::new();
+        create_token_account(
+            &mut program_context,
+            &account,
+            token_account_min_rent,
+            mint,
+            owner,
+        )
+        .await
+        .unwrap();
+        keys.push(account.pubkey());
+    }
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_collection.pubkey(),
+        &keys[0],
+        &pool.mint_collection_authority,
+        user_investment_amount,
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    let transaction = start_pool_transaction(&program_context, &pool);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    pool.participate(
+        &mut program_context,
+        &user_wallet,
+        &keys[0],
+        &keys[2],
+        &pool_lock_token,
+        None,
+        None,
+        None,
+        user_investment_amount,
+        2,
+    )
+    .await
+    .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[spl_token::instruction::transfer(
+            &spl_token::id(),
+            &keys[2],
+            &keys[3],
+            &user_wallet.pubkey(),
+            &[],
+            user_investment_amount - first_claim,
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&program_context.payer, &user_wallet],
+        program_context.last_blockhash,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+
+    FinishedPool {
+        program_context,
+        pool,
+        user_wallet,
+        user_collection_account: keys[0],
+        user_distribution_account: keys[1],
+        first_pool_token_account: keys[2],
+        second_pool_token_account: keys[3],
+        owner_collection_account: keys[4],
+        owner_distribution_account: keys[5],
+        price: init_args.price,
+    }
+}
+
+#[tokio::test]
+async fn test_withdraw_after_partial_claims() {
+    let user_investment_amount = 50;
+    let first_claim = 30;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_distribution_account,
+        first_pool_token_account,
+        second_pool_token_account,
+        owner_collection_account,
+        owner_distribution_account,
+        price,
+        ..
+    } = setup_finished_pool(5, 10, user_investment_amount, first_claim).await;
+    let to_distribute = |collected: u64| collected * crate::state::Pool::PRECISION / price;
+
+    let error = pool
+        .withdraw(
+            &mut program_context,
+            &pool.account_distribution.pubkey(),
+            &owner_distribution_account,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::NotEnoughTokensReservedForUsers.to_u32().unwrap()
+    ));
+
+    let distribution_deposit = 100 * crate::state::Pool::PRECISION;
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        distribution_deposit,
+    )
+    .await
+    .unwrap();
+    pool.fund_distribution(&mut program_context, None, 0)
+        .await
+        .unwrap();
+
+    pool.claim(
+        &mut program_context,
+        &first_pool_token_account,
+        &user_wallet,
+        &user_distribution_account,
+        false,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_distribution_account)
+            .await
+            .amount,
+        to_distribute(first_claim)
+    );
+
+    pool.withdraw(
+        &mut program_context,
+        &pool.account_collection.pubkey(),
+        &owner_collection_account,
+    )
+    .await
+    .unwrap();
+    pool.withdraw(
+        &mut program_context,
+        &pool.account_distribution.pubkey(),
+        &owner_distribution_account,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_collection_account)
+            .await
+            .amount,
+        user_investment_amount
+    );
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_distribution_account)
+            .await
+            .amount,
+        distribution_deposit - to_distribute(user_investment_amount)
+    );
+
+    pool.claim(
+        &mut program_context,
+        &second_pool_token_account,
+        &user_wallet,
+        &user_distribution_account,
+        false,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_distribution_account)
+            .await
+            .amount,
+        to_distribute(user_investment_amount)
+    );
+    assert_eq!(
+        get_token_account_state(&mut program_context, &pool.account_distribution.pubkey())
+            .await
+            .amount,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_withdraw_all() {
+    let user_investment_amount = 50;
+    let first_claim = 30;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_distribution_account,
+        second_pool_token_account,
+        owner_collection_account,
+        owner_distribution_account,
+        price,
+        ..
+    } = setup_finished_pool(5, 10, user_investment_amount, first_claim).await;
+    let to_distribute = |collected: u64| collected * crate::state::Pool::PRECISION / price;
+
+    let distribution_deposit = 100 * crate::state::Pool::PRECISION;
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        distribution_deposit,
+    )
+    .await
+    .unwrap();
+    pool.fund_distribution(&mut program_context, None, 0)
         .await
         .unwrap();
 
-    let user_wallet = Keypair::new();
-    let user_whitelist_account = Keypair::new();
-    let rent = program_context.banks_client.get_rent().await.unwrap();
-    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
-    create_token_account(
+    // each pool account passed in place of the other fails with own error
+    let pool_accounts = [
+        (
+            pool.account_distribution.pubkey(),
+            pool.account_distribution.pubkey(),
+            Error::WrongCollectAccount,
+        ),
+        (
+            pool.account_collection.pubkey(),
+            pool.account_collection.pubkey(),
+            Error::WrongPoolAccountToSendTokensFrom,
+        ),
+    ];
+    for (account_collection, account_distribution, expected) in pool_accounts.iter().cloned() {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::withdraw_all(
+                &crate::program_id(),
+                &pool.pool.pubkey(),
+                &pool.market,
+                &program_context.payer.pubkey(),
+                &account_collection,
+                &account_distribution,
+                &owner_collection_account,
+                &owner_distribution_account,
+                instruction::Withdraw::CHECKED,
+            )
+            .unwrap()],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        let error = program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TransportError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code),
+            )) if code == expected.to_u32().unwrap()
+        ));
+    }
+
+    // every destination must be owned by pool owner
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw_all(
+            &crate::program_id(),
+            &pool.pool.pubkey(),
+            &pool.market,
+            &program_context.payer.pubkey(),
+            &pool.account_collection.pubkey(),
+            &pool.account_distribution.pubkey(),
+            &owner_collection_account,
+            &user_distribution_account,
+            instruction::Withdraw::CHECKED,
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WithdrawDestinationMustBeOwnedByPoolOwner.to_u32().unwrap()
+    ));
+
+    // only pool owner can withdraw
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw_all(
+            &crate::program_id(),
+            &pool.pool.pubkey(),
+            &pool.market,
+            &user_wallet.pubkey(),
+            &pool.account_collection.pubkey(),
+            &pool.account_distribution.pubkey(),
+            &owner_collection_account,
+            &owner_distribution_account,
+            instruction::Withdraw::CHECKED,
+        )
+        .unwrap()],
+        Some(&program_context.payer.pubkey()),
+    );
+    transaction.sign(
+        &[&program_context.payer, &user_wallet],
+        program_context.last_blockhash,
+    );
+    let error = program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongPoolOwner.to_u32().unwrap()
+    ));
+
+    pool.withdraw_all(
         &mut program_context,
-        &user_whitelist_account,
-        token_account_min_rent,
-        &pool.mint_whitelist_account.unwrap(),
-        &user_wallet.pubkey(),
+        &owner_collection_account,
+        &owner_distribution_account,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_collection_account)
+            .await
+            .amount,
+        user_investment_amount
+    );
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_distribution_account)
+            .await
+            .amount,
+        distribution_deposit - to_distribute(user_investment_amount)
+    );
+
+    pool.claim(
+        &mut program_context,
+        &second_pool_token_account,
+        &user_wallet,
+        &user_distribution_account,
+        false,
+        None,
     )
     .await
     .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_distribution_account)
+            .await
+            .amount,
+        to_distribute(user_investment_amount - first_claim)
+    );
+}
 
-    pool.add_to_whitelist(&mut program_context, &user_whitelist_account.pubkey())
+#[tokio::test]
+async fn test_claim_rounding() {
+    let user_investment_amount = 50;
+    let first_claim = 30;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_distribution_account,
+        first_pool_token_account,
+        second_pool_token_account,
+        price,
+        ..
+    } = setup_finished_pool(3, 10, user_investment_amount, first_claim).await;
+    let precision = crate::state::Pool::PRECISION;
+    assert_ne!(user_investment_amount * precision % price, 0);
+
+    // participation reserved rounded up
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
         .await
         .unwrap();
+    let reserved = (user_investment_amount * precision + price - 1) / price;
+    assert_eq!(pool_state.amount_to_distribute, reserved);
 
-    let user_whitelist_account_info =
-        get_account(&mut program_context, &user_whitelist_account.pubkey()).await;
-    let user_whitelist_account =
-        spl_token::state::Account::unpack_from_slice(user_whitelist_account_info.data.as_slice())
-            .unwrap();
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        reserved,
+    )
+    .await
+    .unwrap();
+    pool.fund_distribution(&mut program_context, None, 0)
+        .await
+        .unwrap();
+
+    for pool_token_account in [first_pool_token_account, second_pool_token_account].iter() {
+        pool.claim(
+            &mut program_context,
+            pool_token_account,
+            &user_wallet,
+            &user_distribution_account,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+    }
 
+    // each claim rounded down
+    let claimed = get_token_account_state(&mut program_context, &user_distribution_account)
+        .await
+        .amount;
     assert_eq!(
-        user_whitelist_account.amount,
-        state::WHITELIST_TOKEN_AMOUNT as u64
+        claimed,
+        first_claim * precision / price
+            + (user_investment_amount - first_claim) * precision / price
     );
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    let dust = get_token_account_state(&mut program_context, &pool.account_distribution.pubkey())
+        .await
+        .amount;
+    assert_eq!(pool_state.amount_to_distribute, dust);
+    assert!(dust <= 1);
 }
 
 #[tokio::test]
-async fn test_withdraw() {
-    let mut program_context = program_test();
+async fn test_fund_distribution() {
+    let user_investment_amount = 50;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_distribution_account,
+        first_pool_token_account,
+        owner_distribution_account,
+        price,
+        ..
+    } = setup_finished_pool(5, 10, user_investment_amount, user_investment_amount).await;
+    let to_distribute = user_investment_amount * crate::state::Pool::PRECISION / price;
 
-    program_context.add_program(
-        "sol_starter_staking",
-        sol_starter_staking::id(),
-        processor!(crate::processor::Processor::process_instruction),
+    let error = pool
+        .claim(
+            &mut program_context,
+            &first_pool_token_account,
+            &user_wallet,
+            &user_distribution_account,
+            false,
+            None,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::DistributionNotFunded.to_u32().unwrap()
+    ));
+
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &owner_distribution_account,
+        &pool.mint_distribution_authority,
+        to_distribute,
+    )
+    .await
+    .unwrap();
+
+    let error = pool
+        .fund_distribution(
+            &mut program_context,
+            Some(&owner_distribution_account),
+            to_distribute - 1,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::NotEnoughTokensReservedForUsers.to_u32().unwrap()
+    ));
+
+    pool.fund_distribution(
+        &mut program_context,
+        Some(&owner_distribution_account),
+        to_distribute,
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<crate::state::Pool>(pool.pool.pubkey())
+        .await
+        .unwrap();
+    assert!(pool_state.funded);
+
+    pool.claim(
+        &mut program_context,
+        &first_pool_token_account,
+        &user_wallet,
+        &user_distribution_account,
+        false,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_distribution_account)
+            .await
+            .amount,
+        to_distribute
     );
-    let user_wallet = Keypair::new();
-    program_context.add_account(
-        user_wallet.pubkey(),
-        Account {
-            lamports: 1_000_000_000_000_000,
-            ..Default::default()
-        },
+}
+
+#[tokio::test]
+async fn test_claim_wrong_mint_account_from() {
+    let user_investment_amount = 50;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_collection_account,
+        user_distribution_account,
+        first_pool_token_account,
+        ..
+    } = setup_finished_pool(5, 10, user_investment_amount, user_investment_amount).await;
+
+    let error = pool
+        .claim(
+            &mut program_context,
+            &user_collection_account,
+            &user_wallet,
+            &user_distribution_account,
+            false,
+            None,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongPoolTokenMint.to_u32().unwrap()
+    ));
+    assert_eq!(
+        get_token_account_state(&mut program_context, &first_pool_token_account)
+            .await
+            .amount,
+        user_investment_amount
+    );
+}
+
+#[tokio::test]
+async fn test_withdraw_when_goal_min_not_reached() {
+    let user_investment_amount = 50;
+    let first_claim = 30;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_collection_account,
+        first_pool_token_account,
+        second_pool_token_account,
+        owner_collection_account,
+        owner_distribution_account,
+        ..
+    } = setup_finished_pool(5, 100, user_investment_amount, first_claim).await;
+
+    let distribution_deposit = 100 * crate::state::Pool::PRECISION;
+    mint_tokens_to(
+        &mut program_context,
+        &pool.mint_distribution.pubkey(),
+        &pool.account_distribution.pubkey(),
+        &pool.mint_distribution_authority,
+        distribution_deposit,
+    )
+    .await
+    .unwrap();
+
+    pool.claim(
+        &mut program_context,
+        &first_pool_token_account,
+        &user_wallet,
+        &user_collection_account,
+        true,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_collection_account)
+            .await
+            .amount,
+        first_claim
+    );
+
+    pool.withdraw(
+        &mut program_context,
+        &pool.account_distribution.pubkey(),
+        &owner_distribution_account,
+    )
+    .await
+    .unwrap();
+    pool.withdraw(
+        &mut program_context,
+        &pool.account_collection.pubkey(),
+        &owner_collection_account,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_distribution_account)
+            .await
+            .amount,
+        distribution_deposit
+    );
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_collection_account)
+            .await
+            .amount,
+        0
     );
 
-    let market = Keypair::new();
-    let tiers_balance = [50, 100, 150, 200];
-    let (mut program_context, stake_pool, pool_lock, pool_lock_token) = setup_staking(
-        program_context,
-        market.pubkey(),
+    pool.claim(
+        &mut program_context,
+        &second_pool_token_account,
         &user_wallet,
-        tiers_balance,
-        2500,
+        &user_collection_account,
+        true,
+        None,
     )
-    .await;
-
-    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
-    let now = get_clock(&mut program_context).await.unix_timestamp;
-    let init_args = instruction::InitializePool {
-        pool_owner: program_context.payer.pubkey(),
-        price: 5,
-        goal_max: 150,
-        goal_min: 10,
-        amount_min: 3,
-        amount_max: 100,
-        time_start: now + 60 * 60,
-        time_finish: now + 3 * 60 * 60,
-        kyc_requirement: KycRequirement::NotRequired,
-        time_table: [0; crate::STAGES_ACTIVE_COUNT],
-    };
-
-    let mut pool = Pool::new(&market.pubkey(), stake_pool.pubkey(), pool_lock);
-    pool.create_pool(&mut program_context, false, init_args.clone())
-        .await
-        .unwrap();
+    .await
+    .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_collection_account)
+            .await
+            .amount,
+        user_investment_amount
+    );
+}
 
+#[tokio::test]
+async fn test_withdraw_after_refund_deadline() {
     let user_investment_amount = 50;
-    let user_collection_account = Keypair::new();
+    let first_claim = 30;
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_collection_account,
+        first_pool_token_account,
+        second_pool_token_account,
+        owner_collection_account,
+        ..
+    } = setup_finished_pool(5, 100, user_investment_amount, first_claim).await;
 
-    let rent = program_context.banks_client.get_rent().await.unwrap();
-    let token_account_min_rent = rent.minimum_balance(spl_token::state::Account::LEN);
-    create_token_account(
+    pool.claim(
         &mut program_context,
+        &first_pool_token_account,
+        &user_wallet,
         &user_collection_account,
-        token_account_min_rent,
-        &pool.mint_collection.pubkey(),
-        &user_wallet.pubkey(),
+        true,
+        None,
     )
     .await
     .unwrap();
-    mint_tokens_to(
+
+    // not refunded tokens are reserved for users before deadline
+    pool.withdraw(
         &mut program_context,
-        &pool.mint_collection.pubkey(),
-        &user_collection_account.pubkey(),
-        &pool.mint_collection_authority,
-        user_investment_amount,
+        &pool.account_collection.pubkey(),
+        &owner_collection_account,
     )
     .await
     .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_collection_account)
+            .await
+            .amount,
+        0
+    );
 
-    let user_pool_token_account = Keypair::new();
-    create_token_account(
+    // same withdraw transaction as above needs new blockhash
+    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+    program_context.last_blockhash = program_context
+        .banks_client
+        .get_recent_blockhash()
+        .await
+        .unwrap();
+    pool.withdraw(
         &mut program_context,
-        &user_pool_token_account,
-        token_account_min_rent,
-        &pool.mint_pool.pubkey(),
-        &user_wallet.pubkey(),
+        &pool.account_collection.pubkey(),
+        &owner_collection_account,
     )
     .await
     .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &owner_collection_account)
+            .await
+            .amount,
+        user_investment_amount - first_claim
+    );
+    assert_eq!(
+        get_token_account_state(&mut program_context, &pool.account_collection.pubkey())
+            .await
+            .amount,
+        0
+    );
 
-    // Rewind slots to do investment
-    warp_seconds(&mut program_context, 2 * 60 * 60).await;
-
-    pool.participate(
+    pool.claim(
         &mut program_context,
+        &second_pool_token_account,
         &user_wallet,
-        &user_collection_account.pubkey(),
-        &user_pool_token_account.pubkey(),
-        &pool_lock_token,
-        None,
-        None,
+        &user_collection_account,
+        true,
         None,
+    )
+    .await
+    .unwrap_err();
+}
+
+#[tokio::test]
+async fn test_claim_refund_exceeds_collected() {
+    let user_investment_amount = 50;
+    let mint_pool = Keypair::new();
+    let over_minted_wallet = Keypair::new();
+    let over_minted_account = Pubkey::new_unique();
+    let mut program_test = program_test();
+    program_test.add_packable_account(
+        over_minted_account,
+        solana_program::rent::Rent::default().minimum_balance(TokenAccount::LEN),
+        &TokenAccount {
+            mint: mint_pool.pubkey(),
+            owner: over_minted_wallet.pubkey(),
+            amount: user_investment_amount + 1,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        },
+        &spl_token::id(),
+    );
+
+    let FinishedPool {
+        mut program_context,
+        pool,
+        user_wallet,
+        user_collection_account,
+        first_pool_token_account,
+        ..
+    } = setup_finished_pool_with(
+        program_test,
+        mint_pool,
+        5,
+        100,
         user_investment_amount,
-        2,
+        user_investment_amount,
+    )
+    .await;
+
+    let error = pool
+        .claim(
+            &mut program_context,
+            &over_minted_account,
+            &over_minted_wallet,
+            &user_collection_account,
+            true,
+            None,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::RefundExceedsCollected.to_u32().unwrap()
+    ));
+    assert_eq!(
+        get_token_account_state(&mut program_context, &over_minted_account)
+            .await
+            .amount,
+        user_investment_amount + 1
+    );
+
+    pool.claim(
+        &mut program_context,
+        &first_pool_token_account,
+        &user_wallet,
+        &user_collection_account,
+        true,
+        None,
     )
     .await
     .unwrap();
+    assert_eq!(
+        get_token_account_state(&mut program_context, &user_collection_account)
+            .await
+            .amount,
+        user_investment_amount
+    );
+}
 
-    warp_seconds(&mut program_context, 2 * 60 * 60).await;
+/// creates associated token account with accounts in order of [crate::utils::invoke::create_associated_token_account],
+/// idempotent if first byte of input is 1
+fn process_create_associated_token_account(
+    _program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    input: &[u8],
+) -> ProgramResult {
+    match accounts {
+        [account, payer, wallet, mint, system_program, token_program, rent, associated_token_program] =>
+        {
+            let create = if input.first() == Some(&1) {
+                crate::utils::invoke::create_associated_token_account_idempotent
+            } else {
+                crate::utils::invoke::create_associated_token_account
+            };
+            create(
+                account.clone(),
+                payer.clone(),
+                wallet.clone(),
+                mint.clone(),
+                system_program.clone(),
+                token_program.clone(),
+                rent.clone(),
+                associated_token_program.clone(),
+            )
+        }
+        _ => Err(ProgramError::NotEnoughAccountKeys),
+    }
+}
 
-    let collectible_account_for_withdraw = Keypair::new();
-    create_token_account(
+#[tokio::test]
+async fn test_create_associated_token_account() {
+    let test_program_id = Pubkey::new_unique();
+    let mut program_test = program_test();
+    program_test.add_program(
+        "create_associated_token_account",
+        test_program_id,
+        processor!(process_create_associated_token_account),
+    );
+    let mut program_context = program_test.start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+
+    let mint = Keypair::new();
+    create_mint(
         &mut program_context,
-        &collectible_account_for_withdraw,
-        token_account_min_rent,
-        &pool.mint_collection.pubkey(),
-        &user_wallet.pubkey(),
+        &mint,
+        rent.minimum_balance(Mint::LEN),
+        &Pubkey::new_unique(),
+        0,
+        true,
     )
     .await
     .unwrap();
 
-    let account_collection_info =
-        get_account(&mut program_context, &pool.account_collection.pubkey()).await;
-    let account_collection_info =
-        spl_token::state::Account::unpack_from_slice(account_collection_info.data.as_slice())
-            .unwrap();
-    let collection_balance_before = account_collection_info.amount;
+    let wallet = Pubkey::new_unique();
+    let account =
+        spl_associated_token_account::get_associated_token_address(&wallet, &mint.pubkey());
+    let create = |program_context: &ProgramTestContext, account: Pubkey, idempotent: bool| {
+        let mut transaction = Transaction::new_with_payer(
+            &[SolanaInstruction::new_with_bytes(
+                test_program_id,
+                &[idempotent as u8],
+                vec![
+                    AccountMeta::new(account, false),
+                    AccountMeta::new(program_context.payer.pubkey(), true),
+                    AccountMeta::new_readonly(wallet, false),
+                    AccountMeta::new_readonly(mint.pubkey(), false),
+                    AccountMeta::new_readonly(solana_program::system_program::id(), false),
+                    AccountMeta::new_readonly(spl_token::id(), false),
+                    AccountMeta::new_readonly(solana_program::sysvar::rent::id(), false),
+                    AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+                ],
+            )],
+            Some(&program_context.payer.pubkey()),
+        );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        transaction
+    };
 
-    let mut transaction = Transaction::new_with_payer(
-        &[instruction::withdraw(
-            &crate::program_id(),
-            &pool.pool.pubkey(),
-            &pool.market,
-            &program_context.payer.pubkey(),
-            &pool.account_collection.pubkey(),
-            &collectible_account_for_withdraw.pubkey(),
-        )
-        .unwrap()],
-        Some(&program_context.payer.pubkey()),
-    );
+    let error = program_context
+        .banks_client
+        .process_transaction(create(&program_context, Pubkey::new_unique(), false))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == Error::WrongAssociatedTokenAccount.to_u32().unwrap()
+    ));
 
-    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
     program_context
         .banks_client
-        .process_transaction(transaction)
+        .process_transaction(create(&program_context, account, false))
         .await
         .unwrap();
+    let state = get_token_account_state(&mut program_context, &account).await;
+    assert_eq!(state.mint, mint.pubkey());
+    assert_eq!(state.owner, wallet);
 
-    let withdraw_acc_info = get_account(
-        &mut program_context,
-        &collectible_account_for_withdraw.pubkey(),
+    program_context
+        .banks_client
+        .process_transaction(create(&program_context, account, true))
+        .await
+        .unwrap();
+
+    // same transaction as above needs new blockhash to be processed again
+    warp(&mut program_context, 2).await;
+    program_context.last_blockhash = program_context
+        .banks_client
+        .get_recent_blockhash()
+        .await
+        .unwrap();
+    assert!(program_context
+        .banks_client
+        .process_transaction(create(&program_context, account, false))
+        .await
+        .is_err());
+}
+
+/// Funds `users` wallets, locks their amounts in stake pool of tiers [50, 100, 150, 200]
+/// and creates market of it. Returns market key and lock with its token account of each user.
+async fn setup_staked_market(
+    users: &[(&Keypair, u64)],
+) -> (ProgramTestContext, Keypair, Pubkey, Vec<(Pubkey, Pubkey)>) {
+    let mut program_test = program_test();
+    program_test.add_program(
+        "sol_starter_staking",
+        sol_starter_staking::id(),
+        processor!(crate::processor::Processor::process_instruction),
+    );
+    for (wallet, _) in users.iter() {
+        program_test.add_account(
+            wallet.pubkey(),
+            Account {
+                lamports: 1_000_000_000_000_000,
+                ..Default::default()
+            },
+        );
+    }
+
+    let market = Keypair::new();
+    let (mut program_context, stake_pool, locks) = setup_staking_users(
+        program_test,
+        Keypair::new(),
+        market.pubkey(),
+        users,
+        [50, 100, 150, 200],
     )
     .await;
-    let withdraw_acc_info =
-        spl_token::state::Account::unpack_from_slice(withdraw_acc_info.data.as_slice()).unwrap();
+    let market = create_market(&mut program_context, stake_pool.pubkey(), market).await;
 
-    assert_eq!(withdraw_acc_info.amount, collection_balance_before);
+    (program_context, stake_pool, market.pubkey(), locks)
 }
 
 async fn setup_staking(
@@ -1401,78 +6849,38 @@ async fn setup_staking(
     tier_balance: [u64; TIERS_COUNT],
     pool_lock_amount: u64,
 ) -> (ProgramTestContext, Keypair, Pubkey, Pubkey) {
+    let (program_context, pool, mut locks) = setup_staking_users(
+        program_test,
+        Keypair::new(),
+        ido_market,
+        &[(user_wallet, pool_lock_amount)],
+        tier_balance,
+    )
+    .await;
+    let (pool_lock, pool_lock_token) = locks.remove(0);
+
+    (program_context, pool, pool_lock, pool_lock_token)
+}
+
+/// Same as [setup_staking] with several users, each staking and locking its own amount in stake `pool`.
+/// Returns `(pool_lock, pool_lock_token)` for each user in order of `users`.
+async fn setup_staking_users(
+    program_test: ProgramTest,
+    pool: Keypair,
+    ido_market: Pubkey,
+    users: &[(&Keypair, u64)],
+    tier_balance: [u64; TIERS_COUNT],
+) -> (ProgramTestContext, Keypair, Vec<(Pubkey, Pubkey)>) {
     let mut program_context = program_test.start_with_context().await;
     let rent = &program_context.banks_client.get_rent().await.unwrap();
 
-    let pool = Keypair::new();
     let mint_sos = Keypair::new();
     let mint_sos_authority = Keypair::new();
     let mint_xsos = Keypair::new();
     let pool_token_sos = Keypair::new();
 
-    let pool_transit_from = Keypair::new();
-    let pool_transit_from_token = Keypair::new();
-
-    let pool_transit_to = Keypair::new();
-    let pool_transit_to_token = Keypair::new();
-
-    let user_token_sos = Keypair::new();
-    let user_token_xsos = Keypair::new();
-
     let rent = rent.minimum_balance(1_000);
 
-    let pool_lock_token = Keypair::new();
-
-    create_account(
-        &mut program_context,
-        &pool_lock_token,
-        rent,
-        TokenAccount::LEN as u64,
-        &spl_token_id(),
-    )
-    .await
-    .unwrap();
-
-    create_account(
-        &mut program_context,
-        &pool_transit_from,
-        rent,
-        PoolTransit::LEN as u64,
-        &sol_starter_staking::program_id(),
-    )
-    .await
-    .unwrap();
-
-    create_account(
-        &mut program_context,
-        &pool_transit_from_token,
-        rent,
-        TokenAccount::LEN as u64,
-        &spl_token_id(),
-    )
-    .await
-    .unwrap();
-
-    create_account(
-        &mut program_context,
-        &pool_transit_to_token,
-        rent,
-        TokenAccount::LEN as u64,
-        &spl_token_id(),
-    )
-    .await
-    .unwrap();
-
-    create_account(
-        &mut program_context,
-        &pool_transit_to,
-        rent,
-        PoolTransit::LEN as u64,
-        &sol_starter_staking::program_id(),
-    )
-    .await
-    .unwrap();
-
     create_account(
         &mut program_context,
         &pool,
@@ -1491,26 +6899,6 @@ async fn setup_staking(
     )
     .await
     .unwrap();
-    create_account_user(
-        &mut program_context,
-        &user_token_sos,
-        rent,
-        TokenAccount::LEN as u64,
-        &spl_token_id().pubkey(),
-        user_wallet,
-    )
-    .await
-    .unwrap();
-    create_account_user(
-        &mut program_context,
-        &user_token_xsos,
-        rent,
-        TokenAccount::LEN as u64,
-        &spl_token_id().pubkey(),
-        user_wallet,
-    )
-    .await
-    .unwrap();
 
     create_account(
         &mut program_context,
@@ -1550,33 +6938,20 @@ async fn setup_staking(
         .await
         .unwrap();
 
-    let instruction = spl_token::instruction::initialize_account(
-        &spl_token_id().pubkey(),
-        &user_token_sos.pubkey(),
-        &mint_sos.pubkey(),
-        &user_wallet.pubkey(),
-    )
-    .unwrap();
-    let mut transaction =
-        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
-
-    transaction.sign(&[&program_context.payer], program_context.last_blockhash);
-    program_context
-        .banks_client
-        .process_transaction(transaction)
-        .await
-        .unwrap();
-
     let instruction = sol_starter_staking::instruction::initialize_pool(
         &pool.pubkey(),
         &pool_token_sos.pubkey(),
         &mint_sos.pubkey(),
         &mint_xsos.pubkey(),
+        None,
         InitializePoolInput {
             tier_balance,
             transit_incoming: 3 * 100 * 60,
             transit_outgoing: 3 * 100 * 60,
             ido_authority: Pubkey::find_key_program_address(&ido_market, &crate::program_id()).0,
+            reward_rate: 0,
+            min_lock_seconds: 0,
+            twab_window_seconds: 0,
         },
     )
     .unwrap();
@@ -1591,121 +6966,620 @@ async fn setup_staking(
         .await
         .unwrap();
 
-    let instruction = spl_token::instruction::mint_to(
-        &spl_token_id().pubkey(),
-        &mint_sos.pubkey(),
-        &user_token_sos.pubkey(),
-        &mint_sos_authority.pubkey(),
-        &[],
-        1_000_000,
-    )
-    .unwrap();
-    let mut transaction =
-        Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
+    let mut stakes = Vec::with_capacity(users.len());
+    for &(user_wallet, _) in users {
+        let pool_transit_to = Keypair::new();
+        let pool_transit_to_token = Keypair::new();
+        let user_token_sos = Keypair::new();
+        let user_token_xsos = Keypair::new();
+        let pool_lock_token = Keypair::new();
 
-    transaction.sign(
-        &[&program_context.payer, &mint_sos_authority],
-        program_context.last_blockhash,
-    );
-    program_context
-        .banks_client
-        .process_transaction(transaction)
+        create_account(
+            &mut program_context,
+            &pool_lock_token,
+            rent,
+            TokenAccount::LEN as u64,
+            &spl_token_id(),
+        )
         .await
         .unwrap();
 
-    let instruction = sol_starter_staking::instruction::stake_start(
-        &pool.pubkey(),
-        &pool_transit_to.pubkey(),
-        &pool_token_sos.pubkey(),
-        &pool_transit_to_token.pubkey(),
-        &mint_sos.pubkey(),
-        &user_wallet.pubkey(),
-        &user_token_sos.pubkey(),
-        StakeStartInput { amount: 10000 },
-    )
-    .unwrap();
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
-
-    transaction.sign(&[user_wallet], program_context.last_blockhash);
-    program_context
-        .banks_client
-        .process_transaction(transaction)
+        create_account(
+            &mut program_context,
+            &pool_transit_to_token,
+            rent,
+            TokenAccount::LEN as u64,
+            &spl_token_id(),
+        )
         .await
         .unwrap();
 
-    let instruction = spl_token::instruction::initialize_account(
-        &spl_token_id().pubkey(),
-        &user_token_xsos.pubkey(),
-        &mint_xsos.pubkey(),
-        &user_wallet.pubkey(),
-    )
-    .unwrap();
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
+        create_account(
+            &mut program_context,
+            &pool_transit_to,
+            rent,
+            PoolTransit::LEN as u64,
+            &sol_starter_staking::program_id(),
+        )
+        .await
+        .unwrap();
 
-    transaction.sign(&[user_wallet], program_context.last_blockhash);
-    program_context
-        .banks_client
-        .process_transaction(transaction)
+        create_account_user(
+            &mut program_context,
+            &user_token_sos,
+            rent,
+            TokenAccount::LEN as u64,
+            &spl_token_id().pubkey(),
+            user_wallet,
+        )
+        .await
+        .unwrap();
+        create_account_user(
+            &mut program_context,
+            &user_token_xsos,
+            rent,
+            TokenAccount::LEN as u64,
+            &spl_token_id().pubkey(),
+            user_wallet,
+        )
         .await
         .unwrap();
 
-    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+        let instruction = spl_token::instruction::initialize_account(
+            &spl_token_id().pubkey(),
+            &user_token_sos.pubkey(),
+            &mint_sos.pubkey(),
+            &user_wallet.pubkey(),
+        )
+        .unwrap();
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
 
-    let transaction = crate::utils::sdk::stake_finish(
-        &pool,
-        &pool_token_sos,
-        &pool_transit_to,
-        &pool_transit_to_token,
-        &user_token_xsos,
-        &user_wallet,
-        &mint_xsos,
-        &program_context,
-    );
+        transaction.sign(&[&program_context.payer], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
 
-    program_context
-        .banks_client
-        .process_transaction(transaction)
-        .await
+        let instruction = spl_token::instruction::mint_to(
+            &spl_token_id().pubkey(),
+            &mint_sos.pubkey(),
+            &user_token_sos.pubkey(),
+            &mint_sos_authority.pubkey(),
+            &[],
+            1_000_000,
+        )
         .unwrap();
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&program_context.payer.pubkey()));
 
-    let instruction = sol_starter_staking::instruction::initialize_lock(
-        &pool.pubkey(),
-        &user_wallet.pubkey(),
-        &mint_xsos.pubkey(),
-        &pool_lock_token.pubkey(),
-    )
-    .unwrap();
-    let mut transaction = Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
+        transaction.sign(
+            &[&program_context.payer, &mint_sos_authority],
+            program_context.last_blockhash,
+        );
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
 
-    transaction.sign(&[user_wallet], program_context.last_blockhash);
-    program_context
-        .banks_client
-        .process_transaction(transaction)
-        .await
+        let instruction = sol_starter_staking::instruction::stake_start(
+            &pool.pubkey(),
+            &pool_transit_to.pubkey(),
+            &pool_token_sos.pubkey(),
+            &pool_transit_to_token.pubkey(),
+            &mint_sos.pubkey(),
+            &user_wallet.pubkey(),
+            &user_token_sos.pubkey(),
+            StakeStartInput { amount: 10000 },
+        )
         .unwrap();
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
 
-    let pool_lock_token_key = pool_lock_token.pubkey();
-    let transaction = lock_transaction(
-        &pool,
-        user_wallet,
-        pool_lock_token,
-        user_token_xsos,
-        pool_lock_amount,
-        &program_context,
-    );
-    program_context
-        .banks_client
-        .process_transaction(transaction)
-        .await
+        transaction.sign(&[user_wallet], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        let instruction = spl_token::instruction::initialize_account(
+            &spl_token_id().pubkey(),
+            &user_token_xsos.pubkey(),
+            &mint_xsos.pubkey(),
+            &user_wallet.pubkey(),
+        )
         .unwrap();
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
 
-    let pool_user_authority = sol_starter_staking::instruction::find_2key_program_address(&pool.pubkey(), &user_wallet.pubkey());
-    let pool_lock = Pubkey::create_with_seed(
-        &pool_user_authority,
-        sol_starter_staking::LOCK_SEED,
-        &sol_starter_staking::id(),
-    )
+        transaction.sign(&[user_wallet], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        stakes.push((
+            pool_transit_to,
+            pool_transit_to_token,
+            user_token_xsos,
+            pool_lock_token,
+        ));
+    }
+
+    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+
+    let mut locks = Vec::with_capacity(users.len());
+    for (&(user_wallet, pool_lock_amount), stake) in users.iter().zip(stakes) {
+        let (pool_transit_to, pool_transit_to_token, user_token_xsos, pool_lock_token) = stake;
+        let transaction = crate::utils::sdk::stake_finish(
+            &pool,
+            &pool_token_sos,
+            &pool_transit_to,
+            &pool_transit_to_token,
+            &user_token_xsos,
+            user_wallet,
+            &mint_xsos,
+            &program_context,
+        );
+
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        let instruction = sol_starter_staking::instruction::initialize_lock(
+            &pool.pubkey(),
+            &user_wallet.pubkey(),
+            &mint_xsos.pubkey(),
+            &pool_lock_token.pubkey(),
+        )
+        .unwrap();
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&user_wallet.pubkey()));
+
+        transaction.sign(&[user_wallet], program_context.last_blockhash);
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        let pool_lock_token_key = pool_lock_token.pubkey();
+        let transaction = lock_transaction(
+            &pool,
+            user_wallet,
+            pool_lock_token,
+            user_token_xsos,
+            pool_lock_amount,
+            &program_context,
+        );
+        program_context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+
+        let pool_user_authority = sol_starter_staking::instruction::find_2key_program_address(
+            &pool.pubkey(),
+            &user_wallet.pubkey(),
+        );
+        let pool_lock = Pubkey::create_with_seed(
+            &pool_user_authority,
+            sol_starter_staking::LOCK_SEED,
+            &sol_starter_staking::id(),
+        )
+        .unwrap();
+
+        locks.push((pool_lock, pool_lock_token_key));
+    }
+
+    (program_context, pool, locks)
+}
+
+#[test]
+fn test_process_instruction_malformed_data() {
+    let program_id = crate::id();
+    let data = instruction::Instruction::InitializeMarket(InitializeMarket {
+        stake_pool: Pubkey::new_unique(),
+    })
+    .try_to_vec()
     .unwrap();
+    assert_eq!(
+        crate::processor::Processor::process_instruction(&program_id, &[], &data),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+
+    let mut trailing = data.clone();
+    trailing.push(0);
+    for malformed in [
+        &trailing[..],
+        &data[..data.len() - 1],
+        &[][..],
+        &[u8::MAX][..],
+    ]
+    .iter()
+    {
+        assert_eq!(
+            crate::processor::Processor::process_instruction(&program_id, &[], malformed),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+}
+
+#[cfg(feature = "verbose")]
+#[tokio::test]
+async fn test_failed_cpi_is_tagged_in_logs() {
+    use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+    use std::cell::RefCell;
+
+    thread_local! {
+        static LOGS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+    }
+
+    /// fails CPIs and captures logs while capturing on this thread, otherwise defers to wrapped stubs
+    struct FailingStubs(Box<dyn SyscallStubs>);
+
+    impl SyscallStubs for FailingStubs {
+        fn sol_log(&self, message: &str) {
+            let captured = LOGS.with(|logs| match logs.borrow_mut().as_mut() {
+                Some(logs) => {
+                    logs.push(message.to_string());
+                    true
+                }
+                None => false,
+            });
+            if !captured {
+                self.0.sol_log(message);
+            }
+        }
+
+        fn sol_invoke_signed(
+            &self,
+            instruction: &SolanaInstruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            if LOGS.with(|logs| logs.borrow().is_some()) {
+                Err(ProgramError::Custom(42))
+            } else {
+                self.0
+                    .sol_invoke_signed(instruction, account_infos, signers_seeds)
+            }
+        }
+    }
+
+    struct Placeholder;
+    impl SyscallStubs for Placeholder {}
+
+    // program test installs its stubs on first start, so they are wrapped
+    program_test().start_with_context().await;
+    let previous = set_syscall_stubs(Box::new(Placeholder));
+    set_syscall_stubs(Box::new(FailingStubs(previous)));
+
+    let owner = Pubkey::default();
+    let mut accounts: Vec<_> = (0..3).map(|_| (Pubkey::new_unique(), 0, vec![])).collect();
+    let infos: Vec<_> = accounts
+        .iter_mut()
+        .map(|(key, lamports, data)| {
+            AccountInfo::new(key, true, true, lamports, data, &owner, false, 0)
+        })
+        .collect();
+
+    LOGS.with(|logs| *logs.borrow_mut() = Some(vec![]));
+    let result = crate::invoke::token_transfer_with_user_authority(
+        infos[0].clone(),
+        infos[1].clone(),
+        infos[2].clone(),
+        1,
+    );
+    let logs = LOGS.with(|logs| logs.borrow_mut().take()).unwrap();
+
+    assert_eq!(result, Err(ProgramError::Custom(42)));
+    assert_eq!(logs.last().unwrap(), "CPI spl_token transfer");
+}
+
+mod fuzz {
+    use super::*;
+    use crate::instruction::{
+        FundDistribution, Instruction, MigrateAccount, Participate, RecomputeTierUsers,
+        SetMarketFee, SetStakePoolIdoAuthority, SetStakesPaused, Withdraw,
+    };
+    use proptest::prelude::*;
+    use sol_starter_staking::borsh::try_instruction_from_slice;
+
+    /// dispatches `data` with `accounts_count` unsigned system accounts without data
+    fn process_with_accounts(data: &[u8], accounts_count: usize) -> ProgramResult {
+        let keys: Vec<_> = (0..accounts_count).map(|_| Pubkey::new_unique()).collect();
+        let owner = Pubkey::default();
+        let mut lamports = vec![0; accounts_count];
+        let mut accounts_data = vec![Vec::new(); accounts_count];
+        let accounts: Vec<_> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(accounts_data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        crate::processor::Processor::process_instruction(&crate::id(), &accounts, data)
+    }
+
+    fn any_pubkey() -> impl Strategy<Value = Pubkey> {
+        any::<[u8; 32]>().prop_map(|bytes| Pubkey::new(&bytes))
+    }
+
+    fn any_kyc_requirement() -> impl Strategy<Value = KycRequirement> {
+        prop_oneof![
+            Just(KycRequirement::NotRequired),
+            Just(KycRequirement::AnyRequired),
+            any_pubkey().prop_map(KycRequirement::SpecificProvider),
+        ]
+    }
+
+    fn any_initialize_pool() -> impl Strategy<Value = instruction::InitializePool> {
+        (
+            (any_pubkey(), any::<[u64; 5]>(), any::<(i64, i64)>()),
+            (
+                prop::option::of(any_kyc_requirement()),
+                prop::option::of(any::<[u32; crate::STAGES_ACTIVE_COUNT]>()),
+                any::<(u8, u64, u64, u32)>(),
+                prop::option::of(any::<u64>()),
+            ),
+        )
+            .prop_map(
+                |(
+                    (pool_owner, [price, goal_max, goal_min, amount_min, amount_max], times),
+                    (kyc_requirement, time_table, rest, nonce),
+                )| {
+                    let (time_start, time_finish) = times;
+                    let (stages_count, whitelist_ticket_amount, per_user_max, refund_grace_period) =
+                        rest;
+                    instruction::InitializePool {
+                        pool_owner,
+                        price,
+                        goal_max,
+                        goal_min,
+                        amount_min,
+                        amount_max,
+                        time_start,
+                        time_finish,
+                        kyc_requirement,
+                        time_table,
+                        stages_count,
+                        whitelist_ticket_amount,
+                        per_user_max,
+                        refund_grace_period,
+                        nonce,
+                    }
+                },
+            )
+    }
+
+    fn any_pool_defaults() -> impl Strategy<Value = state::PoolDefaults> {
+        (
+            any::<(bool, u64, u64)>(),
+            any_kyc_requirement(),
+            any::<[u32; crate::STAGES_ACTIVE_COUNT]>(),
+            any::<u8>(),
+        )
+            .prop_map(
+                |(
+                    (is_set, amount_investment_min, amount_investment_max),
+                    kyc_requirement,
+                    time_table,
+                    stages_count,
+                )| state::PoolDefaults {
+                    is_set,
+                    amount_investment_min,
+                    amount_investment_max,
+                    kyc_requirement,
+                    time_table,
+                    stages_count,
+                },
+            )
+    }
+
+    fn any_instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            any_pubkey().prop_map(|stake_pool| {
+                Instruction::InitializeMarket(InitializeMarket { stake_pool })
+            }),
+            any_initialize_pool().prop_map(Instruction::InitializePool),
+            any_pool_defaults().prop_map(Instruction::SetMarketDefaults),
+            any::<(u64, u64, u64, bool)>().prop_map(
+                |(amount, whitelist_tickets, min_accepted, receipt)| {
+                    Instruction::Participate(Participate {
+                        amount,
+                        whitelist_tickets,
+                        min_accepted,
+                        receipt,
+                    })
+                }
+            ),
+            any::<bool>().prop_map(|skip_owner_check| {
+                Instruction::WithdrawTo(Withdraw { skip_owner_check })
+            }),
+            any::<bool>().prop_map(|skip_owner_check| {
+                Instruction::WithdrawAllTo(Withdraw { skip_owner_check })
+            }),
+            any::<i64>().prop_map(|expiration| {
+                Instruction::CreateMarketUserKyc(CreateMarketUserKyc { expiration })
+            }),
+            any::<bool>().prop_map(|pool| {
+                Instruction::Migrate(if pool {
+                    MigrateAccount::Pool
+                } else {
+                    MigrateAccount::Market
+                })
+            }),
+            any::<i64>().prop_map(|expiration| {
+                Instruction::RenewMarketUserKyc(RenewMarketUserKyc { expiration })
+            }),
+            any::<u64>()
+                .prop_map(|amount| { Instruction::FundDistribution(FundDistribution { amount }) }),
+            (any::<u16>(), any_pubkey()).prop_map(|(fee_basis_points, fee_account)| {
+                Instruction::SetMarketFee(SetMarketFee {
+                    fee_basis_points,
+                    fee_account,
+                })
+            }),
+            (any::<bool>(), any::<bool>()).prop_map(|(first, last)| {
+                Instruction::RecomputeTierUsers(RecomputeTierUsers { first, last })
+            }),
+            any::<bool>().prop_map(|stakes_paused| {
+                Instruction::SetStakesPaused(SetStakesPaused { stakes_paused })
+            }),
+            any_pubkey().prop_map(|ido_authority| {
+                Instruction::SetStakePoolIdoAuthority(SetStakePoolIdoAuthority { ido_authority })
+            }),
+            (0..7u8).prop_map(|variant| match variant {
+                0 => Instruction::Claim,
+                1 => Instruction::AddToWhitelist,
+                2 => Instruction::DeleteMarketUserKyc,
+                3 => Instruction::StartPool,
+                4 => Instruction::WithdrawAll,
+                5 => Instruction::Withdraw,
+                _ => Instruction::AddManyToWhitelist,
+            }),
+        ]
+    }
+
+    #[test]
+    fn too_few_accounts() {
+        let initialize_pool = instruction::InitializePool {
+            pool_owner: Pubkey::new_unique(),
+            price: 5,
+            goal_max: 150,
+            goal_min: 10,
+            amount_min: 3,
+            amount_max: 100,
+            time_start: 1,
+            time_finish: 2,
+            kyc_requirement: None,
+            time_table: None,
+            stages_count: 2,
+            whitelist_ticket_amount: 0,
+            per_user_max: 0,
+            refund_grace_period: 0,
+            nonce: None,
+        };
+        let instructions = [
+            (
+                Instruction::InitializeMarket(InitializeMarket {
+                    stake_pool: Pubkey::new_unique(),
+                }),
+                3,
+            ),
+            (Instruction::InitializePool(initialize_pool), 13),
+            (
+                Instruction::Participate(Participate {
+                    amount: 1,
+                    whitelist_tickets: 0,
+                    min_accepted: 0,
+                    receipt: true,
+                }),
+                20,
+            ),
+            (Instruction::Claim, 10),
+            (Instruction::AddToWhitelist, 6),
+            (Instruction::AddManyToWhitelist, 5),
+            (Instruction::Withdraw, 8),
+            (Instruction::WithdrawTo(Withdraw::CHECKED), 8),
+            (
+                Instruction::CreateMarketUserKyc(CreateMarketUserKyc { expiration: 0 }),
+                8,
+            ),
+            (Instruction::DeleteMarketUserKyc, 6),
+            (Instruction::StartPool, 7),
+            (Instruction::Migrate(MigrateAccount::Pool), 2),
+            (
+                Instruction::RenewMarketUserKyc(RenewMarketUserKyc { expiration: 0 }),
+                6,
+            ),
+            (
+                Instruction::FundDistribution(FundDistribution { amount: 0 }),
+                5,
+            ),
+            (Instruction::WithdrawAll, 10),
+            (Instruction::WithdrawAllTo(Withdraw::CHECKED), 10),
+            (
+                Instruction::SetMarketDefaults(state::PoolDefaults::default()),
+                2,
+            ),
+            (
+                Instruction::SetMarketFee(SetMarketFee {
+                    fee_basis_points: 0,
+                    fee_account: Pubkey::new_unique(),
+                }),
+                3,
+            ),
+            (
+                Instruction::RecomputeTierUsers(RecomputeTierUsers {
+                    first: true,
+                    last: true,
+                }),
+                6,
+            ),
+            (
+                Instruction::SetStakesPaused(SetStakesPaused {
+                    stakes_paused: true,
+                }),
+                5,
+            ),
+            (
+                Instruction::SetStakePoolIdoAuthority(SetStakePoolIdoAuthority {
+                    ido_authority: Pubkey::new_unique(),
+                }),
+                5,
+            ),
+        ];
+        for (instruction, required) in instructions.iter() {
+            let data = instruction.try_to_vec().unwrap();
+            assert_eq!(
+                process_with_accounts(&data, required - 1),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "{:?}",
+                instruction
+            );
+            assert_ne!(
+                process_with_accounts(&data, *required),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "{:?}",
+                instruction
+            );
+        }
+    }
 
-    (program_context, pool, pool_lock, pool_lock_token_key)
+    proptest! {
+        #[test]
+        fn process_instruction_never_panics(
+            tag in 0..32u8,
+            rest in prop::collection::vec(any::<u8>(), 0..256),
+            accounts_count in 0..24usize,
+        ) {
+            let mut data = vec![tag];
+            data.extend(rest);
+            let result = process_with_accounts(&data, accounts_count);
+            if try_instruction_from_slice::<Instruction>(&data).is_err() {
+                prop_assert_eq!(result, Err(ProgramError::InvalidInstructionData));
+            } else {
+                prop_assert!(result.is_err());
+            }
+        }
+
+        #[test]
+        fn instruction_round_trip(instruction in any_instruction()) {
+            let data = instruction.try_to_vec().unwrap();
+            prop_assert_eq!(
+                process_with_accounts(&data, 0),
+                Err(ProgramError::NotEnoughAccountKeys)
+            );
+            prop_assert_eq!(try_instruction_from_slice::<Instruction>(&data), Ok(instruction));
+        }
+    }
 }
@@ -3,14 +3,48 @@ use sol_starter_staking::program::ProgramPubkey;
 use solana_program::{
     account_info::AccountInfo,
     entrypoint::ProgramResult,
+    instruction::Instruction,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
     pubkey::Pubkey,
     system_instruction,
 };
-use spl_token::instruction::{initialize_account, initialize_mint as initialize_token_mint};
+use spl_token::instruction::{
+    initialize_account, initialize_mint as initialize_token_mint, AuthorityType,
+};
+
+use crate::{error::Error, spl_token_id, utils::program::is_associated_token_account};
+
+/// Logs `tag` of cross program invocation about to be made with `verbose` feature,
+/// so logs of failed transaction end with the failed one. Does nothing without the feature.
+#[inline(always)]
+pub fn log_cpi(tag: &str) {
+    #[cfg(feature = "verbose")]
+    solana_program::msg!("CPI {}", tag);
+    #[cfg(not(feature = "verbose"))]
+    let _ = tag;
+}
 
-use crate::spl_token_id;
+/// [invoke] logged by [log_cpi]
+pub fn invoke_tagged(
+    tag: &str,
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    log_cpi(tag);
+    invoke(instruction, account_infos)
+}
+
+/// [invoke_signed] logged by [log_cpi]
+pub fn invoke_signed_tagged(
+    tag: &str,
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    log_cpi(tag);
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
 
 /// Create account
 pub fn create_account<'a>(
@@ -21,7 +55,8 @@ pub fn create_account<'a>(
     owner: &ProgramPubkey,
     signer_seeds: &[&[u8]],
 ) -> ProgramResult {
-    invoke_signed(
+    invoke_signed_tagged(
+        "system create_account",
         &system_instruction::create_account(
             &funder.key,
             &account_to_create.key,
@@ -41,7 +76,8 @@ pub fn transfer_program<'a>(
     lamports: u64,
     signer_seeds: &[&[u8]],
 ) -> ProgramResult {
-    invoke_signed(
+    invoke_signed_tagged(
+        "system transfer",
         &system_instruction::transfer(&from.key, &to.key, lamports),
         &[from.clone(), to.clone()],
         &[&signer_seeds],
@@ -55,7 +91,8 @@ pub fn initialize_token_account<'a>(
     owner: AccountInfo<'a>,
     rent_account: AccountInfo<'a>,
 ) -> ProgramResult {
-    invoke(
+    invoke_tagged(
+        "spl_token initialize_account",
         &initialize_account(
             &spl_token_id().pubkey(),
             &account_to_initialize.key,
@@ -73,7 +110,8 @@ pub fn initialize_mint<'a>(
     decimals: u8,
     rent_account: AccountInfo<'a>,
 ) -> ProgramResult {
-    invoke(
+    invoke_tagged(
+        "spl_token initialize_mint",
         &initialize_token_mint(
             &spl_token_id().pubkey(),
             &mint_to_initialize.key,
@@ -106,7 +144,12 @@ pub fn token_transfer<'a>(
         &[&authority.key],
         amount,
     )?;
-    invoke_signed(&tx, &[source, destination, authority], signers)
+    invoke_signed_tagged(
+        "spl_token transfer",
+        &tx,
+        &[source, destination, authority],
+        signers,
+    )
 }
 
 /// Transfer tokens with user transfer authority
@@ -124,7 +167,7 @@ pub fn token_transfer_with_user_authority<'a>(
         &[&authority.key],
         amount,
     )?;
-    invoke(&tx, &[source, destination, authority])
+    invoke_tagged("spl_token transfer", &tx, &[source, destination, authority])
 }
 
 /// Issue a spl_token `MintTo` instruction
@@ -148,7 +191,41 @@ pub fn token_mint_to<'a>(
         amount,
     )?;
 
-    invoke_signed(&ix, &[mint, destination, authority], signers)
+    invoke_signed_tagged(
+        "spl_token mint_to",
+        &ix,
+        &[mint, destination, authority],
+        signers,
+    )
+}
+
+/// Issue a spl_token `SetAuthority` instruction signed by pool authority
+#[allow(clippy::too_many_arguments)]
+pub fn token_set_authority<'a>(
+    pool: &Pubkey,
+    account: AccountInfo<'a>,
+    authority: AccountInfo<'a>,
+    bump_seed: u8,
+    authority_type: AuthorityType,
+    new_authority: &Pubkey,
+) -> Result<(), ProgramError> {
+    let authority_signature_seeds = [&pool.to_bytes()[..32], &[bump_seed]];
+    let signers = &[&authority_signature_seeds[..]];
+    let ix = spl_token::instruction::set_authority(
+        &spl_token_id().pubkey(),
+        account.key,
+        Some(new_authority),
+        authority_type,
+        authority.key,
+        &[],
+    )?;
+
+    invoke_signed_tagged(
+        "spl_token set_authority",
+        &ix,
+        &[account, authority],
+        signers,
+    )
 }
 
 /// Burn tokens with user authority
@@ -167,5 +244,70 @@ pub fn burn_tokens_with_user_authority<'a>(
         amount,
     )?;
 
-    invoke(&tx, &[burn_account, mint, authority])
+    invoke_tagged("spl_token burn", &tx, &[burn_account, mint, authority])
+}
+
+/// Creates associated token account of `wallet` for `mint`, fails if `account` already exists
+#[allow(clippy::too_many_arguments)]
+pub fn create_associated_token_account<'a>(
+    account: AccountInfo<'a>,
+    payer: AccountInfo<'a>,
+    wallet: AccountInfo<'a>,
+    mint: AccountInfo<'a>,
+    system_program: AccountInfo<'a>,
+    token_program: AccountInfo<'a>,
+    rent: AccountInfo<'a>,
+    associated_token_program: AccountInfo<'a>,
+) -> ProgramResult {
+    if *associated_token_program.key != spl_associated_token_account::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    if !is_associated_token_account(account.key, wallet.key, mint.key) {
+        return Err(Error::WrongAssociatedTokenAccount.into());
+    }
+
+    invoke_tagged(
+        "spl_associated_token_account create",
+        &spl_associated_token_account::create_associated_token_account(
+            payer.key, wallet.key, mint.key,
+        ),
+        &[
+            payer,
+            account,
+            wallet,
+            mint,
+            system_program,
+            token_program,
+            rent,
+            associated_token_program,
+        ],
+    )
+}
+
+/// Same as [create_associated_token_account], but does nothing if `account` already exists
+#[allow(clippy::too_many_arguments)]
+pub fn create_associated_token_account_idempotent<'a>(
+    account: AccountInfo<'a>,
+    payer: AccountInfo<'a>,
+    wallet: AccountInfo<'a>,
+    mint: AccountInfo<'a>,
+    system_program: AccountInfo<'a>,
+    token_program: AccountInfo<'a>,
+    rent: AccountInfo<'a>,
+    associated_token_program: AccountInfo<'a>,
+) -> ProgramResult {
+    if !account.data_is_empty() {
+        return Ok(());
+    }
+
+    create_associated_token_account(
+        account,
+        payer,
+        wallet,
+        mint,
+        system_program,
+        token_program,
+        rent,
+        associated_token_program,
+    )
 }
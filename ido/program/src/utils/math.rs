@@ -2,6 +2,7 @@
 
 use solana_program::clock::UnixTimestamp;
 use solana_program::program_error::ProgramError;
+use std::convert::TryFrom;
 
 use crate::error::Error;
 
@@ -108,3 +109,99 @@ impl ErrorMulDiv<u128> for u128 {
             .ok_or_else(|| Error::DivisionByZero.into())
     }
 }
+
+/// Rounding of [error_mul_div] result
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Rounding {
+    /// round down
+    Floor,
+    /// round up
+    Ceil,
+}
+
+/// `a * num / denom` with 128 bit intermediate, so only result not fitting into u64 overflows
+pub fn error_mul_div(a: u64, num: u64, denom: u64, round: Rounding) -> Result<u64, ProgramError> {
+    if denom == 0 {
+        return Err(Error::DivisionByZero.into());
+    }
+    let denom = denom as u128;
+    let product = (a as u128).error_mul(num as u128)?;
+    // product is at most (2^64 - 1)^2, so adding denom - 1 never overflows
+    let product = match round {
+        Rounding::Floor => product,
+        Rounding::Ceil => product.error_add(denom - 1)?,
+    };
+    u64::try_from(product.error_div(denom)?).map_err(|_| Error::Overflow.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn mul_div() {
+        assert_eq!(
+            error_mul_div(1, 1_000_000_000, 3, Rounding::Floor),
+            Ok(333333333)
+        );
+        assert_eq!(
+            error_mul_div(1, 1_000_000_000, 3, Rounding::Ceil),
+            Ok(333333334)
+        );
+        assert_eq!(
+            error_mul_div(3, 1_000_000_000, 3, Rounding::Ceil),
+            Ok(1_000_000_000)
+        );
+        assert_eq!(error_mul_div(0, 5, 3, Rounding::Ceil), Ok(0));
+        assert_eq!(
+            error_mul_div(u64::MAX, u64::MAX, u64::MAX, Rounding::Ceil),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            error_mul_div(u64::MAX, 2, 1, Rounding::Floor),
+            Err(Error::Overflow.into())
+        );
+        assert_eq!(
+            error_mul_div(1, 1, 0, Rounding::Floor),
+            Err(Error::DivisionByZero.into())
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn mul_div_never_overflows_intermediate(
+            a in any::<u64>(),
+            num in any::<u64>(),
+            denom in 1..=u64::MAX,
+        ) {
+            let exact = a as u128 * num as u128 / denom as u128;
+            let floor = error_mul_div(a, num, denom, Rounding::Floor);
+            let ceil = error_mul_div(a, num, denom, Rounding::Ceil);
+            if exact < u64::MAX as u128 {
+                let floor = floor.unwrap();
+                let ceil = ceil.unwrap();
+                prop_assert_eq!(floor as u128, exact);
+                prop_assert!(ceil >= floor);
+                prop_assert!(ceil - floor <= 1);
+            } else if exact > u64::MAX as u128 {
+                prop_assert_eq!(floor, Err(Error::Overflow.into()));
+                prop_assert_eq!(ceil, Err(Error::Overflow.into()));
+            }
+        }
+
+        #[test]
+        fn mul_div_by_fraction_fits(
+            a in any::<u64>(),
+            num in any::<u64>(),
+            extra in any::<u64>(),
+        ) {
+            let denom = num.saturating_add(extra).max(1);
+            let floor = error_mul_div(a, num, denom, Rounding::Floor).unwrap();
+            let ceil = error_mul_div(a, num, denom, Rounding::Ceil).unwrap();
+            prop_assert!(floor <= a);
+            prop_assert!(ceil <= a);
+            prop_assert!(ceil >= floor);
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 pub mod invoke;
 pub mod math;
+pub mod prelude;
 pub mod program;
 
 #[cfg(all(feature = "test-bpf", test))]
@@ -0,0 +1,55 @@
+//! Reusable imports
+pub use borsh::BorshDeserialize;
+
+pub use solana_program::pubkey::Pubkey;
+
+pub use crate::{
+    error::Error,
+    instruction::{
+        add_many_to_whitelist, add_to_whitelist, claim, create_market_user_kyc,
+        delete_market_user_kyc, fund_distribution, initialize_market, initialize_pool, migrate,
+        participate, renew_market_user_kyc, set_market_defaults, start_pool, withdraw,
+        withdraw_all, CreateAccountTo, CreateMarketUserKyc, FundDistribution, InitializeMarket,
+        InitializePool, Instruction, MigrateAccount, Participate, RenewMarketUserKyc,
+    },
+    math::{ErrorAddSub, ErrorMulDiv},
+    program_id,
+    state::{KycRequirement, Market, MintWhitelist, Pool, PoolDefaults},
+};
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use solana_program::program_error::ProgramError;
+
+    #[test]
+    fn reexports() {
+        let market = Pubkey::new_unique();
+        let instruction = initialize_market(
+            &program_id(),
+            &market,
+            &Pubkey::new_unique(),
+            InitializeMarket {
+                stake_pool: Pubkey::new_unique(),
+            },
+        )
+        .unwrap();
+        assert_eq!(instruction.program_id, crate::id());
+        assert!(matches!(
+            Instruction::try_from_slice(&instruction.data).unwrap(),
+            Instruction::InitializeMarket(_)
+        ));
+
+        let pool = Pool {
+            market,
+            kyc_requirement: KycRequirement::default(),
+            ..Default::default()
+        };
+        assert_eq!(pool.market, market);
+        assert_eq!(Market::default().stake_pool, Pubkey::default());
+        assert_eq!(
+            1u64.error_add(2).unwrap().error_sub(3).unwrap_err(),
+            ProgramError::from(Error::Underflow)
+        );
+    }
+}
@@ -2,17 +2,23 @@
 
 use sol_starter_staking::program::ProgramPubkey;
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, program_error::ProgramError,
-    pubkey::Pubkey, system_instruction,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    hash::hashv,
+    program_error::ProgramError,
+    pubkey::{Pubkey, PubkeyError, MAX_SEED_LEN},
+    system_instruction,
 };
 
+use crate::error::Error;
+
 /// some reusable methods around accounts
 pub trait AccountPatterns {
     /// public key
     fn pubkey(&self) -> Pubkey;
 
-    /// checks if program_id owner of self
-    fn is_owner(&self, program_id: &ProgramPubkey) -> ProgramResult;
+    /// checks if `program` is owner of self, errors with [ProgramError::IncorrectProgramId] otherwise
+    fn owned_by(&self, program: &ProgramPubkey) -> ProgramResult;
 
     /// checks if account is signer
     fn is_signer(&self) -> ProgramResult;
@@ -23,8 +29,8 @@ impl<'a> AccountPatterns for AccountInfo<'a> {
         *self.key
     }
 
-    fn is_owner(&self, program_id: &ProgramPubkey) -> ProgramResult {
-        if *self.owner != program_id.pubkey() {
+    fn owned_by(&self, program: &ProgramPubkey) -> ProgramResult {
+        if *self.owner != program.pubkey() {
             return Err(ProgramError::IncorrectProgramId);
         }
 
@@ -63,7 +69,8 @@ pub fn create_account_with_seed_signed<'a>(
         &program_owner.pubkey(),
     );
     let signature = &[&account_owner.key.to_bytes()[..32], &[bump_seed]];
-    solana_program::program::invoke_signed(
+    crate::invoke::invoke_signed_tagged(
+        "system create_account_with_seed",
         instruction,
         &[from_account.clone(), to_account.clone(), base.clone()],
         &[&signature[..]],
@@ -71,10 +78,104 @@ pub fn create_account_with_seed_signed<'a>(
     Ok(())
 }
 
-/// burns account
-pub fn burn_account(burned: &AccountInfo, beneficiary: &AccountInfo) {
-    let mut from = burned.try_borrow_mut_lamports().unwrap();
-    let mut to = beneficiary.try_borrow_mut_lamports().unwrap();
-    **to += **from;
+/// true if `account` is associated token account of `wallet` for `mint`
+pub fn is_associated_token_account(account: &Pubkey, wallet: &Pubkey, mint: &Pubkey) -> bool {
+    *account == spl_associated_token_account::get_associated_token_address(wallet, mint)
+}
+
+/// Seed of pool account of `kind` (like [crate::POOL_SEED]) for pool number `nonce` of `market`.
+/// Hex of hash, so it fits [MAX_SEED_LEN] and clients can compute it before pool creation.
+pub fn pool_account_seed(market: &Pubkey, nonce: u64, kind: &str) -> String {
+    let hash = hashv(&[market.as_ref(), &nonce.to_le_bytes(), kind.as_bytes()]);
+    hash.to_bytes()[..MAX_SEED_LEN / 2]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Address of pool account of `kind` created by `market_owner` with [pool_account_seed] for `owner` program,
+/// see [crate::instruction::InitializePool::nonce]
+pub fn derive_pool_account(
+    market_owner: &Pubkey,
+    market: &Pubkey,
+    nonce: u64,
+    kind: &str,
+    owner: &Pubkey,
+) -> Result<Pubkey, PubkeyError> {
+    Pubkey::create_with_seed(market_owner, &pool_account_seed(market, nonce, kind), owner)
+}
+
+/// burns account, moving all its lamports to `beneficiary`
+pub fn burn_account(burned: &AccountInfo, beneficiary: &AccountInfo) -> ProgramResult {
+    let mut from = burned.try_borrow_mut_lamports()?;
+    let mut to = beneficiary.try_borrow_mut_lamports()?;
+    **to = to.checked_add(**from).ok_or(Error::Overflow)?;
     **from = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_by() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [];
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &program_id.0,
+            false,
+            0,
+        );
+        assert_eq!(account.owned_by(&program_id), Ok(()));
+        assert_eq!(
+            account.owned_by(&ProgramPubkey(Pubkey::new_unique())),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn associated_token_account() {
+        let wallet = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let associated = spl_associated_token_account::get_associated_token_address(&wallet, &mint);
+        assert!(is_associated_token_account(&associated, &wallet, &mint));
+        assert!(!is_associated_token_account(
+            &Pubkey::new_unique(),
+            &wallet,
+            &mint
+        ));
+        assert!(!is_associated_token_account(
+            &associated,
+            &wallet,
+            &Pubkey::new_unique()
+        ));
+    }
+
+    #[test]
+    fn pool_account_seeds() {
+        let market = Pubkey::new_unique();
+        let seed = pool_account_seed(&market, 7, crate::POOL_SEED);
+        assert_eq!(seed.len(), MAX_SEED_LEN);
+        assert_eq!(seed, pool_account_seed(&market, 7, crate::POOL_SEED));
+        assert_ne!(seed, pool_account_seed(&market, 8, crate::POOL_SEED));
+        assert_ne!(seed, pool_account_seed(&market, 7, crate::POOL_MINT_SEED));
+        assert_ne!(
+            seed,
+            pool_account_seed(&Pubkey::new_unique(), 7, crate::POOL_SEED)
+        );
+
+        let market_owner = Pubkey::new_unique();
+        assert_eq!(
+            derive_pool_account(&market_owner, &market, 7, crate::POOL_SEED, &crate::id()).unwrap(),
+            Pubkey::create_with_seed(&market_owner, &seed, &crate::id()).unwrap()
+        );
+    }
 }
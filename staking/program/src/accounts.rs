@@ -0,0 +1,250 @@
+//! Typed accounts of instructions, named in order of instruction accounts
+
+use crate::instruction_accounts;
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::InitializePool]
+    pub(crate) struct InitializePoolAccounts {
+        pool,
+        token_account_sos,
+        mint_sos,
+        pool_mint_xsos,
+        program_authority,
+        rent,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::StakeStart]
+    pub(crate) struct StakeStartAccounts {
+        pool,
+        pool_transit,
+        pool_authority,
+        pool_token_account_sos,
+        pool_transit_token_account_sos,
+        mint_sos,
+        user_wallet,
+        user_token_account_sos,
+        rent,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::StakeFinish]
+    pub(crate) struct StakeFinishAccounts {
+        pool,
+        pool_authority,
+        pool_token_account_sos,
+        pool_transit,
+        pool_transit_token_account_sos,
+        user_token_account_xsos,
+        user_wallet,
+        pool_mint_xsos,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::UnstakeStart]
+    pub(crate) struct UnstakeStartAccounts {
+        pool,
+        pool_authority,
+        pool_token_account_sos,
+        pool_transit,
+        pool_transit_token_account_sos,
+        mint_sos,
+        user_wallet,
+        user_token_account_xsos,
+        mint_xsos,
+        rent,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::UnstakeFinish]
+    pub(crate) struct UnstakeFinishAccounts {
+        pool,
+        pool_transit,
+        pool_authority,
+        pool_transit_account_sos,
+        user_wallet,
+        user_token_account_sos,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::InitializeLock]
+    pub(crate) struct InitializeLockAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_mint_xsos,
+        pool_lock_token_account_xsos,
+        rent,
+        system_program,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Lock]
+    pub(crate) struct LockAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_lock_token_account_xsos,
+        user_token_account_xsos,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Unlock]
+    pub(crate) struct UnlockAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_lock_token_account_xsos,
+        user_token_account_xsos,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::StartPool]
+    pub(crate) struct StartPoolAccounts {
+        pool,
+        market_authority,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::ClaimStakingReward]
+    pub(crate) struct ClaimStakingRewardAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_lock_token_account_xsos,
+        pool_authority,
+        reward_mint,
+        user_token_account_reward,
+        clock,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::CancelTransit]
+    pub(crate) struct CancelTransitAccounts {
+        pool,
+        pool_authority,
+        pool_token_account_sos,
+        pool_transit,
+        pool_transit_token_account_sos,
+        user_wallet,
+        user_token_account_sos,
+        user_token_account_xsos,
+        pool_mint_xsos,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::ExtendPoolActive]
+    pub(crate) struct ExtendPoolActiveAccounts {
+        pool,
+        market_authority,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::RecomputeTierUsers]
+    pub(crate) struct RecomputeTierUsersAccounts {
+        pool,
+        market_authority,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::RecomputeTierUsersBatch]
+    pub(crate) struct RecomputeTierUsersBatchAccounts {
+        pool,
+        market_authority,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::InitializeLockAndLock]
+    pub(crate) struct InitializeLockAndLockAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_mint_xsos,
+        pool_lock_token_account_xsos,
+        user_token_account_xsos,
+        rent,
+        clock,
+        system_program,
+        token_program,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetStakesPaused]
+    pub(crate) struct SetStakesPausedAccounts {
+        pool,
+        market_authority,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::SetIdoAuthority]
+    pub(crate) struct SetIdoAuthorityAccounts {
+        pool,
+        market_authority,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::RefreshLock]
+    pub(crate) struct RefreshLockAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_lock_token_account_xsos,
+        clock,
+    }
+}
+
+instruction_accounts! {
+    /// Accounts of [crate::instruction::Instruction::Compound]
+    pub(crate) struct CompoundAccounts {
+        pool,
+        user_wallet,
+        pool_lock,
+        pool_user_authority,
+        pool_lock_token_account_xsos,
+        user_token_account_xsos,
+        clock,
+        token_program,
+    }
+}
@@ -1,6 +1,6 @@
 //! Error types
 
-use num_derive::FromPrimitive;
+use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::FromPrimitive;
 use solana_program::{
     decode_error::DecodeError,
@@ -9,7 +9,7 @@ use solana_program::{
 };
 
 /// Errors that may be returned by the SolStarter program.
-#[derive(Clone, Debug, Eq, thiserror::Error, FromPrimitive, PartialEq)]
+#[derive(Clone, Debug, Eq, thiserror::Error, FromPrimitive, PartialEq, ToPrimitive)]
 pub enum Error {
     /// Wrong market owner account
     #[error("Wrong owner account")]
@@ -70,6 +70,109 @@ pub enum Error {
     /// Pool transit must be of provided pool
     #[error("Pool transit must be of provided pool")]
     PoolTransitMustBeOfProvidedPool,
+
+    /// Rewards are not enabled for the pool
+    #[error("Rewards are not enabled for the pool")]
+    RewardsAreNotEnabled,
+
+    /// Reward mint authority must be pool authority
+    #[error("Reward mint authority must be pool authority")]
+    RewardMintAuthorityMustBePoolAuthority,
+
+    /// Nothing to claim
+    #[error("Nothing to claim")]
+    NothingToClaim,
+
+    /// Tier balance thresholds must be strictly increasing
+    #[error("Tier balance thresholds must be strictly increasing")]
+    TierBalanceMustBeIncreasing,
+
+    /// Pool active window can only be extended
+    #[error("Pool active window can only be extended")]
+    PoolActiveCannotBeShortened,
+
+    /// Same lock passed more than once
+    #[error("Same lock passed more than once")]
+    DuplicateLock,
+
+    /// Account was written by unknown version of the program
+    #[error("Account was written by unknown version of the program")]
+    UnsupportedStateVersion,
+
+    /// New stakes are paused by IDO authority
+    #[error("New stakes are paused by IDO authority")]
+    StakesPaused,
+
+    /// IDO authority must not be default key
+    #[error("IDO authority must not be default key")]
+    IdoAuthorityInvalid,
+
+    /// Amount must be greater than zero
+    #[error("Amount must be greater than zero")]
+    ZeroAmount,
+
+    /// Locks of recount batches must be passed in ascending order of keys
+    #[error("Locks must be passed in ascending order of keys")]
+    LocksMustBeSorted,
+
+    /// Recount batch continues recount which was not started
+    #[error("Recount must be started by first batch")]
+    RecountNotStarted,
+
+    /// Recount of several batches requires active pool
+    #[error("Recount of several batches requires active pool")]
+    RecountRequiresActivePool,
+
+    /// Account data starts with type of other account
+    #[error("Account data is of other account type")]
+    WrongAccountType,
+}
+
+impl Error {
+    /// Stable human readable description, same as [std::fmt::Display]
+    pub fn description(&self) -> &'static str {
+        match self {
+            Error::WrongOwner => "Wrong owner account",
+            Error::PoolMustBeRelatedToMarket => "Pool must be related to market",
+            Error::LockMustBeRelatedToPool => "Lock must be related to pool",
+            Error::PoolMustBeActiveForSomeTime => "Pool must be active for some time",
+            Error::CannotUnlockWhenPoolIsActive => "Cannot unlock when pool is active",
+            Error::CannotLockWhenPoolIsActive => "Cannot lock when pool is active",
+            Error::InvalidAuthority => "Invalid authority",
+            Error::WrongAccountSpecified => "Wrong account specified",
+            Error::Overflow => "Overflow",
+            Error::Underflow => "Underflow",
+            Error::CannotTransitAnythingNow => "Cannot transit anything now",
+            Error::DerivedAccountKeyIsNotEqualToCalculated => {
+                "Derived account key is not equal to calculated"
+            }
+            Error::DerivedPoolLockAccountKeyIsNotEqualToCalculated => {
+                "Derived pool lock account key is not equal to calculated"
+            }
+            Error::PoolTransitWrongDirection => "Pool transit wrong direction",
+            Error::PoolTransitMustBeOfProvidedPool => "Pool transit must be of provided pool",
+            Error::RewardsAreNotEnabled => "Rewards are not enabled for the pool",
+            Error::RewardMintAuthorityMustBePoolAuthority => {
+                "Reward mint authority must be pool authority"
+            }
+            Error::NothingToClaim => "Nothing to claim",
+            Error::TierBalanceMustBeIncreasing => {
+                "Tier balance thresholds must be strictly increasing"
+            }
+            Error::PoolActiveCannotBeShortened => "Pool active window can only be extended",
+            Error::DuplicateLock => "Same lock passed more than once",
+            Error::UnsupportedStateVersion => {
+                "Account was written by unknown version of the program"
+            }
+            Error::StakesPaused => "New stakes are paused by IDO authority",
+            Error::IdoAuthorityInvalid => "IDO authority must not be default key",
+            Error::ZeroAmount => "Amount must be greater than zero",
+            Error::LocksMustBeSorted => "Locks must be passed in ascending order of keys",
+            Error::RecountNotStarted => "Recount must be started by first batch",
+            Error::RecountRequiresActivePool => "Recount of several batches requires active pool",
+            Error::WrongAccountType => "Account data is of other account type",
+        }
+    }
 }
 
 impl From<Error> for ProgramError {
@@ -91,3 +194,25 @@ impl PrintProgramError for Error {
         msg!(&self.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use num_traits::ToPrimitive;
+
+    use super::*;
+
+    #[test]
+    fn round_trip_u32() {
+        let mut code = 0;
+        while let Some(error) = Error::from_u32(code) {
+            assert_eq!(error.to_u32(), Some(code));
+            assert_eq!(
+                ProgramError::from(error.clone()),
+                ProgramError::Custom(code)
+            );
+            assert_eq!(error.to_string(), error.description());
+            code += 1;
+        }
+        assert!(code > Error::TierBalanceMustBeIncreasing as u32);
+    }
+}
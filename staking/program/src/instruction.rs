@@ -6,13 +6,13 @@ use solana_program::{
     instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey, sysvar,
 };
 
-use crate::program::PubkeyPatterns;
+use crate::program::{PoolUserAuthority, PubkeyPatterns};
 
 /// input
 #[repr(C)]
 #[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub struct InitializePoolInput {
-    /// Balances qualifying for different tiers
+    /// Balances qualifying for different tiers, must be strictly increasing
     pub tier_balance: [u64; crate::TIERS_COUNT],
 
     /// authority of IDO which controls the pool                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                             pool
@@ -23,6 +23,15 @@ pub struct InitializePoolInput {
 
     /// Seconds for tokens unstake lock
     pub transit_outgoing: UnixTimestamp,
+
+    /// Reward per locked xSOS token per second, see [crate::state::StakePool::reward_rate]
+    pub reward_rate: u64,
+
+    /// Seconds xSOS must stay locked before lock is counted in tiers, see [crate::state::StakePool::min_lock_seconds]
+    pub min_lock_seconds: UnixTimestamp,
+
+    /// Seconds of time weighted average of locked xSOS counted in tiers, see [crate::state::StakePool::twab_window_seconds]
+    pub twab_window_seconds: UnixTimestamp,
 }
 
 /// input
@@ -65,10 +74,45 @@ pub struct StartPoolInput {
     pub pool_active_until: UnixTimestamp,
 }
 
+/// input
+#[repr(C)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct ExtendPoolActiveInput {
+    /// new end of active window, must not be before current [crate::state::StakePool::pool_active_until]
+    pub pool_active_until: UnixTimestamp,
+}
+
+/// input
+#[repr(C)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct SetStakesPausedInput {
+    /// new value of [crate::state::StakePool::stakes_paused]
+    pub stakes_paused: bool,
+}
+
+/// input
+#[repr(C)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct SetIdoAuthorityInput {
+    /// new value of [crate::state::StakePool::ido_authority], must not be default key
+    pub ido_authority: Pubkey,
+}
+
+/// input
+#[repr(C)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct RecomputeTierUsersInput {
+    /// discards unfinished recount and starts new one with this batch
+    pub first: bool,
+    /// finishes recount, counted users replace [crate::state::StakePool::tier_users]
+    pub last: bool,
+}
+
 /// Splits stake and lock to make xSOS liquid.
 /// Forces xSOS token transfers via program authority to track tiers.
 #[repr(C)]
 #[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[non_exhaustive]
 pub enum Instruction {
     /// Initializes pool with valid mints and token account. Mints and token account created off chain.
     ///
@@ -80,6 +124,7 @@ pub enum Instruction {
     /// - *read, derived*  `pool_authority`          used to `initialize pool_mint_xsos` and `pool_token_account_sos`
     /// - *read, system*   `rent`
     /// - *read*           `token_program`
+    /// - *read, optional* `reward_mint`             mint of lock rewards with `pool_authority` as mint authority, required if `reward_rate` is not zero
     ///
     InitializePool(InitializePoolInput),
 
@@ -102,6 +147,8 @@ pub enum Instruction {
 
     /// Moves SOS tokens to pool. Mints xSOS tokens into user account if time in transit elapsed   
     /// Allows to transfer amount of tokens linearly proportional to passed time since stake requested till finish.     
+    /// Fails with [crate::error::Error::CannotTransitAnythingNow] if nothing is released yet, does nothing if transit is fully claimed.
+    /// [Instruction::CancelTransit] of fully claimed transit closes it.
     ///
     /// Accounts:
     /// - *read*               `pool`                               initialized pool account
@@ -141,6 +188,7 @@ pub enum Instruction {
 
     /// Transit SOS tokens to any user owned account if time elapsed.
     /// Allows to transfer amount of tokens linearly proportional to passed time since unstake requested till finish.
+    /// Same as [Instruction::StakeFinish] for fully claimed transit.
     ///
     /// Accounts:
     /// - *read*               `pool`
@@ -154,6 +202,7 @@ pub enum Instruction {
     UnstakeFinish,
 
     /// Creates and initializes [crate::state::PoolLock] account.
+    /// Succeeds without changes if the same lock was already initialized, so it is safe to retry.
     ///
     /// Accounts:
     /// - *read*                   `pool`                            initialized pool account
@@ -168,6 +217,10 @@ pub enum Instruction {
     InitializeLock,
 
     /// Transfers xSOS from user to lock. Updates tiers in pool.
+    /// Lock is counted in tiers only after [crate::state::StakePool::min_lock_seconds] since last increase,
+    /// [Instruction::RefreshLock] counts lock which became old enough.
+    /// Lock is counted by time weighted average of locked amount over [crate::state::StakePool::twab_window_seconds],
+    /// [Instruction::RefreshLock] also updates average counted in tiers. Fails with [crate::error::Error::ZeroAmount] on zero `amount`.
     ///
     /// Accounts:
     /// - *write*                 `pool`
@@ -181,6 +234,8 @@ pub enum Instruction {
     Lock(LockInput),
 
     /// Moves xSOS from lock to user. Updates tiers in pool.
+    /// While pool is active only amount keeping user in the same tier can be unlocked.
+    /// Tier is counted by time weighted average of locked amount as in [Instruction::Lock].
     ///
     /// Accounts:
     /// - *write*              `pool`
@@ -189,7 +244,7 @@ pub enum Instruction {
     /// - *read, derived*      `pool_user_authority`             Authority derived from pool and user
     /// - *write*              `pool_lock_token_account_xsos`    source
     /// - *write*              `user_token_account_xsos`         destination
-    /// - *read, system*       `clock`                           Unlock period must lapsed
+    /// - *read, system*       `clock`                           Checks if pool is active
     /// - *read*               `_token_program`    
     Unlock(UnlockInput),
 
@@ -198,6 +253,132 @@ pub enum Instruction {
     // - *read, derived,signer*       `market_authority`  IDO market derived authority (from ido_market and IDO program_id )
     // - *read, system*               `clock`             Pool must be active for some time
     StartPool(StartPoolInput),
+
+    /// Mints reward accrued by xSOS locked since last claim into user account.
+    ///
+    /// Accounts:
+    /// - *read*               `pool`
+    /// - *read, signer*       `user_wallet`
+    /// - *write, derived*     `pool_lock`                       Lock account with relevant keys
+    /// - *read*               `pool_lock_token_account_xsos`    Locked xSOS balance
+    /// - *read, derived*      `pool_authority`                  Mint authority of `reward_mint`
+    /// - *write*              `reward_mint`
+    /// - *write*              `user_token_account_reward`       destination
+    /// - *read, system*       `clock`
+    /// - *read*               `_token_program`
+    ClaimStakingReward,
+
+    /// Returns tokens not yet released from transit and closes transit and its token account.
+    /// Incoming transit returns SOS to user, outgoing transit returns SOS to pool and mints back burned xSOS.
+    ///
+    /// Accounts:
+    /// - *read*               `pool`
+    /// - *read, derived*      `pool_authority`
+    /// - *write*              `pool_token_account_sos`             destination of outgoing transit
+    /// - *write*              `pool_transit`                       closed, rent goes to `user_wallet`
+    /// - *write*              `pool_transit_token_account_sos`     source, closed, rent goes to `user_wallet`
+    /// - *write, signer*      `user_wallet`
+    /// - *write*              `user_token_account_sos`             destination of incoming transit
+    /// - *write*              `user_token_account_xsos`            receives xSOS minted back for outgoing transit
+    /// - *write*              `pool_mint_xsos`
+    /// - *read*               `_token_program`
+    CancelTransit,
+
+    /// Moves [crate::state::StakePool::pool_active_until] later, so unlocks stay blocked while IDO sale is extended.
+    /// Active window is never shortened.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, derived, signer*  `market_authority`  IDO market derived authority, must be [crate::state::StakePool::ido_authority]
+    /// - *read, system*           `clock`             New end of active window must not be in past
+    ExtendPoolActive(ExtendPoolActiveInput),
+
+    /// Recovery of corrupted [crate::state::StakePool::tier_users], recounts them from scratch.
+    /// Each lock is refreshed as by zero [Instruction::Lock] with its token balance and counted in tier of it.
+    /// All locks of the pool holding xSOS must be passed, omitted ones are not counted.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, derived, signer*  `market_authority`  IDO market derived authority, must be [crate::state::StakePool::ido_authority]
+    /// - *read, system*           `clock`             Used to refresh locks
+    /// - *write*                  `pool_lock`                       Repeated pairs, lock of `pool`
+    /// - *read*                   `pool_lock_token_account_xsos`    and its locked xSOS
+    RecomputeTierUsers,
+
+    /// [Instruction::InitializeLock] followed by [Instruction::Lock] in one instruction, both checked as if sent separately.
+    /// Lets user with xSOS from [Instruction::StakeFinish] get counted in tiers in one transaction.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, signer, payer*    `user_wallet`                     Must be used to derive address of `pool_lock`
+    /// - *write, derived*         `pool_lock`                       Uninitialized or already initialized with the same keys
+    /// - *read, derived*          `pool_user_authority`             Authority derived from pool and user
+    /// - *read*                   `pool_mint_xsos`                  Pool mint
+    /// - *write*                  `pool_lock_token_account_xsos`    Under pool authority (user can transfer only via this program)
+    /// - *write*                  `user_token_account_xsos`         source
+    /// - *read, system*           `rent`                            Used to make sure lock account created rent exempt
+    /// - *read, system*           `clock`                           Used to calculate lock period
+    /// - *read, system*           `_system_program`                 Used to create lock account
+    /// - *read*                   `_token_program`
+    InitializeLockAndLock(LockInput),
+
+    /// Pauses or resumes new stakes, e.g. during migration.
+    /// Paused pool rejects [Instruction::StakeStart] and [Instruction::Lock],
+    /// while [Instruction::StakeFinish], [Instruction::UnstakeStart], [Instruction::UnstakeFinish], [Instruction::Unlock] and [Instruction::RefreshLock] proceed.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, derived, signer*  `market_authority`  IDO market derived authority, must be [crate::state::StakePool::ido_authority]
+    SetStakesPaused(SetStakesPausedInput),
+
+    /// Binds pool to other IDO authority, e.g. when IDO market is redeployed or its program changes.
+    /// After it only the new authority can start, extend, pause and recompute the pool.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, derived, signer*  `market_authority`  IDO market derived authority, must be current [crate::state::StakePool::ido_authority]
+    SetIdoAuthority(SetIdoAuthorityInput),
+
+    /// [Instruction::RecomputeTierUsers] split into batches, for pools with more locks than fit one transaction.
+    /// Batches count locks into [crate::state::StakePool::recount_tier_users] and must pass locks in ascending order of keys,
+    /// continuing after [crate::state::StakePool::recount_cursor], so no lock is counted twice.
+    /// Recount of several batches requires pool to be active, so locks do not move between tiers meanwhile.
+    ///
+    /// Accounts:
+    /// - *write*                  `pool`
+    /// - *read, derived, signer*  `market_authority`  IDO market derived authority, must be [crate::state::StakePool::ido_authority]
+    /// - *read, system*           `clock`             Used to refresh locks and check pool is active
+    /// - *write*                  `pool_lock`                       Repeated pairs, lock of `pool`
+    /// - *read*                   `pool_lock_token_account_xsos`    and its locked xSOS
+    RecomputeTierUsersBatch(RecomputeTierUsersInput),
+
+    /// Settles reward and time weighted average of lock and recounts it in tiers without moving tokens,
+    /// e.g. after lock aged past [crate::state::StakePool::min_lock_seconds].
+    /// Fails while pool is active as [Instruction::Lock] does.
+    ///
+    /// Accounts:
+    /// - *write*                 `pool`
+    /// - *read, signer*          `user_wallet`
+    /// - *write, derived*        `pool_lock`                       Lock account with relevant keys
+    /// - *read, derived*         `pool_user_authority`             Authority derived from pool and user
+    /// - *read*                  `pool_lock_token_account_xsos`    locked xSOS
+    /// - *read, system*          `clock`                           Used to calculate lock period
+    RefreshLock,
+
+    /// [Instruction::Lock] of whole xSOS balance of `user_token_account_xsos` into already initialized lock,
+    /// e.g. to lock xSOS received by later [Instruction::StakeFinish] in one step.
+    /// Fails with [crate::error::Error::ZeroAmount] if there is nothing to lock.
+    ///
+    /// Accounts:
+    /// - *write*                 `pool`
+    /// - *read, signer*          `user_wallet`
+    /// - *write, derived*        `pool_lock`                       Lock account with relevant keys
+    /// - *read, derived*         `pool_user_authority`             Authority derived from pool and user
+    /// - *write*                 `pool_lock_token_account_xsos`    under pool authority (user can transfer only via this program)
+    /// - *write*                 `user_token_account_xsos`         source, whole balance is locked
+    /// - *read, system*          `clock`                           Used to calculate lock period
+    /// - *read*                  `_token_program`
+    Compound,
 }
 
 /// Calculate authority pubkey
@@ -206,7 +387,7 @@ pub fn find_key_program_address(owner: &Pubkey) -> Pubkey {
     authority
 }
 
-/// Calculate authority from 2 pubkeys
+/// Calculate authority from 2 pubkeys, prefer [PoolUserAuthority] which fixes their order
 pub fn find_2key_program_address(key1: &Pubkey, key2: &Pubkey) -> Pubkey {
     let (authority, _) = Pubkey::find_2key_program_address(key1, key2, &crate::program_id());
     authority
@@ -219,9 +400,10 @@ pub fn initialize_pool(
     token_account_sos: &Pubkey,
     mint_sos: &Pubkey,
     pool_mint_xsos: &Pubkey,
+    reward_mint: Option<Pubkey>,
     input: InitializePoolInput,
 ) -> Result<solana_program::instruction::Instruction, ProgramError> {
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*pool, false),
         AccountMeta::new(*token_account_sos, false),
         AccountMeta::new_readonly(*mint_sos, false),
@@ -230,6 +412,9 @@ pub fn initialize_pool(
         AccountMeta::new_readonly(sysvar::rent::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
     ];
+    if let Some(reward_mint) = reward_mint {
+        accounts.push(AccountMeta::new_readonly(reward_mint, false));
+    }
     Ok(solana_program::instruction::Instruction::new_with_borsh(
         crate::id(),
         &Instruction::InitializePool(input),
@@ -366,7 +551,8 @@ pub fn initialize_lock(
     pool_mint_xsos: &Pubkey,
     pool_lock_token_account_xsos: &Pubkey,
 ) -> Result<solana_program::instruction::Instruction, ProgramError> {
-    let pool_user_authority = find_2key_program_address(pool, user_wallet);
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
     let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
     let accounts = vec![
         AccountMeta::new_readonly(*pool, false),
@@ -395,7 +581,8 @@ pub fn lock(
     user_token_account_xsos: &Pubkey,
     input: LockInput,
 ) -> Result<solana_program::instruction::Instruction, ProgramError> {
-    let pool_user_authority = find_2key_program_address(pool, user_wallet);
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
     let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
 
     let accounts = vec![
@@ -415,6 +602,92 @@ pub fn lock(
     ))
 }
 
+/// create instruction
+pub fn compound(
+    pool: &Pubkey,
+    user_wallet: &Pubkey,
+    pool_lock_token_account_xsos: &Pubkey,
+    user_token_account_xsos: &Pubkey,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
+    let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
+
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*user_wallet, true),
+        AccountMeta::new(pool_lock, false),
+        AccountMeta::new_readonly(pool_user_authority, false),
+        AccountMeta::new(*pool_lock_token_account_xsos, false),
+        AccountMeta::new(*user_token_account_xsos, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::Compound,
+        accounts,
+    ))
+}
+
+/// create instruction
+pub fn refresh_lock(
+    pool: &Pubkey,
+    user_wallet: &Pubkey,
+    pool_lock_token_account_xsos: &Pubkey,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
+    let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
+
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*user_wallet, true),
+        AccountMeta::new(pool_lock, false),
+        AccountMeta::new_readonly(pool_user_authority, false),
+        AccountMeta::new_readonly(*pool_lock_token_account_xsos, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    Ok(solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::RefreshLock,
+        accounts,
+    ))
+}
+
+/// create instruction
+pub fn initialize_lock_and_lock(
+    pool: &Pubkey,
+    user_wallet: &Pubkey,
+    pool_mint_xsos: &Pubkey,
+    pool_lock_token_account_xsos: &Pubkey,
+    user_token_account_xsos: &Pubkey,
+    input: LockInput,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
+    let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
+
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*user_wallet, true),
+        AccountMeta::new(pool_lock, false),
+        AccountMeta::new_readonly(pool_user_authority, false),
+        AccountMeta::new_readonly(*pool_mint_xsos, false),
+        AccountMeta::new(*pool_lock_token_account_xsos, false),
+        AccountMeta::new(*user_token_account_xsos, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::InitializeLockAndLock(input),
+        accounts,
+    ))
+}
+
 /// create instruction
 #[allow(clippy::too_many_arguments)]
 pub fn unlock(
@@ -424,7 +697,8 @@ pub fn unlock(
     user_token_account_xsos: &Pubkey,
     input: UnlockInput,
 ) -> Result<solana_program::instruction::Instruction, ProgramError> {
-    let pool_user_authority = find_2key_program_address(pool, user_wallet);
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
     let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
 
     let accounts = vec![
@@ -461,3 +735,166 @@ pub fn start_pool(
         accounts,
     )
 }
+
+/// create instruction
+pub fn claim_staking_reward(
+    pool: &Pubkey,
+    user_wallet: &Pubkey,
+    pool_lock_token_account_xsos: &Pubkey,
+    reward_mint: &Pubkey,
+    user_token_account_reward: &Pubkey,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let pool_user_authority =
+        PoolUserAuthority::derive(pool, user_wallet, &crate::program_id()).key;
+    let pool_lock = Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id())?;
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new_readonly(*user_wallet, true),
+        AccountMeta::new(pool_lock, false),
+        AccountMeta::new_readonly(*pool_lock_token_account_xsos, false),
+        AccountMeta::new_readonly(find_key_program_address(pool), false),
+        AccountMeta::new(*reward_mint, false),
+        AccountMeta::new(*user_token_account_reward, false),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::ClaimStakingReward,
+        accounts,
+    ))
+}
+
+/// create instruction
+#[allow(clippy::too_many_arguments)]
+pub fn cancel_transit(
+    pool: &Pubkey,
+    pool_token_account_sos: &Pubkey,
+    pool_transit: &Pubkey,
+    pool_transit_token_account_sos: &Pubkey,
+    user_wallet: &Pubkey,
+    user_token_account_sos: &Pubkey,
+    user_token_account_xsos: &Pubkey,
+    pool_mint_xsos: &Pubkey,
+) -> Result<solana_program::instruction::Instruction, ProgramError> {
+    let accounts = vec![
+        AccountMeta::new_readonly(*pool, false),
+        AccountMeta::new_readonly(find_key_program_address(pool), false),
+        AccountMeta::new(*pool_token_account_sos, false),
+        AccountMeta::new(*pool_transit, false),
+        AccountMeta::new(*pool_transit_token_account_sos, false),
+        AccountMeta::new(*user_wallet, true),
+        AccountMeta::new(*user_token_account_sos, false),
+        AccountMeta::new(*user_token_account_xsos, false),
+        AccountMeta::new(*pool_mint_xsos, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    Ok(solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::CancelTransit,
+        accounts,
+    ))
+}
+
+/// create instruction
+pub fn extend_pool_active(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    input: ExtendPoolActiveInput,
+) -> solana_program::instruction::Instruction {
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*market_authority, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::ExtendPoolActive(input),
+        accounts,
+    )
+}
+
+/// Accounts of [Instruction::RecomputeTierUsers] and [Instruction::RecomputeTierUsersBatch], `locks` are pairs of lock and its xSOS token account
+pub fn recompute_tier_users_accounts(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    locks: &[(Pubkey, Pubkey)],
+) -> Vec<AccountMeta> {
+    let mut accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*market_authority, true),
+        AccountMeta::new_readonly(sysvar::clock::id(), false),
+    ];
+    for (pool_lock, pool_lock_token_account_xsos) in locks {
+        accounts.push(AccountMeta::new(*pool_lock, false));
+        accounts.push(AccountMeta::new_readonly(
+            *pool_lock_token_account_xsos,
+            false,
+        ));
+    }
+    accounts
+}
+
+/// Creates [Instruction::RecomputeTierUsers], `locks` are pairs of lock and its xSOS token account
+pub fn recompute_tier_users(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    locks: &[(Pubkey, Pubkey)],
+) -> solana_program::instruction::Instruction {
+    let accounts = recompute_tier_users_accounts(pool, market_authority, locks);
+    solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::RecomputeTierUsers,
+        accounts,
+    )
+}
+
+/// Creates [Instruction::SetStakesPaused]
+pub fn set_stakes_paused(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    input: SetStakesPausedInput,
+) -> solana_program::instruction::Instruction {
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*market_authority, true),
+    ];
+    solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::SetStakesPaused(input),
+        accounts,
+    )
+}
+
+/// Creates [Instruction::SetIdoAuthority]
+pub fn set_ido_authority(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    input: SetIdoAuthorityInput,
+) -> solana_program::instruction::Instruction {
+    let accounts = vec![
+        AccountMeta::new(*pool, false),
+        AccountMeta::new_readonly(*market_authority, true),
+    ];
+    solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::SetIdoAuthority(input),
+        accounts,
+    )
+}
+
+/// Creates [Instruction::RecomputeTierUsersBatch], `locks` must be sorted by lock key
+pub fn recompute_tier_users_batch(
+    pool: &Pubkey,
+    market_authority: &Pubkey,
+    locks: &[(Pubkey, Pubkey)],
+    input: RecomputeTierUsersInput,
+) -> solana_program::instruction::Instruction {
+    let accounts = recompute_tier_users_accounts(pool, market_authority, locks);
+    solana_program::instruction::Instruction::new_with_borsh(
+        crate::id(),
+        &Instruction::RecomputeTierUsersBatch(input),
+        accounts,
+    )
+}
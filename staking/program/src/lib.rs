@@ -1,6 +1,7 @@
 //! SolStarter Staking program
 #![deny(missing_docs)]
 
+mod accounts;
 pub mod error;
 pub mod instruction;
 pub mod processor;
@@ -14,7 +15,7 @@ pub use utils::prelude;
 pub use utils::program;
 
 /// Current program version
-pub const PROGRAM_VERSION: u8 = 1;
+pub const PROGRAM_VERSION: u8 = 3;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
@@ -1,12 +1,12 @@
 //! Program state processor
 
-use borsh::BorshDeserialize;
 use solana_program::{
     account_info::AccountInfo,
     clock::{self, Clock},
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
+    program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
     sysvar::Sysvar,
@@ -15,37 +15,27 @@ use solana_program::{
 use spl_token::state::{Account, Mint};
 
 use crate::{
-    borsh::{BorshDeserialiseConst, BorshSerializeConst},
+    accounts::*,
+    borsh::{try_instruction_from_slice, BorshSerializeConst},
     error::Error,
     instruction::{
-        InitializePoolInput, Instruction, LockInput, StakeStartInput, StartPoolInput, UnlockInput,
-        UnstakeStartInput,
+        ExtendPoolActiveInput, InitializePoolInput, Instruction, LockInput,
+        RecomputeTierUsersInput, SetIdoAuthorityInput, SetStakesPausedInput, StakeStartInput,
+        StartPoolInput, UnlockInput, UnstakeStartInput,
     },
     invoke::{self},
     math::{self, ErrorAdd},
     program::{
-        create_account_with_seed_signed, AccountPatterns, ProgramAccountInfo, ProgramPubkey,
-        PubkeyPatterns,
+        burn_account, create_account_with_seed_signed, AccountPatterns, PoolUserAuthority,
+        ProgramAccountInfo, ProgramPubkey, PubkeyPatterns,
+    },
+    state::{
+        state_from_slice, tier_balance_is_increasing, PoolLock, PoolTransit, StakePool,
+        StateVersion, TransitDirection, ACCOUNT_TYPE_POOL_LOCK, ACCOUNT_TYPE_POOL_TRANSIT,
+        ACCOUNT_TYPE_STAKE_POOL,
     },
-    state::{get_tier, PoolLock, PoolTransit, StakePool, StateVersion, TransitDirection},
 };
 
-macro_rules! is_owner {
-        (
-            $program_id:expr,
-            $($account:expr),+
-        )
-        => {
-            {
-                $(
-                    if *$account.owner != $program_id.pubkey() {
-                        return Err(ProgramError::IncorrectProgramId);
-                    }
-                )+
-            }
-        }
-    }
-
 /// Program state handler.
 pub struct Processor {}
 impl Processor {
@@ -60,15 +50,20 @@ impl Processor {
         program_authority: &AccountInfo<'a>,
         rent: &AccountInfo<'a>,
         _token_program: &AccountInfo<'a>, // Used implicitly
+        reward_mint: Option<&AccountInfo<'a>>,
         input: &InitializePoolInput,
     ) -> ProgramResult {
-        is_owner!(program_id, pool);
-        let (expected_program_authority, _) =
+        pool.owned_by(program_id)?;
+        let (expected_program_authority, authority_bump_seed) =
             Pubkey::find_key_program_address(pool.key, program_id);
         if *program_authority.key != expected_program_authority {
             return Err(Error::InvalidAuthority.into());
         }
 
+        if !tier_balance_is_increasing(&input.tier_balance) {
+            return Err(Error::TierBalanceMustBeIncreasing.into());
+        }
+
         let decimals = Mint::unpack_from_slice(&mint_sos.data.borrow())?.decimals;
 
         invoke::initialize_mint(
@@ -91,10 +86,15 @@ impl Processor {
             return Err(ProgramError::AccountNotRentExempt);
         }
 
-        let mut pool_state = StakePool::try_from_slice(&pool.data.borrow())?;
+        let mut pool_state = state_from_slice::<StakePool>(
+            &pool.data.borrow(),
+            ACCOUNT_TYPE_STAKE_POOL,
+            StakePool::LEN,
+        )?;
 
         pool_state.uninitialized()?;
-        pool_state.version = StateVersion::V1;
+        pool_state.account_type = ACCOUNT_TYPE_STAKE_POOL;
+        pool_state.version = StateVersion::V3;
         pool_state.tier_users = [0; crate::TIERS_COUNT];
 
         pool_state.transit_incoming = input.transit_incoming;
@@ -105,6 +105,21 @@ impl Processor {
         pool_state.pool_mint_xsos = *pool_mint_xsos.key;
 
         pool_state.ido_authority = input.ido_authority;
+        pool_state.authority_bump_seed = authority_bump_seed;
+
+        if let Some(reward_mint) = reward_mint {
+            let mint_authority =
+                Mint::unpack_from_slice(&reward_mint.data.borrow())?.mint_authority;
+            if mint_authority != COption::Some(expected_program_authority) {
+                return Err(Error::RewardMintAuthorityMustBePoolAuthority.into());
+            }
+            pool_state.reward_mint = *reward_mint.key;
+        } else if input.reward_rate != 0 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        pool_state.reward_rate = input.reward_rate;
+        pool_state.min_lock_seconds = input.min_lock_seconds;
+        pool_state.twab_window_seconds = input.twab_window_seconds;
 
         pool_state.serialize_const(&mut *pool.try_borrow_mut_data()?)?;
 
@@ -128,10 +143,17 @@ impl Processor {
         _token_program: &AccountInfo<'a>, // Used implicitly
         input: &StakeStartInput,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_transit);
+        if input.amount == 0 {
+            return Err(Error::ZeroAmount.into());
+        }
+        pool.owned_by(program_id)?;
+        pool_transit.owned_by(program_id)?;
         user_wallet.is_signer()?;
-        let pool_state = StakePool::try_from_slice(&pool.data.borrow())?;
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
         pool_state.initialized()?;
+        if pool_state.stakes_paused {
+            return Err(Error::StakesPaused.into());
+        }
         same_key(
             pool_state.token_account_sos,
             pool_token_account_sos,
@@ -142,11 +164,8 @@ impl Processor {
             return Err(Error::WrongAccountSpecified.into());
         }
 
-        let (pool_authority_key, _) = Pubkey::find_key_program_address(pool.key, program_id);
-
-        if *pool_authority.key != pool_authority_key {
-            return Err(Error::InvalidAuthority.into());
-        }
+        let (pool_authority_key, _) = pool_state.authority(pool.key, program_id)?;
+        same_key(pool_authority_key, pool_authority, Error::InvalidAuthority)?;
 
         invoke::initialize_token_account(
             pool_transit_token_account_sos.clone(),
@@ -168,9 +187,14 @@ impl Processor {
             return Err(ProgramError::AccountNotRentExempt);
         }
 
-        let mut pool_transit_state = PoolTransit::try_from_slice(&pool_transit.data.borrow())?;
+        let mut pool_transit_state = state_from_slice::<PoolTransit>(
+            &pool_transit.data.borrow(),
+            ACCOUNT_TYPE_POOL_TRANSIT,
+            PoolTransit::LEN,
+        )?;
 
         pool_transit_state.uninitialized()?;
+        pool_transit_state.account_type = ACCOUNT_TYPE_POOL_TRANSIT;
         pool_transit_state.version = StateVersion::V1;
         pool_transit_state.direction = TransitDirection::Incoming;
         pool_transit_state.pool = *pool.key;
@@ -202,17 +226,18 @@ impl Processor {
         clock: &AccountInfo<'a>,
         _token_program: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_transit);
+        pool.owned_by(program_id)?;
+        pool_transit.owned_by(program_id)?;
         user_wallet.is_signer()?;
 
-        let pool_transit_state = PoolTransit::try_from_slice(&pool_transit.data.borrow())?;
+        let pool_transit_state = PoolTransit::migrate(&pool_transit.data.borrow())?;
         pool_transit_state.initialized()?;
 
         if pool_transit_state.direction != TransitDirection::Incoming {
             return Err(Error::PoolTransitWrongDirection.into());
         }
 
-        let pool_state = StakePool::try_from_slice(&pool.data.borrow())?;
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
         same_key(
             pool_state.token_account_sos,
             pool_token_account_sos,
@@ -236,8 +261,11 @@ impl Processor {
             Account::unpack_from_slice(&pool_transit_token_account_sos.data.borrow())?.amount;
 
         let amount_to_claim = finish(pool_transit_state, clock, remaining_amount, pool_transit)?;
+        if amount_to_claim == 0 {
+            return Ok(());
+        }
 
-        let (_, bump_seed) = Pubkey::find_key_program_address(pool.key, program_id);
+        let bump_seed = pool_authority_bump_seed(&pool_state, pool, pool_authority, program_id)?;
         invoke::token_transfer_program_authority(
             pool.key,
             pool_transit_token_account_sos.clone(),
@@ -277,8 +305,12 @@ impl Processor {
         _token_program: &AccountInfo<'a>,
         input: &UnstakeStartInput,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_transit);
-        let pool_state = StakePool::try_from_slice(&pool.data.borrow())?;
+        if input.amount == 0 {
+            return Err(Error::ZeroAmount.into());
+        }
+        pool.owned_by(program_id)?;
+        pool_transit.owned_by(program_id)?;
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
         if pool_state.pool_mint_xsos != mint_xsos.pubkey() {
             return Err(Error::WrongAccountSpecified.into());
         }
@@ -296,7 +328,7 @@ impl Processor {
         }
 
         let clock = sysvar::clock::Clock::from_account_info(clock)?;
-        let bump_seed = pool_authority.is_derived(&pool.pubkey(), program_id)?;
+        let bump_seed = pool_authority_bump_seed(&pool_state, pool, pool_authority, program_id)?;
         invoke::initialize_token_account(
             pool_transit_token_account_sos.clone(),
             mint_sos.clone(),
@@ -320,18 +352,23 @@ impl Processor {
             input.amount,
         )?;
 
-        let mut pool_transit_state = PoolTransit::try_from_slice(&pool_transit.data.borrow())?;
+        let mut pool_transit_state = state_from_slice::<PoolTransit>(
+            &pool_transit.data.borrow(),
+            ACCOUNT_TYPE_POOL_TRANSIT,
+            PoolTransit::LEN,
+        )?;
         pool_transit_state.uninitialized()?;
         pool_transit_state.pool = *pool.key;
         pool_transit_state.token_account_sos = *pool_transit_token_account_sos.key;
         pool_transit_state.user_wallet = *user_wallet.key;
-        let pool_state = StakePool::try_from_slice(*pool.data.borrow())?;
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
 
         pool_transit_state.transit_from = clock.unix_timestamp;
         pool_transit_state.transit_until = pool_transit_state
             .transit_from
             .error_add(pool_state.transit_outgoing)?;
 
+        pool_transit_state.account_type = ACCOUNT_TYPE_POOL_TRANSIT;
         pool_transit_state.version = StateVersion::V1;
         pool_transit_state.direction = TransitDirection::Outgoing;
         pool_transit_state.serialize_const(&mut *pool_transit.try_borrow_mut_data()?)?;
@@ -351,12 +388,13 @@ impl Processor {
         clock: &AccountInfo<'a>,
         _token_program: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_transit);
+        pool.owned_by(program_id)?;
+        pool_transit.owned_by(program_id)?;
         user_wallet.is_signer()?;
 
         let clock = sysvar::clock::Clock::from_account_info(clock)?;
 
-        let pool_transit_state = PoolTransit::try_from_slice(&pool_transit.data.borrow())?;
+        let pool_transit_state = PoolTransit::migrate(&pool_transit.data.borrow())?;
 
         if pool_transit_state.pool != pool.pubkey() {
             return Err(Error::PoolTransitMustBeOfProvidedPool.into());
@@ -377,8 +415,12 @@ impl Processor {
             Account::unpack_from_slice(&pool_transit_token_account_sos.data.borrow())?.amount;
 
         let amount_to_claim = finish(pool_transit_state, clock, remaining_amount, pool_transit)?;
+        if amount_to_claim == 0 {
+            return Ok(());
+        }
 
-        let (_, bump_seed) = Pubkey::find_key_program_address(pool.key, program_id);
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
+        let bump_seed = pool_authority_bump_seed(&pool_state, pool, pool_authority, program_id)?;
 
         invoke::token_transfer_program_authority(
             pool.key,
@@ -405,24 +447,38 @@ impl Processor {
         _system_program: &ProgramAccountInfo<'a, 'b>,
         _token_program: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(program_id, pool);
+        pool.owned_by(program_id)?;
         user_wallet.is_signer()?;
 
-        let pool_state = StakePool::try_from_slice(*pool.data.borrow())?;
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
 
-        let (pool_user_authority_key, bump_seed) =
-            Pubkey::find_2key_program_address(pool.key, user_wallet.key, program_id);
+        let authority = PoolUserAuthority::derive(pool.key, user_wallet.key, program_id);
 
-        same_key(
-            pool_user_authority_key,
-            pool_user_authority,
-            Error::InvalidAuthority,
-        )?;
+        same_key(authority.key, pool_user_authority, Error::InvalidAuthority)?;
 
         if pool_state.pool_mint_xsos != pool_mint_xsos.pubkey() {
             return Err(Error::WrongAccountSpecified.into());
         }
 
+        let pool_lock_key =
+            Pubkey::create_with_seed(&authority.key, crate::LOCK_SEED, &program_id.pubkey())?;
+
+        if pool_lock_key != *pool_lock.key {
+            return Err(Error::DerivedPoolLockAccountKeyIsNotEqualToCalculated.into());
+        }
+
+        // retried instruction, lock is already there
+        if *pool_lock.owner == program_id.pubkey() {
+            let state = PoolLock::migrate(&pool_lock.data.borrow())?;
+            state.initialized()?;
+            same_key(state.user_wallet, user_wallet, Error::WrongOwner)?;
+            same_key(state.pool, pool, Error::LockMustBeRelatedToPool)?;
+            if state.token_account_xsos != *pool_lock_token_account_xsos.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+            return Ok(());
+        }
+
         invoke::initialize_token_account(
             pool_lock_token_account_xsos.clone(),
             pool_mint_xsos.clone(),
@@ -430,25 +486,11 @@ impl Processor {
             rent.clone(),
         )?;
 
-        let pool_lock_key = Pubkey::create_with_seed(
-            &pool_user_authority_key,
-            crate::LOCK_SEED,
-            &program_id.pubkey(),
-        )?;
-
-        if pool_lock_key != *pool_lock.key {
-            return Err(Error::DerivedPoolLockAccountKeyIsNotEqualToCalculated.into());
-        }
-
         let rent = Rent::from_account_info(rent)?;
         let lamports = rent.minimum_balance(PoolLock::LEN);
         let space = PoolLock::LEN as u64;
 
-        let signature = &[
-            &pool.key.to_bytes()[..32],
-            &user_wallet.key.to_bytes()[..32],
-            &[bump_seed],
-        ];
+        let signer = authority.signer();
 
         create_account_with_seed_signed(
             user_wallet,
@@ -458,10 +500,15 @@ impl Processor {
             lamports,
             space,
             program_id,
-            signature,
+            &signer.seeds(),
         )?;
 
-        let mut state = PoolLock::try_from_slice(*pool_lock.data.borrow())?;
+        let mut state = state_from_slice::<PoolLock>(
+            &pool_lock.data.borrow(),
+            ACCOUNT_TYPE_POOL_LOCK,
+            PoolLock::LEN,
+        )?;
+        state.account_type = ACCOUNT_TYPE_POOL_LOCK;
         state.pool = *pool.key;
         state.version = StateVersion::V1;
         state.token_account_xsos = *pool_lock_token_account_xsos.key;
@@ -485,16 +532,25 @@ impl Processor {
         _token_program: &AccountInfo<'a>,
         input: &LockInput,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_lock);
+        if input.amount == 0 {
+            return Err(Error::ZeroAmount.into());
+        }
+        user_wallet.is_signer()?;
+        pool.owned_by(program_id)?;
+        pool_lock.owned_by(program_id)?;
         let token_state = Account::unpack_from_slice(*pool_lock_token_account_xsos.data.borrow())?;
-        let mut pool_state = StakePool::try_from_slice(*pool.data.borrow())?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
         let clock = Clock::from_account_info(&clock)?;
 
+        if pool_state.stakes_paused {
+            return Err(Error::StakesPaused.into());
+        }
+
         if clock.unix_timestamp < pool_state.pool_active_until {
             return Err(Error::CannotLockWhenPoolIsActive.into());
         }
 
-        let pool_lock_state = PoolLock::try_from_slice(*pool_lock.data.borrow())?;
+        let mut pool_lock_state = PoolLock::migrate(&pool_lock.data.borrow())?;
         same_key(pool_lock_state.user_wallet, user_wallet, Error::WrongOwner)?;
         same_key(pool_lock_state.pool, pool, Error::LockMustBeRelatedToPool)?;
 
@@ -514,15 +570,32 @@ impl Processor {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        let old_tier = get_tier(pool_state.tier_balance, token_state.amount);
-        let new_value = token_state.amount.error_add(input.amount)?;
-        let new_tier = get_tier(pool_state.tier_balance, new_value);
-        if let Some(new_tier) = new_tier {
-            if let Some(old_tier) = old_tier {
-                pool_state.tier_users[old_tier] =
-                    pool_state.tier_users[old_tier].error_decrement()?;
-            }
+        let locked_amount = pool_lock_state.counted_balance(token_state.amount);
+        accrue_reward(
+            &pool_state,
+            &mut pool_lock_state,
+            locked_amount,
+            clock.unix_timestamp,
+        )?;
+
+        let old_tier = pool_state.lock_tier(&pool_lock_state, locked_amount);
+        let new_value = locked_amount.error_add(input.amount)?;
+        // increased lock must age again before it is counted
+        pool_lock_state.locked_since = clock.unix_timestamp;
+        pool_lock_state.tier_counted =
+            pool_state.lock_is_aged(&pool_lock_state, clock.unix_timestamp);
+        pool_lock_state.update_twab(
+            pool_state.twab_window_seconds,
+            clock.unix_timestamp,
+            new_value,
+        );
+        let new_tier = pool_state.lock_tier(&pool_lock_state, new_value);
+
+        if let Some(old_tier) = old_tier {
+            pool_state.tier_users[old_tier] = pool_state.tier_users[old_tier].error_decrement()?;
+        }
 
+        if let Some(new_tier) = new_tier {
             pool_state.tier_users[new_tier] = pool_state.tier_users[new_tier].error_increment()?;
         }
 
@@ -533,15 +606,14 @@ impl Processor {
             input.amount,
         )?;
 
-        pool_state
-            .serialize_const(&mut *pool.try_borrow_mut_data().unwrap())
-            .unwrap();
+        pool_state.write(&mut *pool.try_borrow_mut_data()?)?;
+        pool_lock_state.write(&mut *pool_lock.try_borrow_mut_data()?)?;
 
         Ok(())
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn unlock<'a>(
+    fn compound<'a>(
         program_id: &ProgramPubkey,
         pool: &AccountInfo<'a>,
         user_wallet: &AccountInfo<'a>,
@@ -550,21 +622,44 @@ impl Processor {
         pool_lock_token_account_xsos: &AccountInfo<'a>,
         user_token_account_xsos: &AccountInfo<'a>,
         clock: &AccountInfo<'a>,
-        _token_program: &AccountInfo<'a>,
-        input: &UnlockInput,
+        token_program: &AccountInfo<'a>,
     ) -> ProgramResult {
-        is_owner!(program_id, pool, pool_lock);
-        user_wallet.is_signer()?;
+        let amount = Account::unpack_from_slice(&user_token_account_xsos.data.borrow())?.amount;
+        Self::lock(
+            program_id,
+            pool,
+            user_wallet,
+            pool_lock,
+            pool_user_authority,
+            pool_lock_token_account_xsos,
+            user_token_account_xsos,
+            clock,
+            token_program,
+            &LockInput { amount },
+        )
+    }
 
+    fn refresh_lock<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        user_wallet: &AccountInfo<'a>,
+        pool_lock: &AccountInfo<'a>,
+        pool_user_authority: &AccountInfo<'a>,
+        pool_lock_token_account_xsos: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        user_wallet.is_signer()?;
+        pool.owned_by(program_id)?;
+        pool_lock.owned_by(program_id)?;
         let token_state = Account::unpack_from_slice(*pool_lock_token_account_xsos.data.borrow())?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
         let clock = Clock::from_account_info(&clock)?;
-        let mut pool_state = StakePool::try_from_slice(*pool.data.borrow())?;
 
         if clock.unix_timestamp < pool_state.pool_active_until {
-            return Err(Error::CannotUnlockWhenPoolIsActive.into());
+            return Err(Error::CannotLockWhenPoolIsActive.into());
         }
 
-        let pool_lock_state = PoolLock::try_from_slice(*pool_lock.data.borrow())?;
+        let mut pool_lock_state = PoolLock::migrate(&pool_lock.data.borrow())?;
         same_key(pool_lock_state.user_wallet, user_wallet, Error::WrongOwner)?;
         same_key(pool_lock_state.pool, pool, Error::LockMustBeRelatedToPool)?;
 
@@ -584,39 +679,125 @@ impl Processor {
             return Err(ProgramError::InvalidAccountData);
         }
 
-        let old_tier = get_tier(pool_state.tier_balance, token_state.amount);
+        let locked_amount = pool_lock_state.counted_balance(token_state.amount);
+        accrue_reward(
+            &pool_state,
+            &mut pool_lock_state,
+            locked_amount,
+            clock.unix_timestamp,
+        )?;
+
+        let old_tier = pool_state.lock_tier(&pool_lock_state, locked_amount);
+        pool_lock_state.tier_counted =
+            pool_state.lock_is_aged(&pool_lock_state, clock.unix_timestamp);
+        pool_lock_state.update_twab(
+            pool_state.twab_window_seconds,
+            clock.unix_timestamp,
+            locked_amount,
+        );
+        let new_tier = pool_state.lock_tier(&pool_lock_state, locked_amount);
 
         if let Some(old_tier) = old_tier {
             pool_state.tier_users[old_tier] = pool_state.tier_users[old_tier].error_decrement()?;
         }
 
-        let new_value = token_state.amount.error_sub(input.amount)?;
+        if let Some(new_tier) = new_tier {
+            pool_state.tier_users[new_tier] = pool_state.tier_users[new_tier].error_increment()?;
+        }
 
-        let new_tier = get_tier(pool_state.tier_balance, new_value);
+        pool_state.write(&mut *pool.try_borrow_mut_data()?)?;
+        pool_lock_state.write(&mut *pool_lock.try_borrow_mut_data()?)?;
 
-        if let Some(new_tier) = new_tier {
-            pool_state.tier_balance[new_tier] =
-                pool_state.tier_balance[new_tier].error_increment()?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn unlock<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        user_wallet: &AccountInfo<'a>,
+        pool_lock: &AccountInfo<'a>,
+        pool_user_authority: &AccountInfo<'a>,
+        pool_lock_token_account_xsos: &AccountInfo<'a>,
+        user_token_account_xsos: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        _token_program: &AccountInfo<'a>,
+        input: &UnlockInput,
+    ) -> ProgramResult {
+        if input.amount == 0 {
+            return Err(Error::ZeroAmount.into());
+        }
+        pool.owned_by(program_id)?;
+        pool_lock.owned_by(program_id)?;
+        user_wallet.is_signer()?;
+
+        let token_state = Account::unpack_from_slice(*pool_lock_token_account_xsos.data.borrow())?;
+        let clock = Clock::from_account_info(&clock)?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+
+        let mut pool_lock_state = PoolLock::migrate(&pool_lock.data.borrow())?;
+        same_key(pool_lock_state.user_wallet, user_wallet, Error::WrongOwner)?;
+        same_key(pool_lock_state.pool, pool, Error::LockMustBeRelatedToPool)?;
+
+        let pool_lock_key = Pubkey::create_with_seed(
+            &pool_user_authority.key,
+            crate::LOCK_SEED,
+            &program_id.pubkey(),
+        )?;
+
+        same_key(
+            pool_lock_key,
+            pool_lock,
+            Error::DerivedPoolLockAccountKeyIsNotEqualToCalculated,
+        )?;
+
+        if *pool_lock_token_account_xsos.key != pool_lock_state.token_account_xsos {
+            return Err(ProgramError::InvalidAccountData);
         }
 
-        let (_, bump_seed) =
-            Pubkey::find_2key_program_address(pool.key, user_wallet.key, program_id);
+        let locked_amount = pool_lock_state.counted_balance(token_state.amount);
+        accrue_reward(
+            &pool_state,
+            &mut pool_lock_state,
+            locked_amount,
+            clock.unix_timestamp,
+        )?;
 
-        let signature = &[
-            &pool.key.to_bytes()[..32],
-            &user_wallet.key.to_bytes()[..32],
-            &[bump_seed],
-        ];
+        let old_tier = pool_state.lock_tier(&pool_lock_state, locked_amount);
+        // tokens transferred into lock token account directly were never locked, so go out first
+        let new_value = locked_amount.min(token_state.amount.error_sub(input.amount)?);
+        pool_lock_state.update_twab(
+            pool_state.twab_window_seconds,
+            clock.unix_timestamp,
+            new_value,
+        );
+        let new_tier = pool_state.lock_tier(&pool_lock_state, new_value);
+
+        // active pool allocations depend on tiers, so only moves within same tier are allowed
+        if clock.unix_timestamp < pool_state.pool_active_until && old_tier != new_tier {
+            return Err(Error::CannotUnlockWhenPoolIsActive.into());
+        }
+
+        if let Some(old_tier) = old_tier {
+            pool_state.tier_users[old_tier] = pool_state.tier_users[old_tier].error_decrement()?;
+        }
+
+        if let Some(new_tier) = new_tier {
+            pool_state.tier_users[new_tier] = pool_state.tier_users[new_tier].error_increment()?;
+        }
+
+        let signer = PoolUserAuthority::derive(pool.key, user_wallet.key, program_id).signer();
 
         invoke::token_transfer_signature(
             pool_lock_token_account_xsos.clone(),
             user_token_account_xsos.clone(),
             pool_user_authority.clone(),
-            signature,
+            &signer.seeds(),
             input.amount,
         )?;
 
-        pool_state.serialize_const(&mut *pool.try_borrow_mut_data()?)?;
+        pool_state.write(&mut *pool.try_borrow_mut_data()?)?;
+        pool_lock_state.write(&mut *pool_lock.try_borrow_mut_data()?)?;
 
         Ok(())
     }
@@ -628,9 +809,37 @@ impl Processor {
         clock: &AccountInfo<'a>,
         input: &StartPoolInput,
     ) -> ProgramResult {
-        is_owner!(program_id, pool);
+        pool.owned_by(program_id)?;
+        market_authority.is_signer()?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        let clock = clock::Clock::from_account_info(clock)?;
+
+        if market_authority.pubkey() != pool_state.ido_authority {
+            return Err(Error::PoolMustBeRelatedToMarket.into());
+        }
+
+        if clock.unix_timestamp > input.pool_active_until {
+            return Err(Error::PoolMustBeActiveForSomeTime.into());
+        }
+
+        pool_state.pool_active_until = input.pool_active_until;
+
+        pool_state.write(&mut pool.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn extend_pool_active<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        input: &ExtendPoolActiveInput,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
         market_authority.is_signer()?;
-        let mut pool_state = StakePool::try_from_slice(&pool.data.borrow())?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
         let clock = clock::Clock::from_account_info(clock)?;
 
         if market_authority.pubkey() != pool_state.ido_authority {
@@ -641,13 +850,370 @@ impl Processor {
             return Err(Error::PoolMustBeActiveForSomeTime.into());
         }
 
+        if input.pool_active_until < pool_state.pool_active_until {
+            return Err(Error::PoolActiveCannotBeShortened.into());
+        }
+
         pool_state.pool_active_until = input.pool_active_until;
 
-        pool_state.serialize_const(&mut pool.data.borrow_mut())?;
+        pool_state.write(&mut pool.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn set_stakes_paused<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        input: &SetStakesPausedInput,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        market_authority.is_signer()?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
+
+        if market_authority.pubkey() != pool_state.ido_authority {
+            return Err(Error::PoolMustBeRelatedToMarket.into());
+        }
+
+        pool_state.stakes_paused = input.stakes_paused;
+
+        pool_state.write(&mut pool.data.borrow_mut())?;
 
         Ok(())
     }
 
+    fn set_ido_authority<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        input: &SetIdoAuthorityInput,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        market_authority.is_signer()?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
+
+        if market_authority.pubkey() != pool_state.ido_authority {
+            return Err(Error::PoolMustBeRelatedToMarket.into());
+        }
+
+        if input.ido_authority == Pubkey::default() {
+            return Err(Error::IdoAuthorityInvalid.into());
+        }
+
+        pool_state.ido_authority = input.ido_authority;
+
+        pool_state.write(&mut pool.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn recompute_tier_users<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        locks: &[AccountInfo<'a>],
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        market_authority.is_signer()?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
+        let clock = Clock::from_account_info(clock)?;
+
+        if market_authority.pubkey() != pool_state.ido_authority {
+            return Err(Error::PoolMustBeRelatedToMarket.into());
+        }
+
+        if locks.len() % 2 != 0 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        let mut tier_users = [0u32; crate::TIERS_COUNT];
+        for (index, pair) in locks.chunks_exact(2).enumerate() {
+            let (pool_lock, pool_lock_token_account_xsos) = (&pair[0], &pair[1]);
+            if locks[..index * 2]
+                .iter()
+                .step_by(2)
+                .any(|counted| counted.key == pool_lock.key)
+            {
+                return Err(Error::DuplicateLock.into());
+            }
+
+            if let Some(tier) = Self::recount_lock(
+                program_id,
+                pool,
+                &pool_state,
+                clock.unix_timestamp,
+                pool_lock,
+                pool_lock_token_account_xsos,
+            )? {
+                tier_users[tier] = tier_users[tier].error_increment()?;
+            }
+        }
+
+        pool_state.tier_users = tier_users;
+        pool_state.write(&mut pool.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    fn recompute_tier_users_batch<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        market_authority: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        locks: &[AccountInfo<'a>],
+        input: &RecomputeTierUsersInput,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        market_authority.is_signer()?;
+        let mut pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
+        let clock = Clock::from_account_info(clock)?;
+
+        if market_authority.pubkey() != pool_state.ido_authority {
+            return Err(Error::PoolMustBeRelatedToMarket.into());
+        }
+
+        if locks.is_empty() || locks.len() % 2 != 0 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+
+        // locks can not be moved between tiers by users only while pool is active
+        if !(input.first && input.last) && clock.unix_timestamp >= pool_state.pool_active_until {
+            return Err(Error::RecountRequiresActivePool.into());
+        }
+
+        if input.first {
+            pool_state.recount_cursor = Pubkey::default();
+            pool_state.recount_tier_users = [0; crate::TIERS_COUNT];
+        } else if pool_state.recount_cursor == Pubkey::default() {
+            return Err(Error::RecountNotStarted.into());
+        }
+
+        for pair in locks.chunks_exact(2) {
+            let (pool_lock, pool_lock_token_account_xsos) = (&pair[0], &pair[1]);
+            if *pool_lock.key <= pool_state.recount_cursor {
+                return Err(Error::LocksMustBeSorted.into());
+            }
+
+            if let Some(tier) = Self::recount_lock(
+                program_id,
+                pool,
+                &pool_state,
+                clock.unix_timestamp,
+                pool_lock,
+                pool_lock_token_account_xsos,
+            )? {
+                pool_state.recount_tier_users[tier] =
+                    pool_state.recount_tier_users[tier].error_increment()?;
+            }
+            pool_state.recount_cursor = *pool_lock.key;
+        }
+
+        if input.last {
+            pool_state.tier_users = pool_state.recount_tier_users;
+            pool_state.recount_cursor = Pubkey::default();
+            pool_state.recount_tier_users = [0; crate::TIERS_COUNT];
+        }
+
+        pool_state.write(&mut pool.data.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Refreshes `pool_lock` as [Instruction::RefreshLock] does, returns tier it is counted in
+    fn recount_lock<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        pool_state: &StakePool,
+        now: clock::UnixTimestamp,
+        pool_lock: &AccountInfo<'a>,
+        pool_lock_token_account_xsos: &AccountInfo<'a>,
+    ) -> Result<Option<usize>, ProgramError> {
+        pool_lock.owned_by(program_id)?;
+        let mut pool_lock_state = PoolLock::migrate(&pool_lock.data.borrow())?;
+        pool_lock_state.initialized()?;
+        same_key(pool_lock_state.pool, pool, Error::LockMustBeRelatedToPool)?;
+        if *pool_lock_token_account_xsos.key != pool_lock_state.token_account_xsos {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let token_state = Account::unpack_from_slice(*pool_lock_token_account_xsos.data.borrow())?;
+
+        let locked_amount = pool_lock_state.counted_balance(token_state.amount);
+        pool_lock_state.tier_counted = pool_state.lock_is_aged(&pool_lock_state, now);
+        pool_lock_state.update_twab(pool_state.twab_window_seconds, now, locked_amount);
+        let tier = pool_state.lock_tier(&pool_lock_state, locked_amount);
+
+        pool_lock_state.write(&mut *pool_lock.try_borrow_mut_data()?)?;
+
+        Ok(tier)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn claim_staking_reward<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        user_wallet: &AccountInfo<'a>,
+        pool_lock: &AccountInfo<'a>,
+        pool_lock_token_account_xsos: &AccountInfo<'a>,
+        pool_authority: &AccountInfo<'a>,
+        reward_mint: &AccountInfo<'a>,
+        user_token_account_reward: &AccountInfo<'a>,
+        clock: &AccountInfo<'a>,
+        _token_program: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        pool_lock.owned_by(program_id)?;
+        user_wallet.is_signer()?;
+
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
+        pool_state.initialized()?;
+        if pool_state.reward_rate == 0 {
+            return Err(Error::RewardsAreNotEnabled.into());
+        }
+        same_key(
+            pool_state.reward_mint,
+            reward_mint,
+            Error::WrongAccountSpecified,
+        )?;
+
+        let mut pool_lock_state = PoolLock::migrate(&pool_lock.data.borrow())?;
+        pool_lock_state.initialized()?;
+        same_key(pool_lock_state.user_wallet, user_wallet, Error::WrongOwner)?;
+        same_key(pool_lock_state.pool, pool, Error::LockMustBeRelatedToPool)?;
+
+        if *pool_lock_token_account_xsos.key != pool_lock_state.token_account_xsos {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let bump_seed = pool_authority_bump_seed(&pool_state, pool, pool_authority, program_id)?;
+        let clock = Clock::from_account_info(clock)?;
+        let token_state = Account::unpack_from_slice(*pool_lock_token_account_xsos.data.borrow())?;
+
+        let locked_amount = pool_lock_state.counted_balance(token_state.amount);
+        accrue_reward(
+            &pool_state,
+            &mut pool_lock_state,
+            locked_amount,
+            clock.unix_timestamp,
+        )?;
+
+        let amount = pool_lock_state.reward_debt;
+        if amount == 0 {
+            return Err(Error::NothingToClaim.into());
+        }
+        pool_lock_state.reward_debt = 0;
+        pool_lock_state.write(&mut *pool_lock.try_borrow_mut_data()?)?;
+
+        invoke::token_mint_to(
+            pool.key,
+            reward_mint.clone(),
+            user_token_account_reward.clone(),
+            pool_authority.clone(),
+            bump_seed,
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cancel_transit<'a>(
+        program_id: &ProgramPubkey,
+        pool: &AccountInfo<'a>,
+        pool_authority: &AccountInfo<'a>,
+        pool_token_account_sos: &AccountInfo<'a>,
+        pool_transit: &AccountInfo<'a>,
+        pool_transit_token_account_sos: &AccountInfo<'a>,
+        user_wallet: &AccountInfo<'a>,
+        user_token_account_sos: &AccountInfo<'a>,
+        user_token_account_xsos: &AccountInfo<'a>,
+        pool_mint_xsos: &AccountInfo<'a>,
+        _token_program: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        pool.owned_by(program_id)?;
+        pool_transit.owned_by(program_id)?;
+        user_wallet.is_signer()?;
+
+        let pool_state = StakePool::migrate(&pool.data.borrow())?;
+        same_key(
+            pool_state.token_account_sos,
+            pool_token_account_sos,
+            Error::WrongAccountSpecified,
+        )?;
+        same_key(
+            pool_state.pool_mint_xsos,
+            pool_mint_xsos,
+            Error::WrongAccountSpecified,
+        )?;
+
+        let pool_transit_state = PoolTransit::migrate(&pool_transit.data.borrow())?;
+        pool_transit_state.initialized()?;
+        same_key(
+            pool_transit_state.pool,
+            pool,
+            Error::PoolTransitMustBeOfProvidedPool,
+        )?;
+        if pool_transit_state.token_account_sos != *pool_transit_token_account_sos.key {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if pool_transit_state.user_wallet != *user_wallet.key {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        let bump_seed = pool_authority_bump_seed(&pool_state, pool, pool_authority, program_id)?;
+        let remaining_amount =
+            Account::unpack_from_slice(&pool_transit_token_account_sos.data.borrow())?.amount;
+
+        match pool_transit_state.direction {
+            TransitDirection::Incoming => {
+                invoke::token_transfer_program_authority(
+                    pool.key,
+                    pool_transit_token_account_sos.clone(),
+                    user_token_account_sos.clone(),
+                    pool_authority.clone(),
+                    bump_seed,
+                    remaining_amount,
+                )?;
+            }
+            TransitDirection::Outgoing => {
+                // xSOS is minted and burned 1:1 to SOS in pool, so returning SOS restores backing
+                invoke::token_transfer_program_authority(
+                    pool.key,
+                    pool_transit_token_account_sos.clone(),
+                    pool_token_account_sos.clone(),
+                    pool_authority.clone(),
+                    bump_seed,
+                    remaining_amount,
+                )?;
+                invoke::token_mint_to(
+                    pool.key,
+                    pool_mint_xsos.clone(),
+                    user_token_account_xsos.clone(),
+                    pool_authority.clone(),
+                    bump_seed,
+                    remaining_amount,
+                )?;
+            }
+            TransitDirection::Uninitialized => {
+                return Err(Error::PoolTransitWrongDirection.into());
+            }
+        }
+
+        invoke::close_token_account(
+            pool_transit_token_account_sos.clone(),
+            user_wallet.clone(),
+            pool_authority.clone(),
+            &[&pool.key.to_bytes()[..32], &[bump_seed]],
+        )?;
+
+        PoolTransit::default().write(&mut *pool_transit.try_borrow_mut_data()?)?;
+        burn_account(pool_transit, user_wallet)
+    }
+
     /// Processes an instruction
     pub fn process_instruction(
         program_id: &Pubkey,
@@ -655,187 +1221,317 @@ impl Processor {
         input: &[u8],
     ) -> ProgramResult {
         let program_id = ProgramPubkey(*program_id);
-        let instruction = Instruction::deserialize_const(input)?;
+        let instruction = try_instruction_from_slice::<Instruction>(input)?;
         match instruction {
             Instruction::InitializePool(input) => {
                 msg!("Instruction::InitializePool");
-                match accounts {
-                    [pool, token_account_sos, mint_sos, pool_mint_xsos, program_authority, rent, token_program, ..] => {
-                        Self::initialize_pool(
-                            &program_id,
-                            pool,
-                            token_account_sos,
-                            mint_sos,
-                            pool_mint_xsos,
-                            program_authority,
-                            rent,
-                            token_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = InitializePoolAccounts::new(accounts)?;
+                Self::initialize_pool(
+                    &program_id,
+                    accounts.pool,
+                    accounts.token_account_sos,
+                    accounts.mint_sos,
+                    accounts.pool_mint_xsos,
+                    accounts.program_authority,
+                    accounts.rent,
+                    accounts.token_program,
+                    accounts.remaining.first(),
+                    &input,
+                )
             }
             Instruction::StakeStart(input) => {
                 msg!("Instruction::StakeStart");
-                match accounts {
-                    [pool, pool_transit, pool_authority, pool_token_account_sos, pool_transit_token_account_sos, mint_sos, user_wallet, user_token_account_sos, rent, clock, token_program, ..] => {
-                        Self::stake_start(
-                            &program_id,
-                            pool,
-                            pool_transit,
-                            pool_authority,
-                            pool_token_account_sos,
-                            pool_transit_token_account_sos,
-                            mint_sos,
-                            user_wallet,
-                            user_token_account_sos,
-                            rent,
-                            clock,
-                            token_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = StakeStartAccounts::new(accounts)?;
+                Self::stake_start(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_transit,
+                    accounts.pool_authority,
+                    accounts.pool_token_account_sos,
+                    accounts.pool_transit_token_account_sos,
+                    accounts.mint_sos,
+                    accounts.user_wallet,
+                    accounts.user_token_account_sos,
+                    accounts.rent,
+                    accounts.clock,
+                    accounts.token_program,
+                    &input,
+                )
             }
             Instruction::StakeFinish => {
                 msg!("Instruction::StakeFinish");
-                match accounts {
-                    [pool, pool_authority, pool_token_account_sos, pool_transit, pool_transit_token_account_sos, user_token_account_xsos, user_wallet, pool_mint_xsos, clock, token_program, ..] => {
-                        Self::stake_finish(
-                            &program_id,
-                            pool,
-                            pool_authority,
-                            pool_token_account_sos,
-                            pool_transit,
-                            pool_transit_token_account_sos,
-                            user_token_account_xsos,
-                            user_wallet,
-                            pool_mint_xsos,
-                            clock,
-                            token_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = StakeFinishAccounts::new(accounts)?;
+                Self::stake_finish(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_token_account_sos,
+                    accounts.pool_transit,
+                    accounts.pool_transit_token_account_sos,
+                    accounts.user_token_account_xsos,
+                    accounts.user_wallet,
+                    accounts.pool_mint_xsos,
+                    accounts.clock,
+                    accounts.token_program,
+                )
             }
             Instruction::UnstakeStart(input) => {
                 msg!("Instruction::UnstakeStart");
-                match accounts {
-                    [pool, pool_authority, pool_token_account_sos, pool_transit, pool_transit_token_account_sos, mint_sos, user_wallet, user_token_account_xsos, mint_xsos, rent, clock, token_program, ..] => {
-                        Self::unstake_start(
-                            &program_id,
-                            pool,
-                            pool_authority,
-                            pool_token_account_sos,
-                            pool_transit,
-                            pool_transit_token_account_sos,
-                            mint_sos,
-                            user_wallet,
-                            user_token_account_xsos,
-                            mint_xsos,
-                            rent,
-                            clock,
-                            token_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = UnstakeStartAccounts::new(accounts)?;
+                Self::unstake_start(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_token_account_sos,
+                    accounts.pool_transit,
+                    accounts.pool_transit_token_account_sos,
+                    accounts.mint_sos,
+                    accounts.user_wallet,
+                    accounts.user_token_account_xsos,
+                    accounts.mint_xsos,
+                    accounts.rent,
+                    accounts.clock,
+                    accounts.token_program,
+                    &input,
+                )
             }
             Instruction::UnstakeFinish => {
                 msg!("Instruction::UnstakeFinish");
-                match accounts {
-                    [pool, pool_transit, pool_authority, pool_transit_account_sos, user_wallet, user_token_account_sos, clock, token_program, ..] => {
-                        Self::unstake_finish(
-                            &program_id,
-                            pool,
-                            pool_transit,
-                            pool_authority,
-                            pool_transit_account_sos,
-                            user_wallet,
-                            user_token_account_sos,
-                            clock,
-                            token_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = UnstakeFinishAccounts::new(accounts)?;
+                Self::unstake_finish(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_transit,
+                    accounts.pool_authority,
+                    accounts.pool_transit_account_sos,
+                    accounts.user_wallet,
+                    accounts.user_token_account_sos,
+                    accounts.clock,
+                    accounts.token_program,
+                )
             }
             Instruction::InitializeLock => {
                 msg!("Instruction::InitializeLock");
-                match accounts {
-                    [pool, user_wallet, pool_lock, pool_user_authority, pool_mint_xsos, pool_lock_token_account_xsos, rent, _system_program, _token_program] => {
-                        Self::initialize_lock(
-                            &program_id,
-                            pool,
-                            user_wallet,
-                            pool_lock,
-                            pool_user_authority,
-                            pool_mint_xsos,
-                            pool_lock_token_account_xsos,
-                            rent,
-                            &ProgramAccountInfo(_system_program),
-                            _token_program,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = InitializeLockAccounts::new(accounts)?;
+                Self::initialize_lock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_mint_xsos,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.rent,
+                    &ProgramAccountInfo(accounts.system_program),
+                    accounts.token_program,
+                )
             }
             Instruction::Lock(input) => {
                 msg!("Instruction::Lock");
-                match accounts {
-                    [pool, user_wallet, pool_lock, pool_user_authority, pool_lock_token_account_xsos, user_token_account_xsos, clock, token_program, ..] => {
-                        Self::lock(
-                            &program_id,
-                            pool,
-                            user_wallet,
-                            pool_lock,
-                            pool_user_authority,
-                            pool_lock_token_account_xsos,
-                            user_token_account_xsos,
-                            clock,
-                            token_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = LockAccounts::new(accounts)?;
+                Self::lock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.user_token_account_xsos,
+                    accounts.clock,
+                    accounts.token_program,
+                    &input,
+                )
             }
             Instruction::Unlock(input) => {
                 msg!("Instruction::Unlock");
-                match accounts {
-                    [pool, user_wallet, pool_lock, pool_user_authority, pool_lock_token_account_xsos, user_token_account_xsos, clock, token_program, ..] => {
-                        Self::unlock(
-                            &program_id,
-                            pool,
-                            user_wallet,
-                            pool_lock,
-                            pool_user_authority,
-                            pool_lock_token_account_xsos,
-                            user_token_account_xsos,
-                            clock,
-                            token_program,
-                            &input,
-                        )
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = UnlockAccounts::new(accounts)?;
+                Self::unlock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.user_token_account_xsos,
+                    accounts.clock,
+                    accounts.token_program,
+                    &input,
+                )
             }
             Instruction::StartPool(input) => {
                 msg!("Instruction::StartPool");
-                match accounts {
-                    [pool, market_authority, clock, ..] => {
-                        Self::start_pool(&program_id, pool, market_authority, clock, &input)
-                    }
-                    _ => Err(ProgramError::NotEnoughAccountKeys),
-                }
+                let accounts = StartPoolAccounts::new(accounts)?;
+                Self::start_pool(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    accounts.clock,
+                    &input,
+                )
+            }
+            Instruction::ClaimStakingReward => {
+                msg!("Instruction::ClaimStakingReward");
+                let accounts = ClaimStakingRewardAccounts::new(accounts)?;
+                Self::claim_staking_reward(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.pool_authority,
+                    accounts.reward_mint,
+                    accounts.user_token_account_reward,
+                    accounts.clock,
+                    accounts.token_program,
+                )
+            }
+            Instruction::CancelTransit => {
+                msg!("Instruction::CancelTransit");
+                let accounts = CancelTransitAccounts::new(accounts)?;
+                Self::cancel_transit(
+                    &program_id,
+                    accounts.pool,
+                    accounts.pool_authority,
+                    accounts.pool_token_account_sos,
+                    accounts.pool_transit,
+                    accounts.pool_transit_token_account_sos,
+                    accounts.user_wallet,
+                    accounts.user_token_account_sos,
+                    accounts.user_token_account_xsos,
+                    accounts.pool_mint_xsos,
+                    accounts.token_program,
+                )
+            }
+            Instruction::ExtendPoolActive(input) => {
+                msg!("Instruction::ExtendPoolActive");
+                let accounts = ExtendPoolActiveAccounts::new(accounts)?;
+                Self::extend_pool_active(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    accounts.clock,
+                    &input,
+                )
+            }
+            Instruction::RecomputeTierUsers => {
+                msg!("Instruction::RecomputeTierUsers");
+                let accounts = RecomputeTierUsersAccounts::new(accounts)?;
+                Self::recompute_tier_users(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    accounts.clock,
+                    accounts.remaining,
+                )
+            }
+            Instruction::RecomputeTierUsersBatch(input) => {
+                msg!("Instruction::RecomputeTierUsersBatch");
+                let accounts = RecomputeTierUsersBatchAccounts::new(accounts)?;
+                Self::recompute_tier_users_batch(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    accounts.clock,
+                    accounts.remaining,
+                    &input,
+                )
+            }
+            Instruction::InitializeLockAndLock(input) => {
+                msg!("Instruction::InitializeLockAndLock");
+                let accounts = InitializeLockAndLockAccounts::new(accounts)?;
+                Self::initialize_lock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_mint_xsos,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.rent,
+                    &ProgramAccountInfo(accounts.system_program),
+                    accounts.token_program,
+                )?;
+                Self::lock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.user_token_account_xsos,
+                    accounts.clock,
+                    accounts.token_program,
+                    &input,
+                )
+            }
+            Instruction::SetStakesPaused(input) => {
+                msg!("Instruction::SetStakesPaused");
+                let accounts = SetStakesPausedAccounts::new(accounts)?;
+                Self::set_stakes_paused(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    &input,
+                )
+            }
+            Instruction::SetIdoAuthority(input) => {
+                msg!("Instruction::SetIdoAuthority");
+                let accounts = SetIdoAuthorityAccounts::new(accounts)?;
+                Self::set_ido_authority(
+                    &program_id,
+                    accounts.pool,
+                    accounts.market_authority,
+                    &input,
+                )
+            }
+            Instruction::Compound => {
+                msg!("Instruction::Compound");
+                let accounts = CompoundAccounts::new(accounts)?;
+                Self::compound(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.user_token_account_xsos,
+                    accounts.clock,
+                    accounts.token_program,
+                )
+            }
+            Instruction::RefreshLock => {
+                msg!("Instruction::RefreshLock");
+                let accounts = RefreshLockAccounts::new(accounts)?;
+                Self::refresh_lock(
+                    &program_id,
+                    accounts.pool,
+                    accounts.user_wallet,
+                    accounts.pool_lock,
+                    accounts.pool_user_authority,
+                    accounts.pool_lock_token_account_xsos,
+                    accounts.clock,
+                )
             }
         }
     }
 }
 
+/// bump seed of `pool_authority`, errors if it is not authority of `pool`
+fn pool_authority_bump_seed(
+    pool_state: &StakePool,
+    pool: &AccountInfo,
+    pool_authority: &AccountInfo,
+    program_id: &ProgramPubkey,
+) -> Result<u8, ProgramError> {
+    let (authority, bump_seed) = pool_state.authority(pool.key, program_id)?;
+    same_key(
+        authority,
+        pool_authority,
+        Error::DerivedAccountKeyIsNotEqualToCalculated,
+    )?;
+    Ok(bump_seed)
+}
+
 /// errors if relation is not expected
 fn same_key(relation: Pubkey, related: &AccountInfo, error: Error) -> ProgramResult {
     if relation != related.pubkey() {
@@ -845,13 +1541,18 @@ fn same_key(relation: Pubkey, related: &AccountInfo, error: Error) -> ProgramRes
     Ok(())
 }
 
-/// finishes some or whole of stake to or from pool
+/// finishes some or whole of stake to or from pool, amount released till now is recorded as claimed.
+/// Zero if transit is fully claimed, error if nothing is released yet.
 fn finish(
     mut pool_transit_state: PoolTransit,
     clock: clock::Clock,
     remaining_amount: u64,
     pool_transit: &AccountInfo,
 ) -> Result<u64, ProgramError> {
+    if remaining_amount == 0 {
+        msg!("Transit is fully claimed");
+        return Ok(0);
+    }
     let amount_claimed = pool_transit_state.amount_claimed;
     let transit_from = pool_transit_state.transit_from;
     let transit_until = pool_transit_state.transit_until;
@@ -867,6 +1568,25 @@ fn finish(
     pool_transit_state.amount_claimed = pool_transit_state
         .amount_claimed
         .error_add(amount_to_claim)?;
-    pool_transit_state.serialize_const(&mut *pool_transit.try_borrow_mut_data()?)?;
+    pool_transit_state.write(&mut *pool_transit.try_borrow_mut_data()?)?;
     Ok(amount_to_claim)
 }
+
+/// moves reward accrued by `locked_amount` since last claim into reward debt.
+/// Callers pass [PoolLock::counted_balance], so tokens transferred into lock token account directly earn nothing.
+fn accrue_reward(
+    pool_state: &StakePool,
+    pool_lock_state: &mut PoolLock,
+    locked_amount: u64,
+    now: clock::UnixTimestamp,
+) -> ProgramResult {
+    // lock which never held tokens via this program has nothing accrued
+    if pool_state.reward_rate != 0 && pool_lock_state.last_claim != 0 {
+        let elapsed = now.error_sub(pool_lock_state.last_claim)?;
+        let accrued =
+            math::reward(locked_amount, pool_state.reward_rate, elapsed).ok_or(Error::Overflow)?;
+        pool_lock_state.reward_debt = pool_lock_state.reward_debt.error_add(accrued)?;
+    }
+    pool_lock_state.last_claim = now;
+    Ok(())
+}
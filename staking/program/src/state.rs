@@ -3,16 +3,27 @@
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 use solana_program::clock::UnixTimestamp;
 use solana_program::pubkey::Pubkey;
-use solana_program::{entrypoint::ProgramResult, program_error::ProgramError};
+use solana_program::{entrypoint::ProgramResult, program_error::ProgramError, program_pack::Pack};
+
+use crate::{
+    borsh::try_from_slice_exact,
+    error::Error,
+    math::{self, time_weighted_average},
+    program::{canonical_authority, ProgramDerivedPubkey, ProgramPubkey, PubkeyPatterns},
+};
 
 /// state version
 #[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
 pub enum StateVersion {
     /// new
     Uninitialized,
     /// version 1
     V1,
+    /// version 2, [StakePool] with [StakePool::stakes_paused]
+    V2,
+    /// version 3, [StakePool] with [StakePool::recount_cursor]
+    V3,
 }
 
 impl Default for StateVersion {
@@ -21,10 +32,116 @@ impl Default for StateVersion {
     }
 }
 
+/// Set in first byte of accounts of current layout, [StateVersion] starting accounts of older layouts is always below it
+pub const ACCOUNT_TYPE_FLAG: u8 = 0x80;
+/// [StakePool::account_type]
+pub const ACCOUNT_TYPE_STAKE_POOL: u8 = ACCOUNT_TYPE_FLAG | 1;
+/// [PoolTransit::account_type]
+pub const ACCOUNT_TYPE_POOL_TRANSIT: u8 = ACCOUNT_TYPE_FLAG | 2;
+/// [PoolLock::account_type]
+pub const ACCOUNT_TYPE_POOL_LOCK: u8 = ACCOUNT_TYPE_FLAG | 3;
+
+/// Errors if account `data` has [StateVersion] newer than [crate::PROGRAM_VERSION],
+/// so account of newer layout is never read by older program.
+/// Version follows account type if `data` starts with it.
+pub fn check_version(data: &[u8]) -> ProgramResult {
+    let version = match data.first() {
+        Some(&account_type) if account_type & ACCOUNT_TYPE_FLAG != 0 => data.get(1),
+        version => version,
+    };
+    match version {
+        Some(&version) if version > crate::PROGRAM_VERSION => {
+            Err(Error::UnsupportedStateVersion.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// true if account `data` is of layout starting with account type, which is at least `len_typed` bytes.
+/// Accounts can not grow, so accounts of older layouts, written before account type was added, are smaller.
+fn is_typed(data: &[u8], len_typed: usize) -> bool {
+    data.len() >= len_typed && (data[0] == 0 || data[0] & ACCOUNT_TYPE_FLAG != 0)
+}
+
+/// Deserializes state of `len` bytes after [check_version], account `data` must be of `len` bytes.
+/// Account must be uninitialized or of `account_type`.
+pub fn state_from_slice<T: BorshDeserialize>(
+    data: &[u8],
+    account_type: u8,
+    len: usize,
+) -> Result<T, ProgramError> {
+    if let Some(&first) = data.first() {
+        if first != 0 && first != account_type {
+            return Err(Error::WrongAccountType.into());
+        }
+    }
+    check_version(data)?;
+    try_from_slice_exact(data, len)
+}
+
+/// Same as [state_from_slice] for account `data` which may be written with older layout of at least `len_v1` bytes.
+/// Older layouts have no account type, it is prepended, and fields appended since are missing and read as zeros.
+/// Layouts with account type start from `len_typed` bytes, fields appended to them are read as zeros too.
+pub fn state_from_legacy_slice<T: BorshDeserialize>(
+    data: &[u8],
+    account_type: u8,
+    len_v1: usize,
+    len_typed: usize,
+    len: usize,
+) -> Result<T, ProgramError> {
+    if is_typed(data, len_typed) {
+        if data.len() >= len {
+            return state_from_slice(data, account_type, len);
+        }
+        let mut data = data.to_vec();
+        data.resize(len, 0);
+        return state_from_slice(&data, account_type, len);
+    }
+    if data.len() < len_v1 {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    check_version(data)?;
+    let mut data = [&[account_type][..], data].concat();
+    data.resize(data.len().max(len), 0);
+    try_from_slice_exact(&data, len)
+}
+
+/// Serialized `state` of `len` bytes as stored in account `data`, account of older layout has no account type
+fn state_bytes<T: BorshSerialize>(
+    state: &T,
+    data: &[u8],
+    len_typed: usize,
+) -> Result<Vec<u8>, ProgramError> {
+    let mut state = state.try_to_vec()?;
+    if !is_typed(data, len_typed) {
+        state.remove(0);
+    }
+    Ok(state)
+}
+
+/// Writes `state` of `len` bytes to account `data`, which may be written with older layout shorter than current one.
+/// Fields appended since do not fit such account, so write fails with [ProgramError::AccountDataTooSmall] unless they are zeros.
+pub fn state_to_slice<T: BorshSerialize>(
+    state: &T,
+    data: &mut [u8],
+    len_typed: usize,
+) -> ProgramResult {
+    let bytes = state_bytes(state, data, len_typed)?;
+    let stored = data.len().min(bytes.len());
+    let (kept, dropped) = bytes.split_at(stored);
+    if dropped.iter().any(|&byte| byte != 0) {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    data[..stored].copy_from_slice(kept);
+    Ok(())
+}
+
 /// pool state
 #[repr(C)]
-#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default, Clone)]
 pub struct StakePool {
+    /// [ACCOUNT_TYPE_STAKE_POOL]
+    pub account_type: u8,
     /// version
     pub version: StateVersion,
     /// Account accumulating staked SOS tokens
@@ -46,11 +163,35 @@ pub struct StakePool {
 
     /// if now is less than this - prevents [Instruction::Unlock]
     pub pool_active_until: UnixTimestamp,
+
+    /// Mint issuing rewards for locked xSOS, mint authority must be pool authority
+    pub reward_mint: Pubkey,
+
+    /// Reward tokens per locked xSOS token per second, scaled by [crate::math::REWARD_PRECISION]. Zero disables rewards.
+    pub reward_rate: u64,
+
+    /// Seconds since [PoolLock::locked_since] before lock is counted in [Self::tier_users]. Zero counts immediately.
+    pub min_lock_seconds: UnixTimestamp,
+
+    /// Seconds of time weighted average of locked amount counted in [Self::tier_users]. Zero counts locked amount.
+    pub twab_window_seconds: UnixTimestamp,
+
+    /// Blocks [crate::instruction::Instruction::StakeStart] and [crate::instruction::Instruction::Lock] of new tokens, exits are not blocked
+    pub stakes_paused: bool,
+
+    /// Last lock counted by unfinished [crate::instruction::Instruction::RecomputeTierUsersBatch], default key if none is in progress
+    pub recount_cursor: Pubkey,
+
+    /// Users counted in each tier by unfinished [crate::instruction::Instruction::RecomputeTierUsersBatch]
+    pub recount_tier_users: [u32; crate::TIERS_COUNT],
+
+    /// Bump seed of pool authority, zero for pools of layout before it was stored
+    pub authority_bump_seed: u8,
 }
 
 /// flow of stake
 #[repr(C)]
-#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema, Clone, Copy)]
 pub enum TransitDirection {
     /// something went wrong
     Uninitialized,
@@ -68,8 +209,10 @@ impl Default for TransitDirection {
 
 /// derived
 #[repr(C)]
-#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default, Clone)]
 pub struct PoolTransit {
+    /// [ACCOUNT_TYPE_POOL_TRANSIT]
+    pub account_type: u8,
     /// version
     pub version: StateVersion,
     /// [StakePool] this transit area belongs to
@@ -91,8 +234,10 @@ pub struct PoolTransit {
 
 /// derived from pool and user_wallet (unique per such pair), can withdraw only via program
 #[repr(C)]
-#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default)]
+#[derive(Debug, BorshDeserialize, BorshSerialize, BorshSchema, Default, Clone)]
 pub struct PoolLock {
+    /// [ACCOUNT_TYPE_POOL_LOCK]
+    pub account_type: u8,
     /// version
     pub version: StateVersion,
     /// [StakePool] this lock belongs to
@@ -101,11 +246,61 @@ pub struct PoolLock {
     pub user_wallet: Pubkey,
     /// Token account storing locked xSOS tokens
     pub token_account_xsos: Pubkey,
+    /// Timestamp up to which reward was accrued into [Self::reward_debt]
+    pub last_claim: UnixTimestamp,
+    /// Reward accrued, but not yet claimed
+    pub reward_debt: u64,
+    /// Timestamp of last lock increasing locked amount
+    pub locked_since: UnixTimestamp,
+    /// Tier of locked amount is counted in [StakePool::tier_users]
+    pub tier_counted: bool,
+    /// Time weighted average of locked amount at [Self::twab_updated], see [StakePool::twab_window_seconds]
+    pub twab_amount: u64,
+    /// Amount locked by [crate::instruction::Instruction::Lock] and [crate::instruction::Instruction::Unlock] since [Self::twab_updated].
+    /// Tokens transferred into [Self::token_account_xsos] directly are not counted, rewards accrue on this amount.
+    pub twab_balance: u64,
+    /// Timestamp of last [Self::twab_amount] update
+    pub twab_updated: UnixTimestamp,
 }
 
 impl StakePool {
     /// LEN
-    pub const LEN: usize = 169;
+    pub const LEN: usize = 276;
+    /// LEN of first [StateVersion::V1] layout, pools of it have no account type, rewards, min lock, TWAB, pause and recount fields
+    pub const LEN_V1: usize = 169;
+    /// LEN of first layout with account type, pools of it have no [Self::authority_bump_seed]
+    pub const LEN_TYPED: usize = 275;
+
+    /// Reads pool of current or older layout, see [state_from_legacy_slice]
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        state_from_legacy_slice(
+            data,
+            ACCOUNT_TYPE_STAKE_POOL,
+            Self::LEN_V1,
+            Self::LEN_TYPED,
+            Self::LEN,
+        )
+    }
+
+    /// Writes pool to its account of current or older layout, see [state_to_slice]
+    pub fn write(&self, data: &mut [u8]) -> ProgramResult {
+        state_to_slice(self, data, Self::LEN_TYPED)
+    }
+
+    /// Authority of `pool` and its bump seed, recreated by [canonical_authority] from [Self::authority_bump_seed],
+    /// found again for pools which do not store it
+    pub fn authority(
+        &self,
+        pool: &Pubkey,
+        program_id: &ProgramPubkey,
+    ) -> Result<(ProgramDerivedPubkey, u8), ProgramError> {
+        if self.authority_bump_seed == 0 {
+            return Ok(Pubkey::find_key_program_address(pool, program_id));
+        }
+        let authority = canonical_authority(pool, self.authority_bump_seed, program_id)?;
+        Ok((authority, self.authority_bump_seed))
+    }
+
     /// Check if already initialized
     pub fn uninitialized(&self) -> ProgramResult {
         if self.version == StateVersion::Uninitialized {
@@ -122,11 +317,68 @@ impl StakePool {
             Err(ProgramError::UninitializedAccount)
         }
     }
+
+    /// tier of user with `locked_amount` of xSOS, `None` if below lowest tier
+    pub fn user_tier(&self, locked_amount: u64) -> Option<usize> {
+        get_tier(self.tier_balance, locked_amount)
+    }
+
+    /// tier `pool_lock` with `locked_amount` is counted in, `None` until lock is counted in tiers,
+    /// so lock never counted is not in lowest tier of zero threshold
+    pub fn lock_tier(&self, pool_lock: &PoolLock, locked_amount: u64) -> Option<usize> {
+        if !pool_lock.tier_counted {
+            return None;
+        }
+        self.user_tier(pool_lock.tier_amount(locked_amount))
+    }
+
+    /// lock was not increased for [Self::min_lock_seconds] till `now`
+    pub fn lock_is_aged(&self, pool_lock: &PoolLock, now: UnixTimestamp) -> bool {
+        now.saturating_sub(pool_lock.locked_since) >= self.min_lock_seconds
+    }
+
+    /// Total value locked, SOS balance of [Self::token_account_sos] read from its `data`.
+    /// `None` if account is missing or is not an initialized token account.
+    pub fn total_value_locked(token_account_sos: Option<&[u8]>) -> Option<u64> {
+        spl_token::state::Account::unpack(token_account_sos?)
+            .ok()
+            .map(|account| account.amount)
+    }
 }
 
 impl PoolLock {
     /// LEN
-    pub const LEN: usize = 97;
+    pub const LEN: usize = 147;
+    /// LEN of first [StateVersion::V1] layout, locks of it have no account type
+    pub const LEN_V1: usize = 97;
+    /// LEN since [Self::tier_counted] was added
+    pub const LEN_TIER_COUNTED: usize = 122;
+
+    /// Reads lock of current or older layout, see [state_from_legacy_slice].
+    /// Locks older than [Self::tier_counted] belong to pools without min lock, so they were counted when locked.
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        let mut pool_lock: Self = state_from_legacy_slice(
+            data,
+            ACCOUNT_TYPE_POOL_LOCK,
+            Self::LEN_V1,
+            Self::LEN,
+            Self::LEN,
+        )?;
+        if data.len() < Self::LEN_TIER_COUNTED {
+            pool_lock.tier_counted = true;
+        }
+        Ok(pool_lock)
+    }
+
+    /// Writes lock to its account of current or older layout.
+    /// Lock of older layout belongs to pool older than fields appended since, they are derived for such pool, so are dropped.
+    pub fn write(&self, data: &mut [u8]) -> ProgramResult {
+        let state = state_bytes(self, data, Self::LEN)?;
+        let len = data.len().min(state.len());
+        data[..len].copy_from_slice(&state[..len]);
+        Ok(())
+    }
+
     /// Check if already initialized
     pub fn uninitialized(&self) -> ProgramResult {
         if self.version == StateVersion::Uninitialized {
@@ -143,11 +395,75 @@ impl PoolLock {
             Err(ProgramError::UninitializedAccount)
         }
     }
+
+    /// amount qualifying for tier, zero until lock is counted in tiers, at most [Self::twab_amount].
+    /// Lock written before TWAB has no [Self::twab_updated], it qualifies by `locked_amount`.
+    pub fn tier_amount(&self, locked_amount: u64) -> u64 {
+        if !self.tier_counted {
+            0
+        } else if self.twab_updated == 0 {
+            locked_amount
+        } else {
+            locked_amount.min(self.twab_amount)
+        }
+    }
+
+    /// amount locked through this program, [StakePool::tier_users] and rewards are counted by it.
+    /// `held_amount` is held by lock token account now, lock written before TWAB has no [Self::twab_updated],
+    /// so it is counted by held amount.
+    pub fn counted_balance(&self, held_amount: u64) -> u64 {
+        if self.twab_updated == 0 {
+            held_amount
+        } else {
+            self.twab_balance
+        }
+    }
+
+    /// time weighted average of locked amount over `window` seconds till `now`
+    pub fn twab(&self, window: UnixTimestamp, now: UnixTimestamp) -> u64 {
+        time_weighted_average(
+            self.twab_amount,
+            self.twab_balance,
+            now.saturating_sub(self.twab_updated),
+            window,
+        )
+    }
+
+    /// settles time weighted average till `now`, `locked_amount` is locked from `now`.
+    /// Zero `window` makes average equal to `locked_amount`.
+    pub fn update_twab(&mut self, window: UnixTimestamp, now: UnixTimestamp, locked_amount: u64) {
+        self.twab_amount = if window > 0 {
+            self.twab(window, now)
+        } else {
+            locked_amount
+        };
+        self.twab_balance = locked_amount;
+        self.twab_updated = now;
+    }
 }
 
 impl PoolTransit {
     /// LEN
-    pub const LEN: usize = 122;
+    pub const LEN: usize = 123;
+    /// LEN of [StateVersion::V1] layout without account type
+    pub const LEN_V1: usize = 122;
+
+    /// Reads transit of current or older layout, see [state_from_legacy_slice]
+    pub fn migrate(data: &[u8]) -> Result<Self, ProgramError> {
+        state_from_legacy_slice(
+            data,
+            ACCOUNT_TYPE_POOL_TRANSIT,
+            Self::LEN_V1,
+            Self::LEN,
+            Self::LEN,
+        )
+    }
+
+    /// Writes transit to its account of current or older layout, see [state_to_slice]
+    pub fn write(&self, data: &mut [u8]) -> ProgramResult {
+        state_to_slice(self, data, Self::LEN)
+    }
+
     /// Check if already initialized
     pub fn uninitialized(&self) -> ProgramResult {
         if self.version == StateVersion::Uninitialized {
@@ -164,6 +480,19 @@ impl PoolTransit {
             Err(ProgramError::UninitializedAccount)
         }
     }
+
+    /// Amount finish of this transit would move at `now`, `remaining` is balance of [Self::token_account_sos].
+    /// Zero if nothing can be moved yet, see [crate::instruction::Instruction::StakeFinish].
+    pub fn claimable_now(&self, now: UnixTimestamp, remaining: u64) -> u64 {
+        math::finish(
+            self.transit_from,
+            now,
+            self.transit_until,
+            self.amount_claimed,
+            remaining,
+        )
+        .unwrap_or(0)
+    }
 }
 
 #[cfg(test)]
@@ -171,6 +500,211 @@ mod tests {
 
     use super::*;
 
+    /// account data written by program before account type was added, truncated to `len`
+    fn untyped<T: BorshSerialize>(state: &T, len: usize) -> Vec<u8> {
+        state.try_to_vec().unwrap()[1..=len].to_vec()
+    }
+
+    #[test]
+    fn state_version() {
+        let mut data = StakePool {
+            account_type: ACCOUNT_TYPE_STAKE_POOL,
+            version: StateVersion::V2,
+            ..Default::default()
+        }
+        .try_to_vec()
+        .unwrap();
+        let read = |data: &[u8]| {
+            state_from_slice::<StakePool>(data, ACCOUNT_TYPE_STAKE_POOL, StakePool::LEN)
+        };
+        assert!(read(&data).is_ok());
+
+        data[1] = crate::PROGRAM_VERSION + 1;
+        assert_eq!(
+            read(&data).unwrap_err(),
+            Error::UnsupportedStateVersion.into()
+        );
+        assert_eq!(
+            check_version(&PoolLock::default().try_to_vec().unwrap()),
+            Ok(())
+        );
+        assert_eq!(check_version(&[]), Ok(()));
+        assert_eq!(
+            check_version(&[crate::PROGRAM_VERSION + 1]),
+            Err(Error::UnsupportedStateVersion.into())
+        );
+
+        data[0] = ACCOUNT_TYPE_POOL_LOCK;
+        assert_eq!(read(&data).unwrap_err(), Error::WrongAccountType.into());
+        assert_eq!(
+            StakePool::migrate(&data).unwrap_err(),
+            Error::WrongAccountType.into()
+        );
+    }
+
+    #[test]
+    fn account_type() {
+        let stake_pool = StakePool {
+            account_type: ACCOUNT_TYPE_STAKE_POOL,
+            version: StateVersion::V3,
+            tier_users: [1, 2, 3, 4],
+            stakes_paused: true,
+            ..Default::default()
+        };
+        let mut data = stake_pool.try_to_vec().unwrap();
+        let mut migrated = StakePool::migrate(&data).unwrap();
+        assert_eq!(migrated.account_type, ACCOUNT_TYPE_STAKE_POOL);
+        assert!(migrated.stakes_paused);
+
+        migrated.tier_users = [4, 3, 2, 1];
+        migrated.write(&mut data).unwrap();
+        assert_eq!(data[0], ACCOUNT_TYPE_STAKE_POOL);
+        assert_eq!(StakePool::migrate(&data).unwrap().tier_users, [4, 3, 2, 1]);
+
+        // account of older layout keeps it, type is added only in memory
+        let mut data = untyped(&stake_pool, StakePool::LEN_TYPED - 1);
+        let migrated = StakePool::migrate(&data).unwrap();
+        assert_eq!(migrated.account_type, ACCOUNT_TYPE_STAKE_POOL);
+        assert_eq!(migrated.tier_users, [1, 2, 3, 4]);
+        migrated.write(&mut data).unwrap();
+        assert_eq!(data, untyped(&stake_pool, StakePool::LEN_TYPED - 1));
+    }
+
+    #[test]
+    fn stake_pool_without_authority_bump_seed() {
+        let stake_pool = StakePool {
+            account_type: ACCOUNT_TYPE_STAKE_POOL,
+            version: StateVersion::V3,
+            tier_users: [1, 2, 3, 4],
+            ..Default::default()
+        };
+        let mut data = stake_pool.try_to_vec().unwrap()[..StakePool::LEN_TYPED].to_vec();
+        let mut migrated = StakePool::migrate(&data).unwrap();
+        assert_eq!(migrated.account_type, ACCOUNT_TYPE_STAKE_POOL);
+        assert_eq!(migrated.tier_users, [1, 2, 3, 4]);
+        assert_eq!(migrated.authority_bump_seed, 0);
+
+        migrated.tier_users = [4, 3, 2, 1];
+        migrated.write(&mut data).unwrap();
+        assert_eq!(data.len(), StakePool::LEN_TYPED);
+        assert_eq!(StakePool::migrate(&data).unwrap().tier_users, [4, 3, 2, 1]);
+
+        migrated.authority_bump_seed = 1;
+        assert_eq!(
+            migrated.write(&mut data).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+    }
+
+    #[test]
+    fn stake_pool_authority() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let pool = Pubkey::new_unique();
+        let found = Pubkey::find_key_program_address(&pool, &program_id);
+
+        let stake_pool = StakePool::default();
+        assert_eq!(stake_pool.authority(&pool, &program_id), Ok(found));
+
+        let stake_pool = StakePool {
+            authority_bump_seed: found.1,
+            ..Default::default()
+        };
+        assert_eq!(stake_pool.authority(&pool, &program_id), Ok(found));
+    }
+
+    #[test]
+    fn stake_pool_legacy_layout() {
+        let stake_pool = StakePool {
+            version: StateVersion::V1,
+            ido_authority: Pubkey::new_unique(),
+            tier_users: [1, 2, 3, 4],
+            pool_active_until: 42,
+            ..Default::default()
+        };
+        let mut data = untyped(&stake_pool, StakePool::LEN_V1);
+
+        let mut migrated = StakePool::migrate(&data).unwrap();
+        assert_eq!(migrated.ido_authority, stake_pool.ido_authority);
+        assert_eq!(migrated.tier_users, stake_pool.tier_users);
+        assert_eq!(migrated.pool_active_until, 42);
+        assert_eq!(migrated.reward_rate, 0);
+        assert!(!migrated.stakes_paused);
+
+        migrated.tier_users = [4, 3, 2, 1];
+        migrated.write(&mut data).unwrap();
+        assert_eq!(StakePool::migrate(&data).unwrap().tier_users, [4, 3, 2, 1]);
+
+        // paused flag does not fit, account is left as is
+        migrated.stakes_paused = true;
+        assert_eq!(
+            migrated.write(&mut data).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+        assert_eq!(StakePool::migrate(&data).unwrap().tier_users, [4, 3, 2, 1]);
+
+        assert_eq!(
+            StakePool::migrate(&data[..StakePool::LEN_V1 - 1]).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+    }
+
+    #[test]
+    fn pool_lock_legacy_layout() {
+        let pool_lock = PoolLock {
+            version: StateVersion::V1,
+            pool: Pubkey::new_unique(),
+            user_wallet: Pubkey::new_unique(),
+            token_account_xsos: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        let mut data = untyped(&pool_lock, PoolLock::LEN_V1);
+
+        let mut migrated = PoolLock::migrate(&data).unwrap();
+        assert_eq!(migrated.pool, pool_lock.pool);
+        assert_eq!(migrated.token_account_xsos, pool_lock.token_account_xsos);
+        assert!(migrated.tier_counted);
+
+        // derived fields are dropped
+        migrated.update_twab(0, 1000, 2500);
+        migrated.write(&mut data).unwrap();
+        let migrated = PoolLock::migrate(&data).unwrap();
+        assert_eq!(migrated.user_wallet, pool_lock.user_wallet);
+        assert_eq!(migrated.twab_updated, 0);
+
+        let pool_lock = PoolLock {
+            tier_counted: false,
+            ..pool_lock
+        };
+        let data = untyped(&pool_lock, PoolLock::LEN_TIER_COUNTED);
+        assert!(!PoolLock::migrate(&data).unwrap().tier_counted);
+        let data = untyped(&pool_lock, PoolLock::LEN - 1);
+        assert!(!PoolLock::migrate(&data).unwrap().tier_counted);
+    }
+
+    #[test]
+    fn pool_transit_legacy_layout() {
+        let pool_transit = PoolTransit {
+            version: StateVersion::V1,
+            pool: Pubkey::new_unique(),
+            direction: TransitDirection::Incoming,
+            transit_until: 42,
+            ..Default::default()
+        };
+        let mut data = untyped(&pool_transit, PoolTransit::LEN_V1);
+
+        let mut migrated = PoolTransit::migrate(&data).unwrap();
+        assert_eq!(migrated.account_type, ACCOUNT_TYPE_POOL_TRANSIT);
+        assert_eq!(migrated.pool, pool_transit.pool);
+        assert_eq!(migrated.transit_until, 42);
+
+        migrated.amount_claimed = 10;
+        migrated.write(&mut data).unwrap();
+        assert_eq!(PoolTransit::migrate(&data).unwrap().amount_claimed, 10);
+
+        PoolTransit::default().write(&mut data).unwrap();
+        assert!(data.iter().all(|&byte| byte == 0));
+    }
+
     #[test]
     fn len() {
         assert_eq!(
@@ -186,9 +720,235 @@ mod tests {
             PoolTransit::default().try_to_vec().unwrap().len()
         );
     }
+
+    #[test]
+    fn len_populated() {
+        let stake_pool = StakePool {
+            account_type: ACCOUNT_TYPE_STAKE_POOL,
+            version: StateVersion::V3,
+            token_account_sos: Pubkey::new_unique(),
+            pool_mint_xsos: Pubkey::new_unique(),
+            ido_authority: Pubkey::new_unique(),
+            tier_users: [u32::MAX; crate::TIERS_COUNT],
+            tier_balance: [u64::MAX; crate::TIERS_COUNT],
+            transit_incoming: UnixTimestamp::MAX,
+            transit_outgoing: UnixTimestamp::MAX,
+            pool_active_until: UnixTimestamp::MAX,
+            reward_mint: Pubkey::new_unique(),
+            reward_rate: u64::MAX,
+            min_lock_seconds: UnixTimestamp::MAX,
+            twab_window_seconds: UnixTimestamp::MAX,
+            stakes_paused: true,
+            recount_cursor: Pubkey::new_unique(),
+            recount_tier_users: [u32::MAX; crate::TIERS_COUNT],
+            authority_bump_seed: u8::MAX,
+        };
+        assert_eq!(StakePool::LEN, stake_pool.try_to_vec().unwrap().len());
+
+        let pool_lock = PoolLock {
+            account_type: ACCOUNT_TYPE_POOL_LOCK,
+            version: StateVersion::V1,
+            pool: Pubkey::new_unique(),
+            user_wallet: Pubkey::new_unique(),
+            token_account_xsos: Pubkey::new_unique(),
+            last_claim: UnixTimestamp::MAX,
+            reward_debt: u64::MAX,
+            locked_since: UnixTimestamp::MAX,
+            tier_counted: true,
+            twab_amount: u64::MAX,
+            twab_balance: u64::MAX,
+            twab_updated: UnixTimestamp::MAX,
+        };
+        assert_eq!(PoolLock::LEN, pool_lock.try_to_vec().unwrap().len());
+
+        let pool_transit = PoolTransit {
+            account_type: ACCOUNT_TYPE_POOL_TRANSIT,
+            version: StateVersion::V1,
+            pool: Pubkey::new_unique(),
+            direction: TransitDirection::Outgoing,
+            user_wallet: Pubkey::new_unique(),
+            token_account_sos: Pubkey::new_unique(),
+            transit_from: UnixTimestamp::MAX,
+            transit_until: UnixTimestamp::MAX,
+            amount_claimed: u64::MAX,
+        };
+        assert_eq!(PoolTransit::LEN, pool_transit.try_to_vec().unwrap().len());
+    }
+
+    #[test]
+    fn tier() {
+        let tier_balance = [1000, 2000, 3000, 4000];
+        assert_eq!(get_tier(tier_balance, 0), None);
+        assert_eq!(get_tier(tier_balance, 999), None);
+        assert_eq!(get_tier(tier_balance, 1000), Some(0));
+        assert_eq!(get_tier(tier_balance, 1999), Some(0));
+        assert_eq!(get_tier(tier_balance, 2000), Some(1));
+        assert_eq!(get_tier(tier_balance, 3000), Some(2));
+        assert_eq!(get_tier(tier_balance, 4000), Some(3));
+        assert_eq!(get_tier(tier_balance, u64::MAX), Some(3));
+    }
+
+    #[test]
+    fn stake_pool_user_tier() {
+        let stake_pool = StakePool {
+            tier_balance: [1000, 2000, 3000, 4000],
+            ..Default::default()
+        };
+        assert_eq!(stake_pool.user_tier(999), None);
+        assert_eq!(stake_pool.user_tier(1000), Some(0));
+        assert_eq!(stake_pool.user_tier(2999), Some(1));
+        assert_eq!(stake_pool.user_tier(3001), Some(2));
+    }
+
+    #[test]
+    fn stake_pool_total_value_locked() {
+        assert_eq!(StakePool::total_value_locked(None), None);
+
+        let mut data = vec![0; spl_token::state::Account::LEN];
+        assert_eq!(StakePool::total_value_locked(Some(&data)), None);
+        assert_eq!(StakePool::total_value_locked(Some(&data[1..])), None);
+
+        let account = spl_token::state::Account {
+            amount: 4200,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        };
+        spl_token::state::Account::pack(account, &mut data).unwrap();
+        assert_eq!(StakePool::total_value_locked(Some(&data)), Some(4200));
+    }
+
+    #[test]
+    fn lock_is_aged() {
+        let stake_pool = StakePool {
+            min_lock_seconds: 100,
+            ..Default::default()
+        };
+        let pool_lock = PoolLock {
+            locked_since: 1000,
+            ..Default::default()
+        };
+        assert!(!stake_pool.lock_is_aged(&pool_lock, 1000));
+        assert!(!stake_pool.lock_is_aged(&pool_lock, 1099));
+        assert!(stake_pool.lock_is_aged(&pool_lock, 1100));
+
+        let stake_pool = StakePool::default();
+        assert!(stake_pool.lock_is_aged(&pool_lock, 1000));
+    }
+
+    #[test]
+    fn pool_lock_tier_amount() {
+        let mut pool_lock = PoolLock {
+            twab_amount: 2500,
+            twab_updated: 1,
+            ..Default::default()
+        };
+        assert_eq!(pool_lock.tier_amount(2500), 0);
+        pool_lock.tier_counted = true;
+        assert_eq!(pool_lock.tier_amount(2500), 2500);
+        pool_lock.twab_amount = 1000;
+        assert_eq!(pool_lock.tier_amount(2500), 1000);
+        assert_eq!(pool_lock.tier_amount(500), 500);
+    }
+
+    #[test]
+    fn pool_lock_written_before_twab() {
+        let pool_lock = PoolLock {
+            version: StateVersion::V1,
+            pool: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        let pool_lock = PoolLock::migrate(&untyped(&pool_lock, PoolLock::LEN_V1)).unwrap();
+        assert_eq!(pool_lock.twab_updated, 0);
+        assert_eq!(pool_lock.tier_amount(2500), 2500);
+        assert_eq!(pool_lock.counted_balance(2500), 2500);
+
+        let stake_pool = StakePool {
+            tier_balance: [1000, 2000, 3000, 4000],
+            ..Default::default()
+        };
+        assert_eq!(stake_pool.lock_tier(&pool_lock, 2500), Some(1));
+
+        let mut pool_lock = pool_lock;
+        pool_lock.update_twab(0, 1000, 3500);
+        assert_eq!(pool_lock.counted_balance(2500), 3500);
+        assert_eq!(stake_pool.lock_tier(&pool_lock, 3500), Some(2));
+    }
+
+    #[test]
+    fn stake_pool_lock_tier_of_zero_threshold() {
+        let stake_pool = StakePool {
+            tier_balance: [0, 2000, 3000, 4000],
+            ..Default::default()
+        };
+        let fresh = PoolLock::default();
+        assert_eq!(stake_pool.lock_tier(&fresh, fresh.counted_balance(0)), None);
+
+        let counted = PoolLock {
+            tier_counted: true,
+            ..fresh
+        };
+        assert_eq!(stake_pool.lock_tier(&counted, 0), Some(0));
+        assert_eq!(stake_pool.lock_tier(&counted, 2500), Some(1));
+    }
+
+    #[test]
+    fn pool_lock_update_twab() {
+        let mut pool_lock = PoolLock::default();
+        pool_lock.update_twab(1000, 10_000, 2500);
+        assert_eq!(pool_lock.twab_amount, 0);
+        assert_eq!(pool_lock.twab(1000, 10_500), 1250);
+        assert_eq!(pool_lock.twab(1000, 11_000), 2500);
+
+        pool_lock.update_twab(1000, 10_500, 500);
+        assert_eq!(pool_lock.twab_amount, 1250);
+        assert_eq!(pool_lock.twab_balance, 500);
+        assert_eq!(pool_lock.twab(1000, 10_750), 1062);
+
+        pool_lock.update_twab(0, 10_750, 3000);
+        assert_eq!(pool_lock.twab_amount, 3000);
+    }
+
+    #[test]
+    fn tier_with_zero_threshold() {
+        let tier_balance = [0, 2000, 3000, 4000];
+        assert_eq!(get_tier(tier_balance, 0), Some(0));
+        assert_eq!(get_tier(tier_balance, 1999), Some(0));
+        assert_eq!(get_tier(tier_balance, 2000), Some(1));
+    }
+
+    #[test]
+    fn tier_balance_increasing() {
+        assert!(tier_balance_is_increasing(&[0, 2000, 3000, 4000]));
+        assert!(tier_balance_is_increasing(&[1000, 2000, 3000, 4000]));
+        assert!(!tier_balance_is_increasing(&[1000, 1000, 3000, 4000]));
+        assert!(!tier_balance_is_increasing(&[1000, 3000, 2000, 4000]));
+        assert!(!tier_balance_is_increasing(&[0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn transit_claimable_now() {
+        let mut transit = PoolTransit {
+            version: StateVersion::V1,
+            transit_from: 1_000,
+            transit_until: 2_000,
+            ..Default::default()
+        };
+        assert_eq!(transit.claimable_now(500, 100), 0);
+        assert_eq!(transit.claimable_now(1_000, 100), 0);
+        assert_eq!(transit.claimable_now(1_500, 100), 50);
+        assert_eq!(transit.claimable_now(2_000, 100), 100);
+        assert_eq!(transit.claimable_now(3_000, 100), 100);
+
+        // half claimed, half remains
+        transit.amount_claimed = 50;
+        assert_eq!(transit.claimable_now(1_500, 50), 0);
+        assert_eq!(transit.claimable_now(1_750, 50), 25);
+        assert_eq!(transit.claimable_now(3_000, 50), 50);
+    }
 }
 
-/// gets tier for ticket
+/// gets tier for ticket, `tier_balance` must be strictly increasing (see [tier_balance_is_increasing]),
+/// zero threshold is reached by any amount including zero
 pub fn get_tier(tier_balance: [u64; crate::TIERS_COUNT], pool_lock_amount: u64) -> Option<usize> {
     tier_balance
         .iter()
@@ -196,3 +956,8 @@ pub fn get_tier(tier_balance: [u64; crate::TIERS_COUNT], pool_lock_amount: u64)
         .rfind(|(_, val)| pool_lock_amount >= **val)
         .map(|(i, _)| i)
 }
+
+/// checks tier thresholds are strictly increasing, so higher tier always requires more locked
+pub fn tier_balance_is_increasing(tier_balance: &[u64; crate::TIERS_COUNT]) -> bool {
+    tier_balance.windows(2).all(|pair| pair[0] < pair[1])
+}
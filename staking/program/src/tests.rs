@@ -1,17 +1,30 @@
 use crate::{
     id,
     instruction::{
-        self, InitializePoolInput, LockInput, StakeStartInput, UnlockInput, UnstakeStartInput,
+        self, ExtendPoolActiveInput, InitializePoolInput, LockInput, RecomputeTierUsersInput,
+        SetIdoAuthorityInput, SetStakesPausedInput, StakeStartInput, StartPoolInput, UnlockInput,
+        UnstakeStartInput,
     },
     prelude::*,
-    state::{PoolTransit, StakePool},
+    state::{
+        PoolLock, PoolTransit, StakePool, StateVersion, ACCOUNT_TYPE_POOL_LOCK,
+        ACCOUNT_TYPE_STAKE_POOL,
+    },
+};
+use borsh::BorshSerialize;
+use solana_program::{
+    clock::{Clock, UnixTimestamp},
+    instruction::{AccountMeta, InstructionError},
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_instruction,
 };
-use solana_program::{clock::Clock, program_pack::Pack, pubkey::Pubkey, system_instruction};
 use solana_program_test::*;
 use solana_sdk::{
     account::Account,
     signature::{Keypair, Signer},
-    transaction::Transaction,
+    transaction::{Transaction, TransactionError},
     transport::TransportError,
 };
 use spl_token::state::{Account as TokenAccount, Mint};
@@ -316,11 +329,15 @@ async fn flow() {
         &pool_token_account_sos.pubkey(),
         &mint_sos.pubkey(),
         &mint_xsos.pubkey(),
+        None,
         InitializePoolInput {
             tier_balance: [1000, 2000, 3000, 4000],
             ido_authority: ido_market,
             transit_incoming: 3 * 100 * 60,
             transit_outgoing: 3 * 100 * 60,
+            reward_rate: 0,
+            min_lock_seconds: 0,
+            twab_window_seconds: 0,
         },
     )
     .unwrap();
@@ -444,6 +461,29 @@ async fn flow() {
         get_token_account_state(&mut program_context, &pool_token_account_sos).await;
     assert_eq!(account_state.amount, 10000);
 
+    // fully claimed transit finishes without transfer
+    warp_seconds(&mut program_context, 100 * 60).await;
+    let transaction = crate::utils::sdk::stake_finish(
+        &pool,
+        &pool_token_account_sos,
+        &pool_transit_to,
+        &pool_transit_to_token,
+        &user_token_xsos,
+        &user_wallet,
+        &mint_xsos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    let account_state =
+        get_token_account_state(&mut program_context, &pool_token_account_sos).await;
+    assert_eq!(account_state.amount, 10000);
+    let account_state = get_token_account_state(&mut program_context, &user_token_xsos).await;
+    assert_eq!(account_state.amount, 10000);
+
     let instruction = instruction::initialize_lock(
         &pool.pubkey(),
         &user_wallet.pubkey(),
@@ -632,11 +672,18 @@ async fn flow() {
         &program_context,
     );
 
-    program_context
+    let error = program_context
         .banks_client
         .process_transaction(transaction)
         .await
         .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::CannotTransitAnythingNow as u32
+    ));
 
     warp_seconds(&mut program_context, 100 * 60).await;
 
@@ -677,6 +724,23 @@ async fn flow() {
     let account_state = get_token_account_state(&mut program_context, &user_token_sos).await;
 
     assert_eq!(account_state.amount, 990420);
+
+    warp_seconds(&mut program_context, 100 * 60).await;
+    let transaction = unstake_finish(
+        &pool,
+        &pool_transit_from,
+        &pool_transit_from_token,
+        &user_wallet,
+        &user_token_sos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    let account_state = get_token_account_state(&mut program_context, &user_token_sos).await;
+    assert_eq!(account_state.amount, 990420);
 }
 
 async fn get_token_account_state(
@@ -711,3 +775,2199 @@ fn unstake_finish(
     );
     transaction
 }
+
+async fn process_instructions(
+    program_context: &mut ProgramTestContext,
+    instructions: &[solana_program::instruction::Instruction],
+    signers: &[&Keypair],
+) -> Result<(), TransportError> {
+    let mut transaction =
+        Transaction::new_with_payer(instructions, Some(&program_context.payer.pubkey()));
+    let mut all_signers = vec![&program_context.payer];
+    all_signers.extend_from_slice(signers);
+    transaction.sign(&all_signers, program_context.last_blockhash);
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+}
+
+fn pool_lock_key(pool: &Pubkey, user_wallet: &Pubkey) -> Pubkey {
+    let pool_user_authority = instruction::find_2key_program_address(pool, user_wallet);
+    Pubkey::create_with_seed(&pool_user_authority, crate::LOCK_SEED, &crate::id()).unwrap()
+}
+
+/// accounts of initialized pool with SOS of single user in incoming transit
+struct StakedTransit {
+    pool: Keypair,
+    ido_authority: Keypair,
+    mint_xsos: Keypair,
+    mint_reward: Keypair,
+    pool_token_account_sos: Keypair,
+    pool_transit: Keypair,
+    pool_transit_token: Keypair,
+    user_token_sos: Keypair,
+    user_token_xsos: Keypair,
+}
+
+async fn setup_transit(
+    program_context: &mut ProgramTestContext,
+    user_wallet: &Keypair,
+    reward_rate: u64,
+    min_lock_seconds: i64,
+    twab_window_seconds: i64,
+    stake_amount: u64,
+) -> StakedTransit {
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(1_000);
+
+    let pool = Keypair::new();
+    let ido_authority = Keypair::new();
+    let mint_sos = Keypair::new();
+    let mint_sos_authority = Keypair::new();
+    let mint_xsos = Keypair::new();
+    let mint_reward = Keypair::new();
+    let pool_token_account_sos = Keypair::new();
+    let pool_transit = Keypair::new();
+    let pool_transit_token = Keypair::new();
+    let user_token_sos = Keypair::new();
+    let user_token_xsos = Keypair::new();
+
+    for (account, space, owner) in [
+        (&pool, StakePool::LEN, id()),
+        (&pool_transit, PoolTransit::LEN, id()),
+        (&mint_sos, Mint::LEN, spl_token::id()),
+        (&mint_xsos, Mint::LEN, spl_token::id()),
+        (&mint_reward, Mint::LEN, spl_token::id()),
+        (&pool_token_account_sos, TokenAccount::LEN, spl_token::id()),
+        (&pool_transit_token, TokenAccount::LEN, spl_token::id()),
+        (&user_token_sos, TokenAccount::LEN, spl_token::id()),
+        (&user_token_xsos, TokenAccount::LEN, spl_token::id()),
+    ]
+    .iter()
+    {
+        create_account(program_context, account, rent, *space as u64, owner)
+            .await
+            .unwrap();
+    }
+
+    let instructions = [
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint_sos.pubkey(),
+            &mint_sos_authority.pubkey(),
+            None,
+            2,
+        )
+        .unwrap(),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint_reward.pubkey(),
+            &instruction::find_key_program_address(&pool.pubkey()),
+            None,
+            2,
+        )
+        .unwrap(),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &user_token_sos.pubkey(),
+            &mint_sos.pubkey(),
+            &user_wallet.pubkey(),
+        )
+        .unwrap(),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &mint_sos.pubkey(),
+            &user_token_sos.pubkey(),
+            &mint_sos_authority.pubkey(),
+            &[],
+            1_000_000,
+        )
+        .unwrap(),
+        instruction::initialize_pool(
+            &pool.pubkey(),
+            &pool_token_account_sos.pubkey(),
+            &mint_sos.pubkey(),
+            &mint_xsos.pubkey(),
+            Some(mint_reward.pubkey()),
+            InitializePoolInput {
+                tier_balance: [1000, 2000, 3000, 4000],
+                ido_authority: ido_authority.pubkey(),
+                transit_incoming: 3 * 100 * 60,
+                transit_outgoing: 3 * 100 * 60,
+                reward_rate,
+                min_lock_seconds,
+                twab_window_seconds,
+            },
+        )
+        .unwrap(),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &user_token_xsos.pubkey(),
+            &mint_xsos.pubkey(),
+            &user_wallet.pubkey(),
+        )
+        .unwrap(),
+        instruction::stake_start(
+            &pool.pubkey(),
+            &pool_transit.pubkey(),
+            &pool_token_account_sos.pubkey(),
+            &pool_transit_token.pubkey(),
+            &mint_sos.pubkey(),
+            &user_wallet.pubkey(),
+            &user_token_sos.pubkey(),
+            StakeStartInput {
+                amount: stake_amount,
+            },
+        )
+        .unwrap(),
+    ];
+    process_instructions(
+        program_context,
+        &instructions,
+        &[&mint_sos_authority, user_wallet],
+    )
+    .await
+    .unwrap();
+
+    StakedTransit {
+        pool,
+        ido_authority,
+        mint_xsos,
+        mint_reward,
+        pool_token_account_sos,
+        pool_transit,
+        pool_transit_token,
+        user_token_sos,
+        user_token_xsos,
+    }
+}
+
+/// accounts of initialized pool with xSOS of single user locked
+struct StakedLock {
+    pool: Keypair,
+    ido_authority: Keypair,
+    mint_reward: Keypair,
+    pool_lock_token_xsos: Keypair,
+    user_token_xsos: Keypair,
+}
+
+async fn setup_lock(
+    program_context: &mut ProgramTestContext,
+    user_wallet: &Keypair,
+    reward_rate: u64,
+    min_lock_seconds: i64,
+    twab_window_seconds: i64,
+    lock_amount: u64,
+) -> StakedLock {
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(1_000);
+
+    let transit = setup_transit(
+        program_context,
+        user_wallet,
+        reward_rate,
+        min_lock_seconds,
+        twab_window_seconds,
+        lock_amount,
+    )
+    .await;
+    let pool_lock_token_xsos = Keypair::new();
+    create_account(
+        program_context,
+        &pool_lock_token_xsos,
+        rent,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+
+    warp_seconds(program_context, 3 * 100 * 60).await;
+
+    let transaction = crate::utils::sdk::stake_finish(
+        &transit.pool,
+        &transit.pool_token_account_sos,
+        &transit.pool_transit,
+        &transit.pool_transit_token,
+        &transit.user_token_xsos,
+        user_wallet,
+        &transit.mint_xsos,
+        program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let instructions = [
+        instruction::initialize_lock(
+            &transit.pool.pubkey(),
+            &user_wallet.pubkey(),
+            &transit.mint_xsos.pubkey(),
+            &pool_lock_token_xsos.pubkey(),
+        )
+        .unwrap(),
+        instruction::lock(
+            &transit.pool.pubkey(),
+            &user_wallet.pubkey(),
+            &pool_lock_token_xsos.pubkey(),
+            &transit.user_token_xsos.pubkey(),
+            LockInput {
+                amount: lock_amount,
+            },
+        )
+        .unwrap(),
+    ];
+    process_instructions(program_context, &instructions, &[user_wallet])
+        .await
+        .unwrap();
+
+    StakedLock {
+        pool: transit.pool,
+        ido_authority: transit.ido_authority,
+        mint_reward: transit.mint_reward,
+        pool_lock_token_xsos,
+        user_token_xsos: transit.user_token_xsos,
+    }
+}
+
+#[tokio::test]
+async fn unlock_moves_user_between_tiers() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let lock_amount = 2500;
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, lock_amount).await;
+
+    let unlock = instruction::unlock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        UnlockInput { amount: 1000 },
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[unlock], &[&user_wallet])
+        .await
+        .unwrap();
+
+    // 2500 -> 1500 moves user from tier 1 to tier 0, tier thresholds stay intact
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [1, 0, 0, 0]);
+    assert_eq!(pool_state.tier_balance, [1000, 2000, 3000, 4000]);
+}
+
+/// creates and initializes token account of `mint` owned by `owner`
+async fn create_token_account(
+    program_context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Keypair {
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let account = Keypair::new();
+    create_account(
+        program_context,
+        &account,
+        rent.minimum_balance(TokenAccount::LEN),
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+    let instruction = spl_token::instruction::initialize_account(
+        &spl_token::id(),
+        &account.pubkey(),
+        mint,
+        owner,
+    )
+    .unwrap();
+    process_instructions(program_context, &[instruction], &[])
+        .await
+        .unwrap();
+    account
+}
+
+#[tokio::test]
+async fn claim_staking_reward() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let reward_rate = 1_000_000;
+    let lock_amount = 2500;
+
+    let staked = setup_lock(
+        &mut program_context,
+        &user_wallet,
+        reward_rate,
+        0,
+        0,
+        lock_amount,
+    )
+    .await;
+
+    let user_token_reward = create_token_account(
+        &mut program_context,
+        &staked.mint_reward.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await;
+
+    let pool_lock = pool_lock_key(&staked.pool.pubkey(), &user_wallet.pubkey());
+    let locked_at = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap()
+        .last_claim;
+
+    warp_seconds(&mut program_context, 100 * 60).await;
+
+    let claim = instruction::claim_staking_reward(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.mint_reward.pubkey(),
+        &user_token_reward.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[claim.clone()], &[&user_wallet])
+        .await
+        .unwrap();
+
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    let expected = reward(
+        lock_amount,
+        reward_rate,
+        pool_lock_state.last_claim - locked_at,
+    )
+    .unwrap();
+    assert!(expected > 0);
+    assert_eq!(pool_lock_state.reward_debt, 0);
+
+    let account_state = get_token_account_state(&mut program_context, &user_token_reward).await;
+    assert_eq!(account_state.amount, expected);
+
+    // no time passed within transaction, so second claim has nothing to mint
+    let error = process_instructions(
+        &mut program_context,
+        &[claim.clone(), claim],
+        &[&user_wallet],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::NothingToClaim as u32
+    ));
+
+    let account_state = get_token_account_state(&mut program_context, &user_token_reward).await;
+    assert_eq!(account_state.amount, expected);
+
+    let account_state =
+        get_token_account_state(&mut program_context, &staked.user_token_xsos).await;
+    assert_eq!(account_state.amount, 0);
+}
+
+#[tokio::test]
+async fn claim_staking_reward_ignores_transfer_into_lock() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let reward_rate = 1_000_000;
+
+    let staked = setup_lock(&mut program_context, &user_wallet, reward_rate, 0, 0, 2500).await;
+    let user_token_reward = create_token_account(
+        &mut program_context,
+        &staked.mint_reward.pubkey(),
+        &user_wallet.pubkey(),
+    )
+    .await;
+
+    let unlock = instruction::unlock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        UnlockInput { amount: 1000 },
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[unlock], &[&user_wallet])
+        .await
+        .unwrap();
+
+    let pool_lock = pool_lock_key(&staked.pool.pubkey(), &user_wallet.pubkey());
+    let unlocked_at = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap()
+        .last_claim;
+
+    warp_seconds(&mut program_context, 100 * 60).await;
+
+    // unlocked tokens are sent back by plain transfer right before claim
+    let transfer = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &staked.user_token_xsos.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &user_wallet.pubkey(),
+        &[],
+        1000,
+    )
+    .unwrap();
+    let claim = instruction::claim_staking_reward(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.mint_reward.pubkey(),
+        &user_token_reward.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[transfer, claim], &[&user_wallet])
+        .await
+        .unwrap();
+
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert_eq!(pool_lock_state.counted_balance(2500), 1500);
+    let expected = reward(1500, reward_rate, pool_lock_state.last_claim - unlocked_at).unwrap();
+    assert!(expected > 0);
+
+    let account_state = get_token_account_state(&mut program_context, &user_token_reward).await;
+    assert_eq!(account_state.amount, expected);
+}
+
+#[tokio::test]
+async fn initialize_lock_retry() {
+    let mut program_context = program_test().start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(1_000);
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let lock_amount = 2500;
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, lock_amount).await;
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    let pool_lock = pool_lock_key(&staked.pool.pubkey(), &user_wallet.pubkey());
+    let pool_lock_before = get_account(&mut program_context, &pool_lock).await;
+
+    let instruction = instruction::initialize_lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &pool_state.pool_mint_xsos,
+        &staked.pool_lock_token_xsos.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[instruction], &[&user_wallet])
+        .await
+        .unwrap();
+
+    let pool_lock_after = get_account(&mut program_context, &pool_lock).await;
+    assert_eq!(pool_lock_before, pool_lock_after);
+    let account_state =
+        get_token_account_state(&mut program_context, &staked.pool_lock_token_xsos).await;
+    assert_eq!(account_state.amount, lock_amount);
+
+    let other_token_xsos = Keypair::new();
+    create_account(
+        &mut program_context,
+        &other_token_xsos,
+        rent,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+    let instruction = instruction::initialize_lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &pool_state.pool_mint_xsos,
+        &other_token_xsos.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[instruction], &[&user_wallet])
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn initialize_lock_and_lock() {
+    let mut program_context = program_test().start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(1_000);
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let lock_amount = 2500;
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, lock_amount).await;
+    let pool_lock_token_xsos = Keypair::new();
+    create_account(
+        &mut program_context,
+        &pool_lock_token_xsos,
+        rent,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+    let transaction = crate::utils::sdk::stake_finish(
+        &transit.pool,
+        &transit.pool_token_account_sos,
+        &transit.pool_transit,
+        &transit.pool_transit_token,
+        &transit.user_token_xsos,
+        &user_wallet,
+        &transit.mint_xsos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let initialize_lock_and_lock = |pool_mint_xsos: &Pubkey| {
+        instruction::initialize_lock_and_lock(
+            &transit.pool.pubkey(),
+            &user_wallet.pubkey(),
+            pool_mint_xsos,
+            &pool_lock_token_xsos.pubkey(),
+            &transit.user_token_xsos.pubkey(),
+            LockInput {
+                amount: lock_amount,
+            },
+        )
+        .unwrap()
+    };
+
+    // lock initialization is checked as by separate instruction
+    let error = process_instructions(
+        &mut program_context,
+        &[initialize_lock_and_lock(&transit.mint_reward.pubkey())],
+        &[&user_wallet],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::WrongAccountSpecified as u32
+    ));
+
+    process_instructions(
+        &mut program_context,
+        &[initialize_lock_and_lock(&transit.mint_xsos.pubkey())],
+        &[&user_wallet],
+    )
+    .await
+    .unwrap();
+
+    let pool_lock = pool_lock_key(&transit.pool.pubkey(), &user_wallet.pubkey());
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert_eq!(
+        pool_lock_state.token_account_xsos,
+        pool_lock_token_xsos.pubkey()
+    );
+    assert!(pool_lock_state.tier_counted);
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(transit.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 1, 0, 0]);
+
+    let account_state = get_token_account_state(&mut program_context, &pool_lock_token_xsos).await;
+    assert_eq!(account_state.amount, lock_amount);
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_xsos).await;
+    assert_eq!(account_state.amount, 0);
+}
+
+#[tokio::test]
+async fn compound_into_existing_lock_moves_tier() {
+    let mut program_context = program_test().start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let rent = rent.minimum_balance(1_000);
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, 2500).await;
+    let pool_lock_token_xsos = Keypair::new();
+    create_account(
+        &mut program_context,
+        &pool_lock_token_xsos,
+        rent,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+    let transaction = crate::utils::sdk::stake_finish(
+        &transit.pool,
+        &transit.pool_token_account_sos,
+        &transit.pool_transit,
+        &transit.pool_transit_token,
+        &transit.user_token_xsos,
+        &user_wallet,
+        &transit.mint_xsos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+
+    let initialize_lock_and_lock = instruction::initialize_lock_and_lock(
+        &transit.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &transit.mint_xsos.pubkey(),
+        &pool_lock_token_xsos.pubkey(),
+        &transit.user_token_xsos.pubkey(),
+        LockInput { amount: 1500 },
+    )
+    .unwrap();
+    process_instructions(
+        &mut program_context,
+        &[initialize_lock_and_lock],
+        &[&user_wallet],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(transit.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [1, 0, 0, 0]);
+
+    // rest of xSOS in wallet goes into existing lock, 1500 -> 2500 moves user into tier 1
+    let compound = instruction::compound(
+        &transit.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &pool_lock_token_xsos.pubkey(),
+        &transit.user_token_xsos.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[compound.clone()], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(transit.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 1, 0, 0]);
+
+    let account_state = get_token_account_state(&mut program_context, &pool_lock_token_xsos).await;
+    assert_eq!(account_state.amount, 2500);
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_xsos).await;
+    assert_eq!(account_state.amount, 0);
+
+    // nothing left to compound, new slot lets the same transaction be sent again
+    warp(&mut program_context, 1).await;
+    let error = process_instructions(&mut program_context, &[compound], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::ZeroAmount as u32
+    ));
+}
+
+#[tokio::test]
+async fn lock_without_user_signature() {
+    let mut program_test = program_test();
+    let user_wallet = Keypair::new();
+    program_test.add_account(
+        user_wallet.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Default::default()
+        },
+    );
+    let mut program_context = program_test.start_with_context().await;
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, 2500).await;
+
+    let mut lock = instruction::lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        LockInput { amount: 1 },
+    )
+    .unwrap();
+    lock.accounts[1].is_signer = false;
+    let error = process_instructions(&mut program_context, &[lock], &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::MissingRequiredSignature,
+        ))
+    ));
+}
+
+#[tokio::test]
+async fn unlock_within_tier_when_pool_is_active() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let lock_amount = 2500;
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, lock_amount).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let start_pool = instruction::start_pool(
+        &staked.pool.pubkey(),
+        &staked.ido_authority.pubkey(),
+        StartPoolInput {
+            pool_active_until: now + 100 * 60,
+        },
+    );
+    process_instructions(
+        &mut program_context,
+        &[start_pool],
+        &[&staked.ido_authority],
+    )
+    .await
+    .unwrap();
+
+    let unlock = |amount| {
+        instruction::unlock(
+            &staked.pool.pubkey(),
+            &user_wallet.pubkey(),
+            &staked.pool_lock_token_xsos.pubkey(),
+            &staked.user_token_xsos.pubkey(),
+            UnlockInput { amount },
+        )
+        .unwrap()
+    };
+
+    // 2500 -> 2000 keeps user in tier 1
+    process_instructions(&mut program_context, &[unlock(500)], &[&user_wallet])
+        .await
+        .unwrap();
+    let account_state =
+        get_token_account_state(&mut program_context, &staked.pool_lock_token_xsos).await;
+    assert_eq!(account_state.amount, 2000);
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 1, 0, 0]);
+    assert_eq!(pool_state.tier_balance, [1000, 2000, 3000, 4000]);
+
+    // 2000 -> 1999 drops user into tier 0
+    let error = process_instructions(&mut program_context, &[unlock(1)], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::CannotUnlockWhenPoolIsActive as u32
+    ));
+
+    warp_seconds(&mut program_context, 100 * 60 + 1).await;
+    process_instructions(&mut program_context, &[unlock(1)], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [1, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn extend_pool_active_blocks_unlock() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let lock_amount = 2000;
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, lock_amount).await;
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let start_pool = instruction::start_pool(
+        &staked.pool.pubkey(),
+        &staked.ido_authority.pubkey(),
+        StartPoolInput {
+            pool_active_until: now + 10 * 60,
+        },
+    );
+    process_instructions(
+        &mut program_context,
+        &[start_pool],
+        &[&staked.ido_authority],
+    )
+    .await
+    .unwrap();
+
+    let extend = |pool_active_until| {
+        instruction::extend_pool_active(
+            &staked.pool.pubkey(),
+            &staked.ido_authority.pubkey(),
+            ExtendPoolActiveInput { pool_active_until },
+        )
+    };
+
+    let error = process_instructions(
+        &mut program_context,
+        &[extend(now + 5 * 60)],
+        &[&staked.ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::PoolActiveCannotBeShortened as u32
+    ));
+
+    process_instructions(
+        &mut program_context,
+        &[extend(now + 100 * 60)],
+        &[&staked.ido_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.pool_active_until, now + 100 * 60);
+
+    // unlock dropping tier would be allowed after original window
+    warp_seconds(&mut program_context, 10 * 60 + 1).await;
+    let unlock = instruction::unlock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        UnlockInput { amount: 1 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[unlock], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::CannotUnlockWhenPoolIsActive as u32
+    ));
+
+    // window already passed can not be extended to the past
+    warp_seconds(&mut program_context, 100 * 60).await;
+    let error = process_instructions(
+        &mut program_context,
+        &[extend(now + 100 * 60)],
+        &[&staked.ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::PoolMustBeActiveForSomeTime as u32
+    ));
+}
+
+#[tokio::test]
+async fn cancel_incoming_transit() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let stake_amount = 10_000;
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, stake_amount).await;
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_sos).await;
+    assert_eq!(account_state.amount, 1_000_000 - stake_amount);
+
+    warp_seconds(&mut program_context, 100 * 60).await;
+
+    let cancel = instruction::cancel_transit(
+        &transit.pool.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &transit.pool_transit.pubkey(),
+        &transit.pool_transit_token.pubkey(),
+        &user_wallet.pubkey(),
+        &transit.user_token_sos.pubkey(),
+        &transit.user_token_xsos.pubkey(),
+        &transit.mint_xsos.pubkey(),
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[cancel], &[&user_wallet])
+        .await
+        .unwrap();
+
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_sos).await;
+    assert_eq!(account_state.amount, 1_000_000);
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_xsos).await;
+    assert_eq!(account_state.amount, 0);
+    for closed in [&transit.pool_transit, &transit.pool_transit_token].iter() {
+        let account = program_context
+            .banks_client
+            .get_account(closed.pubkey())
+            .await
+            .unwrap();
+        assert!(account.is_none());
+    }
+}
+
+#[tokio::test]
+async fn lock_counted_in_tier_after_min_lock_seconds() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let min_lock_seconds = 100 * 60;
+    let lock_amount = 2500;
+
+    let staked = setup_lock(
+        &mut program_context,
+        &user_wallet,
+        0,
+        min_lock_seconds,
+        0,
+        lock_amount,
+    )
+    .await;
+    let pool_lock = pool_lock_key(&staked.pool.pubkey(), &user_wallet.pubkey());
+
+    // just locked user is not counted
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 0, 0, 0]);
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert!(!pool_lock_state.tier_counted);
+
+    let lock = |amount| {
+        instruction::lock(
+            &staked.pool.pubkey(),
+            &user_wallet.pubkey(),
+            &staked.pool_lock_token_xsos.pubkey(),
+            &staked.user_token_xsos.pubkey(),
+            LockInput { amount },
+        )
+        .unwrap()
+    };
+    let unlock = instruction::unlock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        UnlockInput { amount: 100 },
+    )
+    .unwrap();
+    let refresh = instruction::refresh_lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+    )
+    .unwrap();
+
+    // not yet counted lock does not change tiers
+    process_instructions(&mut program_context, &[unlock], &[&user_wallet])
+        .await
+        .unwrap();
+    process_instructions(&mut program_context, &[refresh.clone()], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 0, 0, 0]);
+
+    // lock aged past the threshold is counted
+    warp_seconds(&mut program_context, min_lock_seconds).await;
+    process_instructions(&mut program_context, &[refresh], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 1, 0, 0]);
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert!(pool_lock_state.tier_counted);
+
+    // increased lock must age again
+    process_instructions(&mut program_context, &[lock(100)], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 0, 0, 0]);
+}
+
+#[tokio::test]
+async fn lock_counted_in_tier_by_time_weighted_average() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let twab_window_seconds = 2 * 100 * 60;
+    let lock_amount = 2500;
+
+    let staked = setup_lock(
+        &mut program_context,
+        &user_wallet,
+        0,
+        0,
+        twab_window_seconds,
+        lock_amount,
+    )
+    .await;
+    let pool_lock = pool_lock_key(&staked.pool.pubkey(), &user_wallet.pubkey());
+
+    // instantaneous balance would count late lock in tier right away, average did not grow yet
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(pool_state.user_tier(lock_amount), Some(1));
+    assert_eq!(pool_state.tier_users, [0, 0, 0, 0]);
+
+    let refresh = instruction::refresh_lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+    )
+    .unwrap();
+
+    // about half of window passed, average is about half of locked
+    warp_seconds(&mut program_context, twab_window_seconds / 2).await;
+    process_instructions(&mut program_context, &[refresh.clone()], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert!(pool_lock_state.twab_amount > lock_amount / 2);
+    assert!(pool_lock_state.twab_amount < 2000);
+    assert_eq!(pool_state.tier_users, [1, 0, 0, 0]);
+
+    // whole window passed, average is locked amount
+    warp_seconds(&mut program_context, twab_window_seconds).await;
+    process_instructions(&mut program_context, &[refresh], &[&user_wallet])
+        .await
+        .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(staked.pool.pubkey())
+        .await
+        .unwrap();
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(pool_lock)
+        .await
+        .unwrap();
+    assert_eq!(pool_lock_state.twab_amount, lock_amount);
+    assert_eq!(pool_state.tier_users, [0, 1, 0, 0]);
+}
+
+#[tokio::test]
+async fn stake_start_transit_token_not_rent_exempt() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, 10_000).await;
+    let mint_sos = get_token_account_state(&mut program_context, &transit.pool_token_account_sos)
+        .await
+        .mint;
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let pool_transit = Keypair::new();
+    let pool_transit_token = Keypair::new();
+    create_account(
+        &mut program_context,
+        &pool_transit,
+        rent.minimum_balance(PoolTransit::LEN),
+        PoolTransit::LEN as u64,
+        &id(),
+    )
+    .await
+    .unwrap();
+    // one lamport short of exemption
+    create_account(
+        &mut program_context,
+        &pool_transit_token,
+        rent.minimum_balance(TokenAccount::LEN) - 1,
+        TokenAccount::LEN as u64,
+        &spl_token::id(),
+    )
+    .await
+    .unwrap();
+
+    let stake_start = instruction::stake_start(
+        &transit.pool.pubkey(),
+        &pool_transit.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &pool_transit_token.pubkey(),
+        &mint_sos,
+        &user_wallet.pubkey(),
+        &transit.user_token_sos.pubkey(),
+        StakeStartInput { amount: 10_000 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[stake_start], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::AccountNotRentExempt,
+        ))
+    ));
+}
+
+/// creates rent exempt transit and its token account for new stake or unstake
+async fn create_transit_accounts(program_context: &mut ProgramTestContext) -> (Keypair, Keypair) {
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let pool_transit = Keypair::new();
+    let pool_transit_token = Keypair::new();
+    for (account, space, owner) in [
+        (&pool_transit, PoolTransit::LEN, id()),
+        (&pool_transit_token, TokenAccount::LEN, spl_token::id()),
+    ]
+    .iter()
+    {
+        create_account(
+            program_context,
+            account,
+            rent.minimum_balance(*space),
+            *space as u64,
+            owner,
+        )
+        .await
+        .unwrap();
+    }
+    (pool_transit, pool_transit_token)
+}
+
+#[tokio::test]
+async fn stake_start_zero_amount() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, 10_000).await;
+    let mint_sos = get_token_account_state(&mut program_context, &transit.pool_token_account_sos)
+        .await
+        .mint;
+    let (pool_transit, pool_transit_token) = create_transit_accounts(&mut program_context).await;
+
+    let stake_start = instruction::stake_start(
+        &transit.pool.pubkey(),
+        &pool_transit.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &pool_transit_token.pubkey(),
+        &mint_sos,
+        &user_wallet.pubkey(),
+        &transit.user_token_sos.pubkey(),
+        StakeStartInput { amount: 0 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[stake_start], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::ZeroAmount as u32
+    ));
+}
+
+#[tokio::test]
+async fn unstake_start_zero_amount() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, 10_000).await;
+    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+    let transaction = crate::utils::sdk::stake_finish(
+        &transit.pool,
+        &transit.pool_token_account_sos,
+        &transit.pool_transit,
+        &transit.pool_transit_token,
+        &transit.user_token_xsos,
+        &user_wallet,
+        &transit.mint_xsos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    let mint_sos = get_token_account_state(&mut program_context, &transit.pool_token_account_sos)
+        .await
+        .mint;
+    let (pool_transit, pool_transit_token) = create_transit_accounts(&mut program_context).await;
+
+    let unstake_start = instruction::unstake_start(
+        &transit.pool.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &pool_transit.pubkey(),
+        &pool_transit_token.pubkey(),
+        &mint_sos,
+        &user_wallet.pubkey(),
+        &transit.user_token_xsos.pubkey(),
+        &transit.mint_xsos.pubkey(),
+        UnstakeStartInput { amount: 0 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[unstake_start], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::ZeroAmount as u32
+    ));
+}
+
+#[tokio::test]
+async fn lock_zero_amount() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, 2500).await;
+    let lock = instruction::lock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        LockInput { amount: 0 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[lock], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::ZeroAmount as u32
+    ));
+}
+
+#[tokio::test]
+async fn unlock_zero_amount() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let staked = setup_lock(&mut program_context, &user_wallet, 0, 0, 0, 2500).await;
+    let unlock = instruction::unlock(
+        &staked.pool.pubkey(),
+        &user_wallet.pubkey(),
+        &staked.pool_lock_token_xsos.pubkey(),
+        &staked.user_token_xsos.pubkey(),
+        UnlockInput { amount: 0 },
+    )
+    .unwrap();
+    let error = process_instructions(&mut program_context, &[unlock], &[&user_wallet])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::ZeroAmount as u32
+    ));
+}
+
+#[tokio::test]
+async fn stakes_paused_blocks_stake_start_but_not_unstake_start() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+    let stake_amount = 10_000;
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, stake_amount).await;
+    warp_seconds(&mut program_context, 3 * 100 * 60).await;
+    let transaction = crate::utils::sdk::stake_finish(
+        &transit.pool,
+        &transit.pool_token_account_sos,
+        &transit.pool_transit,
+        &transit.pool_transit_token,
+        &transit.user_token_xsos,
+        &user_wallet,
+        &transit.mint_xsos,
+        &program_context,
+    );
+    program_context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .unwrap();
+    let mint_sos = get_token_account_state(&mut program_context, &transit.pool_token_account_sos)
+        .await
+        .mint;
+
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let stake_transit = Keypair::new();
+    let stake_transit_token = Keypair::new();
+    let unstake_transit = Keypair::new();
+    let unstake_transit_token = Keypair::new();
+    for (account, space, owner) in [
+        (&stake_transit, PoolTransit::LEN, id()),
+        (&stake_transit_token, TokenAccount::LEN, spl_token::id()),
+        (&unstake_transit, PoolTransit::LEN, id()),
+        (&unstake_transit_token, TokenAccount::LEN, spl_token::id()),
+    ]
+    .iter()
+    {
+        create_account(
+            &mut program_context,
+            account,
+            rent.minimum_balance(*space),
+            *space as u64,
+            owner,
+        )
+        .await
+        .unwrap();
+    }
+
+    let set_stakes_paused = |stakes_paused| {
+        instruction::set_stakes_paused(
+            &transit.pool.pubkey(),
+            &transit.ido_authority.pubkey(),
+            SetStakesPausedInput { stakes_paused },
+        )
+    };
+    process_instructions(
+        &mut program_context,
+        &[set_stakes_paused(true)],
+        &[&transit.ido_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(transit.pool.pubkey())
+        .await
+        .unwrap();
+    assert!(pool_state.stakes_paused);
+
+    let stake_start = instruction::stake_start(
+        &transit.pool.pubkey(),
+        &stake_transit.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &stake_transit_token.pubkey(),
+        &mint_sos,
+        &user_wallet.pubkey(),
+        &transit.user_token_sos.pubkey(),
+        StakeStartInput { amount: 1_000 },
+    )
+    .unwrap();
+    let error = process_instructions(
+        &mut program_context,
+        &[stake_start.clone()],
+        &[&user_wallet],
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == crate::error::Error::StakesPaused as u32
+    ));
+
+    let unstake_start = instruction::unstake_start(
+        &transit.pool.pubkey(),
+        &transit.pool_token_account_sos.pubkey(),
+        &unstake_transit.pubkey(),
+        &unstake_transit_token.pubkey(),
+        &mint_sos,
+        &user_wallet.pubkey(),
+        &transit.user_token_xsos.pubkey(),
+        &transit.mint_xsos.pubkey(),
+        UnstakeStartInput { amount: 420 },
+    )
+    .unwrap();
+    process_instructions(&mut program_context, &[unstake_start], &[&user_wallet])
+        .await
+        .unwrap();
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_xsos).await;
+    assert_eq!(account_state.amount, stake_amount - 420);
+
+    process_instructions(
+        &mut program_context,
+        &[set_stakes_paused(false), stake_start],
+        &[&transit.ido_authority, &user_wallet],
+    )
+    .await
+    .unwrap();
+    let account_state =
+        get_token_account_state(&mut program_context, &transit.user_token_sos).await;
+    assert_eq!(account_state.amount, 1_000_000 - stake_amount - 1_000);
+}
+
+#[tokio::test]
+async fn set_ido_authority_moves_start_pool_to_new_authority() {
+    let mut program_context = program_test().start_with_context().await;
+    let user_wallet = Keypair::from_bytes(&program_context.payer.to_bytes()[..]).unwrap();
+
+    let transit = setup_transit(&mut program_context, &user_wallet, 0, 0, 0, 10_000).await;
+    let pool = transit.pool.pubkey();
+    let old_authority = &transit.ido_authority;
+    let new_authority = Keypair::new();
+    let assert_custom = |error: TransportError, expected: crate::error::Error| {
+        assert!(matches!(
+            error,
+            TransportError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code),
+            )) if code == expected as u32
+        ));
+    };
+    let set_ido_authority = |market_authority: &Keypair, ido_authority| {
+        instruction::set_ido_authority(
+            &pool,
+            &market_authority.pubkey(),
+            SetIdoAuthorityInput { ido_authority },
+        )
+    };
+
+    let error = process_instructions(
+        &mut program_context,
+        &[set_ido_authority(old_authority, Pubkey::default())],
+        &[old_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom(error, crate::error::Error::IdoAuthorityInvalid);
+
+    let error = process_instructions(
+        &mut program_context,
+        &[set_ido_authority(&new_authority, new_authority.pubkey())],
+        &[&new_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom(error, crate::error::Error::PoolMustBeRelatedToMarket);
+
+    process_instructions(
+        &mut program_context,
+        &[set_ido_authority(old_authority, new_authority.pubkey())],
+        &[old_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.ido_authority, new_authority.pubkey());
+
+    let now = get_clock(&mut program_context).await.unix_timestamp;
+    let start_pool = |market_authority: &Keypair| {
+        instruction::start_pool(
+            &pool,
+            &market_authority.pubkey(),
+            StartPoolInput {
+                pool_active_until: now + 100 * 60,
+            },
+        )
+    };
+    let error = process_instructions(
+        &mut program_context,
+        &[start_pool(old_authority)],
+        &[old_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom(error, crate::error::Error::PoolMustBeRelatedToMarket);
+
+    process_instructions(
+        &mut program_context,
+        &[start_pool(&new_authority)],
+        &[&new_authority],
+    )
+    .await
+    .unwrap();
+}
+
+/// moves all tokens of `account` to `sink` and closes `account` with lamports going to `destination`,
+/// `account` is owned by authority derived from its key
+fn close_token_account_processor(
+    program_id: &Pubkey,
+    accounts: &[solana_program::account_info::AccountInfo],
+    _input: &[u8],
+) -> solana_program::entrypoint::ProgramResult {
+    match accounts {
+        [account, sink, destination, authority, _token_program, ..] => {
+            let (_, bump_seed) =
+                Pubkey::find_program_address(&[&account.key.to_bytes()[..32]], program_id);
+            let signature = &[&account.key.to_bytes()[..32], &[bump_seed]];
+            let amount = TokenAccount::unpack(&account.data.borrow())?.amount;
+            crate::invoke::token_transfer_signature(
+                account.clone(),
+                sink.clone(),
+                authority.clone(),
+                signature,
+                amount,
+            )?;
+            crate::invoke::close_token_account(
+                account.clone(),
+                destination.clone(),
+                authority.clone(),
+                signature,
+            )
+        }
+        _ => Err(ProgramError::NotEnoughAccountKeys),
+    }
+}
+
+#[tokio::test]
+async fn close_token_account_returns_rent() {
+    let close_program_id = Pubkey::new_unique();
+    let mut program_test = program_test();
+    program_test.add_program(
+        "close_token_account",
+        close_program_id,
+        processor!(close_token_account_processor),
+    );
+    let mut program_context = program_test.start_with_context().await;
+    let rent = program_context.banks_client.get_rent().await.unwrap();
+    let token_rent = rent.minimum_balance(TokenAccount::LEN);
+
+    let mint = Keypair::new();
+    let mint_authority = Keypair::new();
+    let account = Keypair::new();
+    let sink = Keypair::new();
+    let destination = Pubkey::new_unique();
+    let (authority, _) =
+        Pubkey::find_program_address(&[&account.pubkey().to_bytes()[..32]], &close_program_id);
+    for (keypair, space) in [
+        (&mint, Mint::LEN),
+        (&account, TokenAccount::LEN),
+        (&sink, TokenAccount::LEN),
+    ]
+    .iter()
+    {
+        create_account(
+            &mut program_context,
+            keypair,
+            rent.minimum_balance(*space),
+            *space as u64,
+            &spl_token::id(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let instructions = [
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &mint_authority.pubkey(),
+            None,
+            2,
+        )
+        .unwrap(),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account.pubkey(),
+            &mint.pubkey(),
+            &authority,
+        )
+        .unwrap(),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &sink.pubkey(),
+            &mint.pubkey(),
+            &authority,
+        )
+        .unwrap(),
+        spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &account.pubkey(),
+            &mint_authority.pubkey(),
+            &[],
+            1_000,
+        )
+        .unwrap(),
+    ];
+    process_instructions(&mut program_context, &instructions, &[&mint_authority])
+        .await
+        .unwrap();
+
+    let close = solana_program::instruction::Instruction::new_with_bytes(
+        close_program_id,
+        &[],
+        vec![
+            AccountMeta::new(account.pubkey(), false),
+            AccountMeta::new(sink.pubkey(), false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ],
+    );
+    process_instructions(&mut program_context, &[close], &[])
+        .await
+        .unwrap();
+
+    let closed = program_context
+        .banks_client
+        .get_account(account.pubkey())
+        .await
+        .unwrap();
+    assert!(closed.is_none());
+    let destination = get_account(&mut program_context, &destination).await;
+    assert_eq!(destination.lamports, token_rent);
+    let sink = get_token_account_state(&mut program_context, &sink).await;
+    assert_eq!(sink.amount, 1_000);
+}
+
+/// adds stake pool with `tier_users` not matching any locks
+fn add_corrupted_stake_pool(
+    program_test: &mut ProgramTest,
+    ido_authority: &Pubkey,
+    pool_active_until: UnixTimestamp,
+) -> Pubkey {
+    let rent = solana_program::rent::Rent::default();
+    let pool = Pubkey::new_unique();
+    let stake_pool = StakePool {
+        account_type: ACCOUNT_TYPE_STAKE_POOL,
+        version: StateVersion::V3,
+        ido_authority: *ido_authority,
+        tier_balance: [10, 20, 30, 40],
+        tier_users: [5, 0, 7, 1],
+        pool_active_until,
+        ..Default::default()
+    };
+    program_test.add_account(
+        pool,
+        Account {
+            lamports: rent.minimum_balance(StakePool::LEN),
+            data: stake_pool.try_to_vec().unwrap(),
+            owner: id(),
+            ..Default::default()
+        },
+    );
+    pool
+}
+
+/// adds lock of `pool` holding `amount` of xSOS, returns lock and its token account
+fn add_pool_lock(program_test: &mut ProgramTest, pool: &Pubkey, amount: u64) -> (Pubkey, Pubkey) {
+    let rent = solana_program::rent::Rent::default();
+    let pool_lock = Pubkey::new_unique();
+    let pool_lock_token_xsos = Pubkey::new_unique();
+    let pool_lock_state = PoolLock {
+        account_type: ACCOUNT_TYPE_POOL_LOCK,
+        version: StateVersion::V1,
+        pool: *pool,
+        user_wallet: Pubkey::new_unique(),
+        token_account_xsos: pool_lock_token_xsos,
+        ..Default::default()
+    };
+    program_test.add_account(
+        pool_lock,
+        Account {
+            lamports: rent.minimum_balance(PoolLock::LEN),
+            data: pool_lock_state.try_to_vec().unwrap(),
+            owner: id(),
+            ..Default::default()
+        },
+    );
+    program_test.add_packable_account(
+        pool_lock_token_xsos,
+        rent.minimum_balance(TokenAccount::LEN),
+        &TokenAccount {
+            mint: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            amount,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        },
+        &spl_token::id(),
+    );
+    (pool_lock, pool_lock_token_xsos)
+}
+
+fn assert_custom_error(error: TransportError, expected: crate::error::Error) {
+    assert!(matches!(
+        error,
+        TransportError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) if code == expected as u32
+    ));
+}
+
+#[tokio::test]
+async fn recompute_tier_users_repairs_corrupted_counts() {
+    let mut program_test = program_test();
+    let ido_authority = Keypair::new();
+    let pool = add_corrupted_stake_pool(&mut program_test, &ido_authority.pubkey(), 0);
+    let locks: Vec<_> = [5, 15, 25, 45, 45]
+        .iter()
+        .map(|amount| add_pool_lock(&mut program_test, &pool, *amount))
+        .collect();
+    let foreign_lock = add_pool_lock(&mut program_test, &Pubkey::new_unique(), 45);
+
+    let mut program_context = program_test.start_with_context().await;
+
+    let recompute = |locks: &[(Pubkey, Pubkey)]| {
+        instruction::recompute_tier_users(&pool, &ido_authority.pubkey(), locks)
+    };
+
+    let duplicate = [locks[0], locks[1], locks[0]];
+    let error = process_instructions(
+        &mut program_context,
+        &[recompute(&duplicate)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::DuplicateLock);
+
+    let error = process_instructions(
+        &mut program_context,
+        &[recompute(&[locks[0], foreign_lock])],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::LockMustBeRelatedToPool);
+
+    let other_authority = Keypair::new();
+    let error = process_instructions(
+        &mut program_context,
+        &[instruction::recompute_tier_users(
+            &pool,
+            &other_authority.pubkey(),
+            &locks,
+        )],
+        &[&other_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::PoolMustBeRelatedToMarket);
+
+    process_instructions(
+        &mut program_context,
+        &[recompute(&locks)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap();
+
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [1, 1, 0, 2]);
+
+    let pool_lock_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<PoolLock>(locks[3].0)
+        .await
+        .unwrap();
+    assert!(pool_lock_state.tier_counted);
+    assert_eq!(pool_lock_state.twab_balance, 45);
+    assert_eq!(pool_lock_state.counted_balance(0), 45);
+}
+
+#[tokio::test]
+async fn recompute_tier_users_in_batches() {
+    let mut program_test = program_test();
+    let ido_authority = Keypair::new();
+    let pool = add_corrupted_stake_pool(&mut program_test, &ido_authority.pubkey(), i64::MAX);
+    let mut locks: Vec<_> = [5, 15, 25, 45, 45]
+        .iter()
+        .map(|amount| add_pool_lock(&mut program_test, &pool, *amount))
+        .collect();
+    locks.sort();
+    let inactive_pool = add_corrupted_stake_pool(&mut program_test, &ido_authority.pubkey(), 0);
+    let inactive_lock = add_pool_lock(&mut program_test, &inactive_pool, 45);
+
+    let mut program_context = program_test.start_with_context().await;
+
+    let batch = |pool: &Pubkey, locks: &[(Pubkey, Pubkey)], first, last| {
+        instruction::recompute_tier_users_batch(
+            pool,
+            &ido_authority.pubkey(),
+            locks,
+            RecomputeTierUsersInput { first, last },
+        )
+    };
+
+    let error = process_instructions(
+        &mut program_context,
+        &[batch(&pool, &locks[..2], false, false)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::RecountNotStarted);
+
+    let error = process_instructions(
+        &mut program_context,
+        &[batch(&inactive_pool, &[inactive_lock], true, false)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::RecountRequiresActivePool);
+
+    let error = process_instructions(
+        &mut program_context,
+        &[batch(&pool, &[locks[1], locks[0]], true, false)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::LocksMustBeSorted);
+
+    process_instructions(
+        &mut program_context,
+        &[batch(&pool, &locks[..2], true, false)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [5, 0, 7, 1]);
+    assert_eq!(pool_state.recount_cursor, locks[1].0);
+
+    // lock counted by previous batch is not counted again
+    let error = process_instructions(
+        &mut program_context,
+        &[batch(&pool, &locks[1..], false, true)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap_err();
+    assert_custom_error(error, crate::error::Error::LocksMustBeSorted);
+
+    process_instructions(
+        &mut program_context,
+        &[batch(&pool, &locks[2..], false, true)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [1, 1, 0, 2]);
+    assert_eq!(pool_state.recount_cursor, Pubkey::default());
+    assert_eq!(pool_state.recount_tier_users, [0; crate::TIERS_COUNT]);
+
+    // single batch needs no active pool
+    process_instructions(
+        &mut program_context,
+        &[batch(&inactive_pool, &[inactive_lock], true, true)],
+        &[&ido_authority],
+    )
+    .await
+    .unwrap();
+    let pool_state = program_context
+        .banks_client
+        .get_account_data_with_borsh::<StakePool>(inactive_pool)
+        .await
+        .unwrap();
+    assert_eq!(pool_state.tier_users, [0, 0, 0, 1]);
+}
+
+#[test]
+fn test_process_instruction_malformed_data() {
+    let program_id = id();
+    let data = instruction::Instruction::StakeStart(StakeStartInput { amount: 42 })
+        .try_to_vec()
+        .unwrap();
+    assert_eq!(
+        crate::processor::Processor::process_instruction(&program_id, &[], &data),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+
+    let mut trailing = data.clone();
+    trailing.push(0);
+    for malformed in [
+        &trailing[..],
+        &data[..data.len() - 1],
+        &[][..],
+        &[u8::MAX][..],
+    ]
+    .iter()
+    {
+        assert_eq!(
+            crate::processor::Processor::process_instruction(&program_id, &[], malformed),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+}
+
+mod fuzz {
+    use super::*;
+    use crate::{borsh::try_instruction_from_slice, instruction::Instruction};
+    use proptest::prelude::*;
+    use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult};
+
+    /// dispatches `data` with `accounts_count` unsigned system accounts without data
+    fn process_with_accounts(data: &[u8], accounts_count: usize) -> ProgramResult {
+        let keys: Vec<_> = (0..accounts_count).map(|_| Pubkey::new_unique()).collect();
+        let owner = Pubkey::default();
+        let mut lamports = vec![0; accounts_count];
+        let mut accounts_data = vec![Vec::new(); accounts_count];
+        let accounts: Vec<_> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(accounts_data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        crate::processor::Processor::process_instruction(&id(), &accounts, data)
+    }
+
+    fn any_pubkey() -> impl Strategy<Value = Pubkey> {
+        any::<[u8; 32]>().prop_map(|bytes| Pubkey::new(&bytes))
+    }
+
+    fn any_instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            (
+                any::<[u64; crate::TIERS_COUNT]>(),
+                any_pubkey(),
+                any::<(i64, i64, u64, i64, i64)>(),
+            )
+                .prop_map(|(tier_balance, ido_authority, times)| {
+                    let (transit_incoming, transit_outgoing, reward_rate, min_lock, twab_window) =
+                        times;
+                    Instruction::InitializePool(InitializePoolInput {
+                        tier_balance,
+                        ido_authority,
+                        transit_incoming,
+                        transit_outgoing,
+                        reward_rate,
+                        min_lock_seconds: min_lock,
+                        twab_window_seconds: twab_window,
+                    })
+                }),
+            any::<u64>().prop_map(|amount| Instruction::StakeStart(StakeStartInput { amount })),
+            any::<u64>().prop_map(|amount| Instruction::UnstakeStart(UnstakeStartInput { amount })),
+            any::<u64>().prop_map(|amount| Instruction::Lock(LockInput { amount })),
+            any::<u64>().prop_map(|amount| Instruction::Unlock(UnlockInput { amount })),
+            any::<u64>()
+                .prop_map(|amount| Instruction::InitializeLockAndLock(LockInput { amount })),
+            any::<i64>().prop_map(|pool_active_until| {
+                Instruction::StartPool(StartPoolInput { pool_active_until })
+            }),
+            any::<i64>().prop_map(|pool_active_until| {
+                Instruction::ExtendPoolActive(ExtendPoolActiveInput { pool_active_until })
+            }),
+            any::<bool>().prop_map(|stakes_paused| {
+                Instruction::SetStakesPaused(SetStakesPausedInput { stakes_paused })
+            }),
+            any_pubkey().prop_map(|ido_authority| {
+                Instruction::SetIdoAuthority(SetIdoAuthorityInput { ido_authority })
+            }),
+            any::<(bool, bool)>().prop_map(|(first, last)| {
+                Instruction::RecomputeTierUsersBatch(RecomputeTierUsersInput { first, last })
+            }),
+            (0..8u8).prop_map(|variant| match variant {
+                0 => Instruction::StakeFinish,
+                1 => Instruction::UnstakeFinish,
+                2 => Instruction::InitializeLock,
+                3 => Instruction::ClaimStakingReward,
+                4 => Instruction::CancelTransit,
+                5 => Instruction::RefreshLock,
+                6 => Instruction::Compound,
+                _ => Instruction::RecomputeTierUsers,
+            }),
+        ]
+    }
+
+    #[test]
+    fn too_few_accounts() {
+        let instructions = [
+            (
+                Instruction::InitializePool(InitializePoolInput {
+                    tier_balance: [1, 2, 3, 4],
+                    ido_authority: Pubkey::new_unique(),
+                    transit_incoming: 0,
+                    transit_outgoing: 0,
+                    reward_rate: 0,
+                    min_lock_seconds: 0,
+                    twab_window_seconds: 0,
+                }),
+                7,
+            ),
+            (Instruction::StakeStart(StakeStartInput { amount: 1 }), 11),
+            (Instruction::StakeFinish, 10),
+            (
+                Instruction::UnstakeStart(UnstakeStartInput { amount: 1 }),
+                12,
+            ),
+            (Instruction::UnstakeFinish, 8),
+            (Instruction::InitializeLock, 9),
+            (Instruction::Lock(LockInput { amount: 1 }), 8),
+            (Instruction::Unlock(UnlockInput { amount: 1 }), 8),
+            (
+                Instruction::StartPool(StartPoolInput {
+                    pool_active_until: 1,
+                }),
+                3,
+            ),
+            (Instruction::ClaimStakingReward, 9),
+            (Instruction::CancelTransit, 10),
+            (
+                Instruction::ExtendPoolActive(ExtendPoolActiveInput {
+                    pool_active_until: 1,
+                }),
+                2,
+            ),
+            (Instruction::RecomputeTierUsers, 3),
+            (
+                Instruction::InitializeLockAndLock(LockInput { amount: 1 }),
+                11,
+            ),
+            (
+                Instruction::SetStakesPaused(SetStakesPausedInput {
+                    stakes_paused: true,
+                }),
+                2,
+            ),
+            (
+                Instruction::SetIdoAuthority(SetIdoAuthorityInput {
+                    ido_authority: Pubkey::new_unique(),
+                }),
+                2,
+            ),
+            (
+                Instruction::RecomputeTierUsersBatch(RecomputeTierUsersInput {
+                    first: true,
+                    last: true,
+                }),
+                3,
+            ),
+            (Instruction::RefreshLock, 6),
+            (Instruction::Compound, 8),
+        ];
+        for (instruction, required) in instructions.iter() {
+            let data = instruction.try_to_vec().unwrap();
+            assert_eq!(
+                process_with_accounts(&data, required - 1),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "{:?}",
+                instruction
+            );
+            assert_ne!(
+                process_with_accounts(&data, *required),
+                Err(ProgramError::NotEnoughAccountKeys),
+                "{:?}",
+                instruction
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn process_instruction_never_panics(
+            tag in 0..32u8,
+            rest in prop::collection::vec(any::<u8>(), 0..128),
+            accounts_count in 0..20usize,
+        ) {
+            let mut data = vec![tag];
+            data.extend(rest);
+            let result = process_with_accounts(&data, accounts_count);
+            if try_instruction_from_slice::<Instruction>(&data).is_err() {
+                prop_assert_eq!(result, Err(ProgramError::InvalidInstructionData));
+            } else {
+                prop_assert!(result.is_err());
+            }
+        }
+
+        #[test]
+        fn instruction_round_trip(instruction in any_instruction()) {
+            let data = instruction.try_to_vec().unwrap();
+            let decoded = try_instruction_from_slice::<Instruction>(&data).unwrap();
+            prop_assert_eq!(
+                process_with_accounts(&data, 0),
+                Err(ProgramError::NotEnoughAccountKeys)
+            );
+            prop_assert_eq!(decoded.try_to_vec().unwrap(), data);
+        }
+    }
+}
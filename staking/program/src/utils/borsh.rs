@@ -37,6 +37,35 @@ where
     }
 }
 
+/// Deserializes state of `len` bytes from account `data`, which must consume all of it.
+/// Fails with [ProgramError::AccountDataTooSmall] if `data` is shorter, so it was written with older layout,
+/// and with [ProgramError::InvalidAccountData] if state or account leaves bytes beyond `len`, so account is sized for other version.
+pub fn try_from_slice_exact<T: BorshDeserialize>(
+    data: &[u8],
+    len: usize,
+) -> Result<T, ProgramError> {
+    if data.len() < len {
+        return Err(ProgramError::AccountDataTooSmall);
+    }
+    let mut reader = data;
+    let state = T::deserialize(&mut reader)?;
+    if !reader.is_empty() || data.len() != len {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(state)
+}
+
+/// Deserializes instruction which must consume all of `data`.
+/// Fails with [ProgramError::InvalidInstructionData] if data is short, has unknown variant or leftover bytes.
+pub fn try_instruction_from_slice<T: BorshDeserialize>(data: &[u8]) -> Result<T, ProgramError> {
+    let mut reader = data;
+    let instruction = T::deserialize(&mut reader).or(Err(ProgramError::InvalidInstructionData))?;
+    if !reader.is_empty() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    Ok(instruction)
+}
+
 /// helpers
 pub trait AccountWithBorsh {
     /// reads data
@@ -61,3 +90,64 @@ impl<'a> AccountWithBorsh for AccountInfo<'a> {
         Ok((server_data, server_state))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{Instruction, StakeStartInput},
+        state::{PoolLock, StakePool},
+    };
+
+    #[test]
+    fn prefix_len() {
+        let data = StakePool::default().try_to_vec().unwrap();
+        assert_eq!(data.len(), StakePool::LEN);
+        let state = try_from_slice_exact::<StakePool>(&data, StakePool::LEN).unwrap();
+        assert_eq!(state.try_to_vec().unwrap(), data);
+
+        // account sized for other layout leaves trailing bytes
+        let mut trailing = data.clone();
+        trailing.push(0);
+        for len in [StakePool::LEN, trailing.len()].iter() {
+            assert_eq!(
+                try_from_slice_exact::<StakePool>(&trailing, *len).unwrap_err(),
+                ProgramError::InvalidAccountData
+            );
+        }
+        assert_eq!(
+            try_from_slice_exact::<StakePool>(&data[1..], StakePool::LEN).unwrap_err(),
+            ProgramError::AccountDataTooSmall
+        );
+
+        let lock = PoolLock::default().try_to_vec().unwrap();
+        assert!(try_from_slice_exact::<StakePool>(&lock, lock.len()).is_err());
+    }
+
+    #[test]
+    fn instruction_consumes_all() {
+        let data = Instruction::StakeStart(StakeStartInput { amount: 42 })
+            .try_to_vec()
+            .unwrap();
+        assert!(matches!(
+            try_instruction_from_slice::<Instruction>(&data),
+            Ok(Instruction::StakeStart(StakeStartInput { amount: 42 }))
+        ));
+
+        let mut trailing = data.clone();
+        trailing.push(0);
+        for malformed in [
+            &trailing[..],
+            &data[..data.len() - 1],
+            &[][..],
+            &[u8::MAX][..],
+        ]
+        .iter()
+        {
+            assert_eq!(
+                try_instruction_from_slice::<Instruction>(malformed).unwrap_err(),
+                ProgramError::InvalidInstructionData
+            );
+        }
+    }
+}
@@ -6,6 +6,7 @@ use solana_program::{
     program_error::ProgramError,
     pubkey::Pubkey,
     system_instruction,
+    sysvar::{rent::Rent, Sysvar},
 };
 use spl_token::instruction::initialize_account;
 
@@ -31,13 +32,19 @@ pub fn create_account<'a>(
     )
 }
 
-/// Initialize token account
+/// Initialize token account, account must be rent exempt
 pub fn initialize_token_account<'a>(
     account_to_initialize: AccountInfo<'a>,
     mint: AccountInfo<'a>,
     owner: AccountInfo<'a>,
     rent: AccountInfo<'a>,
 ) -> ProgramResult {
+    let lamports = account_to_initialize.lamports();
+    let data_len = account_to_initialize.data_len();
+    if !Rent::from_account_info(&rent)?.is_exempt(lamports, data_len) {
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+
     invoke(
         &initialize_account(
             &spl_token::id(),
@@ -167,6 +174,23 @@ pub fn burn_tokens_with_user_authority<'a>(
     invoke(&tx, &[burn_account, mint, authority])
 }
 
+/// Close empty token account with authority signature, its lamports go to `destination`
+pub fn close_token_account<'a>(
+    account: AccountInfo<'a>,
+    destination: AccountInfo<'a>,
+    authority: AccountInfo<'a>,
+    signature: &[&[u8]],
+) -> ProgramResult {
+    let tx = spl_token::instruction::close_account(
+        &spl_token::id(),
+        account.key,
+        destination.key,
+        authority.key,
+        &[],
+    )?;
+    invoke_signed(&tx, &[account, destination, authority], &[signature])
+}
+
 /// in program invoke to create program signed seeded account
 #[allow(clippy::too_many_arguments)]
 pub fn create_derived_account<'a>(
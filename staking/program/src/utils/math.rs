@@ -91,9 +91,14 @@ pub fn finish(
 
     let total = amount_claimed.checked_add(remaining_amount)?;
 
-    let possible_to_claim = total
-        .checked_mul(time_passed)?
-        .checked_div(transit_interval)?;
+    // zero length transit releases everything at once instead of dividing by zero
+    let possible_to_claim = if time_passed >= transit_interval {
+        total
+    } else {
+        total
+            .checked_mul(time_passed)?
+            .checked_div(transit_interval)?
+    };
     let amount_to_claim = possible_to_claim.checked_sub(amount_claimed)?;
     if amount_to_claim == 0 {
         None
@@ -102,9 +107,67 @@ pub fn finish(
     }
 }
 
+/// Precision of [crate::state::StakePool::reward_rate]
+pub const REWARD_PRECISION: u128 = 1_000_000_000;
+
+/// calculates reward accrued by locked amount during elapsed seconds
+pub fn reward(amount: u64, reward_rate: u64, elapsed: UnixTimestamp) -> Option<u64> {
+    let elapsed = i64::max(0, elapsed) as u128;
+    let reward = (amount as u128)
+        .checked_mul(reward_rate as u128)?
+        .checked_mul(elapsed)?
+        .checked_div(REWARD_PRECISION)?;
+    u64::try_from(reward).ok()
+}
+
+/// time weighted average over `window` seconds, `average` was reached `elapsed` seconds ago and `amount` is held since
+pub fn time_weighted_average(
+    average: u64,
+    amount: u64,
+    elapsed: UnixTimestamp,
+    window: UnixTimestamp,
+) -> u64 {
+    let elapsed = i64::max(0, elapsed);
+    if elapsed >= window {
+        return amount;
+    }
+    let (elapsed, window) = (elapsed as u128, window as u128);
+    // result is between `average` and `amount`, so it fits
+    let weighted = average as u128 * (window - elapsed) + amount as u128 * elapsed;
+    (weighted / window) as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    pub fn calculate_reward() {
+        assert_eq!(reward(0, 1_000_000_000, 100), Some(0));
+        assert_eq!(reward(100, 0, 100), Some(0));
+        assert_eq!(reward(100, 1_000_000_000, 0), Some(0));
+        assert_eq!(reward(100, 1_000_000_000, -5), Some(0));
+        assert_eq!(reward(100, 1_000_000_000, 10), Some(1_000));
+        assert_eq!(reward(2500, 1_000_000, 6000), Some(15_000));
+        assert_eq!(reward(1, 1, 1), Some(0));
+        assert_eq!(reward(u64::MAX, u64::MAX, i64::MAX), None);
+    }
+
+    #[test]
+    pub fn calculate_time_weighted_average() {
+        assert_eq!(time_weighted_average(0, 2500, 0, 100), 0);
+        assert_eq!(time_weighted_average(0, 2500, 50, 100), 1250);
+        assert_eq!(time_weighted_average(0, 2500, 100, 100), 2500);
+        assert_eq!(time_weighted_average(0, 2500, 1000, 100), 2500);
+        assert_eq!(time_weighted_average(2500, 0, 25, 100), 1875);
+        assert_eq!(time_weighted_average(2500, 0, -5, 100), 2500);
+        assert_eq!(time_weighted_average(1000, 2500, 0, 0), 2500);
+        assert_eq!(
+            time_weighted_average(u64::MAX, u64::MAX, 1, i64::MAX),
+            u64::MAX
+        );
+    }
 
     #[test]
     pub fn calculate() {
@@ -144,6 +207,12 @@ mod tests {
         let result = finish(10, 5, 30, 0, 10);
         assert_eq!(result, None);
 
+        let result = finish(10, 10, 10, 0, 10);
+        assert_eq!(result, Some(10));
+
+        let result = finish(10, 5, 0, 0, 10);
+        assert_eq!(result, Some(10));
+
         let result = finish(
             1_000_000_000_000,
             1_000_000_000_000 + 1_000_000,
@@ -217,4 +286,96 @@ mod tests {
             time += 1;
         }
     }
+
+    #[test]
+    pub fn increment_decrement() {
+        assert_eq!(0u64.error_increment(), Ok(1));
+        assert_eq!(1u64.error_decrement(), Ok(0));
+        assert_eq!(0u64.error_decrement(), Err(Error::Underflow.into()));
+        assert_eq!(u64::MAX.error_increment(), Err(Error::Overflow.into()));
+
+        assert_eq!(0u32.error_decrement(), Err(Error::Underflow.into()));
+        assert_eq!(u32::MAX.error_increment(), Err(Error::Overflow.into()));
+
+        let timestamp: UnixTimestamp = i64::MIN;
+        assert_eq!(timestamp.error_decrement(), Err(Error::Underflow.into()));
+        assert_eq!(
+            UnixTimestamp::MAX.error_increment(),
+            Err(Error::Overflow.into())
+        );
+        assert_eq!(0i64.error_decrement(), Ok(-1));
+    }
+
+    proptest! {
+        #[test]
+        fn finish_claims_never_exceed_total(
+            transit_from in 0..1_000_000_000_000i64,
+            transit_interval in 0..100_000_000i64,
+            total in any::<u64>(),
+            mut times in prop::collection::vec(0..200_000_000i64, 1..20),
+        ) {
+            let transit_until = transit_from + transit_interval;
+            times.sort_unstable();
+            let mut amount_claimed = 0u64;
+            let mut remaining_amount = total;
+            for time in times {
+                let now = transit_from + time;
+                let amount =
+                    finish(transit_from, now, transit_until, amount_claimed, remaining_amount);
+                if let Some(amount) = amount {
+                    prop_assert!(amount <= remaining_amount);
+                    amount_claimed += amount;
+                    remaining_amount -= amount;
+                }
+                prop_assert_eq!(amount_claimed + remaining_amount, total);
+            }
+
+            let rest = finish(
+                transit_from,
+                transit_until,
+                transit_until,
+                amount_claimed,
+                remaining_amount,
+            );
+            prop_assert_eq!(rest.unwrap_or(0), remaining_amount);
+        }
+
+        #[test]
+        fn finish_is_monotonic(
+            transit_from in 0..1_000_000_000_000i64,
+            transit_interval in 0..100_000_000i64,
+            total in any::<u64>(),
+            earlier in -1_000i64..200_000_000,
+            delta in 0..200_000_000i64,
+        ) {
+            let transit_until = transit_from + transit_interval;
+            let claim_at = |now| finish(transit_from, now, transit_until, 0, total).unwrap_or(0);
+            let now = transit_from + earlier;
+            prop_assert!(claim_at(now) <= claim_at(now + delta));
+        }
+
+        #[test]
+        fn finish_releases_everything_after_transit(
+            transit_from in 0..1_000_000_000_000i64,
+            transit_interval in 0..100_000_000i64,
+            after in 0..100_000_000i64,
+            amount_claimed in 0..u64::MAX / 2,
+            remaining_amount in 1..u64::MAX / 2,
+        ) {
+            let transit_until = transit_from + transit_interval;
+            let now = transit_until + after;
+            let result = finish(transit_from, now, transit_until, amount_claimed, remaining_amount);
+            prop_assert_eq!(result, Some(remaining_amount));
+        }
+
+        #[test]
+        fn finish_releases_nothing_at_start(
+            transit_from in 0..1_000_000_000_000i64,
+            transit_interval in 1..100_000_000i64,
+            total in any::<u64>(),
+        ) {
+            let transit_until = transit_from + transit_interval;
+            prop_assert_eq!(finish(transit_from, transit_from, transit_until, 0, total), None);
+        }
+    }
 }
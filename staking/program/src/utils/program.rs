@@ -1,14 +1,11 @@
 //! In program helpers
 
-use std::mem;
+use std::{convert::TryFrom, marker::PhantomData, mem};
 
 use borsh::BorshSerialize;
 use solana_program::{
-    account_info::AccountInfo,
-    entrypoint::ProgramResult,
-    program_error::ProgramError,
-    pubkey::Pubkey,
-    system_instruction,
+    account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
+    pubkey::Pubkey, system_instruction,
 };
 
 use crate::error::Error;
@@ -53,6 +50,103 @@ impl PubkeyPatterns for Pubkey {
     }
 }
 
+/// Recreates address of [PubkeyPatterns::find_key_program_address] from its stored bump seed.
+/// Costs one hash, while finding tries every bump from 255 down to the canonical one.
+/// Errors with [ProgramError::InvalidSeeds] if `bump_seed` gives no program address.
+pub fn canonical_authority(
+    key: &Pubkey,
+    bump_seed: u8,
+    program_id: &ProgramPubkey,
+) -> Result<ProgramDerivedPubkey, ProgramError> {
+    Pubkey::create_program_address(
+        &PdaSigner::key(key, bump_seed).seeds(),
+        &program_id.pubkey(),
+    )
+    .map_err(|_| ProgramError::InvalidSeeds)
+}
+
+/// Signer seeds of address derived by [PubkeyPatterns], `K` holds keys address was derived from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PdaSigner<K> {
+    keys: K,
+    bump_seed: [u8; 1],
+}
+
+impl PdaSigner<[[u8; 32]; 1]> {
+    /// signer of [PubkeyPatterns::find_key_program_address]
+    pub fn key(key: &Pubkey, bump_seed: u8) -> Self {
+        Self {
+            keys: [key.to_bytes()],
+            bump_seed: [bump_seed],
+        }
+    }
+
+    /// seeds for `invoke_signed`
+    pub fn seeds(&self) -> [&[u8]; 2] {
+        [&self.keys[0][..], &self.bump_seed[..]]
+    }
+}
+
+impl PdaSigner<[[u8; 32]; 2]> {
+    /// signer of [PubkeyPatterns::find_2key_program_address]
+    pub fn key2(key1: &Pubkey, key2: &Pubkey, bump_seed: u8) -> Self {
+        Self {
+            keys: [key1.to_bytes(), key2.to_bytes()],
+            bump_seed: [bump_seed],
+        }
+    }
+
+    /// seeds for `invoke_signed`
+    pub fn seeds(&self) -> [&[u8]; 3] {
+        [&self.keys[0][..], &self.keys[1][..], &self.bump_seed[..]]
+    }
+}
+
+/// Marks [UserAuthority] derived from a pool
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoolScope {}
+
+/// Marks [UserAuthority] derived from a market
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MarketScope {}
+
+/// Authority derived from scope (pool or market) and user wallet, in this order
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserAuthority<Scope> {
+    /// derived address
+    pub key: ProgramDerivedPubkey,
+    /// bump seed of `key`
+    pub bump_seed: u8,
+    scope: Pubkey,
+    user_wallet: Pubkey,
+    _scope: PhantomData<Scope>,
+}
+
+/// Authority of user wallet in pool
+pub type PoolUserAuthority = UserAuthority<PoolScope>;
+
+/// Authority of user wallet in market
+pub type MarketUserAuthority = UserAuthority<MarketScope>;
+
+impl<Scope> UserAuthority<Scope> {
+    /// derives authority of `user_wallet` in `scope`
+    pub fn derive(scope: &Pubkey, user_wallet: &Pubkey, program_id: &ProgramPubkey) -> Self {
+        let (key, bump_seed) = Pubkey::find_2key_program_address(scope, user_wallet, program_id);
+        Self {
+            key,
+            bump_seed,
+            scope: *scope,
+            user_wallet: *user_wallet,
+            _scope: PhantomData,
+        }
+    }
+
+    /// signer for `invoke_signed`
+    pub fn signer(&self) -> PdaSigner<[[u8; 32]; 2]> {
+        PdaSigner::key2(&self.scope, &self.user_wallet, self.bump_seed)
+    }
+}
+
 /// swaps two accounts data
 /// panics if accounts are borrowedy
 pub fn swap_accounts<'a, T: Default + BorshSerialize>(
@@ -78,8 +172,8 @@ pub trait AccountPatterns {
     /// public key
     fn pubkey(&self) -> Pubkey;
 
-    /// checks if program_id owner of self
-    fn is_owner(&self, program_id: &ProgramPubkey) -> ProgramResult;
+    /// checks if `program` is owner of self, errors with [ProgramError::IncorrectProgramId] otherwise
+    fn owned_by(&self, program: &ProgramPubkey) -> ProgramResult;
 
     /// checks if account is signer
     fn is_signer(&self) -> ProgramResult;
@@ -104,8 +198,8 @@ impl<'a> AccountPatterns for AccountInfo<'a> {
         *self.key
     }
 
-    fn is_owner(&self, program_id: &ProgramPubkey) -> ProgramResult {
-        if *self.owner != program_id.pubkey() {
+    fn owned_by(&self, program: &ProgramPubkey) -> ProgramResult {
+        if *self.owner != program.pubkey() {
             return Err(ProgramError::IncorrectProgramId);
         }
 
@@ -143,6 +237,58 @@ pub type ProgramDerivedPubkey = Pubkey;
 /// marker wrapper for program accounts
 pub struct ProgramAccountInfo<'a, 'b>(pub &'b AccountInfo<'a>);
 
+/// Splits `accounts` of `instruction` into `N` required accounts, to be bound by names in order, and optional rest.
+/// Fails with [ProgramError::NotEnoughAccountKeys] logging `instruction` if less than `N` accounts are passed.
+pub fn required_accounts<'a, 'b, const N: usize>(
+    instruction: &str,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<(&'b [AccountInfo<'a>; N], &'b [AccountInfo<'a>]), ProgramError> {
+    if accounts.len() < N {
+        msg!(
+            "{} requires {} accounts, got {}",
+            instruction,
+            N,
+            accounts.len()
+        );
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    let (required, optional) = accounts.split_at(N);
+    let required = <&[AccountInfo<'a>; N]>::try_from(required)
+        .map_err(|_| ProgramError::NotEnoughAccountKeys)?;
+    Ok((required, optional))
+}
+
+/// Declares typed accounts of instruction, each field names next required account in order.
+/// `new` fails with [ProgramError::NotEnoughAccountKeys] logging struct name if less accounts are passed,
+/// accounts after required ones are `remaining`.
+#[macro_export]
+macro_rules! instruction_accounts {
+    ($(#[$meta:meta])* $vis:vis struct $name:ident { $($field:ident),+ $(,)? }) => {
+        $(#[$meta])*
+        #[allow(dead_code)] // accounts used implicitly, like programs of CPI, are not read
+        $vis struct $name<'a, 'b> {
+            $($vis $field: &'b $crate::solana_program::account_info::AccountInfo<'a>,)+
+            $vis remaining: &'b [$crate::solana_program::account_info::AccountInfo<'a>],
+        }
+
+        impl<'a, 'b> $name<'a, 'b> {
+            /// Takes required accounts in order
+            $vis fn new(
+                accounts: &'b [$crate::solana_program::account_info::AccountInfo<'a>],
+            ) -> Result<Self, $crate::solana_program::program_error::ProgramError> {
+                const COUNT: usize = [$(stringify!($field)),+].len();
+                let (required, remaining) =
+                    $crate::program::required_accounts::<COUNT>(stringify!($name), accounts)?;
+                let [$($field),+] = required;
+                Ok(Self {
+                    $($field,)+
+                    remaining,
+                })
+            }
+        }
+    };
+}
+
 /// Create account with seed signed
 #[allow(clippy::too_many_arguments)]
 pub fn create_account_with_seed_signed<'a>(
@@ -172,3 +318,202 @@ pub fn create_account_with_seed_signed<'a>(
     )?;
     Ok(())
 }
+
+/// burns account, moving all its lamports to `beneficiary`
+pub fn burn_account(burned: &AccountInfo, beneficiary: &AccountInfo) -> ProgramResult {
+    let mut from = burned.try_borrow_mut_lamports()?;
+    let mut to = beneficiary.try_borrow_mut_lamports()?;
+    **to = to.checked_add(**from).ok_or(Error::Overflow)?;
+    **from = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_by() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [];
+        let account = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &program_id.0,
+            false,
+            0,
+        );
+        assert_eq!(account.owned_by(&program_id), Ok(()));
+        assert_eq!(
+            account.owned_by(&ProgramPubkey(Pubkey::new_unique())),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn required_accounts_split() {
+        let program_id = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [0; 3];
+        let mut data: [[u8; 0]; 3] = [[]; 3];
+        let accounts: Vec<_> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &program_id, false, 0)
+            })
+            .collect();
+
+        let ([first, second], rest) = required_accounts::<2>("Test", &accounts).unwrap();
+        assert_eq!((first.key, second.key), (&keys[0], &keys[1]));
+        assert_eq!(rest.len(), 1);
+
+        let (all, rest) = required_accounts::<3>("Test", &accounts).unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(rest.is_empty());
+
+        assert_eq!(
+            required_accounts::<4>("Test", &accounts).unwrap_err(),
+            ProgramError::NotEnoughAccountKeys
+        );
+    }
+
+    #[test]
+    fn instruction_accounts_named() {
+        crate::instruction_accounts! {
+            struct TestAccounts {
+                first,
+                second,
+            }
+        }
+
+        let program_id = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [0; 3];
+        let mut data: [[u8; 0]; 3] = [[]; 3];
+        let accounts: Vec<_> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &program_id, false, 0)
+            })
+            .collect();
+
+        let named = TestAccounts::new(&accounts).unwrap();
+        assert_eq!((named.first.key, named.second.key), (&keys[0], &keys[1]));
+        assert_eq!(named.remaining.len(), 1);
+
+        assert_eq!(
+            TestAccounts::new(&accounts[..1]).err(),
+            Some(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn pda_signer_key() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let key = Pubkey::new_unique();
+        let (address, bump_seed) = Pubkey::find_key_program_address(&key, &program_id);
+
+        let signer = PdaSigner::key(&key, bump_seed);
+        let manual: &[&[u8]] = &[&key.to_bytes()[..32], &[bump_seed]];
+        assert_eq!(&signer.seeds()[..], manual);
+        assert_eq!(
+            Pubkey::create_program_address(&signer.seeds(), &program_id.pubkey()).unwrap(),
+            address
+        );
+    }
+
+    #[test]
+    fn pda_signer_key2() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let key1 = Pubkey::new_unique();
+        let key2 = Pubkey::new_unique();
+        let (address, bump_seed) = Pubkey::find_2key_program_address(&key1, &key2, &program_id);
+
+        let signer = PdaSigner::key2(&key1, &key2, bump_seed);
+        let manual: &[&[u8]] = &[&key1.to_bytes()[..32], &key2.to_bytes()[..32], &[bump_seed]];
+        assert_eq!(&signer.seeds()[..], manual);
+        assert_eq!(
+            Pubkey::create_program_address(&signer.seeds(), &program_id.pubkey()).unwrap(),
+            address
+        );
+
+        let swapped = PdaSigner::key2(&key2, &key1, bump_seed);
+        assert_ne!(signer, swapped);
+    }
+
+    #[test]
+    fn canonical_authority_from_stored_bump() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        for _ in 0..16 {
+            let key = Pubkey::new_unique();
+            let (address, bump_seed) = Pubkey::find_key_program_address(&key, &program_id);
+            assert_eq!(
+                canonical_authority(&key, bump_seed, &program_id),
+                Ok(address)
+            );
+
+            // each of these was hashed and rejected by find before reaching stored bump
+            for bump in (0..=u8::MAX).filter(|bump| *bump > bump_seed) {
+                assert_eq!(
+                    canonical_authority(&key, bump, &program_id),
+                    Err(ProgramError::InvalidSeeds)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn user_authority_seed_order() {
+        let program_id = ProgramPubkey(Pubkey::new_unique());
+        let pool = Pubkey::new_unique();
+        let market = Pubkey::new_unique();
+        let user_wallet = Pubkey::new_unique();
+
+        let authority = PoolUserAuthority::derive(&pool, &user_wallet, &program_id);
+        let (key, bump_seed) = Pubkey::find_2key_program_address(&pool, &user_wallet, &program_id);
+        assert_eq!((authority.key, authority.bump_seed), (key, bump_seed));
+        assert_eq!(
+            authority.signer(),
+            PdaSigner::key2(&pool, &user_wallet, bump_seed)
+        );
+        assert_eq!(
+            Pubkey::create_program_address(&authority.signer().seeds(), &program_id.pubkey())
+                .unwrap(),
+            key
+        );
+        assert_ne!(
+            PoolUserAuthority::derive(&user_wallet, &pool, &program_id).key,
+            key
+        );
+
+        let authority = MarketUserAuthority::derive(&market, &user_wallet, &program_id);
+        let (key, bump_seed) =
+            Pubkey::find_2key_program_address(&market, &user_wallet, &program_id);
+        assert_eq!((authority.key, authority.bump_seed), (key, bump_seed));
+        assert_eq!(
+            authority.signer(),
+            PdaSigner::key2(&market, &user_wallet, bump_seed)
+        );
+        assert_ne!(
+            MarketUserAuthority::derive(&user_wallet, &market, &program_id).key,
+            key
+        );
+    }
+}